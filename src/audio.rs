@@ -0,0 +1,131 @@
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, bail};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample};
+use log::{error, info};
+
+use crate::config::AudioSource;
+
+// how quickly the loudness follows the sound, and how slowly the average it is compared to does
+const LOUDNESS_SMOOTHING: f32 = 0.5;
+const AVERAGE_SMOOTHING: f32 = 0.02;
+// a beat is a sudden rise of the loudness this far above its average
+const BEAT_THRESHOLD: f32 = 1.5;
+// quieter than this is silence, which has no beats
+const MIN_BEAT_LOUDNESS: f32 = 0.02;
+const MIN_BEAT_INTERVAL: Duration = Duration::from_millis(250);
+// loudness at which `AudioFrame::loudness` reaches 1
+const FULL_LOUDNESS: f32 = 0.3;
+
+/// What the audio did since the last poll
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AudioFrame {
+    /// From 0 for silence to 1 for loud music
+    pub loudness: f32,
+    pub is_beat: bool,
+    /// Beats heard so far
+    pub beat_count: u32,
+}
+
+/// Listens to an audio device and picks out loudness and beat onsets, for music visualization
+pub struct AudioListener {
+    // dropping the stream stops capturing
+    _stream: cpal::Stream,
+    // RMS of every buffer the device delivered
+    levels: Receiver<f32>,
+    loudness: f32,
+    average: f32,
+    last_beat: Instant,
+    beat_count: u32,
+}
+
+impl AudioListener {
+    pub fn new(source: AudioSource) -> anyhow::Result<Self> {
+        let host = cpal::default_host();
+        // Capturing from an output device records what it plays, where the platform supports loopback
+        let (device, config) = match source {
+            AudioSource::Microphone => {
+                let device = host.default_input_device().context("No audio input device")?;
+                let config = device.default_input_config()?;
+                (device, config)
+            }
+            AudioSource::Loopback => {
+                let device = host.default_output_device().context("No audio output device")?;
+                let config = device.default_output_config()?;
+                (device, config)
+            }
+        };
+        info!("Listening to audio from {:?}", device.name().unwrap_or_default());
+
+        let (sender, levels) = mpsc::channel();
+        let stream_config = config.config();
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &stream_config, sender)?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &stream_config, sender)?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &stream_config, sender)?,
+            SampleFormat::I32 => build_stream::<i32>(&device, &stream_config, sender)?,
+            format => bail!("Unsupported audio sample format {:?}", format),
+        };
+        stream.play()?;
+
+        Ok(Self {
+            _stream: stream,
+            levels,
+            loudness: 0.0,
+            average: 0.0,
+            last_beat: Instant::now(),
+            beat_count: 0,
+        })
+    }
+
+    pub fn poll(&mut self) -> AudioFrame {
+        let mut is_beat = false;
+        for level in self.levels.try_iter() {
+            self.loudness += (level - self.loudness) * LOUDNESS_SMOOTHING;
+            self.average += (level - self.average) * AVERAGE_SMOOTHING;
+            if level > MIN_BEAT_LOUDNESS
+                && level > self.average * BEAT_THRESHOLD
+                && self.last_beat.elapsed() >= MIN_BEAT_INTERVAL
+            {
+                is_beat = true;
+                self.last_beat = Instant::now();
+                self.beat_count += 1;
+            }
+        }
+        AudioFrame {
+            loudness: (self.loudness / FULL_LOUDNESS).min(1.0),
+            is_beat,
+            beat_count: self.beat_count,
+        }
+    }
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sender: mpsc::Sender<f32>,
+) -> anyhow::Result<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            if data.is_empty() {
+                return;
+            }
+            let sum = data
+                .iter()
+                .map(|sample| f32::from_sample_(*sample).powi(2))
+                .sum::<f32>();
+            // The listener is gone when this fails, and the stream with it soon
+            let _ = sender.send((sum / data.len() as f32).sqrt());
+        },
+        |e| error!("Audio stream error: {:?}", e),
+        None,
+    )?;
+    Ok(stream)
+}
@@ -0,0 +1,178 @@
+use crate::gpu;
+use crate::plugin::{self, PostEffect};
+
+// leaves every pixel as it is
+const NO_EFFECT: &str = "fn effect(color: vec4<f32>, uv: vec2<f32>) -> vec4<f32> { return color; }";
+// writes linear colors to a target which encodes them itself
+const LINEAR_OUTPUT: &str = "fn encode(color: vec4<f32>) -> vec4<f32> { return color; }";
+// encodes linear colors with the sRGB transfer function for a target which doesn't
+const SRGB_OUTPUT: &str = "fn encode(color: vec4<f32>) -> vec4<f32> {
+    let c = clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    let encoded = select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+    return vec4<f32>(encoded, color.a);
+}";
+
+pub const POST_EFFECTS: &[PostEffect] = &[
+    PostEffect {
+        name: "vignette",
+        wgsl: "fn effect(color: vec4<f32>, uv: vec2<f32>) -> vec4<f32> {
+            let darkening = smoothstep(0.4, 0.9, distance(uv, vec2<f32>(0.5, 0.5)));
+            return vec4<f32>(color.rgb * (1.0 - 0.7 * darkening), color.a);
+        }",
+    },
+    PostEffect {
+        name: "scanlines",
+        wgsl: "fn effect(color: vec4<f32>, uv: vec2<f32>) -> vec4<f32> {
+            let line = 0.5 + 0.5 * sin(uv.y * 3.14159 * f32(textureDimensions(source).y));
+            return vec4<f32>(color.rgb * (0.7 + 0.3 * line), color.a);
+        }",
+    },
+];
+
+/// The post effect of the given name, built in or registered by a plugin
+pub fn find_post_effect(name: &str) -> Option<PostEffect> {
+    POST_EFFECTS
+        .iter()
+        .copied()
+        .chain(plugin::post_effects())
+        .find(|effect| effect.name.eq_ignore_ascii_case(name))
+}
+
+pub fn post_effect_names() -> Vec<&'static str> {
+    POST_EFFECTS
+        .iter()
+        .copied()
+        .chain(plugin::post_effects())
+        .map(|effect| effect.name)
+        .collect()
+}
+
+/// Copies a texture onto a render target of another size, filtering linearly, through a post effect
+pub struct Blitter {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+}
+
+impl Blitter {
+    /// Fails if the effect's WGSL doesn't compile. With `encodes_gamma` the colors are encoded for a
+    /// target which isn't sRGB, see `renderer::scene_format`.
+    pub fn new(
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        effect: Option<PostEffect>,
+        encodes_gamma: bool,
+    ) -> anyhow::Result<Self> {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("BlitBindGroupLayout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("BlitPipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let effect = effect.map_or(NO_EFFECT, |effect| effect.wgsl);
+        let output = if encodes_gamma { SRGB_OUTPUT } else { LINEAR_OUTPUT };
+        let source = format!("{}\n{}\n{}", include_str!("blit.wgsl"), effect, output);
+        let shader = gpu::create_shader_module(device, "BlitShader", &source)?;
+        let pipeline = gpu::validated(device, "BlitPipeline", || {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("BlitPipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: target_format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: Default::default(),
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            })
+        })?;
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("BlitSampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+        })
+    }
+
+    /// Bind group to pass to `blit` for copying from `source`
+    pub fn bind_group(&self, device: &wgpu::Device, source: &wgpu::TextureView) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("BlitBindGroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+
+    /// Stretch the source of `bind_group` over the whole of `target`
+    pub fn blit(&self, encoder: &mut wgpu::CommandEncoder, bind_group: &wgpu::BindGroup, target: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("BlitPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,25 @@
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// One triangle covering the whole target, the parts outside of it are clipped
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// `effect` comes after this, from the post effect in use, and `encode` for the target
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return encode(effect(textureSample(source, source_sampler, in.uv), in.uv));
+}
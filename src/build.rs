@@ -0,0 +1,83 @@
+use std::fmt;
+
+use crate::chunks::Chunks;
+use crate::picking::{self, Ray};
+use crate::simulation::Command;
+use crate::world::{Direction, PipeType};
+
+/// Build mode, where pipes are placed by hand rather than grown: the camera aims at a free block through
+/// the middle of the view, and a straight pipe or an elbow heading the chosen way is placed there, see
+/// `World::build`
+#[derive(Copy, Clone, Debug)]
+pub struct BuildTool {
+    pub pipe_type: PipeType,
+    /// Which way the pipe leaves the block. Elbows come in the way the block placed last goes.
+    pub direction: Direction,
+    // the free block aimed at, None while aiming out of the world
+    target: Option<(u32, u32, u32)>,
+}
+
+impl Default for BuildTool {
+    fn default() -> Self {
+        Self {
+            pipe_type: PipeType::I,
+            direction: Direction::X,
+            target: None,
+        }
+    }
+}
+
+impl BuildTool {
+    pub fn toggle_pipe_type(&mut self) {
+        self.pipe_type = match self.pipe_type {
+            PipeType::I => PipeType::L,
+            PipeType::L => PipeType::I,
+        };
+    }
+
+    /// Head the next way of `Direction::ALL`
+    pub fn next_direction(&mut self) {
+        let i = Direction::ALL
+            .iter()
+            .position(|direction| *direction == self.direction)
+            .unwrap_or(0);
+        self.direction = Direction::ALL[(i + 1) % Direction::ALL.len()];
+    }
+
+    /// Aim along `ray` at a world `extents` blocks wide along X, Y and Z, true if that's another block than
+    /// before
+    pub fn aim(&mut self, chunks: &Chunks, ray: &Ray, extents: [u32; 3]) -> bool {
+        let target = chunks.free_block(ray, extents);
+        let is_changed = target != self.target;
+        self.target = target;
+        is_changed
+    }
+
+    /// The free block aimed at, if any
+    pub fn target(&self) -> Option<(u32, u32, u32)> {
+        self.target
+    }
+
+    /// The command placing the pipe at the block aimed at, if any
+    pub fn command(&self) -> Option<Command> {
+        Some(Command::Build {
+            pipe_type: self.pipe_type,
+            position: self.target?,
+            direction: self.direction,
+        })
+    }
+}
+
+impl fmt::Display for BuildTool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.pipe_type {
+            PipeType::I => "Straight",
+            PipeType::L => "Elbow out",
+        };
+        writeln!(f, "Build {} {}", kind, picking::direction_name(self.direction))?;
+        match self.target {
+            Some((x, y, z)) => write!(f, "At block {}, {}, {}", x, y, z),
+            None => write!(f, "Aiming out of the world"),
+        }
+    }
+}
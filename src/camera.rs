@@ -1,6 +1,19 @@
 use cgmath::SquareMatrix;
-use winit::keyboard::KeyCode;
 
+use crate::chunks::{Bounds, Frustum};
+use crate::keybindings::Action;
+use crate::picking::Ray;
+
+const ORBIT_SPEED: f32 = 0.03;
+const ZOOM_SPEED: f32 = 0.03;
+const MIN_DISTANCE: f32 = 0.5;
+const MAX_PITCH: f32 = 0.99;
+// the near plane is never closer than this share of the far one, or depth precision would run out
+const MIN_NEAR_RATIO: f32 = 0.001;
+// the far plane when there is nothing in front of the eye
+const MIN_FAR: f32 = 1.0;
+
+#[derive(Clone)]
 pub struct Camera {
     eye: cgmath::Point3<f32>,
     target: cgmath::Point3<f32>,
@@ -19,6 +32,15 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_co
     cgmath::Vector4::new(0.0, 0.0, 0.5, 1.0),
 );
 
+/// Turns wgpu's depth around, from 0 near and 1 far to 1 near and 0 far
+#[rustfmt::skip]
+const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_cols(
+    cgmath::Vector4::new(1.0, 0.0, 0.0, 0.0),
+    cgmath::Vector4::new(0.0, 1.0, 0.0, 0.0),
+    cgmath::Vector4::new(0.0, 0.0, -1.0, 0.0),
+    cgmath::Vector4::new(0.0, 0.0, 1.0, 1.0),
+);
+
 impl Camera {
     fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
         let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
@@ -37,6 +59,119 @@ impl Camera {
             zfar: 100.0,
         }
     }
+
+    pub fn eye(&self) -> cgmath::Point3<f32> {
+        self.eye
+    }
+
+    pub fn target(&self) -> cgmath::Point3<f32> {
+        self.target
+    }
+
+    pub fn set_aspect(&mut self, aspect: f32) {
+        self.aspect = aspect;
+    }
+
+    pub fn look_at(&mut self, eye: cgmath::Point3<f32>, target: cgmath::Point3<f32>) {
+        self.eye = eye;
+        self.target = target;
+    }
+
+    /// Fit the near and far planes around `bounds` as seen from the eye, so that depth is spread over what
+    /// there is to see: nothing in them is clipped however large, and nothing z-fights however far away
+    pub fn fit_depth_range(&mut self, bounds: &Bounds) {
+        use cgmath::{EuclideanSpace, InnerSpace};
+        let center = bounds.min.midpoint(bounds.max);
+        let radius = (bounds.max - bounds.min).magnitude() / 2.0;
+        // along the view direction, which is what depth is measured along
+        let distance = (center - self.eye).dot((self.target - self.eye).normalize());
+        self.zfar = (distance + radius).max(MIN_FAR);
+        self.znear = (distance - radius).max(self.zfar * MIN_NEAR_RATIO);
+    }
+
+    /// The ray from the eye through a point of the view, given from -1 to 1 left to right and bottom to top
+    pub fn ray(&self, x: f32, y: f32) -> Ray {
+        use cgmath::InnerSpace;
+        let inverse = self
+            .build_view_projection_matrix()
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+        // depth goes from 0 at the near plane to 1 at the far one
+        let near = cgmath::Point3::from_homogeneous(inverse * cgmath::Vector4::new(x, y, 0.0, 1.0));
+        let far = cgmath::Point3::from_homogeneous(inverse * cgmath::Vector4::new(x, y, 1.0, 1.0));
+        Ray {
+            origin: near,
+            direction: (far - near).normalize(),
+        }
+    }
+
+    /// The views of a left and a right eye `ipd` apart, looking in parallel so that far pipes line up
+    pub fn stereo_pair(&self, ipd: f32) -> [Camera; 2] {
+        use cgmath::InnerSpace;
+        let right = (self.target - self.eye).cross(self.up).normalize() * (ipd / 2.0);
+        let shifted = |offset| {
+            let mut camera = self.clone();
+            camera.eye += offset;
+            camera.target += offset;
+            camera
+        };
+        [shifted(-right), shifted(right)]
+    }
+
+    /// Rotate the eye around the target, angles are in radians
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        use cgmath::{InnerSpace, Rotation3};
+        if yaw == 0.0 && pitch == 0.0 {
+            return;
+        }
+
+        let offset = self.eye - self.target;
+        let right = offset.cross(self.up).normalize();
+        let yaw_rotation = cgmath::Quaternion::from_axis_angle(self.up, cgmath::Rad(-yaw));
+        let pitch_rotation = cgmath::Quaternion::from_axis_angle(right, cgmath::Rad(pitch));
+        let mut rotated = yaw_rotation * offset;
+        let pitched = pitch_rotation * rotated;
+        // Stop before looking straight up or down, where `up` and the view direction become parallel
+        if pitched.normalize().dot(self.up).abs() < MAX_PITCH {
+            rotated = pitched;
+        }
+        self.eye = self.target + rotated;
+    }
+
+    /// Scale the eye-target distance
+    pub fn zoom(&mut self, factor: f32) {
+        use cgmath::InnerSpace;
+        if factor == 1.0 {
+            return;
+        }
+
+        let offset = self.eye - self.target;
+        let distance = (offset.magnitude() * factor).max(MIN_DISTANCE);
+        self.eye = self.target + offset.normalize() * distance;
+    }
+
+    /// Pan by a number of pixels of a viewport `height` pixels tall, so that points at the target's depth
+    /// move exactly along with the offset
+    pub fn pan_pixels(&mut self, dx: f32, dy: f32, height: f32) {
+        let view_height = 2.0 * (self.fovy.to_radians() / 2.0).tan();
+        self.pan(dx / height.max(1.0) * view_height, dy / height.max(1.0) * view_height);
+    }
+
+    /// Move both eye and target in the view plane, offsets are relative to the eye-target distance
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        use cgmath::InnerSpace;
+        if dx == 0.0 && dy == 0.0 {
+            return;
+        }
+
+        let forward = self.target - self.eye;
+        let distance = forward.magnitude();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward).normalize();
+        let offset = (right * -dx + up * dy) * distance;
+        self.eye += offset;
+        self.target += offset;
+    }
 }
 
 #[repr(C)]
@@ -58,6 +193,42 @@ impl CameraUniform {
         self.view_position = camera.eye.to_homogeneous().into();
         self.view_projection = camera.build_view_projection_matrix().into();
     }
+
+    pub fn eye(&self) -> cgmath::Point3<f32> {
+        let [x, y, z, _] = self.view_position;
+        cgmath::Point3::new(x, y, z)
+    }
+
+    /// Project depth from 1 at the near plane to 0 at the far one, for a reversed depth buffer. Call after
+    /// updating the view projection.
+    pub fn reverse_depth(&mut self) {
+        self.view_projection = (REVERSE_Z_MATRIX * cgmath::Matrix4::from(self.view_projection)).into();
+    }
+
+    /// Shift what the camera sees by `offset` across the screen, in normalized device coordinates, for TAA.
+    /// Call after everything else changing the view projection.
+    pub fn jitter(&mut self, offset: cgmath::Vector2<f32>) {
+        let shift = cgmath::Matrix4::from_translation(offset.extend(0.0));
+        self.view_projection = (shift * cgmath::Matrix4::from(self.view_projection)).into();
+    }
+
+    /// What the camera sees, to skip whatever lies outside
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_projection(self.view_projection.into())
+    }
+
+    /// View the world mirrored at the horizontal plane at `height`, as if reflected by a floor there
+    pub fn update_reflected(&mut self, camera: &Camera, height: f32) {
+        #[rustfmt::skip]
+        let mirror = cgmath::Matrix4::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, -1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 2.0 * height, 0.0, 1.0,
+        );
+        self.view_position = (mirror * camera.eye.to_homogeneous()).into();
+        self.view_projection = (camera.build_view_projection_matrix() * mirror).into();
+    }
 }
 
 pub struct CameraController {
@@ -66,6 +237,15 @@ pub struct CameraController {
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+
+    // analog input in [-1, 1], e.g. from a gamepad stick
+    orbit_input: (f32, f32),
+    zoom_input: f32,
+
+    // one-off deltas, e.g. from touch gestures, consumed by the next update
+    pending_orbit: (f32, f32),
+    pending_zoom: f32,
+    pending_pan: (f32, f32),
 }
 
 impl CameraController {
@@ -76,24 +256,67 @@ impl CameraController {
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            orbit_input: (0.0, 0.0),
+            zoom_input: 0.0,
+            pending_orbit: (0.0, 0.0),
+            pending_zoom: 1.0,
+            pending_pan: (0.0, 0.0),
         }
     }
 
-    pub fn handle_key(&mut self, code: KeyCode, is_pressed: bool) -> bool {
-        match code {
-            KeyCode::KeyW | KeyCode::ArrowUp => {
+    /// Whether the next update is going to move the camera
+    pub fn is_active(&self) -> bool {
+        self.is_forward_pressed
+            || self.is_backward_pressed
+            || self.is_left_pressed
+            || self.is_right_pressed
+            || self.orbit_input != (0.0, 0.0)
+            || self.zoom_input != 0.0
+            || self.pending_orbit != (0.0, 0.0)
+            || self.pending_zoom != 1.0
+            || self.pending_pan != (0.0, 0.0)
+    }
+
+    /// Angles are in radians
+    pub fn orbit_by(&mut self, yaw: f32, pitch: f32) {
+        self.pending_orbit.0 += yaw;
+        self.pending_orbit.1 += pitch;
+    }
+
+    /// `factor` < 1 moves the eye closer to the target
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.pending_zoom *= factor;
+    }
+
+    /// Offsets are relative to the eye-target distance
+    pub fn pan_by(&mut self, dx: f32, dy: f32) {
+        self.pending_pan.0 += dx;
+        self.pending_pan.1 += dy;
+    }
+
+    /// `yaw` and `pitch` rotate the eye around the target, positive `zoom` moves it closer
+    #[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+    pub fn set_analog_input(&mut self, yaw: f32, pitch: f32, zoom: f32) {
+        self.orbit_input = (yaw, pitch);
+        self.zoom_input = zoom;
+    }
+
+    /// Whether the action is one moving the camera
+    pub fn handle_action(&mut self, action: Action, is_pressed: bool) -> bool {
+        match action {
+            Action::CameraForward => {
                 self.is_forward_pressed = is_pressed;
                 true
             }
-            KeyCode::KeyA | KeyCode::ArrowLeft => {
+            Action::CameraLeft => {
                 self.is_left_pressed = is_pressed;
                 true
             }
-            KeyCode::KeyS | KeyCode::ArrowDown => {
+            Action::CameraBackward => {
                 self.is_backward_pressed = is_pressed;
                 true
             }
-            KeyCode::KeyD | KeyCode::ArrowRight => {
+            Action::CameraRight => {
                 self.is_right_pressed = is_pressed;
                 true
             }
@@ -101,7 +324,7 @@ impl CameraController {
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
+    pub fn update_camera(&mut self, camera: &mut Camera) {
         use cgmath::InnerSpace;
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
@@ -131,5 +354,21 @@ impl CameraController {
         if self.is_left_pressed {
             camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
         }
+
+        self.apply_analog_input(camera);
+    }
+
+    fn apply_analog_input(&mut self, camera: &mut Camera) {
+        let (yaw, pitch) = self.orbit_input;
+        camera.orbit(
+            yaw * ORBIT_SPEED + self.pending_orbit.0,
+            pitch * ORBIT_SPEED + self.pending_orbit.1,
+        );
+        camera.zoom((1.0 - self.zoom_input * ZOOM_SPEED) * self.pending_zoom);
+        camera.pan(self.pending_pan.0, self.pending_pan.1);
+
+        self.pending_orbit = (0.0, 0.0);
+        self.pending_zoom = 1.0;
+        self.pending_pan = (0.0, 0.0);
     }
 }
@@ -0,0 +1,156 @@
+use std::fmt::Write;
+
+use log::warn;
+
+use crate::config::Config;
+use crate::gpu_world::GpuWorld;
+
+/// Device features XPipe makes use of where the adapter has them, their names, and what for
+const OPTIONAL_FEATURES: [(wgpu::Features, &str, &str); 3] = [
+    (wgpu::Features::POLYGON_MODE_LINE, "polygon mode line", "wireframes"),
+    (wgpu::Features::TIMESTAMP_QUERY, "timestamp query", "GPU profiling"),
+    (
+        wgpu::Features::INDIRECT_FIRST_INSTANCE,
+        "indirect first instance",
+        "indirect draws of any instances",
+    ),
+];
+
+/// Limits a device is requested with, the first the adapter can meet, from most to least capable
+const LIMIT_TIERS: [(&str, wgpu::Limits); 3] = [
+    ("default", wgpu::Limits::defaults()),
+    ("downlevel", wgpu::Limits::downlevel_defaults()),
+    ("WebGL2", wgpu::Limits::downlevel_webgl2_defaults()),
+];
+
+/// What an adapter can do, queried before requesting a device from it so that optional rendering
+/// features are only asked for where they are there, and the others degrade gracefully
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    info: wgpu::AdapterInfo,
+    features: wgpu::Features,
+    limits: wgpu::Limits,
+    downlevel: wgpu::DownlevelCapabilities,
+}
+
+impl Capabilities {
+    pub fn of(adapter: &wgpu::Adapter) -> Self {
+        Self {
+            info: adapter.get_info(),
+            features: adapter.features(),
+            limits: adapter.limits(),
+            downlevel: adapter.get_downlevel_capabilities(),
+        }
+    }
+
+    /// The optional features `config` asks for which the adapter has. Timestamp queries cost a little
+    /// every pass, so they are only requested for GPU profiling.
+    pub fn required_features(&self, config: &Config) -> wgpu::Features {
+        let mut features = wgpu::Features::empty();
+        for (feature, _, _) in OPTIONAL_FEATURES {
+            let is_wanted = feature != wgpu::Features::TIMESTAMP_QUERY || config.gpu_profiling;
+            if is_wanted && self.features.contains(feature) {
+                features |= feature;
+            }
+        }
+        if config.gpu_profiling && !features.contains(wgpu::Features::TIMESTAMP_QUERY) {
+            warn!("GPU profiling is unavailable, the adapter doesn't support timestamp queries");
+        }
+        features
+    }
+
+    /// The most capable tier of limits the adapter meets, with textures as large as it can make them so
+    /// that windows on big monitors fit
+    pub fn required_limits(&self) -> wgpu::Limits {
+        let (_, limits) = self.limit_tier();
+        limits.using_resolution(self.limits.clone())
+    }
+
+    fn limit_tier(&self) -> (&'static str, wgpu::Limits) {
+        LIMIT_TIERS
+            .into_iter()
+            .find(|(_, limits)| limits.check_limits(&self.limits))
+            .unwrap_or_else(|| {
+                let (name, limits) = &LIMIT_TIERS[LIMIT_TIERS.len() - 1];
+                (name, limits.clone())
+            })
+    }
+
+    /// A human readable report of the adapter, the optional features and limits XPipe cares about, and
+    /// which of its own features it can run with them, for `--print-caps`
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let info = &self.info;
+        let _ = writeln!(
+            report,
+            "Adapter: {} ({:?}, {:?})",
+            info.name, info.backend, info.device_type
+        );
+        let _ = writeln!(report, "Driver: {} {}", info.driver, info.driver_info);
+
+        let _ = writeln!(report, "\nOptional features:");
+        for (feature, name, purpose) in OPTIONAL_FEATURES {
+            let _ = writeln!(
+                report,
+                "  {:<28} {:<4} for {}",
+                name,
+                yes_no(self.features.contains(feature)),
+                purpose
+            );
+        }
+        let _ = writeln!(report, "\nDownlevel:");
+        for (flag, name) in [
+            (wgpu::DownlevelFlags::COMPUTE_SHADERS, "compute shaders"),
+            (wgpu::DownlevelFlags::INDIRECT_EXECUTION, "indirect execution"),
+            (wgpu::DownlevelFlags::VERTEX_STORAGE, "vertex storage buffers"),
+        ] {
+            let _ = writeln!(report, "  {:<28} {}", name, yes_no(self.downlevel.flags.contains(flag)));
+        }
+        let _ = writeln!(report, "  {:<28} {:?}", "shader model", self.downlevel.shader_model);
+
+        let (tier, _) = self.limit_tier();
+        let limits = &self.limits;
+        let _ = writeln!(report, "\nLimits (meets {} limits):", tier);
+        for (name, value) in [
+            ("max texture size", limits.max_texture_dimension_2d as u64),
+            ("max buffer size", limits.max_buffer_size),
+            (
+                "max storage buffer binding",
+                limits.max_storage_buffer_binding_size as u64,
+            ),
+            (
+                "max storage buffers a stage",
+                limits.max_storage_buffers_per_shader_stage as u64,
+            ),
+            (
+                "max compute invocations",
+                limits.max_compute_invocations_per_workgroup as u64,
+            ),
+        ] {
+            let _ = writeln!(report, "  {:<28} {}", name, value);
+        }
+
+        let _ = writeln!(report, "\nXPipe:");
+        let vertex_storage = self.downlevel.flags.contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+            && limits.max_storage_buffers_per_shader_stage > 0;
+        for (name, is_available) in [
+            ("--gpu-generation", GpuWorld::is_supported_by(&self.downlevel)),
+            (
+                "--gpu-profiling",
+                self.features.contains(wgpu::Features::TIMESTAMP_QUERY),
+            ),
+            ("instance storage", vertex_storage),
+            (
+                "--auto-exposure",
+                self.downlevel.flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS),
+            ),
+        ] {
+            let _ = writeln!(report, "  {:<28} {}", name, yes_no(is_available));
+        }
+        report
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Vector4};
+
+use crate::instance::{self, InstanceRaw};
+use crate::picking::{Ray, SegmentKind, Selection};
+use crate::tubes::Tubes;
+use crate::world::Direction;
+
+// Chunks along each side of the world, each at least MIN_CHUNK_CELLS cells wide. More chunks cull finer,
+// fewer take fewer draw calls.
+const CHUNKS_PER_SIDE: u32 = 8;
+const MIN_CHUNK_CELLS: u32 = 8;
+// share of a block short of a face a ray crosses which still counts as the block before the face
+const FACE_MARGIN: f32 = 0.01;
+
+/// A chunk's layer, 0 for the main world and the others for stacked worlds, see `Chunks::append_to_layer`,
+/// then its place in the grid of chunks, its lowest cell divided by the cells along a chunk's side
+pub type ChunkKey = [i32; 4];
+
+/// Axis aligned box around everything in a chunk
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Bounds {
+    /// Around a whole world `size` blocks wide with blocks `cell_size` apart, up to the faces of its outer
+    /// blocks
+    pub fn of_world(size: u32, cell_size: f32) -> Self {
+        Self::of_extents([size; 3], cell_size)
+    }
+
+    /// Around a world `extents` blocks wide along X, Y and Z, see `Bounds::of_world`
+    pub fn of_extents(extents: [u32; 3], cell_size: f32) -> Self {
+        let [x, y, z] = extents.map(|extent| (extent as f32 - 0.5) * cell_size);
+        let min = -0.5 * cell_size;
+        Self {
+            min: Point3::new(min, min, min),
+            max: Point3::new(x, y, z),
+        }
+    }
+
+    /// Around an instance's mesh, which fills the cube from -0.5 to 0.5 before it's transformed, so up to
+    /// the faces of its cell
+    pub fn of_instance(instance: &InstanceRaw) -> Self {
+        let model = instance.model();
+        let center = instance.position();
+        let half =
+            (model.x.truncate().map(f32::abs) + model.y.truncate().map(f32::abs) + model.z.truncate().map(f32::abs))
+                * 0.5;
+        Self {
+            min: center - half,
+            max: center + half,
+        }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+}
+
+/// A part of the world with its own instances, which are uploaded and culled together
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    pub i_instances: Vec<InstanceRaw>,
+    pub l_instances: Vec<InstanceRaw>,
+    pub bounds: Bounds,
+}
+
+/// A chunk which changed since the last upload
+pub struct ChunkChange<'a> {
+    pub key: ChunkKey,
+    pub chunk: &'a Chunk,
+    /// Instances before these are uploaded already, more were appended after them
+    pub i_from: usize,
+    pub l_from: usize,
+}
+
+/// The world's instances split into cubes of cells, so that the renderer skips chunks out of view and
+/// uploads only what changed since the last upload
+#[derive(Clone, Debug)]
+pub struct Chunks {
+    chunks: BTreeMap<ChunkKey, Chunk>,
+    chunk_cells: f32,
+    // distance between the centers of neighboring cells, see `Config::cell_size`
+    cell_size: f32,
+    // the first straight pipe and elbow of every chunk which changed since the last upload
+    dirty: HashMap<ChunkKey, (usize, usize)>,
+    // chunks which went away since the last upload, without all of them going
+    removed: Vec<ChunkKey>,
+    // all chunks went away, with their GPU buffers
+    is_cleared: bool,
+    // the pipes through the chunks followed as tubes, when drawn as those
+    tubes: Option<Tubes>,
+}
+
+impl Chunks {
+    /// Chunks of a world `world_size` blocks wide, with blocks `cell_size` apart
+    pub fn new(world_size: u32, cell_size: f32) -> Self {
+        Self {
+            chunks: BTreeMap::new(),
+            chunk_cells: world_size.div_ceil(CHUNKS_PER_SIDE).max(MIN_CHUNK_CELLS) as f32,
+            cell_size,
+            dirty: HashMap::new(),
+            removed: vec![],
+            is_cleared: false,
+            tubes: None,
+        }
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    /// Follow the pipes appended from now on as tubes as well, see `Config::pipe_style`
+    pub fn set_tubes(&mut self, has_tubes: bool) {
+        self.tubes = has_tubes.then(Tubes::default);
+    }
+
+    /// The pipes followed as tubes, if they are
+    pub fn tubes_mut(&mut self) -> Option<&mut Tubes> {
+        self.tubes.as_mut()
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.dirty.clear();
+        self.removed.clear();
+        self.is_cleared = true;
+        if let Some(tubes) = &mut self.tubes {
+            tubes.clear();
+        }
+    }
+
+    /// Take every chunk of `layer` away, leaving the other layers as they are
+    pub fn clear_layer(&mut self, layer: i32) {
+        let keys: Vec<_> = self.chunks.keys().filter(|key| key[0] == layer).copied().collect();
+        for key in keys {
+            self.chunks.remove(&key);
+            self.dirty.remove(&key);
+            self.removed.push(key);
+        }
+    }
+
+    /// Add instances of straight pipes and elbows of the main world to the chunks they lie in
+    pub fn append(&mut self, i_instances: Vec<InstanceRaw>, l_instances: Vec<InstanceRaw>) {
+        if let Some(tubes) = &mut self.tubes {
+            tubes.append(&i_instances, &l_instances, self.cell_size);
+        }
+        self.append_to_layer(0, i_instances, l_instances);
+    }
+
+    /// Add instances to the chunks they lie in among the chunks of `layer`, of a stacked world when it's
+    /// above 0. Only the main world's pipes can be picked, erased or followed as tubes, as only its
+    /// instances lie on its blocks.
+    pub fn append_to_layer(&mut self, layer: i32, i_instances: Vec<InstanceRaw>, l_instances: Vec<InstanceRaw>) {
+        for (instance, is_elbow) in i_instances
+            .into_iter()
+            .map(|i| (i, false))
+            .chain(l_instances.into_iter().map(|l| (l, true)))
+        {
+            let position = instance.position();
+            let cell = position.map(|p| (p / (self.chunk_cells * self.cell_size)).floor() as i32);
+            let key = [layer, cell.x, cell.y, cell.z];
+            let bounds = Bounds::of_instance(&instance);
+            let chunk = self.chunks.entry(key).or_insert_with(|| Chunk {
+                i_instances: vec![],
+                l_instances: vec![],
+                bounds,
+            });
+            chunk.bounds = chunk.bounds.union(bounds);
+            self.dirty
+                .entry(key)
+                .or_insert((chunk.i_instances.len(), chunk.l_instances.len()));
+            if is_elbow {
+                chunk.l_instances.push(instance);
+            } else {
+                chunk.i_instances.push(instance);
+            }
+        }
+    }
+
+    /// Take the instances in `blocks` out of the chunks, with the tubes following them
+    pub fn erase(&mut self, blocks: &[(u32, u32, u32)]) {
+        let blocks: HashSet<_> = blocks.iter().map(|&(x, y, z)| (x as i32, y as i32, z as i32)).collect();
+        let cell_size = self.cell_size;
+        let is_kept = |instance: &InstanceRaw| {
+            let block = (instance.position() / cell_size).map(f32::round);
+            !blocks.contains(&(block.x as i32, block.y as i32, block.z as i32))
+        };
+        for (key, chunk) in self.chunks.iter_mut().filter(|(key, _)| key[0] == 0) {
+            let len = chunk.i_instances.len() + chunk.l_instances.len();
+            chunk.i_instances.retain(is_kept);
+            chunk.l_instances.retain(is_kept);
+            if chunk.i_instances.len() + chunk.l_instances.len() < len {
+                // everything after the first erased one moved, so the whole chunk goes up again
+                self.dirty.insert(*key, (0, 0));
+            }
+        }
+        // tubes can't be cut, so they are followed again through what is left
+        if let Some(tubes) = &mut self.tubes {
+            tubes.clear();
+            let (i_instances, l_instances): (Vec<_>, Vec<_>) = self
+                .chunks
+                .iter()
+                .filter(|(key, _)| key[0] == 0)
+                .map(|(_, chunk)| (chunk.i_instances.clone(), chunk.l_instances.clone()))
+                .unzip();
+            tubes.append(&i_instances.concat(), &l_instances.concat(), cell_size);
+        }
+    }
+
+    /// Segments in all chunks
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    pub fn len(&self) -> usize {
+        self.chunks
+            .values()
+            .map(|chunk| chunk.i_instances.len() + chunk.l_instances.len())
+            .sum()
+    }
+
+    /// Every instance, with all chunks marked to upload again
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    pub fn instances_mut(&mut self) -> impl Iterator<Item = &mut InstanceRaw> {
+        self.mark_all_dirty();
+        self.chunks
+            .values_mut()
+            .flat_map(|chunk| chunk.i_instances.iter_mut().chain(&mut chunk.l_instances))
+    }
+
+    /// The segment `ray` hits first, if any
+    pub fn pick(&self, ray: &Ray) -> Option<Selection> {
+        self.nearest(ray)
+            .map(|(_, instance, is_elbow)| Selection::of_instance(instance, is_elbow, self.cell_size))
+    }
+
+    /// The free block right in front of the first segment `ray` hits, or the last block of a world
+    /// `extents` blocks wide along X, Y and Z it passes through if it hits none, to build in. `None` if that
+    /// is out of the world.
+    pub fn free_block(&self, ray: &Ray, extents: [u32; 3]) -> Option<(u32, u32, u32)> {
+        let distance = match self.nearest(ray) {
+            Some((distance, ..)) => distance,
+            None => ray.leave(&Bounds::of_extents(extents, self.cell_size))?,
+        };
+        // a little short of the face the ray crosses there, so in the block before it
+        let point = ray.origin + ray.direction * (distance - FACE_MARGIN * self.cell_size);
+        let block = (point.to_vec() / self.cell_size).map(f32::round);
+        let is_inside = (0..3).all(|axis| (0.0..extents[axis] as f32).contains(&block[axis]));
+        is_inside.then_some((block.x as u32, block.y as u32, block.z as u32))
+    }
+
+    // how far along `ray` it hits the segment it hits first, which one and whether it's an elbow
+    fn nearest(&self, ray: &Ray) -> Option<(f32, &InstanceRaw, bool)> {
+        let mut nearest: Option<(f32, &InstanceRaw, bool)> = None;
+        for chunk in self.main_chunks() {
+            // nothing in a chunk is nearer than the chunk itself
+            match ray.hit(&chunk.bounds) {
+                Some(distance) if nearest.is_none_or(|(nearest, ..)| distance < nearest) => {}
+                _ => continue,
+            }
+            let instances = chunk.i_instances.iter().map(|i| (i, false));
+            for (instance, is_elbow) in instances.chain(chunk.l_instances.iter().map(|l| (l, true))) {
+                if let Some(distance) = ray.hit(&Bounds::of_instance(instance))
+                    && nearest.is_none_or(|(nearest, ..)| distance < nearest)
+                {
+                    nearest = Some((distance, instance, is_elbow));
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Instances of the whole pipe `selection` is a segment of, its straight pipes and caps and its elbows,
+    /// found by following it from block to block both ways. Each segment continues the one numbered
+    /// before it through one of its ends, so that other pipes which happen to touch it are left out.
+    pub fn pipe_instances(&self, selection: &Selection) -> (Vec<InstanceRaw>, Vec<InstanceRaw>) {
+        let mut segments = HashMap::<_, Vec<_>>::new();
+        for chunk in self.main_chunks() {
+            let instances = chunk.i_instances.iter().map(|i| (i, false));
+            for (instance, is_elbow) in instances.chain(chunk.l_instances.iter().map(|l| (l, true))) {
+                let segment = Selection::of_instance(instance, is_elbow, self.cell_size);
+                segments.entry(segment.block).or_default().push((segment, instance));
+            }
+        }
+        // Whether `segment` has an end towards `direction`, either way along it as that is all two
+        // segments need to line up
+        let has_end_towards = |segment: &Selection, direction: Direction| match segment.kind {
+            SegmentKind::Elbow => {
+                direction.is_parallel_to(segment.direction) || direction.is_parallel_to(segment.entry)
+            }
+            _ => direction.is_parallel_to(segment.direction),
+        };
+
+        let mut pipe = HashSet::from([(selection.block, selection.segment)]);
+        let mut unvisited = vec![*selection];
+        while let Some(segment) = unvisited.pop() {
+            let (x, y, z) = segment.block;
+            for direction in Direction::ALL {
+                if segment.kind == SegmentKind::Cap || !has_end_towards(&segment, direction) {
+                    continue;
+                }
+                let v = direction.vector();
+                let block = (x + v.x as i32, y + v.y as i32, z + v.z as i32);
+                // caps only close off the segment in their block
+                let next = segments.get(&block).into_iter().flatten().find(|(next, _)| {
+                    next.kind != SegmentKind::Cap
+                        && next.segment.abs_diff(segment.segment) == 1
+                        && has_end_towards(next, direction)
+                });
+                if let Some((next, _)) = next
+                    && pipe.insert((next.block, next.segment))
+                {
+                    unvisited.push(*next);
+                }
+            }
+        }
+
+        let (mut i_instances, mut l_instances) = (vec![], vec![]);
+        for (block, segment) in pipe {
+            for (selection, instance) in segments.get(&block).into_iter().flatten() {
+                match selection.kind {
+                    _ if selection.segment != segment => {}
+                    SegmentKind::Elbow => l_instances.push(**instance),
+                    SegmentKind::Straight | SegmentKind::Cap => i_instances.push(**instance),
+                }
+            }
+        }
+        (i_instances, l_instances)
+    }
+
+    // the chunks of the main world, of layer 0
+    fn main_chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks
+            .iter()
+            .filter(|(key, _)| key[0] == 0)
+            .map(|(_, chunk)| chunk)
+    }
+
+    /// Order each chunk's instances from the farthest to the nearest to `eye`, see
+    /// `instance::sort_back_to_front`. The renderer orders the chunks themselves.
+    pub fn sort_back_to_front(&mut self, eye: Point3<f32>) {
+        self.sort(|instances| instance::sort_back_to_front(instances, eye));
+    }
+
+    /// Order each chunk's instances from the nearest to the farthest from `eye`, see
+    /// `instance::sort_front_to_back`
+    pub fn sort_front_to_back(&mut self, eye: Point3<f32>) {
+        self.sort(|instances| instance::sort_front_to_back(instances, eye));
+    }
+
+    fn sort(&mut self, sort: impl Fn(&mut [InstanceRaw])) {
+        for chunk in self.chunks.values_mut() {
+            sort(&mut chunk.i_instances);
+            sort(&mut chunk.l_instances);
+        }
+        self.mark_all_dirty();
+    }
+
+    fn mark_all_dirty(&mut self) {
+        self.dirty = self.chunks.keys().map(|key| (*key, (0, 0))).collect();
+    }
+
+    /// Whether all chunks went away since the last call, the chunks which went away on their own since then,
+    /// and the chunks which changed
+    pub fn take_changes(&mut self) -> (bool, Vec<ChunkKey>, Vec<ChunkChange<'_>>) {
+        let is_cleared = std::mem::take(&mut self.is_cleared);
+        let removed = std::mem::take(&mut self.removed);
+        let changes = self
+            .dirty
+            .drain()
+            .map(|(key, (i_from, l_from))| ChunkChange {
+                key,
+                chunk: &self.chunks[&key],
+                i_from,
+                l_from,
+            })
+            .collect();
+        (is_cleared, removed, changes)
+    }
+}
+
+/// The space a camera sees, as the planes around it with their normals pointing inwards
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// The frustum of a view projection matrix, clipping depth to 0..1 like wgpu
+    pub fn from_view_projection(matrix: Matrix4<f32>) -> Self {
+        let row = |i: usize| Vector4::new(matrix.x[i], matrix.y[i], matrix.z[i], matrix.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| plane / plane.truncate().magnitude());
+        Self { planes }
+    }
+
+    /// Whether any of `bounds` may be in view, false only if it's wholly outside one plane
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner farthest along the plane's normal
+            let corner = Vector4::new(
+                if plane.x >= 0.0 { bounds.max.x } else { bounds.min.x },
+                if plane.y >= 0.0 { bounds.max.y } else { bounds.min.y },
+                if plane.z >= 0.0 { bounds.max.z } else { bounds.min.z },
+                1.0,
+            );
+            plane.dot(corner) >= 0.0
+        })
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::BTreeSet;
+
+use cgmath::{Point3, Vector3};
+
+use crate::camera::Camera;
+
+// 5x7 digits, one string per row from the top, `#` for a block pipes may grow in
+const DIGITS: [[&str; 7]; 10] = [
+    ["#####", "#...#", "#...#", "#...#", "#...#", "#...#", "#####"],
+    ["..#..", ".##..", "..#..", "..#..", "..#..", "..#..", ".###."],
+    ["#####", "....#", "....#", "#####", "#....", "#....", "#####"],
+    ["#####", "....#", "....#", ".####", "....#", "....#", "#####"],
+    ["#...#", "#...#", "#...#", "#####", "....#", "....#", "....#"],
+    ["#####", "#....", "#....", "#####", "....#", "....#", "#####"],
+    ["#####", "#....", "#....", "#####", "#...#", "#...#", "#####"],
+    ["#####", "....#", "...#.", "..#..", "..#..", "..#..", "..#.."],
+    ["#####", "#...#", "#...#", "#####", "#...#", "#...#", "#####"],
+    ["#####", "#...#", "#...#", "#####", "....#", "....#", "#####"],
+];
+const COLON: [&str; 7] = [".", ".", "#", ".", "#", ".", "."];
+const GLYPH_HEIGHT: u32 = 7;
+// blocks between glyphs
+const SPACING: u32 = 1;
+// where the clock sits in the world, centered in the 30 blocks wide and high one
+const LEFT: u32 = 2;
+const BOTTOM: u32 = 11;
+// the clock is this many blocks deep, facing +Z, so pipes have room to wind through the strokes
+const FRONT: u32 = 14;
+const DEPTH: u32 = 3;
+const WIDTH: u32 = 4 * 5 + 1 + 4 * SPACING;
+// far enough for the whole clock to fit a landscape view
+const VIEW_DISTANCE: f32 = 30.0;
+
+/// The current local hour and minute
+pub fn now() -> (u8, u8) {
+    let time = jiff::Zoned::now();
+    (time.hour() as u8, time.minute() as u8)
+}
+
+/// Point `camera` straight at the face of the clock
+pub fn frame(camera: &mut Camera) {
+    let center = Point3::new(
+        LEFT as f32 + (WIDTH - 1) as f32 / 2.0,
+        BOTTOM as f32 + (GLYPH_HEIGHT - 1) as f32 / 2.0,
+        FRONT as f32 + (DEPTH - 1) as f32 / 2.0,
+    );
+    camera.look_at(center + Vector3::new(0.0, 0.0, VIEW_DISTANCE), center);
+}
+
+/// Blocks which spell `hour:minute` in 24 hour time, for pipes to fill
+pub fn mask(hour: u8, minute: u8) -> BTreeSet<(u32, u32, u32)> {
+    let glyphs: [&[&str; 7]; 5] = [
+        &DIGITS[hour as usize / 10 % 10],
+        &DIGITS[hour as usize % 10],
+        &COLON,
+        &DIGITS[minute as usize / 10 % 10],
+        &DIGITS[minute as usize % 10],
+    ];
+
+    let mut blocks = BTreeSet::new();
+    let mut left = LEFT;
+    for glyph in glyphs {
+        for (row, line) in glyph.iter().enumerate() {
+            let y = BOTTOM + GLYPH_HEIGHT - 1 - row as u32;
+            for (column, _) in line.chars().enumerate().filter(|(_, c)| *c == '#') {
+                for z in FRONT..FRONT + DEPTH {
+                    blocks.insert((left + column as u32, y, z));
+                }
+            }
+        }
+        left += glyph[0].len() as u32 + SPACING;
+    }
+    blocks
+}
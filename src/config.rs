@@ -0,0 +1,1720 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{Context, bail};
+use clap::{Parser, ValueEnum};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::generator::{Constraints, DirectionWeights};
+use crate::graph::GraphFormat;
+use crate::i18n::Language;
+use crate::keybindings::KeyBindings;
+use crate::light::{self, LightOrbit, OrbitPath};
+use crate::material::{Material, Materials};
+use crate::palette::{self, ColorVision, Palette};
+use crate::recipe::Recipe;
+use crate::region::Region;
+use crate::rhythm::Rhythm;
+use crate::split_screen::MAX_VIEWS;
+use crate::tubes::{self, Tessellation};
+use crate::world::{self, TURN_PROBABILITY};
+use crate::{blit, exposure, generator, gpu_world, models, plugin, texture};
+
+const IDLE_FPS: f32 = 5.0;
+const GLASS_OPACITY: f32 = 0.35;
+const MIN_RENDER_SCALE: f32 = 0.25;
+const MAX_RENDER_SCALE: f32 = 4.0;
+const MAX_PHOTO_SCALE: u32 = 4;
+const MIN_TURNTABLE_WIDTH: u32 = 16;
+const MAX_TURNTABLE_WIDTH: u32 = 2048;
+const IPD: f32 = 0.2;
+// what a scene's `post_effect` is to switch the effect off
+const NO_POST_EFFECT: &str = "none";
+const DAY_START: TimeOfDay = TimeOfDay(7 * 60);
+const NIGHT_START: TimeOfDay = TimeOfDay(19 * 60);
+const NIGHT_LIGHT: f32 = 0.4;
+const TITLE: &str = "{title} - {fps} fps, {segments} segments, seed {seed}";
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about = "Revive WindowXP's pipe screensaver")]
+pub struct Cli {
+    /// TOML file to read settings from, command line flags take precedence
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Number of windows to open, each with its own independent world
+    #[arg(long, default_value_t = 1)]
+    pub windows: u32,
+
+    /// Open a borderless fullscreen window on every monitor, for screensaver use
+    #[arg(long, value_enum)]
+    pub monitors: Option<MonitorMode>,
+
+    /// Run as an animated desktop background, behind the desktop icons
+    #[arg(long)]
+    pub wallpaper: bool,
+
+    /// Grow pipes over the desktop in a transparent, click-through, always-on-top window
+    #[arg(long, conflicts_with = "wallpaper")]
+    pub overlay: bool,
+
+    /// Maximum frames per second, unlimited when not set
+    #[arg(long)]
+    pub fps_cap: Option<f32>,
+
+    /// Frames per second while the window is unfocused or hidden, 0 keeps the normal frame rate
+    #[arg(long)]
+    pub idle_fps: Option<f32>,
+
+    /// Stop growing pipes while the window is minimized or covered, and grow on once it shows again
+    #[arg(long)]
+    pub pause_when_hidden: bool,
+
+    /// Log how long each render pass takes on the GPU, if the adapter supports timestamp queries
+    #[arg(long)]
+    pub gpu_profiling: bool,
+
+    /// What the depth buffer stores depth as, depth32float by default
+    #[arg(long, value_enum)]
+    pub depth_format: Option<DepthFormat>,
+
+    /// Store depth from 1 near to 0 far, which is more precise far away with a float depth format
+    #[arg(long)]
+    pub reversed_z: bool,
+
+    /// Background color as #rrggbb, or #rrggbbaa for a see-through background
+    #[arg(long)]
+    pub background: Option<Rgba>,
+
+    /// How the window is composited with what is behind it, picked from what the platform supports by
+    /// default
+    #[arg(long, value_enum)]
+    pub alpha_mode: Option<AlphaMode>,
+
+    /// Colors to paint pipes with: classic or neon
+    #[arg(long)]
+    pub palette: Option<String>,
+
+    /// Render see-through glass pipes
+    #[arg(long)]
+    pub glass: bool,
+
+    /// How pipes are lit
+    #[arg(long, value_enum)]
+    pub shading: Option<Shading>,
+
+    /// What pipes are built from, segments by default
+    #[arg(long, value_enum)]
+    pub pipe_style: Option<PipeStyle>,
+
+    /// Sides of tubes, 16 by default
+    #[arg(long)]
+    pub tube_sides: Option<u32>,
+
+    /// Pieces the center line of tubes is cut into for every unit of its length, 8 by default
+    #[arg(long)]
+    pub tube_segments_per_unit: Option<u32>,
+
+    /// What pipes are made of
+    #[arg(long, value_enum)]
+    pub material: Option<Material>,
+
+    /// Resolution to render at relative to the window's, e.g. 0.5 for slow machines or 2 to supersample
+    #[arg(long)]
+    pub render_scale: Option<f32>,
+
+    /// Lower the render scale while frames come slower than this many a second, see the config's
+    /// `target_fps`
+    #[arg(long)]
+    pub target_fps: Option<f32>,
+
+    /// How edges are smoothed, see the config's `aa`
+    #[arg(long, value_enum)]
+    pub aa: Option<Antialiasing>,
+
+    /// Resolution of screenshots taken in photo mode relative to the window's, from 1 to 4
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=MAX_PHOTO_SCALE as i64))]
+    pub photo_scale: Option<u32>,
+
+    /// Width of turntable GIFs in pixels, their height follows the window's aspect ratio
+    #[arg(long)]
+    pub turntable_width: Option<u32>,
+
+    /// Split the window into this many views: the free camera, a chase camera, a top and a side view
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=MAX_VIEWS as i64))]
+    pub views: Option<u8>,
+
+    /// Render a side-by-side stereo pair, for phone VR viewers and 3D wallpapers
+    #[arg(long)]
+    pub stereo: bool,
+
+    /// Distance between the eyes in stereo, in pipe segments
+    #[arg(long)]
+    pub ipd: Option<f32>,
+
+    /// Put a glossy floor under the world, with a grid, reflections and shadows of the pipes
+    #[arg(long)]
+    pub floor: bool,
+
+    /// Throw sparks off the heads of growing pipes
+    #[arg(long)]
+    pub sparks: bool,
+
+    /// Draw the depth of solid pipes before shading them, see the config's `depth_prepass`
+    #[arg(long)]
+    pub depth_prepass: bool,
+
+    /// Read pipe instances from storage buffers, see the config's `instance_storage`
+    #[arg(long)]
+    pub instance_storage: bool,
+
+    /// Encode the scene on a worker thread, see the config's `parallel_encoding`
+    #[arg(long)]
+    pub parallel_encoding: bool,
+
+    /// Fade pipes to gray over this many seconds, so the growing ones stand out
+    #[arg(long, value_name = "SECONDS")]
+    pub aging: Option<f32>,
+
+    /// Shrink pipes away over this many seconds when the world starts over, 0 clears them at once
+    #[arg(long, value_name = "SECONDS")]
+    pub drain: Option<f32>,
+
+    /// How the old pipes go while the new world starts growing
+    #[arg(long, value_enum)]
+    pub reset_transition: Option<ResetTransition>,
+
+    /// Distance of the light from the center of its orbit, see `light_orbit` in the config
+    #[arg(long, value_name = "DISTANCE")]
+    pub light_radius: Option<f32>,
+
+    /// Degrees the light travels along its orbit a second, 3 by default
+    #[arg(long, value_name = "DEGREES")]
+    pub light_speed: Option<f32>,
+
+    /// Path the light travels, a circle by default
+    #[arg(long, value_enum)]
+    pub light_path: Option<OrbitPath>,
+
+    /// Ambient light as a multiple of each shading's own, 1 by default, see `ambient` in the config
+    #[arg(long, value_name = "SCALE")]
+    pub ambient: Option<f32>,
+
+    /// Strength of the light along the pipes' silhouettes, 0 (none) by default
+    #[arg(long, value_name = "STRENGTH")]
+    pub rim_light: Option<f32>,
+
+    /// Strength of the light from the sky and ground around the world, 0 (none) by default
+    #[arg(long, value_name = "STRENGTH")]
+    pub sky_light: Option<f32>,
+
+    /// Color of the sky overhead as #rrggbb, which tints pipes from above and metals mirror
+    #[arg(long)]
+    pub sky_color: Option<Rgba>,
+
+    /// Color of the ground below as #rrggbb, which tints pipes from below and metals mirror
+    #[arg(long)]
+    pub ground_color: Option<Rgba>,
+
+    /// Stops to brighten frames by, or darken them by for negative ones, 0 by default
+    #[arg(long, value_name = "STOPS", allow_negative_numbers = true)]
+    pub exposure: Option<f32>,
+
+    /// Adapt the exposure to how bright the frames are, see the config's `auto_exposure`
+    #[arg(long)]
+    pub auto_exposure: bool,
+
+    /// Keep the light and cameras still, grow slowly and skip flashes, for people sensitive to motion
+    #[arg(long)]
+    pub reduced_motion: bool,
+
+    /// Grow, pulse the light and change palettes to the beat of this audio (needs the audio feature)
+    #[arg(long, value_enum)]
+    pub audio: Option<AudioSource>,
+
+    /// Show an icon in the system tray to pause, start over or quit with, e.g. for wallpapers and
+    /// overlays (needs the tray feature)
+    #[arg(long)]
+    pub tray: bool,
+
+    /// Grow pipes that spell the current time, starting over every minute
+    #[arg(long)]
+    pub clock: bool,
+
+    /// Switch between a bright day and a dim night look by the local time, see `day_night` in the config
+    #[arg(long)]
+    pub day_night: bool,
+
+    /// Accept OSC commands over UDP on this address, e.g. 127.0.0.1:9000, or 0.0.0.0:9000 for the network
+    #[arg(long, value_name = "ADDRESS")]
+    pub remote: Option<SocketAddr>,
+
+    /// Serve an HTTP API for stats, resets, settings and snapshots on this address, e.g. 0.0.0.0:8080
+    #[arg(long, value_name = "ADDRESS")]
+    pub http: Option<SocketAddr>,
+
+    /// Rhai script to drive the world, camera and light with (needs the scripting feature)
+    #[arg(long)]
+    pub script: Option<PathBuf>,
+
+    /// Grow the pipes laid out in this file instead of random ones, see `layout::Layout` for the format
+    #[arg(long, value_name = "FILE")]
+    pub layout: Option<PathBuf>,
+
+    /// How pipes grow: classic or zigzag, or one registered by a plugin
+    #[arg(long)]
+    pub generator: Option<String>,
+
+    /// Blocks along each side of the world, 30 by default
+    #[arg(long)]
+    pub world_size: Option<u32>,
+
+    /// Stretch the world to the window's shape, wider for wide windows and taller for tall ones
+    #[arg(long)]
+    pub fit_aspect: bool,
+
+    /// Distance between the centers of neighboring blocks, 1 by default
+    #[arg(long)]
+    pub cell_size: Option<f32>,
+
+    /// Radius of the pipes, 0.5 by default, up to half the cell size
+    #[arg(long)]
+    pub pipe_radius: Option<f32>,
+
+    /// How far segments stray from the grid for a hand-made look, from 0 by default to 1
+    #[arg(long)]
+    pub jitter: Option<f32>,
+
+    /// How far segments' saturation and brightness stray from their pipe's color, from 0 by default to 1
+    #[arg(long)]
+    pub color_jitter: Option<f32>,
+
+    /// Cap pipes after this many segments and start a new one elsewhere
+    #[arg(long, value_name = "SEGMENTS")]
+    pub max_pipe_length: Option<u32>,
+
+    /// Blocks pipes look ahead to steer clear of dead ends, 0 to go wherever the generator says
+    #[arg(long, value_name = "BLOCKS")]
+    pub look_ahead: Option<u32>,
+
+    /// Have this many pipe networks race to fill the world, each in a color of its own
+    #[arg(long, value_name = "COUNT")]
+    pub teams: Option<u32>,
+
+    /// What the graph of the pipes is exported as
+    #[arg(long, value_enum)]
+    pub graph_format: Option<GraphFormat>,
+
+    /// Draw an arrow where each growing pipe heads along with the debug gizmos
+    #[arg(long)]
+    pub gizmo_headings: bool,
+
+    /// Have pipes prefer some directions, like `y=4` to climb or `neg_y=0` to never turn down
+    #[arg(long, value_name = "WEIGHTS")]
+    pub direction_weights: Option<DirectionWeights>,
+
+    /// Grow pipes in a compute shader, thousands of segments a second, see the config's `gpu_generation`
+    #[arg(long)]
+    pub gpu_generation: bool,
+
+    /// Effect over the finished frame: vignette or scanlines, or one registered by a plugin
+    #[arg(long)]
+    pub post_effect: Option<String>,
+
+    /// Scene to start with: classic, neon-night, chrome-showroom or one of the config's `scenes`
+    #[arg(long)]
+    pub scene: Option<String>,
+
+    /// Switch to a random scene, palette and seed every this many minutes, see the config's
+    /// `shuffle_minutes`
+    #[arg(long, value_name = "MINUTES")]
+    pub shuffle: Option<f32>,
+
+    /// Window title, updated every second with {fps}, {pipes}, {segments} and {seed} filled in, as well as
+    /// {title} for the window's own one. Empty keeps the window's own title.
+    #[arg(long, value_name = "FORMAT")]
+    pub title: Option<String>,
+
+    /// Grow the world of a share string copied with the C key, other flags take precedence
+    #[arg(long, value_name = "SHARE_STRING")]
+    pub from_share: Option<Recipe>,
+
+    /// Pick up the world, camera and settings where the last run left off, other flags take precedence
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Print what the GPU can do of what XPipe makes use of, and exit
+    #[arg(long)]
+    pub print_caps: bool,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MonitorMode {
+    /// Every monitor grows its own world
+    Independent,
+    /// All monitors look at one world, with cameras offset to match the monitor layout
+    Shared,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Shading {
+    /// Smooth lighting with soft highlights, Blinn-Phong
+    Phong,
+    /// Matte lighting without highlights
+    Lambert,
+    /// Physically based lighting, like glossy plastic
+    Pbr,
+    /// Flat bands of light with dark outlines, like a cartoon
+    Toon,
+    /// Smooth lighting with glowing bands flowing through the pipes
+    Flow,
+    /// No lighting at all, every pipe in its plain color
+    Unlit,
+}
+
+/// How the edges of pipes are smoothed
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Antialiasing {
+    /// Jagged edges, the fastest
+    #[default]
+    None,
+    /// Temporal anti-aliasing, blending frames drawn a fraction of a pixel apart
+    Taa,
+}
+
+/// What pipes are built from
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PipeStyle {
+    /// A straight pipe or an elbow in every block, like classic plumbing
+    #[default]
+    Segments,
+    /// One smooth tube a pipe, swept along a spline through the centers of its blocks
+    Tubes,
+}
+
+/// How the pipes of a world go when it starts over, over `Config::drain_seconds`. Either way they are drawn
+/// alongside the new world until they are gone.
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetTransition {
+    /// Segments shrink away one after another, the newest first, as if the world grew backwards
+    #[default]
+    Drain,
+    /// Every pipe fades out at once, in a fine dither pattern so that solid pipes need no blending
+    Crossfade,
+}
+
+/// How the compositor blends a window's surface over what is behind it, see
+/// `wgpu::CompositeAlphaMode`
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlphaMode {
+    /// Opaque for solid backgrounds, premultiplied or else postmultiplied for see-through ones, whichever
+    /// the surface supports
+    #[default]
+    Auto,
+    /// Ignore alpha, nothing shows through
+    Opaque,
+    /// Colors are already scaled by their alpha
+    Premultiplied,
+    /// Colors are scaled by their alpha by the compositor
+    Postmultiplied,
+    /// Leave it to the platform, e.g. to what the window was created with
+    Inherit,
+}
+
+/// What the depth buffer stores depth as
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DepthFormat {
+    /// 32 bit float, the most precise, especially with reversed depth
+    #[default]
+    #[value(name = "depth32float")]
+    Depth32Float,
+    /// At least 24 bits, float or not as the GPU likes
+    #[value(name = "depth24plus")]
+    Depth24Plus,
+    /// 16 bit fixed point, the smallest but prone to flickering far away
+    #[value(name = "depth16unorm")]
+    Depth16Unorm,
+}
+
+impl DepthFormat {
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            DepthFormat::Depth32Float => wgpu::TextureFormat::Depth32Float,
+            DepthFormat::Depth24Plus => wgpu::TextureFormat::Depth24Plus,
+            DepthFormat::Depth16Unorm => wgpu::TextureFormat::Depth16Unorm,
+        }
+    }
+}
+
+/// Where audio-reactive mode listens
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioSource {
+    /// The default input device
+    Microphone,
+    /// Whatever the default output device plays, where the platform supports capturing it
+    Loopback,
+}
+
+impl Shading {
+    /// Every shading, in the order they are cycled through
+    pub const ALL: [Shading; 6] = [
+        Shading::Phong,
+        Shading::Lambert,
+        Shading::Pbr,
+        Shading::Toon,
+        Shading::Flow,
+        Shading::Unlit,
+    ];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|shading| *shading == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Themes for the day and the night, and when each of them starts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DayNight {
+    pub day_start: TimeOfDay,
+    pub night_start: TimeOfDay,
+    pub day: Theme,
+    pub night: Theme,
+}
+
+impl Default for DayNight {
+    fn default() -> Self {
+        Self {
+            day_start: DAY_START,
+            night_start: NIGHT_START,
+            day: Theme::default(),
+            night: Theme {
+                palette: palette::NEON.name.to_string(),
+                background: Some(Rgba([0, 0, 0, u8::MAX])),
+                light: NIGHT_LIGHT,
+            },
+        }
+    }
+}
+
+impl DayNight {
+    /// The theme showing at `time`, the night one when it starts before the day one ends
+    pub fn theme_at(&self, time: TimeOfDay) -> &Theme {
+        let is_day = if self.day_start <= self.night_start {
+            self.day_start <= time && time < self.night_start
+        } else {
+            // the day wraps around midnight
+            time >= self.day_start || time < self.night_start
+        };
+        if is_day { &self.day } else { &self.night }
+    }
+}
+
+/// How the scene looks at one time of day
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    /// Name of the palette new pipes are painted with
+    pub palette: String,
+    /// Background color, the configured one when `None`. Transparent windows keep theirs.
+    pub background: Option<Rgba>,
+    /// Brightness of the light, 1 is plain white
+    pub light: f32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            palette: palette::CLASSIC.name.to_string(),
+            background: None,
+            light: 1.0,
+        }
+    }
+}
+
+impl Theme {
+    pub fn palette(&self) -> &'static Palette {
+        palette::find(&self.palette).unwrap_or(&palette::CLASSIC)
+    }
+}
+
+/// A named look bundling settings of the world, its colors and materials, the views and the lighting and
+/// post-processing, switched to all at once, see `Action::NextScene`. Settings a scene leaves out stay as
+/// they are.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    pub name: String,
+    pub turn_probability: Option<f32>,
+    /// Name of the palette new pipes are painted with
+    pub palette: Option<String>,
+    /// Background color. Transparent windows keep theirs.
+    pub background: Option<Rgba>,
+    pub material: Option<Material>,
+    pub accent_material: Option<Material>,
+    pub accent_chance: Option<f32>,
+    /// Split-screen views, from 1 to 4
+    pub views: Option<u8>,
+    pub shading: Option<Shading>,
+    pub exposure: Option<f32>,
+    pub ambient: Option<f32>,
+    pub rim_light: Option<f32>,
+    pub floor: Option<bool>,
+    pub sparks: Option<bool>,
+    /// Name of the effect over the finished frame, `none` for no effect
+    pub post_effect: Option<String>,
+}
+
+impl Scene {
+    /// The scenes there always are, the first one looking like the defaults do
+    fn built_in() -> Vec<Scene> {
+        vec![
+            Scene {
+                name: "classic".to_string(),
+                turn_probability: Some(TURN_PROBABILITY),
+                palette: Some(palette::CLASSIC.name.to_string()),
+                background: Some(Rgba([25, 25, 25, u8::MAX])),
+                material: Some(Material::Plastic),
+                accent_material: Some(Material::Plastic),
+                accent_chance: Some(0.0),
+                views: Some(1),
+                shading: Some(Shading::Phong),
+                exposure: Some(0.0),
+                ambient: Some(light::DEFAULT_AMBIENT),
+                rim_light: Some(0.0),
+                floor: Some(false),
+                sparks: Some(false),
+                post_effect: Some(NO_POST_EFFECT.to_string()),
+            },
+            Scene {
+                name: "neon-night".to_string(),
+                turn_probability: Some(TURN_PROBABILITY),
+                palette: Some(palette::NEON.name.to_string()),
+                background: Some(Rgba([0, 0, 0, u8::MAX])),
+                material: Some(Material::Lamp),
+                accent_material: Some(Material::Chrome),
+                accent_chance: Some(0.2),
+                views: Some(1),
+                shading: Some(Shading::Flow),
+                exposure: Some(0.5),
+                ambient: Some(light::DEFAULT_AMBIENT * 0.25),
+                rim_light: Some(0.5),
+                floor: Some(true),
+                sparks: Some(true),
+                post_effect: Some("vignette".to_string()),
+            },
+            Scene {
+                name: "chrome-showroom".to_string(),
+                // long straight runs show off the reflections
+                turn_probability: Some(TURN_PROBABILITY * 0.5),
+                palette: Some(palette::CLASSIC.name.to_string()),
+                background: Some(Rgba([200, 202, 208, u8::MAX])),
+                material: Some(Material::Chrome),
+                accent_material: Some(Material::Lamp),
+                accent_chance: Some(0.05),
+                views: Some(1),
+                shading: Some(Shading::Pbr),
+                exposure: Some(0.0),
+                ambient: Some(light::DEFAULT_AMBIENT * 1.5),
+                rim_light: Some(0.25),
+                floor: Some(true),
+                sparks: Some(false),
+                post_effect: Some(NO_POST_EFFECT.to_string()),
+            },
+        ]
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        if let Some(palette) = &self.palette {
+            check_palette(palette)?;
+        }
+        for (what, chance) in [
+            ("turn probability", self.turn_probability),
+            ("accent chance", self.accent_chance),
+        ] {
+            if let Some(chance) = chance.filter(|chance| !(0.0..=1.0).contains(chance)) {
+                bail!(
+                    "Scene {:?}: {} must be between 0 and 1, got {}",
+                    self.name,
+                    what,
+                    chance
+                );
+            }
+        }
+        if let Some(views) = self.views.filter(|views| !(1..=MAX_VIEWS as u8).contains(views)) {
+            bail!(
+                "Scene {:?}: views must be between 1 and {}, got {}",
+                self.name,
+                MAX_VIEWS,
+                views
+            );
+        }
+        if let Some(exposure) = self.exposure.filter(|exposure| exposure.abs() > exposure::MAX_STOPS) {
+            bail!(
+                "Scene {:?}: exposure must be within {} stops either way, got {}",
+                self.name,
+                exposure::MAX_STOPS,
+                exposure
+            );
+        }
+        for (what, light) in [("ambient light", self.ambient), ("rim light", self.rim_light)] {
+            if let Some(light) = light.filter(|light| *light < 0.0) {
+                bail!("Scene {:?}: {} must not be negative, got {}", self.name, what, light);
+            }
+        }
+        if let Some(effect) = self
+            .post_effect
+            .as_ref()
+            .filter(|name| name.as_str() != NO_POST_EFFECT && blit::find_post_effect(name).is_none())
+        {
+            bail!(
+                "Scene {:?}: unknown post effect {:?}, available: {}, {}",
+                self.name,
+                effect,
+                NO_POST_EFFECT,
+                blit::post_effect_names().join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    pub fn palette(&self) -> Option<&'static Palette> {
+        palette::find(self.palette.as_deref()?)
+    }
+
+    /// The effect over the finished frame, `Some(None)` for switching it off
+    pub fn post_effect(&self) -> Option<Option<plugin::PostEffect>> {
+        let name = self.post_effect.as_deref()?;
+        Some(blit::find_post_effect(name))
+    }
+}
+
+/// Another world growing along with the main one on a thread of its own, drawn scaled and moved into place
+/// over it, e.g. a small dense cube of pipes inside the main world. Settings it leaves out are the main
+/// world's, but it grows a random world of its own rather than a layout, a clock or teams.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StackedWorld {
+    /// Blocks along each side, see `Config::world_size`
+    pub world_size: u32,
+    pub seed: Option<u64>,
+    pub turn_probability: Option<f32>,
+    /// Name of the palette its pipes are painted with
+    pub palette: Option<String>,
+    pub region: Option<Region>,
+    /// Block of the main world its center goes at, which may lie between blocks, the main world's center
+    /// when `None`
+    pub center: Option<[f32; 3]>,
+    /// Size of its blocks next to the main world's, e.g. 0.5 for pipes half as thick and twice as dense
+    pub scale: f32,
+}
+
+impl Default for StackedWorld {
+    fn default() -> Self {
+        Self {
+            world_size: world::DEFAULT_SIZE,
+            seed: None,
+            turn_probability: None,
+            palette: None,
+            region: None,
+            center: None,
+            scale: 1.0,
+        }
+    }
+}
+
+impl StackedWorld {
+    fn check(&self) -> anyhow::Result<()> {
+        if !(2..=world::MAX_SIZE).contains(&self.world_size) {
+            bail!(
+                "Stacked world size must be between 2 and {}, got {}",
+                world::MAX_SIZE,
+                self.world_size
+            );
+        }
+        if let Some(probability) = self.turn_probability.filter(|p| !(0.0..=1.0).contains(p)) {
+            bail!(
+                "Stacked world turn probability must be between 0 and 1, got {}",
+                probability
+            );
+        }
+        if let Some(palette) = &self.palette {
+            check_palette(palette)?;
+        }
+        if let Some(region) = &self.region {
+            region.check()?;
+        }
+        if self.scale <= 0.0 {
+            bail!("Stacked world scale must be positive, got {}", self.scale);
+        }
+        Ok(())
+    }
+
+    /// The settings of the main world `main` with this world's in their place
+    pub fn config(&self, main: &Config) -> Config {
+        let mut config = main.clone();
+        config.world_size = self.world_size;
+        config.seed = self.seed;
+        config.turn_probability = self.turn_probability.unwrap_or(main.turn_probability);
+        if let Some(palette) = &self.palette {
+            config.palette = palette.clone();
+        }
+        config.region = self.region;
+        config.layout = None;
+        config.clock = false;
+        config.teams = 0;
+        config.stacked_worlds = vec![];
+        config
+    }
+}
+
+/// A time of day in minutes since midnight, written as `HH:MM`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay(pub u16);
+
+impl TimeOfDay {
+    pub fn new(hour: u8, minute: u8) -> Self {
+        Self(hour as u16 * 60 + minute as u16)
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parsed = s
+            .split_once(':')
+            .and_then(|(hour, minute)| Some((hour.parse::<u8>().ok()?, minute.parse::<u8>().ok()?)));
+        match parsed {
+            Some((hour, minute)) if hour < 24 && minute < 60 => Ok(Self::new(hour, minute)),
+            _ => bail!("Expected a time of day like 07:30, got {:?}", s),
+        }
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        format!("{:02}:{:02}", time.0 / 60, time.0 % 60)
+    }
+}
+
+/// An sRGB color with straight alpha, written as `#rrggbb` or `#rrggbbaa`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rgba(pub [u8; 4]);
+
+impl Rgba {
+    pub const TRANSPARENT: Rgba = Rgba([0, 0, 0, 0]);
+
+    pub fn is_opaque(self) -> bool {
+        self.0[3] == u8::MAX
+    }
+
+    /// The color in the linear space render targets are cleared in
+    pub fn to_linear(self) -> wgpu::Color {
+        let srgb_to_linear = |c: u8| {
+            let c = c as f64 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        let [r, g, b, a] = self.0;
+        wgpu::Color {
+            r: srgb_to_linear(r),
+            g: srgb_to_linear(g),
+            b: srgb_to_linear(b),
+            a: a as f64 / 255.0,
+        }
+    }
+
+    /// The color without alpha in linear space, as shaders take colors
+    pub fn to_linear_rgb(self) -> [f32; 3] {
+        let color = self.to_linear();
+        [color.r as f32, color.g as f32, color.b as f32]
+    }
+}
+
+impl FromStr for Rgba {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+            bail!("Expected a color like #rrggbb or #rrggbbaa, got {:?}", s);
+        }
+        let mut rgba = [u8::MAX; 4];
+        for (i, channel) in rgba.iter_mut().take(hex.len() / 2).enumerate() {
+            *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .with_context(|| format!("Invalid hex digits in color {:?}", s))?;
+        }
+        Ok(Self(rgba))
+    }
+}
+
+impl TryFrom<String> for Rgba {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Rgba> for String {
+    fn from(color: Rgba) -> Self {
+        let [r, g, b, a] = color.0;
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
+
+/// Settings every window is created with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Composite the window over whatever is behind it, with a see-through background
+    #[serde(skip)]
+    pub transparent: bool,
+    /// Maximum frames per second, unlimited (i.e. bound by vsync) when `None`
+    pub fps_cap: Option<f32>,
+    /// Frames per second while the window is unfocused or hidden, saving power when nobody is watching.
+    /// 0 keeps the normal frame rate.
+    pub idle_fps: Option<f32>,
+    /// Stop the simulation while the window is minimized or covered, rather than only drawing less
+    pub pause_when_hidden: bool,
+    /// Log how long each render pass takes on the GPU
+    pub gpu_profiling: bool,
+    /// What the depth buffer stores depth as
+    pub depth_format: DepthFormat,
+    /// Depth goes from 1 at the near plane to 0 at the far one, see `texture::Depth::reversed`
+    pub reversed_z: bool,
+    /// Background color, the default dark gray when `None`. Anything not fully opaque makes the window
+    /// transparent where the platform supports it.
+    pub background: Option<Rgba>,
+    /// How the window is composited, where its surface supports it
+    pub alpha_mode: AlphaMode,
+    /// Name of the palette pipes are painted with, built in or one of `palettes`
+    pub palette: String,
+    /// Palettes of the user's own, selectable by name like the built-in ones
+    pub palettes: Vec<CustomPalette>,
+    /// Render see-through pipes, blended over each other
+    pub glass: bool,
+    /// How opaque glass pipes are, from 0 (invisible) to 1 (solid)
+    pub glass_opacity: f32,
+    pub shading: Shading,
+    /// Segments, or tubes which flow round their turns. Tubes are swept again whenever their pipe grows,
+    /// so are slower for long pipes, and pipes grown on the GPU are always segments.
+    pub pipe_style: PipeStyle,
+    /// Sides of tubes, from `tubes::MIN_SIDES` to `tubes::MAX_SIDES`. Fewer draw faster, more look rounder.
+    pub tube_sides: u32,
+    /// Straight pieces the center line of tubes is cut into for every unit of its length, up to
+    /// `tubes::MAX_SEGMENTS_PER_UNIT`. Fewer draw faster, more turn smoother.
+    pub tube_segments_per_unit: u32,
+    /// What pipes are made of, see `material::Material`
+    pub material: Material,
+    /// What some pipes are made of instead of `material`, e.g. one chrome pipe among matte ones
+    pub accent_material: Material,
+    /// Chance of a new pipe to be made of `accent_material`, from 0 to 1
+    pub accent_chance: f32,
+    /// Resolution to render at relative to the window's, the result is filtered to fit the window
+    pub render_scale: f32,
+    /// Frames a second to keep up by lowering the render scale, down from `render_scale`, while they come
+    /// slower and raising it back once they come faster, so that weak GPUs don't stutter. Off when `None`.
+    pub target_fps: Option<f32>,
+    /// How the jagged edges of pipes are smoothed. `taa` blends every frame into the ones before, each
+    /// drawn a fraction of a pixel apart, which smooths edges finer than rendering at a higher scale costs,
+    /// at the price of a little blur. Draws the frames offscreen first.
+    pub aa: Antialiasing,
+    /// Resolution of screenshots taken in photo mode relative to the window's, from 1 to 4. Above 1 they
+    /// are drawn offscreen that much larger, for prints and wallpapers, as far as the GPU's textures reach.
+    pub photo_scale: u32,
+    /// Width of turntable GIFs in pixels, from 16 to 2048, see `Action::ExportTurntable`. Their height
+    /// follows the window's aspect ratio.
+    pub turntable_width: u32,
+    /// Split-screen views per window, from 1 to 4
+    pub views: u8,
+    /// Side-by-side left and right eye views instead of the split-screen ones
+    pub stereo: bool,
+    /// Distance between the eyes in stereo, in pipe segments
+    pub ipd: f32,
+    pub floor: bool,
+    pub sparks: bool,
+    /// Draw solid pipes from the nearest to the farthest, sorting them every now and then, so that less is
+    /// shaded only to be covered. Glass pipes are always sorted the other way.
+    pub depth_sort: bool,
+    /// Draw solid pipes' depth in a pass of its own first, so that every pixel is shaded once however many
+    /// pipes overlap there. Faster in dense worlds on GPUs which shade slower than they draw.
+    pub depth_prepass: bool,
+    /// Read pipe instances from storage buffers by their index rather than from vertex attributes. Where
+    /// the GPU's vertex shaders can't, like with many OpenGL ES drivers, the attributes are read anyway.
+    pub instance_storage: bool,
+    /// Encode the scene's render pass on a worker thread while the blit and overlay are encoded on the
+    /// main one, into command buffers submitted together. Saves CPU time a frame in dense worlds on
+    /// machines with cores to spare.
+    pub parallel_encoding: bool,
+    /// Seconds pipes take to fade to gray, 0 keeps them vivid
+    pub aging: f32,
+    /// Seconds pipes take to drain away when the world starts over, the newest first, 0 clears them at once
+    pub drain_seconds: f32,
+    /// Whether the old pipes drain away or crossfade into the new world
+    pub reset_transition: ResetTransition,
+    /// Where and how fast the light travels around the world, see `light::LightOrbit`
+    pub light_orbit: LightOrbit,
+    /// How much of the light reaches the sides of the pipes facing away from it, as a multiple of the
+    /// little every shading has. Higher brightens the dark half of the scene without moving the light.
+    pub ambient: f32,
+    /// Strength of the light along the pipes' silhouettes, which sets them off from the background and
+    /// each other, 0 for none
+    pub rim_light: f32,
+    /// Strength of the light the pipes get from the sky and ground around the world, 0 for none. Sides
+    /// facing up pick up the sky's tint and those facing down the ground's, so that the pipes look like
+    /// they are out in the world rather than in the dark.
+    pub sky_light: f32,
+    /// Color of the sky overhead in white light, a light gray when `None`. Also what metals mirror.
+    pub sky_color: Option<Rgba>,
+    /// Color of the ground below, nearly black when `None`. Also what metals mirror.
+    pub ground_color: Option<Rgba>,
+    /// Stops everything lit is brightened by, like a camera's exposure, or darkened by for negative ones,
+    /// up to `exposure::MAX_STOPS` either way. With auto-exposure it corrects what that picks.
+    pub exposure: f32,
+    /// Adapt the exposure to how bright the frames are over a second or so, darkening bright neon worlds
+    /// and brightening dim night ones. Needs compute shaders, and draws the frames offscreen first.
+    pub auto_exposure: bool,
+    /// No light orbit, chase camera, sparks or audio pulses, and slower growth
+    pub reduced_motion: bool,
+    /// React to this audio source, when built with the audio feature
+    pub audio: Option<AudioSource>,
+    /// Show an icon in the system tray, when built with the tray feature
+    pub tray: bool,
+    /// Grow pipes only in the digits of the current time, like a desk clock
+    pub clock: bool,
+    /// Switch themes by the local time, overriding the palette and background when the time comes
+    pub day_night: Option<DayNight>,
+    /// Address to accept OSC commands on, see `remote::RemoteControl`
+    pub remote: Option<SocketAddr>,
+    /// Address to serve the HTTP control API on, see `http::HttpServer`
+    pub http: Option<SocketAddr>,
+    /// Rhai script run by every window, see `script::Script` for its hooks
+    pub script: Option<PathBuf>,
+    /// Pipes laid out by hand to grow instead of random ones, like `0,0,2: +X*3, +Y, -Z*2` for a pipe
+    /// starting in the corner, see `layout::Layout`
+    pub layout: Option<PathBuf>,
+    /// Name of the pipe generator, see `generator::PipeGenerator`
+    pub generator: String,
+    /// Chance of a pipe to turn at every block, from 0 to 1
+    pub turn_probability: f32,
+    /// When pipes grow, steadily or in bursts, see `rhythm::Rhythm`
+    pub rhythm: Rhythm,
+    /// Segments a pipe grows at most before it gets an end cap and a new pipe with a color of its own
+    /// starts elsewhere. Pipes grow for as long as they find room when `None`.
+    pub max_pipe_length: Option<u32>,
+    /// How much pipes prefer to start and turn in each direction, e.g. `{ y = 4.0 }` for towers, or a
+    /// higher `z` towards the camera. Pipes grown on the GPU go every way alike.
+    pub direction_weights: DirectionWeights,
+    /// Turns pipes never or seldom take, see `generator::Constraints`
+    pub constraints: Constraints,
+    /// A shape pipes only grow inside of, e.g. `{ shape = "sphere", radius = 8 }`, or a `cylinder` with a
+    /// `radius`, an `ellipsoid` with three `radii`, or a `half-space` behind a plane with a `normal` and an
+    /// `offset`. Pipes grown on the GPU fill the whole world.
+    pub region: Option<Region>,
+    /// More worlds growing along with this one, each on its own, see `StackedWorld`. They are left out of
+    /// pipes drawn as tubes, and of windows showing another window's world.
+    pub stacked_worlds: Vec<StackedWorld>,
+    /// Blocks pipes look ahead before going somewhere, up to `world::MAX_LOOK_AHEAD`. A pipe headed for
+    /// a dead end within them goes where there is more room instead, so that pipes get stuck less often.
+    /// 0 leaves every choice to the generator.
+    pub look_ahead: u32,
+    /// Pipe networks racing to fill the world, up to `world::MAX_TEAMS`, each growing a pipe in a color
+    /// of the palette and taking turns with the others. A team starts new pipes next to its own only, so
+    /// teams hem each other in. The overlay shows how much each took, and the one with the most wins once
+    /// the world is full. 0 grows one pipe after another, and pipes grown on the GPU never race.
+    pub teams: u32,
+    /// What the graph of the pipes is saved as, with their ends and turns as nodes and the straight
+    /// runs between them as edges, see `graph::Graph`
+    pub graph_format: GraphFormat,
+    /// Along with the world's bounds and axes, draw an arrow out of the head of every growing pipe the
+    /// way it heads while the debug gizmos are shown, see `Action::ToggleGizmos`
+    pub gizmo_headings: bool,
+    /// Grow the same world every time from this, a random one every run when `None`
+    pub seed: Option<u64>,
+    /// Blocks along each side of the world, up to `world::MAX_SIZE`. Worlds of hundreds of blocks hold
+    /// hundreds of thousands of segments before they start over.
+    pub world_size: u32,
+    /// Stretch the world along X for windows wider than high, and along Y for ones higher than wide, so
+    /// that pipes fill the window from the front rather than a square in the middle of it, see
+    /// `world::extents_for_aspect`. Follows the window as it's resized. Worlds grown on the GPU stay cubes.
+    pub fit_aspect: bool,
+    /// Distance between the centers of neighboring blocks in the scene, the length of a straight pipe
+    pub cell_size: f32,
+    /// Radius of the pipes in the scene, up to half the cell size where neighboring pipes touch. The meshes
+    /// are thickened or thinned to it around the line they run along, so custom models fit too.
+    pub pipe_radius: f32,
+    /// How far segments stray from the grid, from 0 for perfectly regular plumbing to 1 for a hand-made
+    /// look. Segments shift, tilt and thicken or thin a little, but pipes still connect block to block.
+    /// Pipes grown on the GPU stay on the grid.
+    pub jitter: f32,
+    /// How far each segment's color strays from its pipe's, from 0 for one flat color a pipe to 1, which
+    /// breaks up long straight runs. Segments get a little more or less saturated and brighter or darker
+    /// at random, the same each time a world is grown from a seed. Pipes grown on the GPU stay flat.
+    pub color_jitter: f32,
+    /// Grow pipes by random walks on the GPU, straight into the buffers they are drawn from, rather than
+    /// on the CPU. Far faster, but only the classic generator, in worlds up to `gpu_world::MAX_SIZE` blocks
+    /// wide which can't be sorted, saved or shared, and with the palette they started with.
+    pub gpu_generation: bool,
+    /// Name of the effect over the finished frame, none when `None`
+    pub post_effect: Option<String>,
+    /// Scene to switch to on start, over the settings above, none when `None`
+    pub scene: Option<String>,
+    /// Scenes of the user's own, cycled through after the built-in ones, see `Scene`. One named like a
+    /// built-in scene replaces it.
+    pub scenes: Vec<Scene>,
+    /// Switch to a random scene, then a random palette, and start over from a random seed every this many
+    /// minutes, so that a screensaver left running keeps showing something new. Off when `None`.
+    pub shuffle_minutes: Option<f32>,
+    /// Window title with live stats filled in, see `Cli::title`
+    pub title: String,
+    /// What the keys do, see `keybindings::KeyBindings`
+    pub keys: KeyBindings,
+    /// Language of the overlay text, the system's when `None`
+    pub language: Option<Language>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            transparent: false,
+            fps_cap: None,
+            idle_fps: Some(IDLE_FPS),
+            pause_when_hidden: false,
+            gpu_profiling: false,
+            depth_format: DepthFormat::Depth32Float,
+            reversed_z: false,
+            background: None,
+            alpha_mode: AlphaMode::Auto,
+            palette: palette::CLASSIC.name.to_string(),
+            palettes: vec![],
+            glass: false,
+            glass_opacity: GLASS_OPACITY,
+            shading: Shading::Phong,
+            pipe_style: PipeStyle::Segments,
+            tube_sides: Tessellation::default().sides,
+            tube_segments_per_unit: Tessellation::default().segments_per_unit,
+            material: Material::Plastic,
+            accent_material: Material::Chrome,
+            accent_chance: 0.0,
+            render_scale: 1.0,
+            target_fps: None,
+            aa: Antialiasing::None,
+            photo_scale: 1,
+            turntable_width: 480,
+            views: 1,
+            stereo: false,
+            ipd: IPD,
+            floor: false,
+            sparks: false,
+            depth_sort: true,
+            depth_prepass: false,
+            instance_storage: false,
+            parallel_encoding: false,
+            aging: 0.0,
+            drain_seconds: 2.0,
+            reset_transition: ResetTransition::Drain,
+            light_orbit: LightOrbit::default(),
+            ambient: light::DEFAULT_AMBIENT,
+            rim_light: 0.0,
+            sky_light: 0.0,
+            sky_color: None,
+            ground_color: None,
+            exposure: 0.0,
+            auto_exposure: false,
+            reduced_motion: false,
+            audio: None,
+            tray: false,
+            clock: false,
+            day_night: None,
+            remote: None,
+            http: None,
+            script: None,
+            layout: None,
+            generator: "classic".to_string(),
+            turn_probability: TURN_PROBABILITY,
+            rhythm: Rhythm::default(),
+            max_pipe_length: None,
+            direction_weights: DirectionWeights::default(),
+            constraints: Constraints::default(),
+            region: None,
+            stacked_worlds: vec![],
+            look_ahead: 2,
+            teams: 0,
+            graph_format: GraphFormat::Dot,
+            gizmo_headings: false,
+            seed: None,
+            world_size: world::DEFAULT_SIZE,
+            fit_aspect: false,
+            cell_size: 1.0,
+            pipe_radius: models::MODELED_RADIUS,
+            jitter: 0.0,
+            color_jitter: 0.0,
+            gpu_generation: false,
+            post_effect: None,
+            scene: None,
+            scenes: vec![],
+            shuffle_minutes: None,
+            title: TITLE.to_string(),
+            keys: KeyBindings::default(),
+            language: None,
+        }
+    }
+}
+
+impl Config {
+    /// Read the config file given on the command line, if any, then apply command line overrides. Settings
+    /// `resumed` from the last run replace the config file's.
+    pub fn load(cli: &Cli, resumed: Option<Config>) -> anyhow::Result<Self> {
+        let mut config = match (resumed, &cli.config) {
+            (Some(config), _) => config,
+            (None, Some(path)) => {
+                let text = std::fs::read_to_string(path).with_context(|| format!("Cannot read config {:?}", path))?;
+                toml::from_str(&text).with_context(|| format!("Invalid config {:?}", path))?
+            }
+            (None, None) => Self::default(),
+        };
+        if let Some(recipe) = &cli.from_share {
+            config.apply(recipe);
+        }
+
+        if cli.background.is_some() {
+            config.background = cli.background;
+        } else if cli.overlay && config.background.is_none() {
+            config.background = Some(Rgba::TRANSPARENT);
+        }
+        config.transparent = cli.overlay || config.background.is_some_and(|c| !c.is_opaque());
+        if let Some(mode) = cli.alpha_mode {
+            config.alpha_mode = mode;
+        }
+        if cli.fps_cap.is_some() {
+            config.fps_cap = cli.fps_cap;
+        }
+        config.gpu_profiling |= cli.gpu_profiling;
+        if let Some(format) = cli.depth_format {
+            config.depth_format = format;
+        }
+        config.reversed_z |= cli.reversed_z;
+        config.glass |= cli.glass;
+        if let Some(shading) = cli.shading {
+            config.shading = shading;
+        }
+        if cli.target_fps.is_some() {
+            config.target_fps = cli.target_fps;
+        }
+        if let Some(fps) = config.target_fps.filter(|fps| *fps <= 0.0) {
+            bail!("Target FPS must be positive, got {}", fps);
+        }
+        if let Some(style) = cli.pipe_style {
+            config.pipe_style = style;
+        }
+        if let Some(sides) = cli.tube_sides {
+            config.tube_sides = sides;
+        }
+        if !(tubes::MIN_SIDES..=tubes::MAX_SIDES).contains(&config.tube_sides) {
+            bail!(
+                "Tube sides must be between {} and {}, got {}",
+                tubes::MIN_SIDES,
+                tubes::MAX_SIDES,
+                config.tube_sides
+            );
+        }
+        if let Some(segments) = cli.tube_segments_per_unit {
+            config.tube_segments_per_unit = segments;
+        }
+        if !(1..=tubes::MAX_SEGMENTS_PER_UNIT).contains(&config.tube_segments_per_unit) {
+            bail!(
+                "Tube segments per unit must be between 1 and {}, got {}",
+                tubes::MAX_SEGMENTS_PER_UNIT,
+                config.tube_segments_per_unit
+            );
+        }
+        if let Some(material) = cli.material {
+            config.material = material;
+        }
+        if !(0.0..=1.0).contains(&config.accent_chance) {
+            bail!("Accent chance must be between 0 and 1, got {}", config.accent_chance);
+        }
+        if cli.idle_fps.is_some() {
+            config.idle_fps = cli.idle_fps;
+        }
+        // 0 keeps the normal frame rate, in the file as on the command line
+        config.idle_fps = config.idle_fps.filter(|fps| *fps > 0.0);
+        config.pause_when_hidden |= cli.pause_when_hidden;
+        if let Some(render_scale) = cli.render_scale {
+            config.render_scale = render_scale;
+        }
+        if !(MIN_RENDER_SCALE..=MAX_RENDER_SCALE).contains(&config.render_scale) {
+            bail!(
+                "Render scale must be between {} and {}, got {}",
+                MIN_RENDER_SCALE,
+                MAX_RENDER_SCALE,
+                config.render_scale
+            );
+        }
+        if let Some(aa) = cli.aa {
+            config.aa = aa;
+        }
+        if let Some(photo_scale) = cli.photo_scale {
+            config.photo_scale = photo_scale;
+        }
+        if !(1..=MAX_PHOTO_SCALE).contains(&config.photo_scale) {
+            bail!(
+                "Photo scale must be between 1 and {}, got {}",
+                MAX_PHOTO_SCALE,
+                config.photo_scale
+            );
+        }
+        if let Some(turntable_width) = cli.turntable_width {
+            config.turntable_width = turntable_width;
+        }
+        if !(MIN_TURNTABLE_WIDTH..=MAX_TURNTABLE_WIDTH).contains(&config.turntable_width) {
+            bail!(
+                "Turntable width must be between {} and {}, got {}",
+                MIN_TURNTABLE_WIDTH,
+                MAX_TURNTABLE_WIDTH,
+                config.turntable_width
+            );
+        }
+        if let Some(views) = cli.views {
+            config.views = views;
+        }
+        if !(1..=MAX_VIEWS as u8).contains(&config.views) {
+            bail!("Views must be between 1 and {}, got {}", MAX_VIEWS, config.views);
+        }
+        config.stereo |= cli.stereo;
+        config.floor |= cli.floor;
+        config.sparks |= cli.sparks;
+        config.gizmo_headings |= cli.gizmo_headings;
+        config.depth_prepass |= cli.depth_prepass;
+        config.instance_storage |= cli.instance_storage;
+        config.parallel_encoding |= cli.parallel_encoding;
+        config.reduced_motion |= cli.reduced_motion;
+        config.clock |= cli.clock;
+        config.tray |= cli.tray;
+        if let Some(aging) = cli.aging {
+            config.aging = aging;
+        }
+        if config.aging < 0.0 {
+            bail!("Aging must not be negative, got {}", config.aging);
+        }
+        if let Some(transition) = cli.reset_transition {
+            config.reset_transition = transition;
+        }
+        if let Some(drain) = cli.drain {
+            config.drain_seconds = drain;
+        }
+        if config.drain_seconds < 0.0 {
+            bail!("Drain seconds must not be negative, got {}", config.drain_seconds);
+        }
+        if let Some(radius) = cli.light_radius {
+            config.light_orbit.radius = radius;
+        }
+        if let Some(speed) = cli.light_speed {
+            config.light_orbit.speed = speed;
+        }
+        if let Some(path) = cli.light_path {
+            config.light_orbit.path = path;
+        }
+        if config.light_orbit.radius < 0.0 {
+            bail!(
+                "Light orbit radius must not be negative, got {}",
+                config.light_orbit.radius
+            );
+        }
+        if config.light_orbit.axis == [0.0; 3] {
+            bail!("Light orbit axis must point somewhere");
+        }
+        if let Some(ambient) = cli.ambient {
+            config.ambient = ambient;
+        }
+        if config.ambient < 0.0 {
+            bail!("Ambient light must not be negative, got {}", config.ambient);
+        }
+        if let Some(rim_light) = cli.rim_light {
+            config.rim_light = rim_light;
+        }
+        if config.rim_light < 0.0 {
+            bail!("Rim light must not be negative, got {}", config.rim_light);
+        }
+        if let Some(sky_light) = cli.sky_light {
+            config.sky_light = sky_light;
+        }
+        if config.sky_light < 0.0 {
+            bail!("Sky light must not be negative, got {}", config.sky_light);
+        }
+        if cli.sky_color.is_some() {
+            config.sky_color = cli.sky_color;
+        }
+        if cli.ground_color.is_some() {
+            config.ground_color = cli.ground_color;
+        }
+        if let Some(exposure) = cli.exposure {
+            config.exposure = exposure;
+        }
+        if config.exposure.abs() > exposure::MAX_STOPS {
+            bail!(
+                "Exposure must be within {} stops either way, got {}",
+                exposure::MAX_STOPS,
+                config.exposure
+            );
+        }
+        config.auto_exposure |= cli.auto_exposure;
+        if cli.audio.is_some() {
+            config.audio = cli.audio;
+        }
+        if let Some(ipd) = cli.ipd {
+            config.ipd = ipd;
+        }
+        if config.ipd < 0.0 {
+            bail!("Eye distance must not be negative, got {}", config.ipd);
+        }
+        for custom in &config.palettes {
+            custom.register()?;
+        }
+        if let Some(palette) = &cli.palette {
+            config.palette = palette.clone();
+        }
+        check_palette(&config.palette)?;
+        if cli.remote.is_some() {
+            config.remote = cli.remote;
+        }
+        if cli.http.is_some() {
+            config.http = cli.http;
+        }
+        if cli.script.is_some() {
+            config.script = cli.script.clone();
+        }
+        if cli.layout.is_some() {
+            config.layout = cli.layout.clone();
+        }
+        if let Some(generator) = &cli.generator {
+            config.generator = generator.clone();
+        }
+        if !(0.0..=1.0).contains(&config.turn_probability) {
+            bail!(
+                "Turn probability must be between 0 and 1, got {}",
+                config.turn_probability
+            );
+        }
+        if config.rhythm.burst_interval <= 0.0 {
+            bail!("Burst interval must be positive, got {}", config.rhythm.burst_interval);
+        }
+        if config.rhythm.stagger < 0.0 {
+            bail!("Stagger must not be negative, got {}", config.rhythm.stagger);
+        }
+        if cli.max_pipe_length.is_some() {
+            config.max_pipe_length = cli.max_pipe_length;
+        }
+        if config.max_pipe_length == Some(0) {
+            bail!("Max pipe length must be at least 1");
+        }
+        if let Some(weights) = cli.direction_weights {
+            config.direction_weights = weights;
+        }
+        config.direction_weights.validate()?;
+        if let Some(depth) = cli.look_ahead {
+            config.look_ahead = depth;
+        }
+        if let Some(format) = cli.graph_format {
+            config.graph_format = format;
+        }
+        if let Some(teams) = cli.teams {
+            config.teams = teams;
+        }
+        if config.teams > world::MAX_TEAMS {
+            bail!("There can be at most {} teams, got {}", world::MAX_TEAMS, config.teams);
+        }
+        if let Some(region) = &config.region {
+            region.check()?;
+        }
+        if config.look_ahead > world::MAX_LOOK_AHEAD {
+            bail!(
+                "Look-ahead must be at most {} blocks, got {}",
+                world::MAX_LOOK_AHEAD,
+                config.look_ahead
+            );
+        }
+        if let Some(size) = cli.world_size {
+            config.world_size = size;
+        }
+        config.fit_aspect |= cli.fit_aspect;
+        if !(2..=world::MAX_SIZE).contains(&config.world_size) {
+            bail!(
+                "World size must be between 2 and {}, got {}",
+                world::MAX_SIZE,
+                config.world_size
+            );
+        }
+        if let Some(size) = cli.cell_size {
+            config.cell_size = size;
+        }
+        if config.cell_size <= 0.0 {
+            bail!("Cell size must be positive, got {}", config.cell_size);
+        }
+        if let Some(radius) = cli.pipe_radius {
+            config.pipe_radius = radius;
+        }
+        if config.pipe_radius <= 0.0 || config.pipe_radius > config.cell_size / 2.0 {
+            bail!(
+                "Pipe radius must be positive and at most half the cell size {}, got {}",
+                config.cell_size,
+                config.pipe_radius
+            );
+        }
+        if let Some(jitter) = cli.jitter {
+            config.jitter = jitter;
+        }
+        if !(0.0..=1.0).contains(&config.jitter) {
+            bail!("Jitter must be between 0 and 1, got {}", config.jitter);
+        }
+        if let Some(jitter) = cli.color_jitter {
+            config.color_jitter = jitter;
+        }
+        if !(0.0..=1.0).contains(&config.color_jitter) {
+            bail!("Color jitter must be between 0 and 1, got {}", config.color_jitter);
+        }
+        config.gpu_generation |= cli.gpu_generation;
+        if config.gpu_generation && config.pipe_style == PipeStyle::Tubes {
+            bail!("Pipes grown on the GPU can't be drawn as tubes");
+        }
+        if config.gpu_generation && config.world_size > gpu_world::MAX_SIZE {
+            bail!(
+                "World size must be at most {} when growing on the GPU, got {}",
+                gpu_world::MAX_SIZE,
+                config.world_size
+            );
+        }
+        if generator::find(&config.generator).is_none() {
+            bail!(
+                "Unknown generator {:?}, available: {}",
+                config.generator,
+                generator::names().join(", ")
+            );
+        }
+        if cli.post_effect.is_some() {
+            config.post_effect = cli.post_effect.clone();
+        }
+        if let Some(effect) = config
+            .post_effect
+            .as_ref()
+            .filter(|name| blit::find_post_effect(name).is_none())
+        {
+            bail!(
+                "Unknown post effect {:?}, available: {}",
+                effect,
+                blit::post_effect_names().join(", ")
+            );
+        }
+        for scene in &config.scenes {
+            scene.check()?;
+        }
+        for stacked in &config.stacked_worlds {
+            stacked.check()?;
+        }
+        if cli.scene.is_some() {
+            config.scene = cli.scene.clone();
+        }
+        if let Some(scene) = config.scene.as_ref().filter(|name| config.find_scene(name).is_none()) {
+            let scenes = config.scenes();
+            let names: Vec<_> = scenes.iter().map(|scene| scene.name.as_str()).collect();
+            bail!("Unknown scene {:?}, available: {}", scene, names.join(", "));
+        }
+        if cli.shuffle.is_some() {
+            config.shuffle_minutes = cli.shuffle;
+        }
+        if let Some(minutes) = config.shuffle_minutes.filter(|minutes| *minutes <= 0.0) {
+            bail!("Shuffle minutes must be positive, got {}", minutes);
+        }
+        if let Some(title) = &cli.title {
+            config.title = title.clone();
+        }
+        if cli.day_night && config.day_night.is_none() {
+            config.day_night = Some(DayNight::default());
+        }
+        if let Some(day_night) = &config.day_night {
+            for theme in [&day_night.day, &day_night.night] {
+                check_palette(&theme.palette)?;
+                if theme.light < 0.0 {
+                    bail!("Theme light must not be negative, got {}", theme.light);
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn palette(&self) -> &'static Palette {
+        palette::find(&self.palette).unwrap_or(&palette::CLASSIC)
+    }
+
+    /// The built-in scenes and the config's own, in the order they are cycled through
+    pub fn scenes(&self) -> Vec<Scene> {
+        let mut scenes = Scene::built_in();
+        for scene in &self.scenes {
+            match scenes.iter_mut().find(|built_in| built_in.name == scene.name) {
+                Some(built_in) => *built_in = scene.clone(),
+                None => scenes.push(scene.clone()),
+            }
+        }
+        scenes
+    }
+
+    pub fn find_scene(&self, name: &str) -> Option<Scene> {
+        self.scenes().into_iter().find(|scene| scene.name == name)
+    }
+
+    /// Grow the world of a recipe, see `recipe::Recipe`
+    fn apply(&mut self, recipe: &Recipe) {
+        self.seed = Some(recipe.seed);
+        self.palette = recipe.palette.clone();
+        self.generator = recipe.generator.clone();
+        self.turn_probability = recipe.turn_probability;
+        self.world_size = recipe.world_size;
+        self.max_pipe_length = recipe.max_pipe_length;
+        self.direction_weights = recipe.direction_weights;
+        self.constraints = recipe.constraints;
+        self.region = recipe.region;
+        self.look_ahead = recipe.look_ahead;
+        self.teams = recipe.teams;
+        self.glass = recipe.opacity < 1.0;
+        if self.glass {
+            self.glass_opacity = recipe.opacity;
+        }
+    }
+
+    /// What new pipes are made of
+    pub fn materials(&self) -> Materials {
+        Materials {
+            material: self.material,
+            accent: self.accent_material,
+            accent_chance: self.accent_chance,
+        }
+    }
+
+    pub fn depth(&self) -> texture::Depth {
+        texture::Depth {
+            format: self.depth_format.texture_format(),
+            reversed: self.reversed_z,
+        }
+    }
+
+    pub fn tessellation(&self) -> Tessellation {
+        Tessellation {
+            sides: self.tube_sides,
+            segments_per_unit: self.tube_segments_per_unit,
+        }
+    }
+
+    /// Opacity of new pipes
+    pub fn opacity(&self) -> f32 {
+        if self.glass {
+            self.glass_opacity.clamp(0.0, 1.0)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// A palette in the config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomPalette {
+    pub name: String,
+    /// Colors written like the background, their alpha is ignored
+    pub colors: Vec<Rgba>,
+    /// Chance of a new pipe to glow, see `Palette`
+    #[serde(default)]
+    pub glow_chance: f32,
+    #[serde(default)]
+    pub glow: f32,
+}
+
+impl CustomPalette {
+    /// Make the palette selectable by its name, warning about colors hard to tell apart for anyone
+    fn register(&self) -> anyhow::Result<()> {
+        if self.colors.is_empty() {
+            bail!("Palette {:?} has no colors", self.name);
+        }
+        if palette::find(&self.name).is_some() {
+            bail!("Palette {:?} already exists", self.name);
+        }
+        let colors: Vec<[f32; 3]> = self
+            .colors
+            .iter()
+            .map(|Rgba([r, g, b, _])| [*r, *g, *b].map(|c| c as f32 / 256.0))
+            .collect();
+        for vision in ColorVision::ALL {
+            for (a, b, difference) in palette::similar_colors(&colors, vision) {
+                warn!(
+                    "Palette {:?}: colors {} and {} are hard to tell apart with {:?} color vision (difference {:.0})",
+                    self.name,
+                    String::from(self.colors[a]),
+                    String::from(self.colors[b]),
+                    vision,
+                    difference
+                );
+            }
+        }
+        let name = Box::leak(self.name.clone().into_boxed_str());
+        let colors = Box::leak(colors.into_boxed_slice());
+        plugin::register_palette(Box::leak(Box::new(Palette {
+            name,
+            colors,
+            glow_chance: self.glow_chance,
+            glow: self.glow,
+        })));
+        Ok(())
+    }
+}
+
+fn check_palette(name: &str) -> anyhow::Result<()> {
+    if palette::find(name).is_none() {
+        bail!("Unknown palette {:?}, available: {}", name, palette::names().join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The config loaded from a file of `toml`, with no flags on the command line
+    fn load_toml(name: &str, toml: &str) -> Config {
+        let path = std::env::temp_dir().join(format!("xpipe-test-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, toml).unwrap();
+        let cli = Cli::parse_from(["xpipe".as_ref(), "--config".as_ref(), path.as_os_str()]);
+        let config = Config::load(&cli, None);
+        std::fs::remove_file(&path).unwrap();
+        config.unwrap()
+    }
+
+    #[test]
+    fn idle_fps_of_0_keeps_the_normal_frame_rate() {
+        assert_eq!(load_toml("idle-fps", "idle_fps = 0").idle_fps, None);
+        assert_eq!(load_toml("no-idle-fps", "").idle_fps, Some(IDLE_FPS));
+        let cli = Cli::parse_from(["xpipe", "--idle-fps", "0"]);
+        assert_eq!(Config::load(&cli, None).unwrap().idle_fps, None);
+    }
+}
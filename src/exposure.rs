@@ -0,0 +1,231 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::gpu;
+
+// bins of the histogram, spread evenly over log2 brightness, see exposure.wgsl
+const BINS: usize = 64;
+const MIN_LOG_BRIGHTNESS: f32 = -6.0;
+const MAX_LOG_BRIGHTNESS: f32 = 2.0;
+const WORKGROUP_SIZE: u32 = 16;
+/// Average brightness frames are exposed for, at which the classic palette comes out about as it is
+const KEY: f32 = 0.125;
+// shares of the counted pixels left out at the dark and bright ends, so that a few dark corners or bright
+// sparks don't swing the exposure
+const LOW_PERCENTILE: f32 = 0.1;
+const HIGH_PERCENTILE: f32 = 0.95;
+/// Stops auto-exposure brightens or darkens frames by at most
+pub const MAX_STOPS: f32 = 4.0;
+// how fast the exposure follows the frames, the share of the way left per second is e to the minus this
+const ADAPTATION_SPEED: f32 = 1.5;
+
+/// Adapts the exposure to how bright the frames are, like the eye does: a compute pass counts the pixels of
+/// each frame into a histogram of their brightness, which is read back to average over a few frames later.
+/// Bright neon worlds are darkened and dim night ones brightened. Needs compute shaders.
+pub struct AutoExposure {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    histogram_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // exposure the frame whose histogram is being copied to, or read back from, `readback_buffer` was
+    // drawn with
+    readback_exposure: f32,
+    is_copy_pending: bool,
+    is_mapping: bool,
+    is_mapped: Arc<AtomicBool>,
+    // multiple of the light the frames are exposed with, as adapted so far
+    exposure: f32,
+    last_adapted: Instant,
+}
+
+impl AutoExposure {
+    /// Fails on GPUs without compute shaders
+    pub fn new(device: &wgpu::Device) -> anyhow::Result<Self> {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ExposureBindGroupLayout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ExposurePipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = gpu::create_shader_module(device, "ExposureShader", include_str!("exposure.wgsl"))?;
+        let pipeline = gpu::validated(device, "ExposurePipeline", || {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("ExposurePipeline"),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point: Some("histogram_main"),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        })?;
+        let histogram_size = (BINS * size_of::<u32>()) as wgpu::BufferAddress;
+        Ok(Self {
+            bind_group_layout,
+            pipeline,
+            histogram_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("ExposureHistogramBuffer"),
+                size: histogram_size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("ExposureReadbackBuffer"),
+                size: histogram_size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            readback_exposure: 1.0,
+            is_copy_pending: false,
+            is_mapping: false,
+            is_mapped: Arc::new(AtomicBool::new(false)),
+            exposure: 1.0,
+            last_adapted: Instant::now(),
+        })
+    }
+
+    /// Bind group to pass to `dispatch` for measuring the frames drawn into `frame`
+    pub fn bind_group(&self, device: &wgpu::Device, frame: &wgpu::TextureView) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ExposureBindGroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(frame),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.histogram_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Count the pixels of the frame last drawn into the target of `bind_group`, `width` by `height` and
+    /// drawn with `exposure`, and copy them to the readback buffer, unless it still holds an older frame
+    pub fn dispatch(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        (width, height): (u32, u32),
+        exposure: f32,
+    ) {
+        if self.is_mapping {
+            return;
+        }
+        encoder.clear_buffer(&self.histogram_buffer, 0, None);
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("ExposurePass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(width.div_ceil(WORKGROUP_SIZE), height.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.histogram_buffer, 0, &self.readback_buffer, 0, None);
+        self.readback_exposure = exposure;
+        self.is_copy_pending = true;
+    }
+
+    /// Start reading back the copied histogram, must be called after the frame is submitted
+    pub fn after_submit(&mut self) {
+        if !self.is_copy_pending {
+            return;
+        }
+        self.is_copy_pending = false;
+        self.is_mapping = true;
+        let is_mapped = self.is_mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                is_mapped.store(result.is_ok(), Ordering::Release)
+            });
+    }
+
+    /// Pick up the histogram of an earlier frame once the GPU is done with it, and adapt the exposure
+    /// towards what it takes to show that frame at `KEY`
+    pub fn collect(&mut self, device: &wgpu::Device) {
+        if !self.is_mapping {
+            return;
+        }
+        let _ = device.poll(wgpu::PollType::Poll);
+        if !self.is_mapped.swap(false, Ordering::Acquire) {
+            return;
+        }
+        let average = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            average_log_brightness(bytemuck::cast_slice(&data))
+        };
+        self.readback_buffer.unmap();
+        self.is_mapping = false;
+
+        let seconds = self.last_adapted.elapsed().as_secs_f32();
+        self.last_adapted = Instant::now();
+        // frames with nothing but the background keep the exposure there was
+        let Some(average) = average else {
+            return;
+        };
+        // the frame was exposed already, which doesn't change how bright the scene itself is
+        let scene = average - self.readback_exposure.log2();
+        let target = (KEY.log2() - scene).clamp(-MAX_STOPS, MAX_STOPS);
+        let current = self.exposure.log2();
+        let step = 1.0 - (-seconds * ADAPTATION_SPEED).exp();
+        self.exposure = (current + (target - current) * step).exp2();
+    }
+
+    /// Multiple of the light frames are exposed with, as adapted so far
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+}
+
+/// The average log2 brightness of the pixels counted into `histogram`, without those at the darkest and
+/// brightest ends, `None` if there aren't any
+fn average_log_brightness(histogram: &[u32]) -> Option<f32> {
+    let count: u32 = histogram.iter().sum();
+    if count == 0 {
+        return None;
+    }
+    let (low, high) = (count as f32 * LOW_PERCENTILE, count as f32 * HIGH_PERCENTILE);
+    let (mut below, mut total, mut weight) = (0.0, 0.0, 0.0);
+    for (bin, &pixels) in histogram.iter().enumerate() {
+        // the pixels of the bin between the percentiles
+        let pixels = pixels as f32;
+        let counted = (below + pixels).min(high) - below.max(low);
+        below += pixels;
+        if counted <= 0.0 {
+            continue;
+        }
+        let log_brightness =
+            MIN_LOG_BRIGHTNESS + (bin as f32 + 0.5) / BINS as f32 * (MAX_LOG_BRIGHTNESS - MIN_LOG_BRIGHTNESS);
+        total += log_brightness * counted;
+        weight += counted;
+    }
+    (weight > 0.0).then(|| total / weight)
+}
@@ -0,0 +1,44 @@
+// A histogram of how bright the pixels of a frame are, in bins of log2 brightness. See
+// `exposure::AutoExposure`.
+
+// Must match BINS, MIN_LOG_BRIGHTNESS and MAX_LOG_BRIGHTNESS in exposure.rs
+const BINS: u32 = 64u;
+const MIN_LOG_BRIGHTNESS: f32 = -6.0;
+const MAX_LOG_BRIGHTNESS: f32 = 2.0;
+
+@group(0) @binding(0)
+var frame: texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, BINS>;
+
+// each workgroup counts its own pixels first, so that they don't all contend for the same bins
+var<workgroup> local_histogram: array<atomic<u32>, BINS>;
+
+@compute @workgroup_size(16, 16)
+fn histogram_main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    if index < BINS {
+        atomicStore(&local_histogram[index], 0u);
+    }
+    workgroupBarrier();
+
+    if all(id.xy < textureDimensions(frame)) {
+        let color = textureLoad(frame, id.xy, 0).rgb;
+        // the brightest channel rather than the luminance, as that is what clips in saturated colors
+        let brightness = max(color.r, max(color.g, color.b));
+        let log_brightness = log2(max(brightness, 1e-6));
+        // darker than the first bin is mostly the background, which doesn't count
+        if log_brightness >= MIN_LOG_BRIGHTNESS {
+            let share = (log_brightness - MIN_LOG_BRIGHTNESS) / (MAX_LOG_BRIGHTNESS - MIN_LOG_BRIGHTNESS);
+            let bin = min(u32(share * f32(BINS)), BINS - 1u);
+            atomicAdd(&local_histogram[bin], 1u);
+        }
+    }
+    workgroupBarrier();
+
+    if index < BINS {
+        atomicAdd(&histogram[index], atomicLoad(&local_histogram[index]));
+    }
+}
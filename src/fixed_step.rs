@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+/// Steps per second the simulation and animations advance in, whatever the display's refresh rate
+pub const RATE: u32 = 60;
+pub const STEP: Duration = Duration::from_nanos(1_000_000_000 / RATE as u64);
+/// Seconds of a step, for what moves on by seconds
+pub const STEP_SECONDS: f32 = 1.0 / RATE as f32;
+// longer stalls are clamped, so that everything doesn't catch up at once after them
+const MAX_STEPS: u32 = RATE / 4;
+
+/// Turns the varying time between frames or ticks into whole fixed steps, carrying what is left over
+/// to the next call, so that what happens over some time is the same at any frame rate
+#[derive(Debug, Default)]
+pub struct FixedStep {
+    accumulator: Duration,
+}
+
+impl FixedStep {
+    /// Steps due after `elapsed` more time
+    pub fn steps(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator = (self.accumulator + elapsed).min(STEP * MAX_STEPS);
+        let steps = (self.accumulator.as_nanos() / STEP.as_nanos()) as u32;
+        self.accumulator -= STEP * steps;
+        steps
+    }
+}
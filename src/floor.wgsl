@@ -0,0 +1,160 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Light {
+    position: vec3<f32>,
+    ambient: f32,
+    color: vec3<f32>,
+    rim: f32,
+    sky: vec3<f32>,
+    sky_light: f32,
+    ground: vec3<f32>,
+    exposure: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> light: Light;
+
+struct Time {
+    seconds: f32,
+    aging: f32,
+    drain_start: f32,
+    drain_duration: f32,
+    drain_newest: f32,
+    drain_oldest: f32,
+}
+
+@group(2) @binding(0)
+var<uniform> time: Time;
+
+// Must match FLOOR_Y, FLOOR_CENTER and FLOOR_RADIUS in renderer.rs
+const FLOOR_Y: f32 = -1.0;
+const FLOOR_CENTER: vec2<f32> = vec2<f32>(15.0, 15.0);
+const FLOOR_RADIUS: f32 = 60.0;
+const FLOOR_COLOR: vec3<f32> = vec3<f32>(0.05, 0.05, 0.06);
+// How much of the mirrored pipes below shows through the floor
+const REFLECTIVITY: f32 = 0.5;
+// Lines between the blocks pipes grow in, brighter every GRID_MAJOR blocks
+const GRID_MAJOR: f32 = 5.0;
+const GRID_COLOR: vec3<f32> = vec3<f32>(0.3, 0.3, 0.35);
+const GRID_STRENGTH: f32 = 0.25;
+// Blob shadows grow and fade the higher the pipe casting them is
+const SHADOW_RADIUS: f32 = 0.7;
+const SHADOW_SPREAD: f32 = 0.05;
+const SHADOW_STRENGTH: f32 = 0.5;
+const SHADOW_FADE: f32 = 0.15;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+};
+
+// Two triangles of a square around the world, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let xz = FLOOR_CENTER + corners[index] * FLOOR_RADIUS;
+    var out: VertexOutput;
+    out.world_position = vec3<f32>(xz.x, FLOOR_Y, xz.y);
+    out.clip_position = camera.view_proj * vec4<f32>(out.world_position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let light_dir = normalize(light.position - in.world_position);
+    let diffuse_strength = max(light_dir.y, 0.0);
+
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let specular_strength = pow(max(half_dir.y, 0.0), 64.0);
+
+    let cell = in.world_position.xz + 0.5;
+    let minor_line = grid_line(cell);
+    let major_line = grid_line(cell / GRID_MAJOR);
+    let grid = max(minor_line * 0.5, major_line) * GRID_STRENGTH;
+
+    let color = FLOOR_COLOR * (0.2 * light.ambient + diffuse_strength) + specular_strength * light.color * 0.2 + GRID_COLOR * grid;
+
+    // Fade out towards the edges, so the floor has no visible border
+    let fade = 1.0 - smoothstep(FLOOR_RADIUS * 0.5, FLOOR_RADIUS, distance(in.world_position.xz, FLOOR_CENTER));
+    return vec4<f32>(color * light.exposure, (1.0 - REFLECTIVITY) * fade);
+}
+
+// 1 on the lines between unit cells of `p`, 0 farther than a pixel away from them
+fn grid_line(p: vec2<f32>) -> f32 {
+    let distance = abs(fract(p - 0.5) - 0.5) / fwidth(p);
+    return 1.0 - min(min(distance.x, distance.y), 1.0);
+}
+
+// Blob shadows
+
+struct InstanceInput {
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(12) color: vec4<f32>,
+    @location(15) birth: f32,
+};
+
+struct ShadowOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // position within the blob, from -1 to 1
+    @location(0) offset: vec2<f32>,
+    @location(1) strength: f32,
+};
+
+// A square on the floor right below every pipe
+@vertex
+fn vs_shadow(@builtin(vertex_index) index: u32, instance: InstanceInput) -> ShadowOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let pipe = instance.model_matrix_3.xyz;
+    let height = max(pipe.y - FLOOR_Y, 0.0);
+    let radius = SHADOW_RADIUS + height * SHADOW_SPREAD;
+    let xz = pipe.xz + corners[index] * radius;
+
+    var out: ShadowOutput;
+    // Lifted a little, so the floor doesn't hide it
+    out.clip_position = camera.view_proj * vec4<f32>(xz.x, FLOOR_Y + 0.01, xz.y, 1.0);
+    out.offset = corners[index];
+    // Glass pipes let light through, and so cast lighter shadows
+    out.strength = SHADOW_STRENGTH * instance.color.a / (1.0 + height * SHADOW_FADE) * drained_strength(instance.birth);
+    return out;
+}
+
+// Must match DRAIN_SHRINK in shader.wgsl
+const DRAIN_SHRINK: f32 = 0.2;
+
+// Shadows of draining pipes fade as they shrink, like `drained_scale` in shader.wgsl
+fn drained_strength(birth: f32) -> f32 {
+    if time.drain_duration <= 0.0 {
+        return 1.0;
+    }
+    let order = (time.drain_newest - birth) / max(time.drain_newest - time.drain_oldest, 1e-6);
+    let progress = (time.seconds - time.drain_start) / time.drain_duration;
+    let start = order * (1.0 - DRAIN_SHRINK);
+    return 1.0 - smoothstep(start, start + DRAIN_SHRINK, progress);
+}
+
+@fragment
+fn fs_shadow(in: ShadowOutput) -> @location(0) vec4<f32> {
+    let falloff = 1.0 - smoothstep(0.0, 1.0, length(in.offset));
+    return vec4<f32>(0.0, 0.0, 0.0, in.strength * falloff);
+}
@@ -0,0 +1,82 @@
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use log::info;
+
+const DEAD_ZONE: f32 = 0.15;
+
+#[derive(Copy, Clone, Debug)]
+pub enum GamepadAction {
+    ResetWorld,
+    GrowFaster,
+    GrowSlower,
+    Exit,
+}
+
+/// Analog state of the active gamepad, with the dead zone already applied
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GamepadAxes {
+    pub yaw: f32,
+    pub pitch: f32,
+    pub zoom: f32,
+}
+
+/// Default mapping:
+/// - left stick: orbit camera
+/// - right stick Y: zoom
+/// - D-pad up/down: growth speed
+/// - South (A/Cross): reset world
+/// - Start: exit
+pub struct Gamepad {
+    gilrs: Gilrs,
+    active: Option<GamepadId>,
+}
+
+impl Gamepad {
+    pub fn new() -> anyhow::Result<Self> {
+        // gilrs::Error may hold a dummy context which is not Send, so it cannot be wrapped as is
+        let gilrs = Gilrs::new().map_err(|e| anyhow::anyhow!("Cannot initialize gamepad support: {}", e))?;
+        let active = gilrs.gamepads().next().map(|(id, _)| id);
+        Ok(Self { gilrs, active })
+    }
+
+    pub fn poll(&mut self) -> Vec<GamepadAction> {
+        let mut actions = vec![];
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::Connected => {
+                    info!("Gamepad connected: {}", self.gilrs.gamepad(event.id).name());
+                    self.active = Some(event.id);
+                }
+                EventType::Disconnected if self.active == Some(event.id) => {
+                    self.active = self.gilrs.gamepads().next().map(|(id, _)| id);
+                }
+                EventType::ButtonPressed(button, _) => {
+                    self.active = Some(event.id);
+                    match button {
+                        Button::South => actions.push(GamepadAction::ResetWorld),
+                        Button::DPadUp => actions.push(GamepadAction::GrowFaster),
+                        Button::DPadDown => actions.push(GamepadAction::GrowSlower),
+                        Button::Start => actions.push(GamepadAction::Exit),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        actions
+    }
+
+    pub fn axes(&self) -> GamepadAxes {
+        let Some(gamepad) = self.active.and_then(|id| self.gilrs.connected_gamepad(id)) else {
+            return GamepadAxes::default();
+        };
+        GamepadAxes {
+            yaw: dead_zone(gamepad.value(Axis::LeftStickX)),
+            pitch: dead_zone(gamepad.value(Axis::LeftStickY)),
+            zoom: dead_zone(gamepad.value(Axis::RightStickY)),
+        }
+    }
+}
+
+fn dead_zone(value: f32) -> f32 {
+    if value.abs() < DEAD_ZONE { 0.0 } else { value }
+}
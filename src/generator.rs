@@ -0,0 +1,269 @@
+use std::str::FromStr;
+
+use anyhow::{Context, bail};
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::plugin;
+use crate::world::Direction;
+
+// segments a zigzag pipe runs straight between turns
+const ZIGZAG_RUN: u32 = 3;
+// how much less likely a turn back into the plane of the pipe's last turn is, with `avoid_spirals`
+const SPIRAL_PENALTY: f32 = 0.2;
+
+/// Where a pipe is at, for a generator to decide where it goes next
+#[derive(Copy, Clone, Debug)]
+pub struct Step {
+    /// Block the pipe just grew into
+    pub position: (u32, u32, u32),
+    /// Direction the pipe leaves that block in so far
+    pub direction: Direction,
+    /// Blocks before this one in the pipe
+    pub segment: u32,
+    /// The world's chance of a turn, which generators are free to ignore
+    pub turn_probability: f32,
+    /// The world's preferred ways to turn, see `random_turn`
+    pub weights: DirectionWeights,
+    /// The pipe's latest turn, `None` while it ran straight so far
+    pub last_turn: Option<Turn>,
+    /// Turns the world rules out or discourages, see `random_turn`
+    pub constraints: Constraints,
+}
+
+impl Step {
+    /// Any of the four directions at a right angle to the pipe's, by the world's direction weights and
+    /// within its constraints
+    pub fn random_turn(&self, rng: &mut StdRng) -> Direction {
+        let options = self.direction.perpendiculars();
+        let Some(turn) = self.last_turn else {
+            return self.weights.choose(options, rng);
+        };
+        choose(options, rng, |direction| {
+            if self.is_ruled_out(direction) {
+                0.0
+            } else if self.constraints.avoid_spirals && direction.is_parallel_to(turn.from) {
+                self.weights.weight(direction) * SPIRAL_PENALTY
+            } else {
+                self.weights.weight(direction)
+            }
+        })
+    }
+
+    /// Whether the world's constraints keep the pipe from going this way
+    pub fn is_ruled_out(&self, direction: Direction) -> bool {
+        // turning right after a turn back the way the pipe came folds it into a U
+        self.constraints.no_reversals
+            && self
+                .last_turn
+                .is_some_and(|turn| direction == turn.from.opposite() && self.segment == turn.segment + 1)
+    }
+}
+
+/// Where a pipe last turned
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Turn {
+    /// Direction the pipe came from before the turn
+    pub from: Direction,
+    /// Blocks before the one the pipe turned in
+    pub segment: u32,
+}
+
+/// Turns ruled out or discouraged, for pipes which don't double back on themselves. Configured as the
+/// `[constraints]` table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Constraints {
+    /// Never turn right back the way the pipe came, two elbows next to each other folding it into a U
+    pub no_reversals: bool,
+    /// Turn back into the plane of the pipe's last turn less often, which makes for stairs and tight
+    /// spirals, and more often out into the third axis
+    pub avoid_spirals: bool,
+}
+
+impl Default for Constraints {
+    fn default() -> Self {
+        Self {
+            no_reversals: true,
+            avoid_spirals: false,
+        }
+    }
+}
+
+impl Constraints {
+    /// Any turn goes, like pipes grew before there were constraints
+    pub const NONE: Self = Self {
+        no_reversals: false,
+        avoid_spirals: false,
+    };
+}
+
+/// Relative weights of the directions pipes start and turn in, 1 each by default. Raising one has pipes
+/// prefer it, e.g. `{ y = 4.0 }` makes them climb into towers, and 0 keeps them from turning that way.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirectionWeights {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub neg_x: f32,
+    pub neg_y: f32,
+    pub neg_z: f32,
+}
+
+impl Default for DirectionWeights {
+    fn default() -> Self {
+        Self::EVEN
+    }
+}
+
+impl DirectionWeights {
+    /// Every direction alike
+    pub const EVEN: Self = Self {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+        neg_x: 1.0,
+        neg_y: 1.0,
+        neg_z: 1.0,
+    };
+
+    pub fn weight(&self, direction: Direction) -> f32 {
+        match direction {
+            Direction::X => self.x,
+            Direction::Y => self.y,
+            Direction::Z => self.z,
+            Direction::_X => self.neg_x,
+            Direction::_Y => self.neg_y,
+            Direction::_Z => self.neg_z,
+        }
+    }
+
+    fn weight_mut(&mut self, direction: Direction) -> &mut f32 {
+        match direction {
+            Direction::X => &mut self.x,
+            Direction::Y => &mut self.y,
+            Direction::Z => &mut self.z,
+            Direction::_X => &mut self.neg_x,
+            Direction::_Y => &mut self.neg_y,
+            Direction::_Z => &mut self.neg_z,
+        }
+    }
+
+    /// One of `options` by their weights, or any of them alike when none has a weight
+    pub fn choose(&self, options: &[Direction], rng: &mut StdRng) -> Direction {
+        choose(options, rng, |direction| self.weight(direction))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for direction in Direction::ALL {
+            let weight = self.weight(direction);
+            if !(weight >= 0.0 && weight.is_finite()) {
+                bail!(
+                    "Direction weights must not be negative, got {} for {:?}",
+                    weight,
+                    direction
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One of `options` by `weight`, or any of them alike when none has a weight
+fn choose(options: &[Direction], rng: &mut StdRng, weight: impl Fn(Direction) -> f32) -> Direction {
+    // equal weights draw from the rng like before there were any, so seeds keep growing the same worlds
+    let is_even = options.iter().all(|direction| weight(*direction) == weight(options[0]));
+    if !is_even && let Ok(direction) = options.choose_weighted(rng, |direction| weight(*direction)) {
+        return *direction;
+    }
+    *options.choose(rng).expect("No direction to choose from")
+}
+
+/// Weights like `y=4,neg_y=0.5`, the ones left out weigh 1
+impl FromStr for DirectionWeights {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut weights = Self::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (name, weight) = pair
+                .split_once('=')
+                .with_context(|| format!("Expected a weight like y=4, got {:?}", pair))?;
+            let direction = match name.trim() {
+                "x" => Direction::X,
+                "y" => Direction::Y,
+                "z" => Direction::Z,
+                "neg_x" => Direction::_X,
+                "neg_y" => Direction::_Y,
+                "neg_z" => Direction::_Z,
+                name => bail!("Unknown direction {:?}, expected x, y, z, neg_x, neg_y or neg_z", name),
+            };
+            *weights.weight_mut(direction) = weight
+                .trim()
+                .parse()
+                .with_context(|| format!("Expected a number for the weight of {}, got {:?}", name, weight))?;
+        }
+        weights.validate()?;
+        Ok(weights)
+    }
+}
+
+/// Decides the shape of pipes. Each step a pipe either goes straight on or turns perpendicular to where
+/// it heads, anything else counts as going straight. A pipe blocked in the chosen direction ends there
+/// and a new one starts elsewhere.
+///
+/// Draw any randomness from `rng` only, so that a seeded world always grows the same.
+pub trait PipeGenerator: Send {
+    fn next_direction(&mut self, step: Step, rng: &mut StdRng) -> Direction;
+}
+
+/// Makes a new generator, for every world grown with it
+pub type CreateGenerator = fn() -> Box<dyn PipeGenerator>;
+
+/// The original screensaver's pipes, turning at random
+pub struct Classic;
+
+impl PipeGenerator for Classic {
+    fn next_direction(&mut self, step: Step, rng: &mut StdRng) -> Direction {
+        if rng.random::<f32>() < step.turn_probability {
+            step.random_turn(rng)
+        } else {
+            step.direction
+        }
+    }
+}
+
+/// Pipes turning every few segments, like stairs
+pub struct Zigzag;
+
+impl PipeGenerator for Zigzag {
+    fn next_direction(&mut self, step: Step, rng: &mut StdRng) -> Direction {
+        if step.segment % ZIGZAG_RUN == ZIGZAG_RUN - 1 {
+            step.random_turn(rng)
+        } else {
+            step.direction
+        }
+    }
+}
+
+pub const BUILT_IN: &[(&str, CreateGenerator)] = &[("classic", || Box::new(Classic)), ("zigzag", || Box::new(Zigzag))];
+
+/// A new generator of the given name, built in or registered by a plugin
+pub fn find(name: &str) -> Option<Box<dyn PipeGenerator>> {
+    BUILT_IN
+        .iter()
+        .find(|(built_in, _)| built_in.eq_ignore_ascii_case(name))
+        .map(|(_, create)| create())
+        .or_else(|| plugin::create_generator(name))
+}
+
+pub fn names() -> Vec<String> {
+    BUILT_IN
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .chain(plugin::generator_names())
+        .collect()
+}
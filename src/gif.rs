@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+// the palette has this many levels of red, green and blue, 252 colors in all
+const LEVELS: [u32; 3] = [6, 7, 6];
+// a 4 by 4 Bayer matrix, spreading the error of the coarse palette into a fine pattern rather than bands
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+// bits of the palette's indices, and what the codes of LZW start above
+const MIN_CODE_SIZE: u8 = 8;
+const MAX_CODE_SIZE: u32 = 12;
+
+/// A looping animated GIF, written frame by frame. Frames are reduced to a fixed palette with ordered
+/// dithering, which is quick and looks the same in every frame, rather than picking colors per frame.
+pub struct Gif {
+    bytes: Vec<u8>,
+    width: u16,
+    height: u16,
+    // hundredths of a second every frame shows for
+    delay: u16,
+}
+
+impl Gif {
+    /// An animation `width` by `height` showing every frame for `delay` hundredths of a second
+    pub fn new(width: u16, height: u16, delay: u16) -> Self {
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        // a global palette of 256 colors at 8 bits per channel, no background or aspect
+        bytes.extend([0xf7, 0, 0]);
+        for i in 0..256 {
+            bytes.extend(palette_color(i));
+        }
+        // looping forever, as browsers understand
+        bytes.extend([0x21, 0xff, 0x0b]);
+        bytes.extend(b"NETSCAPE2.0");
+        bytes.extend([0x03, 0x01, 0x00, 0x00, 0x00]);
+        Self {
+            bytes,
+            width,
+            height,
+            delay,
+        }
+    }
+
+    /// Add a frame, of the animation's size
+    pub fn add_frame(&mut self, image: &image::RgbaImage) {
+        debug_assert_eq!(image.dimensions(), (self.width as u32, self.height as u32));
+        // shown for `delay`, and left in place for the next frame
+        self.bytes.extend([0x21, 0xf9, 0x04, 0x04]);
+        self.bytes.extend(self.delay.to_le_bytes());
+        self.bytes.extend([0x00, 0x00]);
+
+        self.bytes.push(0x2c);
+        self.bytes.extend([0, 0, 0, 0]);
+        self.bytes.extend(self.width.to_le_bytes());
+        self.bytes.extend(self.height.to_le_bytes());
+        self.bytes.push(0x00);
+
+        let indices: Vec<u8> = image
+            .enumerate_pixels()
+            .map(|(x, y, pixel)| palette_index(pixel.0, BAYER[y as usize % 4][x as usize % 4]))
+            .collect();
+        self.bytes.push(MIN_CODE_SIZE);
+        // in blocks of at most 255 bytes, each after its length
+        for block in compress(&indices).chunks(255) {
+            self.bytes.push(block.len() as u8);
+            self.bytes.extend(block);
+        }
+        self.bytes.push(0x00);
+    }
+
+    /// The whole file
+    pub fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0x3b);
+        self.bytes
+    }
+}
+
+/// The color at `index` of the palette, black past its colors
+fn palette_color(index: u32) -> [u8; 3] {
+    let [r, g, b] = LEVELS;
+    if index >= r * g * b {
+        return [0; 3];
+    }
+    let level = |i: u32, levels: u32| (i * 255 / (levels - 1)) as u8;
+    [level(index / (g * b), r), level(index / b % g, g), level(index % b, b)]
+}
+
+/// The index of the palette's color nearest to `pixel` once nudged by `threshold`, one of 16 steps
+/// between two of the palette's levels
+fn palette_index(pixel: [u8; 4], threshold: u8) -> u8 {
+    let [r, g, b] = [0, 1, 2].map(|i| {
+        let levels = LEVELS[i];
+        let scaled = pixel[i] as u32 * (levels - 1) * 32 + (threshold as u32 * 2 + 1) * 255;
+        (scaled / (255 * 32)).min(levels - 1)
+    });
+    (r * LEVELS[1] * LEVELS[2] + g * LEVELS[2] + b) as u8
+}
+
+/// Indices of the palette compressed with GIF's LZW, codes growing from 9 to 12 bits and starting over
+/// once the table is full
+fn compress(indices: &[u8]) -> Vec<u8> {
+    let clear = 1u32 << MIN_CODE_SIZE;
+    let end = clear + 1;
+    let mut writer = BitWriter::default();
+    let mut table: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut code_size = MIN_CODE_SIZE as u32 + 1;
+    writer.write(clear, code_size);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        writer.write(end, code_size);
+        return writer.finish();
+    };
+    let mut prefix = first as u32;
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        writer.write(prefix, code_size);
+        if next_code == 1 << MAX_CODE_SIZE {
+            writer.write(clear, code_size);
+            table.clear();
+            next_code = end + 1;
+            code_size = MIN_CODE_SIZE as u32 + 1;
+        } else {
+            table.insert((prefix, index), next_code);
+            next_code += 1;
+            // decoders only widen the codes after reading the next one, which is sure to fit by then
+            if next_code > 1 << code_size && code_size < MAX_CODE_SIZE {
+                code_size += 1;
+            }
+        }
+        prefix = index as u32;
+    }
+    writer.write(prefix, code_size);
+    writer.write(end, code_size);
+    writer.finish()
+}
+
+/// Packs codes into bytes, least significant bits first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u32, size: u32) {
+        self.bits |= code << self.bit_count;
+        self.bit_count += size;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
@@ -0,0 +1,220 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::gpu;
+use crate::texture;
+use crate::world::Direction;
+
+// blocks the axes reach from the origin
+const AXIS_LENGTH: f32 = 5.0;
+// blocks a heading arrow reaches out of the head of its pipe
+const HEADING_LENGTH: f32 = 2.0;
+// share of an arrow its head takes
+const ARROW_HEAD: f32 = 0.25;
+const BOX_COLOR: [f32; 3] = [0.6, 0.6, 0.6];
+const AXIS_COLORS: [[f32; 3]; 3] = [[1.0, 0.2, 0.2], [0.2, 1.0, 0.2], [0.3, 0.5, 1.0]];
+const HEADING_COLOR: [f32; 3] = [1.0, 0.85, 0.2];
+const BUILD_COLOR: [f32; 3] = [0.3, 1.0, 0.9];
+
+/// An end of a line
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GizmoVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl GizmoVertex {
+    fn layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<GizmoVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+/// Lines drawn into the scene to debug with: the world's bounds, its axes as laid out in the doc comment
+/// of `world::World`, where the pipes are heading, and where build mode builds, see `build::BuildTool`
+#[derive(Clone, Debug)]
+pub struct GizmoLines {
+    vertices: Vec<GizmoVertex>,
+    // distance between the centers of neighboring blocks, see `Config::cell_size`
+    cell_size: f32,
+}
+
+impl GizmoLines {
+    /// The box around a world `extents` blocks wide along X, Y and Z with blocks `cell_size` apart, and
+    /// arrows along its axes from the origin
+    pub fn world(extents: [u32; 3], cell_size: f32) -> Self {
+        let mut lines = Self {
+            vertices: vec![],
+            cell_size,
+        };
+        // blocks are centered on whole coordinates, so the world reaches half a block beyond them
+        let [x, y, z] = extents.map(|extent| extent as f32 * cell_size);
+        let min = -0.5 * cell_size;
+        lines.cuboid(Point3::new(min, min, min), Vector3::new(x, y, z), BOX_COLOR);
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        for (direction, color) in [Direction::X, Direction::Y, Direction::Z].into_iter().zip(AXIS_COLORS) {
+            lines.arrow(origin, origin + direction.vector() * AXIS_LENGTH * cell_size, color);
+        }
+        lines
+    }
+
+    /// Add an arrow out of every head of a growing pipe the way it heads
+    pub fn add_headings(&mut self, heads: &[((u32, u32, u32), Direction)]) {
+        for &((x, y, z), direction) in heads {
+            let head = Point3::new(x as f32, y as f32, z as f32) * self.cell_size;
+            let length = HEADING_LENGTH * self.cell_size;
+            self.arrow(head, head + direction.vector() * length, HEADING_COLOR);
+        }
+    }
+
+    /// Add the box around the block a pipe is built in, with an arrow out of it the way the pipe heads
+    pub fn add_build_target(&mut self, (x, y, z): (u32, u32, u32), direction: Direction) {
+        let center = Point3::new(x as f32, y as f32, z as f32) * self.cell_size;
+        let half = Vector3::new(0.5, 0.5, 0.5) * self.cell_size;
+        self.cuboid(center - half, half * 2.0, BUILD_COLOR);
+        self.arrow(center, center + direction.vector() * self.cell_size, BUILD_COLOR);
+    }
+
+    /// The edges of the box from `min` reaching `size` along X, Y and Z
+    fn cuboid(&mut self, min: Point3<f32>, size: Vector3<f32>, color: [f32; 3]) {
+        let corner = |i: u32| {
+            min + Vector3::new(
+                if i & 1 == 0 { 0.0 } else { size.x },
+                if i & 2 == 0 { 0.0 } else { size.y },
+                if i & 4 == 0 { 0.0 } else { size.z },
+            )
+        };
+        // every edge joins two corners which differ along one axis
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.line(corner(i), corner(i | axis), color);
+                }
+            }
+        }
+    }
+
+    fn line(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 3]) {
+        self.vertices.push(GizmoVertex {
+            position: from.into(),
+            color,
+        });
+        self.vertices.push(GizmoVertex {
+            position: to.into(),
+            color,
+        });
+    }
+
+    /// A line with a head of two strokes at `to`
+    fn arrow(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 3]) {
+        self.line(from, to, color);
+        let back = (from - to) * ARROW_HEAD;
+        // any direction across the arrow does, as long as it isn't along it
+        let across = match back.x.abs() > back.y.abs() {
+            true => Vector3::unit_y(),
+            false => Vector3::unit_x(),
+        } * back.magnitude()
+            * 0.5;
+        self.line(to, to + back + across, color);
+        self.line(to, to + back - across, color);
+    }
+}
+
+/// Draws `GizmoLines` as lines over the pipes, which hide those behind them
+pub struct Gizmos {
+    pipeline: wgpu::RenderPipeline,
+    // None while there are no lines
+    vertices: Option<(wgpu::Buffer, u32)>,
+}
+
+impl Gizmos {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        depth: texture::Depth,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GizmoPipelineLayout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = gpu::create_shader_module(device, "GizmoShader", include_str!("gizmos.wgsl"))?;
+        let pipeline = gpu::validated(device, "GizmoPipeline", || {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("GizmoPipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[GizmoVertex::layout()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: depth.format,
+                    depth_write_enabled: false,
+                    depth_compare: depth.compare(wgpu::CompareFunction::Less),
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            })
+        })?;
+        Ok(Self {
+            pipeline,
+            vertices: None,
+        })
+    }
+
+    /// Draw `lines` from now on, or nothing for `None`
+    pub fn set_lines(&mut self, device: &wgpu::Device, lines: Option<&GizmoLines>) {
+        self.vertices = lines.filter(|lines| !lines.vertices.is_empty()).map(|lines| {
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("GizmoVertexBuffer"),
+                contents: bytemuck::cast_slice(&lines.vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+            (buffer, lines.vertices.len() as u32)
+        });
+    }
+
+    /// Draw the lines, if there are any, with the camera already bound
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        if let Some((buffer, count)) = &self.vertices {
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_vertex_buffer(0, buffer.slice(..));
+            render_pass.draw(0..*count, 0..1);
+        }
+    }
+}
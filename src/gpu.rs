@@ -0,0 +1,170 @@
+use std::ops::Deref;
+
+use anyhow::bail;
+use wgpu::util::DeviceExt;
+
+/// Compile the WGSL `source`, failing with every error and the lines of source it points at rather than
+/// panicking once a broken shader is used
+pub fn create_shader_module(device: &wgpu::Device, label: &str, source: &str) -> anyhow::Result<wgpu::ShaderModule> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    let error = pollster::block_on(device.pop_error_scope());
+    let info = pollster::block_on(module.get_compilation_info());
+    let messages: Vec<_> = info
+        .messages
+        .iter()
+        .filter(|message| message.message_type == wgpu::CompilationMessageType::Error)
+        .map(|message| message.message.trim())
+        .collect();
+    if !messages.is_empty() {
+        bail!("Shader {} doesn't compile:\n{}", label, messages.join("\n"));
+    }
+    if let Some(error) = error {
+        bail!("Shader {} is invalid: {}", label, error);
+    }
+    Ok(module)
+}
+
+/// Create something with `create`, e.g. a pipeline, failing with what wgpu found wrong with it, like an entry
+/// point its shader doesn't have, rather than panicking. `what` names it in the error.
+pub fn validated<T>(device: &wgpu::Device, what: &str, create: impl FnOnce() -> T) -> anyhow::Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let created = create();
+    if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+        bail!("Cannot create {}: {}", what, error);
+    }
+    Ok(created)
+}
+
+/// Layout of a bind group with a single uniform buffer at binding 0, which vertex and fragment shaders
+/// both see. Every `UniformBuffer` bound in the same slot shares one.
+pub fn uniform_layout(device: &wgpu::Device, label: &str) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(&format!("{}BindGroupLayout", label)),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+/// A value shaders read as a uniform, kept on the CPU as well so that it can be changed a field at a time.
+/// Derefs to the value, changes go through `update` to reach the GPU.
+pub struct UniformBuffer<T> {
+    value: T,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl<T: bytemuck::Pod> UniformBuffer<T> {
+    /// A buffer holding `value`, bound through a layout made by `uniform_layout`. `label` names the buffer
+    /// and bind group in GPU debuggers.
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, label: &str, value: T) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{}Buffer", label)),
+            contents: bytemuck::bytes_of(&value),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{}BindGroup", label)),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            value,
+            buffer,
+            bind_group,
+        }
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Change the value with `change`, then write all of it to the GPU
+    pub fn update(&mut self, uploader: &mut Uploader, change: impl FnOnce(&mut T)) {
+        change(&mut self.value);
+        uploader.write(&self.buffer, 0, bytemuck::bytes_of(&self.value));
+    }
+}
+
+impl<T> Deref for UniformBuffer<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+/// Writes to buffers between frames, staged in buffers a `StagingBelt` reuses frame after frame rather than
+/// in the fresh staging memory every `Queue::write_buffer` takes. The copies into place are recorded into
+/// a command buffer of their own, which `finish` hands over to go first in the frame's submission.
+pub struct Uploader {
+    device: wgpu::Device,
+    belt: wgpu::util::StagingBelt,
+    // None until something is written after the last `finish`
+    encoder: Option<wgpu::CommandEncoder>,
+    bytes: u64,
+    // bytes written before the last `finish`, see `last_frame_bytes`
+    last_frame_bytes: u64,
+}
+
+impl Uploader {
+    /// Staging in chunks of `chunk_size` bytes, writes larger than that get a chunk of their own
+    pub fn new(device: &wgpu::Device, chunk_size: wgpu::BufferAddress) -> Self {
+        Self {
+            device: device.clone(),
+            belt: wgpu::util::StagingBelt::new(chunk_size),
+            encoder: None,
+            bytes: 0,
+            last_frame_bytes: 0,
+        }
+    }
+
+    /// Write `data` to `buffer` from `offset` on, once the command buffer from the next `finish` runs
+    pub fn write(&mut self, buffer: &wgpu::Buffer, offset: wgpu::BufferAddress, data: &[u8]) {
+        let Some(size) = wgpu::BufferSize::new(data.len() as u64) else {
+            return;
+        };
+        let encoder = self.encoder.get_or_insert_with(|| {
+            self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("UploadEncoder"),
+            })
+        });
+        self.belt
+            .write_buffer(encoder, buffer, offset, size, &self.device)
+            .copy_from_slice(data);
+        self.bytes += size.get();
+    }
+
+    /// The copies of everything written since the last call, to submit before anything reading the buffers,
+    /// `None` if nothing was. `recall` must follow the submission.
+    pub fn finish(&mut self) -> Option<wgpu::CommandBuffer> {
+        self.last_frame_bytes = std::mem::take(&mut self.bytes);
+        let encoder = self.encoder.take()?;
+        self.belt.finish();
+        Some(encoder.finish())
+    }
+
+    /// Have the staging buffers back for reuse once the GPU copied out of them
+    pub fn recall(&mut self) {
+        self.belt.recall();
+    }
+
+    /// Bytes written between the last two `finish` calls, i.e. in the last frame
+    pub fn last_frame_bytes(&self) -> u64 {
+        self.last_frame_bytes
+    }
+}
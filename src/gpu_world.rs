@@ -0,0 +1,367 @@
+use wgpu::util::DeviceExt;
+
+use crate::gpu;
+use crate::instance::InstanceRaw;
+use crate::material::Materials;
+use crate::models;
+use crate::palette::Palette;
+
+/// The most blocks along each side of a world grown on the GPU, as far as its occupancy grid stays small
+pub const MAX_SIZE: u32 = 255;
+// pipes growing at once, a multiple of the workgroup size in grow.wgsl
+const WALKERS: u32 = 64;
+const WORKGROUP_SIZE: u32 = 64;
+const GROWTH_SPEED: f32 = 4000.0; // segments per second
+// steps per dispatch, so that a long frame doesn't stall the GPU catching up
+const MAX_STEPS: u32 = 16;
+// instances each of the straight pipe and elbow buffers hold at most
+const MAX_INSTANCES: u32 = 1 << 17;
+// byte offsets into the draw arguments, see `finish` in grow.wgsl
+const I_DRAW_OFFSET: wgpu::BufferAddress = 0;
+const L_DRAW_OFFSET: wgpu::BufferAddress = 20;
+const I_SHADOW_OFFSET: wgpu::BufferAddress = 40;
+const L_SHADOW_OFFSET: wgpu::BufferAddress = 56;
+const DRAW_ARGS_SIZE: wgpu::BufferAddress = 72;
+
+/// What the walkers grow, see `Params` in grow.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GrowParams {
+    size: [u32; 3],
+    steps: u32,
+    turn_probability: f32,
+    opacity: f32,
+    seconds: f32,
+    palette_len: u32,
+    glow_chance: f32,
+    glow: f32,
+    capacity: u32,
+    seed: u32,
+    i_index_count: u32,
+    l_index_count: u32,
+    material: u32,
+    accent_material: u32,
+    accent_chance: f32,
+    // segments after which a pipe is capped, 0 for no limit
+    max_pipe_length: u32,
+    cell_size: f32,
+    _padding: u32,
+}
+
+/// A world grown by random walks in a compute shader, which writes the instances straight into the buffers
+/// they are drawn from. Nothing goes through the CPU, so it grows thousands of segments a second, but it
+/// can't be sorted, saved or shared: walkers race each other for blocks, so no seed grows it again.
+pub struct GpuWorld {
+    params: GrowParams,
+    params_buffer: wgpu::Buffer,
+    walker_buffer: wgpu::Buffer,
+    occupancy_buffer: wgpu::Buffer,
+    i_buffer: wgpu::Buffer,
+    l_buffer: wgpu::Buffer,
+    count_buffer: wgpu::Buffer,
+    draw_args_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    grow_pipeline: wgpu::ComputePipeline,
+    finish_pipeline: wgpu::ComputePipeline,
+    // segments to grow which didn't make a whole step of every walker yet
+    growth_accumulator: f32,
+    // steps every walker may have taken since the last reset
+    steps_since_reset: u32,
+    // steps after which the world may be full, and starts over
+    steps_until_full: u32,
+    is_reset_pending: bool,
+    // the draw arguments lag behind the counts or meshes
+    is_finish_pending: bool,
+}
+
+impl GpuWorld {
+    /// Whether `adapter` can grow worlds on the GPU at all, which needs compute shaders and indirect draws
+    pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+        Self::is_supported_by(&adapter.get_downlevel_capabilities())
+    }
+
+    /// Like `is_supported`, for an adapter's downlevel capabilities
+    pub fn is_supported_by(downlevel: &wgpu::DownlevelCapabilities) -> bool {
+        downlevel
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION)
+    }
+
+    /// An empty world `size` blocks wide, up to `MAX_SIZE`, painted with `palette`
+    pub fn new(
+        device: &wgpu::Device,
+        size: u32,
+        palette: &Palette,
+        opacity: f32,
+        materials: Materials,
+        turn_probability: f32,
+        max_pipe_length: Option<u32>,
+    ) -> anyhow::Result<Self> {
+        // blocks go from 0 up to and including the size, like in `World`
+        let side = size.min(MAX_SIZE) + 1;
+        let blocks = side * side * side;
+        let capacity = (blocks / 2).clamp(1, MAX_INSTANCES);
+        let params = GrowParams {
+            size: [side; 3],
+            steps: 0,
+            turn_probability,
+            opacity,
+            seconds: 0.0,
+            palette_len: palette.colors.len() as u32,
+            glow_chance: palette.glow_chance,
+            glow: palette.glow,
+            capacity,
+            seed: rand::random(),
+            i_index_count: 0,
+            l_index_count: 0,
+            material: materials.material.index(),
+            accent_material: materials.accent.index(),
+            accent_chance: materials.accent_chance,
+            max_pipe_length: max_pipe_length.unwrap_or(0),
+            cell_size: 1.0,
+            _padding: 0,
+        };
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GrowParamsBuffer"),
+            size: size_of::<GrowParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let colors: Vec<[f32; 4]> = palette.colors.iter().map(|[r, g, b]| [*r, *g, *b, 1.0]).collect();
+        let palette_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GrowPaletteBuffer"),
+            contents: bytemuck::cast_slice(&colors),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let storage = |label: &str, size: wgpu::BufferAddress, usage: wgpu::BufferUsages| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | usage,
+                mapped_at_creation: false,
+            })
+        };
+        // 12 words per walker, see `Walker` in grow.wgsl
+        let walker_buffer = storage(
+            "WalkerBuffer",
+            (WALKERS * 48) as wgpu::BufferAddress,
+            wgpu::BufferUsages::empty(),
+        );
+        let occupancy_buffer = storage(
+            "OccupancyBuffer",
+            (blocks.div_ceil(32) * 4) as wgpu::BufferAddress,
+            wgpu::BufferUsages::empty(),
+        );
+        let instances_size = (capacity as usize * size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+        let i_buffer = storage("GpuInstanceIBuffer", instances_size, wgpu::BufferUsages::VERTEX);
+        let l_buffer = storage("GpuInstanceLBuffer", instances_size, wgpu::BufferUsages::VERTEX);
+        let count_buffer = storage("GrowCountBuffer", 8, wgpu::BufferUsages::empty());
+        let draw_args_buffer = storage("GrowDrawArgsBuffer", DRAW_ARGS_SIZE, wgpu::BufferUsages::INDIRECT);
+
+        let storage_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GrowBindGroupLayout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+                storage_entry(3, false),
+                storage_entry(4, false),
+                storage_entry(5, false),
+                storage_entry(6, false),
+                storage_entry(7, false),
+            ],
+        });
+        let buffers = [
+            &params_buffer,
+            &palette_buffer,
+            &walker_buffer,
+            &occupancy_buffer,
+            &i_buffer,
+            &l_buffer,
+            &count_buffer,
+            &draw_args_buffer,
+        ];
+        let entries: Vec<_> = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GrowBindGroup"),
+            layout: &bind_group_layout,
+            entries: &entries,
+        });
+
+        let shader = gpu::create_shader_module(device, "GrowShader", include_str!("grow.wgsl"))?;
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GrowPipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label: &str, entry_point: &str| {
+            gpu::validated(device, label, || {
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(label),
+                    layout: Some(&layout),
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    compilation_options: Default::default(),
+                    cache: None,
+                })
+            })
+        };
+
+        Ok(Self {
+            params,
+            params_buffer,
+            walker_buffer,
+            occupancy_buffer,
+            i_buffer,
+            l_buffer,
+            count_buffer,
+            draw_args_buffer,
+            bind_group,
+            grow_pipeline: pipeline("GrowPipeline", "grow")?,
+            finish_pipeline: pipeline("GrowFinishPipeline", "finish")?,
+            growth_accumulator: 0.0,
+            steps_since_reset: 0,
+            // half the world's blocks, or as many as the buffers hold
+            steps_until_full: capacity / WALKERS,
+            is_reset_pending: true,
+            is_finish_pending: true,
+        })
+    }
+
+    /// Start over with an empty world on the next dispatch
+    pub fn reset(&mut self) {
+        self.is_reset_pending = true;
+    }
+
+    /// Grow for `dt` more seconds on the next dispatch
+    pub fn grow(&mut self, dt: f32) {
+        self.growth_accumulator += dt * GROWTH_SPEED / WALKERS as f32;
+    }
+
+    /// Space the blocks grown from now on `cell_size` apart in the scene, see `Config::cell_size`
+    pub fn set_cell_size(&mut self, cell_size: f32) {
+        self.params.cell_size = cell_size;
+    }
+
+    /// Draw with other meshes from now on, their index counts go into the draw arguments
+    pub fn set_meshes(&mut self, i_model: &models::Model, l_model: &models::Model) {
+        self.params.i_index_count = i_model.meshes[0].num_elements;
+        self.params.l_index_count = l_model.meshes[0].num_elements;
+        self.is_finish_pending = true;
+    }
+
+    /// Record whatever growth is due into `encoder`, ahead of the passes drawing the world, with its
+    /// parameters written through `uploader`. Instances grown now are born at `seconds`.
+    pub fn dispatch(&mut self, uploader: &mut gpu::Uploader, encoder: &mut wgpu::CommandEncoder, seconds: f32) {
+        if self.steps_since_reset >= self.steps_until_full {
+            self.is_reset_pending = true;
+        }
+        if self.is_reset_pending {
+            encoder.clear_buffer(&self.walker_buffer, 0, None);
+            encoder.clear_buffer(&self.occupancy_buffer, 0, None);
+            encoder.clear_buffer(&self.count_buffer, 0, None);
+            self.params.seed = rand::random();
+            self.steps_since_reset = 0;
+            self.is_reset_pending = false;
+            self.is_finish_pending = true;
+        }
+        let steps = (self.growth_accumulator as u32).min(MAX_STEPS);
+        self.growth_accumulator = self.growth_accumulator.fract();
+        if steps == 0 && !self.is_finish_pending {
+            return;
+        }
+        self.params.steps = steps;
+        self.params.seconds = seconds;
+        uploader.write(&self.params_buffer, 0, bytemuck::cast_slice(&[self.params]));
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("GrowPass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        if steps > 0 {
+            compute_pass.set_pipeline(&self.grow_pipeline);
+            compute_pass.dispatch_workgroups(WALKERS.div_ceil(WORKGROUP_SIZE), 1, 1);
+            self.steps_since_reset += steps;
+        }
+        compute_pass.set_pipeline(&self.finish_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+        self.is_finish_pending = false;
+    }
+
+    /// The buffers of the straight pipes and of the elbows, which hold `InstanceRaw`s
+    pub fn instance_buffers(&self) -> [&wgpu::Buffer; 2] {
+        [&self.i_buffer, &self.l_buffer]
+    }
+
+    /// Draw every pipe with whatever pipeline is set, from the arguments the last dispatch left. Pipelines
+    /// reading instances from storage get them through `storage_bind_groups`, bound in group 3 for the
+    /// straight pipes and the elbows each.
+    pub fn draw(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        i_model: &models::Model,
+        l_model: &models::Model,
+        storage_bind_groups: Option<&[wgpu::BindGroup; 2]>,
+    ) {
+        let meshes = [
+            (
+                l_model,
+                &self.l_buffer,
+                storage_bind_groups.map(|[_, l]| l),
+                L_DRAW_OFFSET,
+            ),
+            (
+                i_model,
+                &self.i_buffer,
+                storage_bind_groups.map(|[i, _]| i),
+                I_DRAW_OFFSET,
+            ),
+        ];
+        for (model, buffer, bind_group, offset) in meshes {
+            let pipe_mesh = &model.meshes[0];
+            render_pass.set_vertex_buffer(0, pipe_mesh.vertex_buffer.slice(..));
+            match bind_group {
+                Some(bind_group) => render_pass.set_bind_group(3, bind_group, &[]),
+                None => render_pass.set_vertex_buffer(1, buffer.slice(..)),
+            }
+            render_pass.set_index_buffer(pipe_mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed_indirect(&self.draw_args_buffer, offset);
+        }
+    }
+
+    /// Draw the shadow of every pipe with the shadow pipeline
+    pub fn draw_shadows(&self, render_pass: &mut wgpu::RenderPass) {
+        for (buffer, offset) in [(&self.l_buffer, L_SHADOW_OFFSET), (&self.i_buffer, I_SHADOW_OFFSET)] {
+            render_pass.set_vertex_buffer(0, buffer.slice(..));
+            render_pass.draw_indirect(&self.draw_args_buffer, offset);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::world::{Block, PipeType};
+
+/// What a pipe graph is written as
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    /// GraphViz DOT, to lay out or render with `dot` and friends
+    #[default]
+    Dot,
+    /// Plain JSON with a list of nodes and one of edges
+    Json,
+}
+
+impl GraphFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            GraphFormat::Dot => "dot",
+            GraphFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    /// Where a pipe starts or stops
+    End,
+    /// Where a pipe turns
+    Joint,
+}
+
+impl NodeKind {
+    fn name(self) -> &'static str {
+        match self {
+            NodeKind::End => "end",
+            NodeKind::Joint => "joint",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub position: (u32, u32, u32),
+    /// Index of the pipe, in the order pipes started
+    pub pipe: usize,
+}
+
+/// A straight run of a pipe between two of its nodes
+#[derive(Copy, Clone, Debug)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    /// Blocks from one node to the other
+    pub length: u32,
+    pub color: [f32; 3],
+}
+
+/// The topology of the pipes, with their ends and turns as nodes and the straight runs between them as
+/// edges, to analyze or render elsewhere
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    /// The graph of blocks in the order they grew, see `World::blocks`, without the erased ones
+    pub fn from_blocks(blocks: &[Block]) -> Self {
+        let mut graph = Self::default();
+        // the last node and block of every pipe, and the pipe growing on at each block next to a pipe's end
+        let mut pipes: Vec<(usize, Block)> = vec![];
+        let mut heads: HashMap<(u32, u32, u32), usize> = HashMap::new();
+        for block in blocks.iter().filter(|block| !block.is_erased()) {
+            let position = block.position();
+            // teams grow several pipes at once, so the block after one isn't necessarily of the same pipe
+            let pipe = match heads.remove(&position) {
+                Some(pipe) if block.segment() > 0 => pipe,
+                _ => {
+                    let node = graph.add_node(NodeKind::End, position, pipes.len());
+                    pipes.push((node, *block));
+                    if !block.is_capped() {
+                        heads.insert(block.direction().neighbor(position), pipes.len() - 1);
+                    }
+                    continue;
+                }
+            };
+            if let PipeType::L = block.pipe_type() {
+                graph.close_run(&mut pipes[pipe], NodeKind::Joint, block);
+            }
+            pipes[pipe].1 = *block;
+            if !block.is_capped() {
+                heads.insert(block.direction().neighbor(position), pipe);
+            }
+        }
+        for pipe in &mut pipes {
+            let (node, last_block) = *pipe;
+            if graph.nodes[node].position != last_block.position() {
+                graph.close_run(pipe, NodeKind::End, &last_block);
+            }
+        }
+        graph
+    }
+
+    fn add_node(&mut self, kind: NodeKind, position: (u32, u32, u32), pipe: usize) -> usize {
+        self.nodes.push(Node { kind, position, pipe });
+        self.nodes.len() - 1
+    }
+
+    /// End the straight run of a pipe at `block` with a node, and an edge from where the run started
+    fn close_run(&mut self, pipe: &mut (usize, Block), kind: NodeKind, block: &Block) {
+        let from = pipe.0;
+        let to = self.add_node(kind, block.position(), self.nodes[from].pipe);
+        let (a, b) = (self.nodes[from].position, block.position());
+        let length = a.0.abs_diff(b.0) + a.1.abs_diff(b.1) + a.2.abs_diff(b.2);
+        self.edges.push(Edge {
+            from,
+            to,
+            length,
+            color: block.color(),
+        });
+        pipe.0 = to;
+    }
+
+    pub fn write(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Json => self.to_json(),
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = "graph xpipe {\n    node [shape=point];\n".to_string();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let (x, y, z) = node.position;
+            let _ = writeln!(
+                dot,
+                "    n{} [kind={}, pipe={}, pos=\"{},{},{}\"];",
+                i,
+                node.kind.name(),
+                node.pipe,
+                x,
+                y,
+                z
+            );
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                dot,
+                "    n{} -- n{} [len={}, color=\"{}\"];",
+                edge.from,
+                edge.to,
+                edge.length,
+                hex_color(edge.color)
+            );
+        }
+        dot + "}\n"
+    }
+
+    fn to_json(&self) -> String {
+        let nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let (x, y, z) = node.position;
+                format!(
+                    "{{\"kind\":\"{}\",\"pipe\":{},\"position\":[{},{},{}]}}",
+                    node.kind.name(),
+                    node.pipe,
+                    x,
+                    y,
+                    z
+                )
+            })
+            .collect();
+        let edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                format!(
+                    "{{\"from\":{},\"to\":{},\"length\":{},\"color\":\"{}\"}}",
+                    edge.from,
+                    edge.to,
+                    edge.length,
+                    hex_color(edge.color)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"nodes\":[\n{}\n],\"edges\":[\n{}\n]}}\n",
+            nodes.join(",\n"),
+            edges.join(",\n")
+        )
+    }
+}
+
+/// A color as `#rrggbb`
+fn hex_color(color: [f32; 3]) -> String {
+    let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NodeKind::{End, Joint};
+    use super::*;
+    use crate::layout::Layout;
+    use crate::world::World;
+
+    #[test]
+    fn ends_and_turns_of_a_layout_make_the_graph() {
+        let layout: Layout = "0,0,2: +X*3, +Y, -Z*2\n5,0,5: +Y*3".parse().unwrap();
+        let mut world = World::with_seed(0);
+        world.set_layout(Some(&layout)).unwrap();
+        while world.add_pipe() {}
+
+        let graph = Graph::from_blocks(world.blocks());
+        let kinds: Vec<_> = graph.nodes.iter().map(|node| node.kind).collect();
+        assert_eq!(kinds, [End, Joint, Joint, End, End, End]);
+        let lengths: Vec<_> = graph.edges.iter().map(|edge| edge.length).collect();
+        assert_eq!(lengths, [3, 1, 2, 3]);
+
+        let dot = graph.write(GraphFormat::Dot);
+        assert_eq!(dot.matches(" [kind=").count(), 6);
+        assert_eq!(dot.matches(" -- ").count(), 4);
+        let json = graph.write(GraphFormat::Json);
+        assert_eq!(json.matches("\"kind\":").count(), 6);
+        assert_eq!(json.matches("\"from\":").count(), 4);
+    }
+}
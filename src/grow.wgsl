@@ -0,0 +1,276 @@
+// Random-walk pipes grown on the GPU, one walker per invocation, written straight into the instance
+// buffers the pipes are drawn from. See `gpu_world::GpuWorld`.
+
+struct Params {
+    // blocks along each axis
+    size: vec3<u32>,
+    // steps every walker takes in this dispatch
+    steps: u32,
+    turn_probability: f32,
+    opacity: f32,
+    // renderer time, the birth of everything grown now
+    seconds: f32,
+    palette_len: u32,
+    glow_chance: f32,
+    glow: f32,
+    // instances each of the straight pipe and elbow buffers hold
+    capacity: u32,
+    seed: u32,
+    // indices of the straight pipe and elbow meshes, for the draw arguments
+    i_index_count: u32,
+    l_index_count: u32,
+    // what new pipes are made of, and the chance of one to be made of the accent instead
+    material: u32,
+    accent_material: u32,
+    accent_chance: f32,
+    // segments after which a pipe is capped, 0 for no limit
+    max_pipe_length: u32,
+    // distance between the centers of neighboring blocks in the scene
+    cell_size: f32,
+    _padding: u32,
+}
+
+// The head of a growing pipe
+struct Walker {
+    position: vec3<i32>,
+    // 0 to 2 along +X, +Y and +Z, 3 to 5 along -X, -Y and -Z
+    direction: u32,
+    color: vec3<f32>,
+    segment: u32,
+    rng: u32,
+    // 0 until it found a free block to start a pipe at, and again once it ran into something
+    is_alive: u32,
+    emissive: f32,
+    material: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> palette: array<vec4<f32>>;
+@group(0) @binding(2)
+var<storage, read_write> walkers: array<Walker>;
+// one bit per block, set once a pipe took it
+@group(0) @binding(3)
+var<storage, read_write> occupancy: array<atomic<u32>>;
+// laid out like `InstanceRaw`
+@group(0) @binding(4)
+var<storage, read_write> i_instances: array<f32>;
+@group(0) @binding(5)
+var<storage, read_write> l_instances: array<f32>;
+// straight pipes and elbows grown so far, which may run past the capacity
+@group(0) @binding(6)
+var<storage, read_write> counts: array<atomic<u32>, 2>;
+// indexed draws of the straight pipes and elbows, then draws of their shadows
+@group(0) @binding(7)
+var<storage, read_write> draw_args: array<u32, 18>;
+
+const FLOATS_PER_INSTANCE: u32 = 34u;
+const SPAWN_ATTEMPTS: u32 = 8u;
+// End caps are straight pipes squashed into a flange at the very end of the last block, like in `World`
+const CAP_SCALE: vec3<f32> = vec3<f32>(1.25, 0.2, 1.25);
+const CAP_OFFSET: f32 = 0.4;
+
+// PCG hash, good enough for where pipes go
+fn random(state: ptr<function, u32>) -> u32 {
+    let s = *state * 747796405u + 2891336453u;
+    *state = s;
+    let word = ((s >> ((s >> 28u) + 4u)) ^ s) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// From 0 to 1, excluding 1
+fn random_float(state: ptr<function, u32>) -> f32 {
+    return f32(random(state) >> 8u) / 16777216.0;
+}
+
+fn direction_vector(direction: u32) -> vec3<i32> {
+    var vector = vec3<i32>(0);
+    vector[direction % 3u] = select(-1, 1, direction < 3u);
+    return vector;
+}
+
+// Either way along either of the other two axes
+fn random_perpendicular(direction: u32, rng: ptr<function, u32>) -> u32 {
+    let axis = (direction + 1u + random(rng) % 2u) % 3u;
+    return axis + select(0u, 3u, random(rng) % 2u == 1u);
+}
+
+// Take the block at `position` if it is in the world and free, whichever walker gets there first
+fn claim(position: vec3<i32>) -> bool {
+    if any(position < vec3<i32>(0)) || any(position >= vec3<i32>(params.size)) {
+        return false;
+    }
+    let p = vec3<u32>(position);
+    let block = (p.z * params.size.y + p.y) * params.size.x + p.x;
+    let bit = 1u << (block % 32u);
+    return (atomicOr(&occupancy[block / 32u], bit) & bit) == 0u;
+}
+
+// Straight pipe meshes run along their local Y axis, turned onto the direction's axis
+fn straight_rotation(direction: u32) -> mat3x3<f32> {
+    switch direction % 3u {
+        case 0u: {
+            return mat3x3<f32>(vec3<f32>(0.0, -1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 0.0, 1.0));
+        }
+        case 2u: {
+            return mat3x3<f32>(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(0.0, -1.0, 0.0));
+        }
+        default: {
+            return mat3x3<f32>(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 0.0, 1.0));
+        }
+    }
+}
+
+// Elbow meshes open towards their local +Y and +X, turned to face back where the pipe came from and on
+// where it goes
+fn elbow_rotation(from_direction: u32, to_direction: u32) -> mat3x3<f32> {
+    let x = vec3<f32>(direction_vector(to_direction));
+    let y = -vec3<f32>(direction_vector(from_direction));
+    return mat3x3<f32>(x, y, cross(x, y));
+}
+
+fn store(is_elbow: bool, index: u32, value: f32) {
+    if is_elbow {
+        l_instances[index] = value;
+    } else {
+        i_instances[index] = value;
+    }
+}
+
+fn store_vec3(is_elbow: bool, index: u32, value: vec3<f32>) {
+    store(is_elbow, index, value.x);
+    store(is_elbow, index + 1u, value.y);
+    store(is_elbow, index + 2u, value.z);
+}
+
+// Append an instance of the walker's block, dropped when the buffer is full
+fn append(is_elbow: bool, walker: Walker, rotation: mat3x3<f32>, flow_axis: f32) {
+    append_scaled(is_elbow, walker, rotation, vec3<f32>(1.0), vec3<f32>(0.0), flow_axis);
+}
+
+// Append an instance scaled along the mesh's local axes and moved by `offset` from the walker's block
+fn append_scaled(
+    is_elbow: bool,
+    walker: Walker,
+    rotation: mat3x3<f32>,
+    scale: vec3<f32>,
+    offset: vec3<f32>,
+    flow_axis: f32,
+) {
+    let slot = atomicAdd(&counts[select(0u, 1u, is_elbow)], 1u);
+    if slot >= params.capacity {
+        return;
+    }
+    let base = slot * FLOATS_PER_INSTANCE;
+    // model matrix
+    for (var column = 0u; column < 3u; column++) {
+        store_vec3(is_elbow, base + column * 4u, rotation[column] * scale[column] * params.cell_size);
+        store(is_elbow, base + column * 4u + 3u, 0.0);
+    }
+    store_vec3(is_elbow, base + 12u, (vec3<f32>(walker.position) + offset) * params.cell_size);
+    store(is_elbow, base + 15u, 1.0);
+    // normal matrix, the inverse transpose of the model's rotation and scale
+    for (var column = 0u; column < 3u; column++) {
+        store_vec3(is_elbow, base + 16u + column * 3u, rotation[column] / (scale[column] * params.cell_size));
+    }
+    store_vec3(is_elbow, base + 25u, walker.color);
+    store(is_elbow, base + 28u, params.opacity);
+    store(is_elbow, base + 29u, walker.emissive);
+    store(is_elbow, base + 30u, f32(walker.segment));
+    store(is_elbow, base + 31u, flow_axis);
+    store(is_elbow, base + 32u, params.seconds);
+    store(is_elbow, base + 33u, bitcast<f32>(walker.material));
+}
+
+// Close the walker's pipe with a cap once it is as long as pipes may grow, and have it start a new one
+fn cap_if_full_length(walker: ptr<function, Walker>) {
+    if params.max_pipe_length == 0u || (*walker).segment + 1u < params.max_pipe_length {
+        return;
+    }
+    let offset = vec3<f32>(direction_vector((*walker).direction)) * CAP_OFFSET;
+    append_scaled(false, *walker, straight_rotation((*walker).direction), CAP_SCALE, offset, 0.0);
+    (*walker).is_alive = 0u;
+}
+
+// Start a new pipe in the lower half of the world, like `World` does
+fn spawn(walker: ptr<function, Walker>) {
+    let half = max(params.size / 2u, vec3<u32>(1u));
+    for (var attempt = 0u; attempt < SPAWN_ATTEMPTS; attempt++) {
+        let position = vec3<i32>(
+            i32(random(&(*walker).rng) % half.x),
+            i32(random(&(*walker).rng) % half.y),
+            i32(random(&(*walker).rng) % half.z),
+        );
+        if !claim(position) {
+            continue;
+        }
+        (*walker).position = position;
+        (*walker).direction = random(&(*walker).rng) % 6u;
+        (*walker).color = palette[random(&(*walker).rng) % params.palette_len].rgb;
+        (*walker).emissive = select(0.0, params.glow, random_float(&(*walker).rng) < params.glow_chance);
+        let is_accent = random_float(&(*walker).rng) < params.accent_chance;
+        (*walker).material = select(params.material, params.accent_material, is_accent);
+        (*walker).segment = 0u;
+        (*walker).is_alive = 1u;
+        append(false, *walker, straight_rotation((*walker).direction), select(-1.0, 1.0, (*walker).direction < 3u));
+        cap_if_full_length(walker);
+        return;
+    }
+}
+
+@compute @workgroup_size(64)
+fn grow(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= arrayLength(&walkers) {
+        return;
+    }
+    var walker = walkers[id.x];
+    if walker.rng == 0u {
+        // cleared since the last dispatch, every walker goes its own way
+        walker.rng = (params.seed ^ (id.x * 2654435769u)) | 1u;
+    }
+    for (var step = 0u; step < params.steps; step++) {
+        if walker.is_alive == 0u {
+            spawn(&walker);
+            continue;
+        }
+        let position = walker.position + direction_vector(walker.direction);
+        if !claim(position) {
+            walker.is_alive = 0u;
+            continue;
+        }
+        walker.position = position;
+        walker.segment += 1u;
+        if random_float(&walker.rng) < params.turn_probability {
+            let direction = random_perpendicular(walker.direction, &walker.rng);
+            append(true, walker, elbow_rotation(walker.direction, direction), 0.0);
+            walker.direction = direction;
+        } else {
+            let flow_axis = select(-1.0, 1.0, walker.direction < 3u);
+            append(false, walker, straight_rotation(walker.direction), flow_axis);
+        }
+        cap_if_full_length(&walker);
+    }
+    walkers[id.x] = walker;
+}
+
+// Draw as many instances as were grown, up to what the buffers hold
+@compute @workgroup_size(1)
+fn finish() {
+    let i_count = min(atomicLoad(&counts[0]), params.capacity);
+    let l_count = min(atomicLoad(&counts[1]), params.capacity);
+    var indexed = array<vec2<u32>, 2>(vec2<u32>(params.i_index_count, i_count), vec2<u32>(params.l_index_count, l_count));
+    for (var i = 0u; i < 2u; i++) {
+        // index count, instance count, first index, base vertex and first instance
+        draw_args[i * 5u] = indexed[i].x;
+        draw_args[i * 5u + 1u] = indexed[i].y;
+        draw_args[i * 5u + 2u] = 0u;
+        draw_args[i * 5u + 3u] = 0u;
+        draw_args[i * 5u + 4u] = 0u;
+        // vertex count, instance count, first vertex and first instance of a shadow quad each
+        draw_args[10u + i * 4u] = 6u;
+        draw_args[10u + i * 4u + 1u] = indexed[i].y;
+        draw_args[10u + i * 4u + 2u] = 0u;
+        draw_args[10u + i * 4u + 3u] = 0u;
+    }
+}
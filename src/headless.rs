@@ -0,0 +1,370 @@
+use anyhow::{Context, bail};
+use log::warn;
+
+use crate::blit;
+use crate::camera::Camera;
+use crate::chunks::{Bounds, Chunks};
+use crate::clock;
+pub use crate::config::{Antialiasing, PipeStyle, Shading};
+pub use crate::generator::DirectionWeights;
+use crate::gizmos::GizmoLines;
+use crate::instance::Instance;
+pub use crate::light::{LightOrbit, OrbitPath};
+pub use crate::material::{Material, Materials};
+use crate::palette::{self, Palette};
+pub use crate::picking::{SegmentKind, Selection};
+use crate::renderer::{CLEAR_COLOR, Renderer};
+pub use crate::texture::Depth;
+pub use crate::tubes::Tessellation;
+use crate::world::{self, World};
+
+const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Renders into an offscreen texture instead of a window, for tests and image exports
+pub struct HeadlessRenderer {
+    renderer: Renderer,
+    width: u32,
+    height: u32,
+    camera: Camera,
+    palette: &'static Palette,
+    glass_opacity: Option<f32>,
+    materials: Materials,
+    max_pipe_length: Option<u32>,
+    direction_weights: DirectionWeights,
+    clock_time: Option<(u8, u8)>,
+    has_gizmos: bool,
+    // distance between the centers of neighboring blocks, see `Config::cell_size`
+    cell_size: f32,
+    pipe_style: PipeStyle,
+    jitter: f32,
+    color_jitter: f32,
+    chunks: Chunks,
+}
+
+impl HeadlessRenderer {
+    /// `None` if there is no GPU adapter to render with, e.g. on a CI machine
+    pub async fn new(width: u32, height: u32) -> anyhow::Result<Option<Self>> {
+        Self::with_formats(width, height, COLOR_FORMAT, Depth::default()).await
+    }
+
+    /// Render into a texture of `color_format` rather than sRGB, like a window whose surface has no sRGB
+    /// format, with a depth buffer like `depth`. Frames still come out the same, see
+    /// `renderer::scene_format`.
+    pub async fn with_formats(
+        width: u32,
+        height: u32,
+        color_format: wgpu::TextureFormat,
+        depth: Depth,
+    ) -> anyhow::Result<Option<Self>> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        let adapter = match instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::LowPower,
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            })
+            .await
+        {
+            Ok(adapter) => adapter,
+            Err(e) => {
+                warn!("No GPU adapter for headless rendering: {:?}", e);
+                return Ok(None);
+            }
+        };
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("HeadlessDevice"),
+                ..Default::default()
+            })
+            .await?;
+
+        let mut renderer = Renderer::new(device, queue, color_format, depth, width, height, CLEAR_COLOR).await?;
+        renderer.set_world_bounds(Some(Bounds::of_world(world::DEFAULT_SIZE, 1.0)));
+
+        let mut headless = Self {
+            renderer,
+            width,
+            height,
+            camera: Camera::new(width as f32, height as f32),
+            palette: &palette::CLASSIC,
+            glass_opacity: None,
+            materials: Materials::default(),
+            max_pipe_length: None,
+            direction_weights: DirectionWeights::EVEN,
+            clock_time: None,
+            has_gizmos: false,
+            cell_size: 1.0,
+            pipe_style: PipeStyle::Segments,
+            jitter: 0.0,
+            color_jitter: 0.0,
+            chunks: Chunks::new(world::DEFAULT_SIZE, 1.0),
+        };
+        headless.set_camera(0.0, 0.0, 1.0);
+        Ok(Some(headless))
+    }
+
+    /// Palette of worlds grown from now on
+    pub fn set_palette(&mut self, name: &str) -> anyhow::Result<()> {
+        let Some(palette) = palette::find(name) else {
+            bail!("Unknown palette {:?}, available: {}", name, palette::names().join(", "));
+        };
+        self.palette = palette;
+        Ok(())
+    }
+
+    /// Render worlds grown from now on with glass pipes of the given opacity, or solid ones for `None`
+    pub fn set_glass(&mut self, opacity: Option<f32>) {
+        self.glass_opacity = opacity;
+        self.renderer.set_glass(opacity.is_some());
+    }
+
+    /// What worlds grown from now on are made of
+    pub fn set_materials(&mut self, materials: Materials) {
+        self.materials = materials;
+    }
+
+    /// Cap worlds grown from now on after this many segments a pipe, see `Config::max_pipe_length`
+    pub fn set_max_pipe_length(&mut self, length: Option<u32>) {
+        self.max_pipe_length = length;
+    }
+
+    /// Have pipes of worlds grown from now on prefer some directions, see `Config::direction_weights`
+    pub fn set_direction_weights(&mut self, weights: DirectionWeights) {
+        self.direction_weights = weights;
+    }
+
+    /// Grow worlds from now on in the digits of `hour:minute`, like clock mode, or anywhere for `None`.
+    /// Cameras set from now on start out facing the clock.
+    pub fn set_clock(&mut self, time: Option<(u8, u8)>) {
+        self.clock_time = time;
+    }
+
+    /// Space the blocks of worlds grown from now on `cell_size` apart, see `Config::cell_size`
+    pub fn set_cell_size(&mut self, cell_size: f32) {
+        self.cell_size = cell_size;
+        self.chunks = Chunks::new(world::DEFAULT_SIZE, cell_size);
+        self.chunks.set_tubes(self.pipe_style == PipeStyle::Tubes);
+        self.renderer
+            .set_world_bounds(Some(Bounds::of_world(world::DEFAULT_SIZE, cell_size)));
+    }
+
+    /// Sweep tubes this finely, those already there again, see `Config::tube_sides`
+    pub fn set_tessellation(&mut self, tessellation: Tessellation) {
+        self.renderer.set_tessellation(tessellation);
+        if let Some(tubes) = self.chunks.tubes_mut() {
+            tubes.mark_all_changed();
+            self.renderer.upload_chunks(&mut self.chunks);
+        }
+    }
+
+    /// Build worlds grown from now on from segments or tubes, see `Config::pipe_style`
+    pub fn set_pipe_style(&mut self, style: PipeStyle) {
+        self.pipe_style = style;
+        self.chunks.set_tubes(style == PipeStyle::Tubes);
+    }
+
+    /// Have segments of worlds grown from now on stray from the grid, see `Config::jitter`
+    pub fn set_jitter(&mut self, jitter: f32) {
+        self.jitter = jitter;
+    }
+
+    /// Have segments of worlds grown from now on stray from their pipe's color, see `Config::color_jitter`
+    pub fn set_color_jitter(&mut self, jitter: f32) {
+        self.color_jitter = jitter;
+    }
+
+    /// Make pipes this thick around, in blocks, see `Config::pipe_radius`
+    pub fn set_pipe_radius(&mut self, radius: f32) {
+        self.renderer.set_pipe_radius(radius);
+    }
+
+    /// Draw the bounds and axes of worlds grown from now on, and where their pipes head, like
+    /// `Action::ToggleGizmos` with `Config::gizmo_headings`
+    pub fn set_gizmos(&mut self, has_gizmos: bool) {
+        self.has_gizmos = has_gizmos;
+    }
+
+    pub fn set_shading(&mut self, shading: Shading) {
+        self.renderer.set_shading(shading);
+    }
+
+    pub fn set_floor(&mut self, has_floor: bool) {
+        self.renderer.set_floor(has_floor);
+    }
+
+    /// See `Config::depth_prepass`
+    pub fn set_depth_prepass(&mut self, has_depth_prepass: bool) {
+        self.renderer.set_depth_prepass(has_depth_prepass);
+    }
+
+    /// See `Config::instance_storage`, fails where the GPU can't
+    pub fn set_instance_storage(&mut self, is_instance_storage: bool) -> anyhow::Result<()> {
+        self.renderer.set_instance_storage(is_instance_storage)
+    }
+
+    /// See `Config::parallel_encoding`
+    pub fn set_parallel_encoding(&mut self, is_parallel_encoding: bool) {
+        self.renderer.set_parallel_encoding(is_parallel_encoding);
+    }
+
+    /// Pass frames through the post effect of the given name, or show them as they are for `None`
+    pub fn set_post_effect(&mut self, name: Option<&str>) -> anyhow::Result<()> {
+        let effect = match name {
+            Some(name) => {
+                Some(blit::find_post_effect(name).with_context(|| format!("Unknown post effect {:?}", name))?)
+            }
+            None => None,
+        };
+        self.renderer.set_post_effect(effect)
+    }
+
+    /// Draw the overlay as if on a screen of this many physical pixels per logical one, see
+    /// `Renderer::set_scale_factor`
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.renderer.set_scale_factor(scale_factor);
+    }
+
+    /// Show text over the frame like the help overlay, or none for `None`
+    pub fn set_overlay_text(&mut self, text: Option<&str>) {
+        self.renderer.set_overlay_text(text);
+    }
+
+    /// Draw at `scale` times the output resolution, see `Config::render_scale`
+    pub fn set_render_scale(&mut self, scale: f32) {
+        self.renderer.set_render_scale(scale);
+    }
+
+    /// Smooth edges with `aa`, see `Config::aa`. TAA blends the frames rendered since, so takes a few to
+    /// settle.
+    pub fn set_antialiasing(&mut self, aa: Antialiasing) -> anyhow::Result<()> {
+        self.renderer.set_antialiasing(aa)
+    }
+
+    /// Shrink the pipes away over `seconds` once the world is cleared, see `Config::drain_seconds`
+    pub fn set_drain_seconds(&mut self, seconds: f32) {
+        self.renderer.set_drain_seconds(seconds);
+    }
+
+    /// Replace whatever is rendered with the first `pipes` pipes of the world grown from `seed`, born a
+    /// second apart in the order they grew
+    pub fn grow_world(&mut self, seed: u64, pipes: u32) {
+        let mut world = World::with_seed(seed);
+        world.set_palette(self.palette);
+        world.set_opacity(self.glass_opacity.unwrap_or(1.0));
+        world.set_materials(self.materials);
+        world.set_max_pipe_length(self.max_pipe_length);
+        world.set_direction_weights(self.direction_weights);
+        world.set_cell_size(self.cell_size);
+        world.set_jitter(self.jitter);
+        world.set_color_jitter(self.color_jitter);
+        world.set_mask(self.clock_time.map(|(hour, minute)| clock::mask(hour, minute)));
+        let mut i_instances = vec![];
+        let mut l_instances = vec![];
+        for pipe in 0..pipes {
+            let start = world.len();
+            if !world.add_pipe() {
+                break;
+            }
+            let to_raw = |instance: &Instance| {
+                let mut raw = instance.to_raw();
+                raw.set_birth(pipe as f32);
+                raw
+            };
+            let (i_grown, l_grown) = world.instances_since(start);
+            i_instances.extend(i_grown.iter().map(to_raw));
+            l_instances.extend(l_grown.iter().map(to_raw));
+        }
+        self.chunks.clear();
+        self.chunks.append(i_instances, l_instances);
+        self.renderer.upload_chunks(&mut self.chunks);
+        let gizmos = self.has_gizmos.then(|| {
+            let mut lines = GizmoLines::world(world.extents(), self.cell_size);
+            lines.add_headings(&world.heads());
+            lines
+        });
+        self.renderer.set_gizmos(gizmos.as_ref());
+    }
+
+    /// Take every pipe away, draining them if `set_drain_seconds` asked for it
+    pub fn clear_world(&mut self) {
+        self.chunks.clear();
+        self.renderer.upload_chunks(&mut self.chunks);
+    }
+
+    /// See `Config::ambient`
+    pub fn set_ambient(&mut self, ambient: f32) {
+        self.renderer.set_ambient(ambient);
+    }
+
+    /// See `Config::rim_light`
+    pub fn set_rim_light(&mut self, rim: f32) {
+        self.renderer.set_rim_light(rim);
+    }
+
+    /// See `Config::sky_light`
+    pub fn set_sky_light(&mut self, strength: f32) {
+        self.renderer.set_sky_light(strength);
+    }
+
+    /// Colors of the sky and the ground in linear space, see `Config::sky_color`
+    pub fn set_sky_colors(&mut self, sky: [f32; 3], ground: [f32; 3]) {
+        self.renderer.set_sky_colors(sky, ground);
+    }
+
+    /// See `Config::exposure`
+    pub fn set_exposure(&mut self, stops: f32) {
+        self.renderer.set_exposure(stops);
+    }
+
+    /// Put the light `degrees` into `orbit`
+    pub fn set_light(&mut self, orbit: LightOrbit, degrees: f32) {
+        self.renderer.set_light_orbit(orbit);
+        self.renderer.orbit_light(cgmath::Deg(degrees));
+    }
+
+    /// Move animations on by `dt` seconds, like a window does between frames
+    pub fn advance_time(&mut self, dt: f32) {
+        self.renderer.advance_time(dt);
+    }
+
+    /// Start from the default camera, orbit it by `yaw` and `pitch` radians and scale its distance by `zoom`
+    pub fn set_camera(&mut self, yaw: f32, pitch: f32, zoom: f32) {
+        self.camera = Camera::new(self.width as f32, self.height as f32);
+        if self.clock_time.is_some() {
+            clock::frame(&mut self.camera);
+        }
+        self.camera.orbit(yaw, pitch);
+        self.camera.zoom(zoom);
+        self.renderer.update_camera(&self.camera);
+    }
+
+    /// Select and highlight the segment at pixel `x`, `y` like a click in a window does, `None` if there is
+    /// nothing there
+    pub fn pick(&mut self, x: u32, y: u32) -> Option<Selection> {
+        let ray = self.camera.ray(
+            (x as f32 + 0.5) / self.width as f32 * 2.0 - 1.0,
+            1.0 - (y as f32 + 0.5) / self.height as f32 * 2.0,
+        );
+        let selection = self.chunks.pick(&ray);
+        self.renderer.set_selection(selection.as_ref());
+        let pipe = selection
+            .as_ref()
+            .map(|selection| self.chunks.pipe_instances(selection));
+        (self.renderer).set_outlined_pipe(pipe.as_ref().map(|(i, l)| (i.as_slice(), l.as_slice())));
+        selection
+    }
+
+    /// Draw a frame and read it back from the GPU
+    pub fn render(&mut self) -> anyhow::Result<image::RgbaImage> {
+        // with the near and far planes fit to the world and floor as they are now
+        self.renderer.update_camera(&self.camera);
+        if self.glass_opacity.is_some() {
+            self.chunks.sort_back_to_front(self.camera.eye());
+            self.renderer.upload_chunks(&mut self.chunks);
+        }
+        self.renderer.render_to_image()
+    }
+}
@@ -0,0 +1,152 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+use anyhow::Context;
+use log::{info, warn};
+
+const MAX_REQUEST_SIZE: usize = 8192;
+// requests are read on the event loop, a slow client must not stall rendering for long
+const READ_TIMEOUT: Duration = Duration::from_millis(200);
+const INDEX_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta name="viewport" content="width=device-width"><title>XPipe</title></head>
+<body style="background:#111;color:#eee;font-family:sans-serif">
+<img id="snapshot" src="/snapshot.png" style="width:100%">
+<p><button onclick="fetch('/reset',{method:'POST'})">Reset</button>
+<button onclick="document.getElementById('snapshot').src='/snapshot.png?'+Date.now()">Refresh</button></p>
+<pre id="stats"></pre>
+<script>fetch('/stats').then(r=>r.text()).then(t=>document.getElementById('stats').textContent=t)</script>
+</body>
+</html>
+"#;
+
+/// What a client asked for
+pub enum Endpoint {
+    /// `GET /stats`, the world's stats as JSON
+    Stats,
+    /// `POST /reset`
+    Reset,
+    /// `POST /config?name=value&...`, change settings of every window
+    Configure(Vec<(String, String)>),
+    /// `GET /snapshot.png`, the current frame
+    Snapshot,
+}
+
+/// A request waiting for its response
+pub struct Request {
+    pub endpoint: Endpoint,
+    stream: TcpStream,
+}
+
+impl Request {
+    pub fn respond(mut self, status: &str, content_type: &str, body: &[u8]) {
+        respond(&mut self.stream, status, content_type, body);
+    }
+}
+
+/// A tiny HTTP server to monitor and control the screensaver from a browser, e.g. a phone on the LAN.
+/// `GET /` serves a page with a snapshot, the stats and a reset button.
+pub struct HttpServer {
+    listener: TcpListener,
+}
+
+impl HttpServer {
+    pub fn bind(address: SocketAddr) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(address).with_context(|| format!("Cannot listen on {}", address))?;
+        // Polled from the event loop, which must never wait for connections
+        listener.set_nonblocking(true)?;
+        info!("Serving the HTTP control API on http://{}", address);
+        Ok(Self { listener })
+    }
+
+    /// Every request received since the last poll which needs a window to answer it
+    pub fn poll(&mut self) -> Vec<Request> {
+        let mut requests = vec![];
+        loop {
+            let (mut stream, client) = match self.listener.accept() {
+                Ok(connection) => connection,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return requests,
+                Err(e) => {
+                    warn!("Cannot accept HTTP connection: {:?}", e);
+                    return requests;
+                }
+            };
+            match read_request(&mut stream) {
+                Ok(Some(endpoint)) => requests.push(Request { endpoint, stream }),
+                Ok(None) => respond(&mut stream, "200 OK", "text/html", INDEX_PAGE.as_bytes()),
+                Err((status, message)) => {
+                    warn!("Bad HTTP request from {}: {}", client, message);
+                    respond(&mut stream, status, "text/plain", message.as_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// The endpoint a request is for, `None` for the index page, or the status and message to fail with
+fn read_request(stream: &mut TcpStream) -> Result<Option<Endpoint>, (&'static str, String)> {
+    let io_error = |e: std::io::Error| ("400 Bad Request", format!("Cannot read request: {}", e));
+    // Accepted streams may inherit the listener's non-blocking mode
+    stream.set_nonblocking(false).map_err(io_error)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT)).map_err(io_error)?;
+
+    // Only the head matters, every parameter is in the query
+    let mut head = vec![];
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_SIZE {
+            return Err(("431 Request Header Fields Too Large", "Request too large".to_string()));
+        }
+        let size = stream.read(&mut buffer).map_err(io_error)?;
+        if size == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..size]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let (method, target) = (
+        request_line.next().unwrap_or_default(),
+        request_line.next().unwrap_or_default(),
+    );
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let expect = |expected: &str, endpoint| {
+        if method == expected {
+            Ok(endpoint)
+        } else {
+            Err(("405 Method Not Allowed", format!("Use {} for {}", expected, path)))
+        }
+    };
+    match path {
+        "/" => expect("GET", None),
+        "/stats" => expect("GET", Some(Endpoint::Stats)),
+        "/snapshot.png" => expect("GET", Some(Endpoint::Snapshot)),
+        "/reset" => expect("POST", Some(Endpoint::Reset)),
+        "/config" => {
+            let settings = query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (name.to_string(), value.to_string())
+                })
+                .collect();
+            expect("POST", Some(Endpoint::Configure(settings)))
+        }
+        _ => Err(("404 Not Found", format!("No such endpoint {}", path))),
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    if let Err(e) = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(body)) {
+        warn!("Cannot send HTTP response: {:?}", e);
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+// the fallback for messages other languages lack
+const ENGLISH: &str = include_str!("../res/locales/en.toml");
+const GERMAN: &str = include_str!("../res/locales/de.toml");
+
+/// Languages the overlay text is available in, written as their ISO 639-1 code
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Language {
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "de")]
+    German,
+}
+
+impl Language {
+    /// The language of a locale like `de-AT` or `de_AT.UTF-8`, English for any not translated
+    pub fn from_locale(locale: &str) -> Self {
+        match locale.get(..2).map(str::to_ascii_lowercase).as_deref() {
+            Some("de") => Language::German,
+            _ => Language::English,
+        }
+    }
+
+    /// The language the user reads the system in
+    pub fn system() -> Self {
+        sys_locale::get_locale().map_or(Language::English, |locale| Self::from_locale(&locale))
+    }
+
+    fn bundle(self) -> &'static str {
+        match self {
+            Language::English => ENGLISH,
+            Language::German => GERMAN,
+        }
+    }
+}
+
+/// The overlay's messages in one language, by their id like `action-pause`
+#[derive(Debug, Clone)]
+pub struct Messages {
+    messages: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+}
+
+impl Messages {
+    pub fn new(language: Language) -> Self {
+        info!("Overlay language: {:?}", language);
+        Self {
+            messages: parse(language.bundle()),
+            fallback: parse(ENGLISH),
+        }
+    }
+
+    /// The message of `id`, in English if it isn't translated and the id itself if it doesn't exist
+    pub fn get<'a>(&'a self, id: &'a str) -> &'a str {
+        self.messages
+            .get(id)
+            .or_else(|| self.fallback.get(id))
+            .map_or(id, String::as_str)
+    }
+}
+
+fn parse(bundle: &str) -> HashMap<String, String> {
+    toml::from_str(bundle).expect("Bundled messages are invalid")
+}
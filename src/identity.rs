@@ -0,0 +1,82 @@
+use log::warn;
+use winit::window::{Icon, Window, WindowAttributes};
+
+const ICON: &[u8] = include_bytes!("../res/icon.png");
+/// What task switchers and docks know XPipe by: the Wayland app id and X11 class, matching
+/// `res/xpipe.desktop`. The macOS bundle identifier is in `Cargo.toml`, for `cargo bundle`.
+pub const APP_ID: &str = "xpipe";
+
+/// Attributes every window starts from, with XPipe's icon and app id
+pub fn window_attributes(title: &str) -> WindowAttributes {
+    let attributes = Window::default_attributes().with_title(title);
+    let attributes = match icon() {
+        Ok(icon) => platform::with_taskbar_icon(attributes.with_window_icon(Some(icon.clone())), icon),
+        Err(e) => {
+            warn!("No window icon: {:?}", e);
+            attributes
+        }
+    };
+    platform::with_app_id(attributes)
+}
+
+fn icon() -> anyhow::Result<Icon> {
+    let (rgba, width, height) = icon_rgba()?;
+    Ok(Icon::from_rgba(rgba, width, height)?)
+}
+
+/// XPipe's icon as RGBA pixels, with its width and height
+pub fn icon_rgba() -> anyhow::Result<(Vec<u8>, u32, u32)> {
+    let image = image::load_from_memory(ICON)?.into_rgba8();
+    let (width, height) = image.dimensions();
+    Ok((image.into_raw(), width, height))
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use winit::platform::windows::WindowAttributesExtWindows;
+    use winit::window::{Icon, WindowAttributes};
+
+    pub fn with_app_id(attributes: WindowAttributes) -> WindowAttributes {
+        attributes
+    }
+
+    /// The window icon is the small one in the title bar, the taskbar and Alt+Tab show this one
+    pub fn with_taskbar_icon(attributes: WindowAttributes, icon: Icon) -> WindowAttributes {
+        attributes.with_taskbar_icon(Some(icon))
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))))]
+mod platform {
+    use winit::platform::{wayland::WindowAttributesExtWayland, x11::WindowAttributesExtX11};
+    use winit::window::{Icon, WindowAttributes};
+
+    use super::APP_ID;
+
+    pub fn with_app_id(attributes: WindowAttributes) -> WindowAttributes {
+        // Whichever of the two the window ends up on, the other one is ignored
+        let attributes = WindowAttributesExtWayland::with_name(attributes, APP_ID, APP_ID);
+        WindowAttributesExtX11::with_name(attributes, APP_ID, APP_ID)
+    }
+
+    pub fn with_taskbar_icon(attributes: WindowAttributes, _icon: Icon) -> WindowAttributes {
+        attributes
+    }
+}
+
+/// The dock takes its icon from the app bundle on macOS, elsewhere there is nothing more to set
+#[cfg(not(any(
+    target_os = "windows",
+    all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")))
+)))]
+mod platform {
+    use winit::window::{Icon, WindowAttributes};
+
+    pub fn with_app_id(attributes: WindowAttributes) -> WindowAttributes {
+        attributes
+    }
+
+    pub fn with_taskbar_icon(attributes: WindowAttributes, _icon: Icon) -> WindowAttributes {
+        attributes
+    }
+}
@@ -1,16 +1,49 @@
+use crate::material::Material;
+
 #[derive(Clone, Debug)]
 pub struct Instance {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
+    /// Along the mesh's local axes, before the rotation
+    pub scale: cgmath::Vector3<f32>,
     pub color: [f32; 3],
+    pub alpha: f32,
+    pub emissive: f32,
+    /// Segments before this one in its pipe, so that flowing bands line up from one to the next
+    pub flow_phase: f32,
+    /// Which way the pipe runs along the mesh's local Y axis, 1 or -1. 0 for curves, which always run
+    /// from their local +Y end to their +X end.
+    pub flow_axis: f32,
+    pub material: Material,
 }
 
 impl Instance {
     pub fn to_raw(&self) -> InstanceRaw {
+        let scale = self.scale;
+        let model = cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z);
+        // the inverse transpose of the model's rotation and scale, which the shaders normalize
+        let normal = cgmath::Matrix3::from(self.rotation)
+            * cgmath::Matrix3::new(
+                1.0 / scale.x,
+                0.0,
+                0.0,
+                0.0,
+                1.0 / scale.y,
+                0.0,
+                0.0,
+                0.0,
+                1.0 / scale.z,
+            );
         InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
-            color: self.color.clone(),
+            model: model.into(),
+            normal: normal.into(),
+            color: [self.color[0], self.color[1], self.color[2], self.alpha],
+            emissive: self.emissive,
+            flow: [self.flow_phase, self.flow_axis],
+            birth: 0.0,
+            material: self.material.index(),
         }
     }
 }
@@ -20,10 +53,76 @@ impl Instance {
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
-    color: [f32; 3],
+    color: [f32; 4],
+    emissive: f32,
+    flow: [f32; 2],
+    // renderer time the instance appeared at, old ones fade
+    birth: f32,
+    // index into the shaders' materials, see `material::Material`
+    material: u32,
 }
 
 impl InstanceRaw {
+    pub fn position(&self) -> cgmath::Point3<f32> {
+        let [x, y, z, _] = self.model[3];
+        cgmath::Point3::new(x, y, z)
+    }
+
+    pub fn model(&self) -> cgmath::Matrix4<f32> {
+        self.model.into()
+    }
+
+    /// The instance scaled by `scale` about the origin, then moved by `offset`. The normals only change
+    /// length, which the shaders normalize away.
+    pub fn transformed(&self, offset: cgmath::Vector3<f32>, scale: f32) -> Self {
+        let model = cgmath::Matrix4::from_translation(offset) * cgmath::Matrix4::from_scale(scale) * self.model();
+        Self {
+            model: model.into(),
+            ..*self
+        }
+    }
+
+    /// See `Instance::flow_axis`
+    pub fn flow_axis(&self) -> f32 {
+        self.flow[1]
+    }
+
+    /// See `Instance::flow_phase`
+    pub fn flow_phase(&self) -> f32 {
+        self.flow[0]
+    }
+
+    /// The instance with its mesh left where it is, for meshes built in the scene itself like
+    /// `tubes::Tube`. Flowing bands run up the scene's Y axis through them.
+    pub fn without_transform(&self) -> Self {
+        let identity: [[f32; 4]; 4] = cgmath::Matrix4::from_scale(1.0).into();
+        Self {
+            model: identity,
+            normal: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            flow: [0.0, 1.0],
+            ..*self
+        }
+    }
+
+    pub fn birth(&self) -> f32 {
+        self.birth
+    }
+
+    pub fn set_birth(&mut self, seconds: f32) {
+        self.birth = seconds;
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        let [r, g, b, _] = self.color;
+        [r, g, b]
+    }
+
+    /// Repaint the instance, keeping its opacity
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    pub fn set_color(&mut self, [r, g, b]: [f32; 3]) {
+        self.color = [r, g, b, self.color[3]];
+    }
+
     pub fn layout() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
             array_stride: size_of::<InstanceRaw>() as wgpu::BufferAddress,
@@ -67,9 +166,46 @@ impl InstanceRaw {
                 wgpu::VertexAttribute {
                     offset: size_of::<[f32; 25]>() as wgpu::BufferAddress,
                     shader_location: 12,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 29]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 30]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 32]>() as wgpu::BufferAddress,
+                    shader_location: 15,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                // the locations after 15 are past what every GPU supports, the mesh leaves 2 free
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 33]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Uint32,
                 },
             ],
         }
     }
 }
+
+/// Order instances from the farthest to the nearest to `eye`, so that blended ones cover each other
+/// correctly. Only orders within one buffer, pipes of different meshes can still overlap wrongly.
+pub fn sort_back_to_front(instances: &mut [InstanceRaw], eye: cgmath::Point3<f32>) {
+    use cgmath::MetricSpace;
+    let distance = |instance: &InstanceRaw| eye.distance2(instance.position());
+    instances.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+}
+
+/// Order instances from the nearest to the farthest from `eye`, so that solid ones fail the depth test
+/// behind the ones in front rather than being shaded and then covered
+pub fn sort_front_to_back(instances: &mut [InstanceRaw], eye: cgmath::Point3<f32>) {
+    use cgmath::MetricSpace;
+    let distance = |instance: &InstanceRaw| eye.distance2(instance.position());
+    instances.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+}
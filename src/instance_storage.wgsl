@@ -0,0 +1,50 @@
+// Appended to shader.wgsl for pipes whose instances are read from a storage buffer by their index, rather
+// than from vertex attributes. See `Renderer::set_instance_storage`.
+
+// laid out like `InstanceRaw`
+@group(3) @binding(1)
+var<storage, read> instances: array<f32>;
+
+const FLOATS_PER_INSTANCE: u32 = 34u;
+
+fn load_vec3(index: u32) -> vec3<f32> {
+    return vec3<f32>(instances[index], instances[index + 1u], instances[index + 2u]);
+}
+
+fn load_vec4(index: u32) -> vec4<f32> {
+    return vec4<f32>(load_vec3(index), instances[index + 3u]);
+}
+
+fn load_instance(index: u32) -> InstanceInput {
+    let base = index * FLOATS_PER_INSTANCE;
+    var instance: InstanceInput;
+    instance.model_matrix_0 = load_vec4(base);
+    instance.model_matrix_1 = load_vec4(base + 4u);
+    instance.model_matrix_2 = load_vec4(base + 8u);
+    instance.model_matrix_3 = load_vec4(base + 12u);
+    instance.normal_matrix_0 = load_vec3(base + 16u);
+    instance.normal_matrix_1 = load_vec3(base + 19u);
+    instance.normal_matrix_2 = load_vec3(base + 22u);
+    instance.color = load_vec4(base + 25u);
+    instance.emissive = instances[base + 29u];
+    instance.flow = vec2<f32>(instances[base + 30u], instances[base + 31u]);
+    instance.birth = instances[base + 32u];
+    instance.material = bitcast<u32>(instances[base + 33u]);
+    return instance;
+}
+
+@vertex
+fn vs_storage_main(
+    model: VertextInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    return vertex(model, load_instance(instance_index));
+}
+
+@vertex
+fn vs_storage_outline(
+    model: VertextInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    return outline_vertex(model, load_instance(instance_index));
+}
@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use winit::keyboard::KeyCode;
+
+/// Something a key does
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Exit,
+    Pause,
+    Reset,
+    /// Save the current frame as a PNG in the pictures directory
+    Screenshot,
+    /// Freeze everything and hide what is drawn over the scene, so that screenshots are taken at
+    /// `Config::photo_scale` and named with the seed
+    TogglePhotoMode,
+    /// Save a looping GIF of the camera orbiting once around the world, see `Config::turntable_width`
+    ExportTurntable,
+    /// Switch to the next scene, see `config::Scene`
+    NextScene,
+    /// Copy a share string to grow the same world from, see `recipe::Recipe`
+    CopyRecipe,
+    /// Save the graph of the pipes in the documents directory, see `graph::Graph`
+    ExportGraph,
+    ToggleFullscreen,
+    CycleBackground,
+    CycleShading,
+    CycleViews,
+    ToggleFloor,
+    ToggleSparks,
+    /// Brighten or dim the sides of the pipes facing away from the light, see `Config::ambient`
+    BrightenAmbient,
+    DimAmbient,
+    /// Light the pipes' silhouettes, stronger and stronger until off again, see `Config::rim_light`
+    CycleRimLight,
+    /// Brighten or darken everything by a step of exposure, see `Config::exposure`
+    BrightenExposure,
+    DarkenExposure,
+    /// Adapt the exposure to how bright the frames are, see `Config::auto_exposure`
+    ToggleAutoExposure,
+    ToggleReducedMotion,
+    /// Draw solid pipes near to far, see `Config::depth_sort`
+    ToggleDepthSort,
+    /// Stop growing to replay how the world grew, scrubbing through it with `ScrubBackward` and
+    /// `ScrubForward`
+    ToggleReplay,
+    /// Take the replay back, or on, for as long as the key is held
+    ScrubBackward,
+    ScrubForward,
+    /// Show the keys and current settings over the frame
+    ToggleHelp,
+    /// Draw the world's bounds and axes, see `gizmos::GizmoLines`
+    ToggleGizmos,
+    /// Stop growing to place pipes by hand, aiming with the camera, see `build::BuildTool`. Clicks erase
+    /// segments meanwhile, with Shift their whole pipe.
+    ToggleBuildMode,
+    /// Switch between building straight pipes and elbows
+    ToggleBuildPipeType,
+    /// Turn the pipe to build the next way
+    NextBuildDirection,
+    /// Place the pipe aimed at in build mode
+    Build,
+    /// Step back the latest change to the world, grown or by hand, with Ctrl held
+    Undo,
+    /// Step forward again what was stepped back, with Ctrl held
+    Redo,
+    /// Move the camera for as long as the key is held
+    CameraForward,
+    CameraBackward,
+    CameraLeft,
+    CameraRight,
+}
+
+const DEFAULT_BINDINGS: &[(Action, &[KeyCode])] = &[
+    (Action::Exit, &[KeyCode::Escape]),
+    (Action::Pause, &[KeyCode::Space]),
+    (Action::Reset, &[KeyCode::KeyR]),
+    (Action::Screenshot, &[KeyCode::F12]),
+    (Action::TogglePhotoMode, &[KeyCode::KeyN]),
+    (Action::ExportTurntable, &[KeyCode::KeyU]),
+    (Action::NextScene, &[KeyCode::KeyJ]),
+    (Action::CopyRecipe, &[KeyCode::KeyC]),
+    (Action::ExportGraph, &[KeyCode::KeyG]),
+    (Action::ToggleFullscreen, &[KeyCode::F11]),
+    (Action::CycleBackground, &[KeyCode::KeyB]),
+    (Action::CycleShading, &[KeyCode::KeyT]),
+    (Action::CycleViews, &[KeyCode::KeyV]),
+    (Action::ToggleFloor, &[KeyCode::KeyF]),
+    (Action::ToggleSparks, &[KeyCode::KeyP]),
+    (Action::BrightenAmbient, &[KeyCode::BracketRight]),
+    (Action::DimAmbient, &[KeyCode::BracketLeft]),
+    (Action::CycleRimLight, &[KeyCode::KeyI]),
+    (Action::BrightenExposure, &[KeyCode::Equal]),
+    (Action::DarkenExposure, &[KeyCode::Minus]),
+    (Action::ToggleAutoExposure, &[KeyCode::KeyX]),
+    (Action::ToggleReducedMotion, &[KeyCode::KeyM]),
+    (Action::ToggleDepthSort, &[KeyCode::KeyO]),
+    (Action::ToggleReplay, &[KeyCode::KeyL]),
+    (Action::ScrubBackward, &[KeyCode::ArrowLeft]),
+    (Action::ScrubForward, &[KeyCode::ArrowRight]),
+    (Action::ToggleHelp, &[KeyCode::KeyH, KeyCode::Slash]),
+    (Action::ToggleGizmos, &[KeyCode::F3]),
+    (Action::ToggleBuildMode, &[KeyCode::KeyK]),
+    (Action::ToggleBuildPipeType, &[KeyCode::KeyQ]),
+    (Action::NextBuildDirection, &[KeyCode::KeyE]),
+    (Action::Build, &[KeyCode::Enter]),
+    (Action::Undo, &[KeyCode::KeyZ]),
+    (Action::Redo, &[KeyCode::KeyY]),
+    (Action::CameraForward, &[KeyCode::KeyW, KeyCode::ArrowUp]),
+    (Action::CameraBackward, &[KeyCode::KeyS, KeyCode::ArrowDown]),
+    (Action::CameraLeft, &[KeyCode::KeyA]),
+    (Action::CameraRight, &[KeyCode::KeyD]),
+];
+
+/// Which key does what. Configured as a table of actions to the physical keys triggering them, named like
+/// winit's `KeyCode`, e.g. `pause = ["Space", "KeyK"]`, which replaces the keys of that action only. Keys
+/// taken from another action stop triggering that one, an empty list leaves an action without keys.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "BTreeMap<Action, Vec<KeyCode>>", into = "BTreeMap<Action, Vec<KeyCode>>")]
+pub struct KeyBindings {
+    keys: BTreeMap<Action, Vec<KeyCode>>,
+    actions: HashMap<KeyCode, Action>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self::try_from(BTreeMap::new()).expect("Default key bindings conflict")
+    }
+}
+
+impl Action {
+    /// Id of the message describing what the action does, see `i18n::Messages`
+    pub fn message_id(self) -> &'static str {
+        match self {
+            Action::Exit => "action-exit",
+            Action::Pause => "action-pause",
+            Action::Reset => "action-reset",
+            Action::Screenshot => "action-screenshot",
+            Action::TogglePhotoMode => "action-toggle-photo-mode",
+            Action::ExportTurntable => "action-export-turntable",
+            Action::NextScene => "action-next-scene",
+            Action::CopyRecipe => "action-copy-recipe",
+            Action::ExportGraph => "action-export-graph",
+            Action::ToggleFullscreen => "action-toggle-fullscreen",
+            Action::CycleBackground => "action-cycle-background",
+            Action::CycleShading => "action-cycle-shading",
+            Action::CycleViews => "action-cycle-views",
+            Action::ToggleFloor => "action-toggle-floor",
+            Action::ToggleSparks => "action-toggle-sparks",
+            Action::BrightenAmbient => "action-brighten-ambient",
+            Action::DimAmbient => "action-dim-ambient",
+            Action::CycleRimLight => "action-cycle-rim-light",
+            Action::BrightenExposure => "action-brighten-exposure",
+            Action::DarkenExposure => "action-darken-exposure",
+            Action::ToggleAutoExposure => "action-toggle-auto-exposure",
+            Action::ToggleReducedMotion => "action-toggle-reduced-motion",
+            Action::ToggleDepthSort => "action-toggle-depth-sort",
+            Action::ToggleReplay => "action-toggle-replay",
+            Action::ScrubBackward => "action-scrub-backward",
+            Action::ScrubForward => "action-scrub-forward",
+            Action::ToggleHelp => "action-toggle-help",
+            Action::ToggleGizmos => "action-toggle-gizmos",
+            Action::ToggleBuildMode => "action-toggle-build-mode",
+            Action::ToggleBuildPipeType => "action-toggle-build-pipe-type",
+            Action::NextBuildDirection => "action-next-build-direction",
+            Action::Build => "action-build",
+            Action::Undo => "action-undo",
+            Action::Redo => "action-redo",
+            Action::CameraForward => "action-camera-forward",
+            Action::CameraBackward => "action-camera-backward",
+            Action::CameraLeft => "action-camera-left",
+            Action::CameraRight => "action-camera-right",
+        }
+    }
+}
+
+impl Action {
+    /// Whether its keys only trigger the action with Ctrl held, like undo does everywhere
+    pub fn is_with_ctrl(self) -> bool {
+        matches!(self, Action::Undo | Action::Redo)
+    }
+}
+
+impl KeyBindings {
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        self.actions.get(&key).copied()
+    }
+
+    /// Every action with the keys bound to it, which may be none
+    pub fn iter(&self) -> impl Iterator<Item = (Action, &[KeyCode])> {
+        self.keys.iter().map(|(action, keys)| (*action, keys.as_slice()))
+    }
+}
+
+/// A key's name for people, e.g. `W` rather than `KeyW`
+pub fn key_name(key: KeyCode) -> String {
+    let name = format!("{:?}", key);
+    match key {
+        KeyCode::Slash => "/".to_string(),
+        _ => name
+            .strip_prefix("Key")
+            .or_else(|| name.strip_prefix("Digit"))
+            .unwrap_or(&name)
+            .to_string(),
+    }
+}
+
+impl TryFrom<BTreeMap<Action, Vec<KeyCode>>> for KeyBindings {
+    type Error = anyhow::Error;
+
+    fn try_from(overrides: BTreeMap<Action, Vec<KeyCode>>) -> anyhow::Result<Self> {
+        let mut actions = HashMap::new();
+        for (action, keys) in &overrides {
+            for key in keys {
+                if let Some(other) = actions.insert(*key, *action).filter(|other| other != action) {
+                    bail!("Key {:?} is bound to both {:?} and {:?}", key, other, action);
+                }
+            }
+        }
+
+        let mut keys = overrides;
+        for (action, default_keys) in DEFAULT_BINDINGS {
+            if keys.contains_key(action) {
+                continue;
+            }
+            let free_keys: Vec<_> = default_keys
+                .iter()
+                .copied()
+                .filter(|key| !actions.contains_key(key))
+                .collect();
+            for key in &free_keys {
+                actions.insert(*key, *action);
+            }
+            keys.insert(*action, free_keys);
+        }
+        Ok(Self { keys, actions })
+    }
+}
+
+impl From<KeyBindings> for BTreeMap<Action, Vec<KeyCode>> {
+    fn from(bindings: KeyBindings) -> Self {
+        bindings.keys
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, bail};
+
+use crate::world::Direction;
+
+/// Pipes laid out by hand rather than grown at random, loaded with `--layout`. A pipe per line, with
+/// the block it starts in and its moves, each a direction and optionally how many blocks it goes that
+/// way:
+///
+/// ```text
+/// # an S climbing out of the corner
+/// 0,0,2: +X*3, +Y, -Z*2
+/// 5,0,5: +Y*10
+/// ```
+///
+/// Anything after a `#` is a comment. Pipes take the colors of the palette in turn.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Layout {
+    pub pipes: Vec<LayoutPipe>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutPipe {
+    pub start: (u32, u32, u32),
+    /// Directions the pipe goes, and how many blocks each
+    pub moves: Vec<(Direction, u32)>,
+    /// Where the pipe is in the file, from 1, for errors about it
+    pub line: usize,
+}
+
+impl Layout {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Cannot read layout {:?}", path))?;
+        text.parse().with_context(|| format!("Invalid layout {:?}", path))
+    }
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut pipes = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let pipe = parse_pipe(line, i + 1).with_context(|| format!("Line {}: {:?}", i + 1, line))?;
+            pipes.push(pipe);
+        }
+        Ok(Self { pipes })
+    }
+}
+
+fn parse_pipe(line: &str, number: usize) -> anyhow::Result<LayoutPipe> {
+    let (start, moves) = line
+        .split_once(':')
+        .context("Expected where the pipe starts and its moves, like 0,0,0: +X*3, +Y")?;
+    let coordinates = start
+        .split(',')
+        .map(|c| {
+            c.trim()
+                .parse::<u32>()
+                .with_context(|| format!("Expected a block coordinate, got {:?}", c))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let [x, y, z] = coordinates[..] else {
+        bail!("Expected x, y and z of the block the pipe starts in, got {:?}", start);
+    };
+
+    let mut parsed: Vec<(Direction, u32)> = vec![];
+    for step in moves.split(',').map(str::trim) {
+        let (direction, count) = match step.split_once('*') {
+            Some((direction, count)) => {
+                let count = count
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Expected how many blocks, got {:?}", count))?;
+                (direction.trim(), count)
+            }
+            None => (step, 1),
+        };
+        let direction = parse_direction(direction)?;
+        if count == 0 {
+            bail!("A move of {:?} goes nowhere", step);
+        }
+        if let Some((last, _)) = parsed.last()
+            && direction == last.opposite()
+        {
+            bail!("A pipe can't turn right back, {:?} goes the way it came", step);
+        }
+        parsed.push((direction, count));
+    }
+    Ok(LayoutPipe {
+        start: (x, y, z),
+        moves: parsed,
+        line: number,
+    })
+}
+
+fn parse_direction(s: &str) -> anyhow::Result<Direction> {
+    Ok(match s.to_ascii_uppercase().as_str() {
+        "+X" | "X" => Direction::X,
+        "+Y" | "Y" => Direction::Y,
+        "+Z" | "Z" => Direction::Z,
+        "-X" => Direction::_X,
+        "-Y" => Direction::_Y,
+        "-Z" => Direction::_Z,
+        _ => bail!("Expected a move like +X, -Y or +Z*3, got {:?}", s),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The whole chain of errors of a layout that doesn't parse
+    fn error(text: &str) -> String {
+        format!("{:#}", text.parse::<Layout>().unwrap_err())
+    }
+
+    #[test]
+    fn parses_pipes_and_skips_comments() {
+        let layout: Layout = "# an S climbing out of the corner\n0,0,2: +X*3, y, -Z*2 # and back\n\n 5, 0, 5 : +Y*10\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            layout.pipes,
+            vec![
+                LayoutPipe {
+                    start: (0, 0, 2),
+                    moves: vec![(Direction::X, 3), (Direction::Y, 1), (Direction::_Z, 2)],
+                    line: 2,
+                },
+                LayoutPipe {
+                    start: (5, 0, 5),
+                    moves: vec![(Direction::Y, 10)],
+                    line: 4,
+                },
+            ]
+        );
+        assert_eq!("# nothing but a comment".parse::<Layout>().unwrap(), Layout::default());
+    }
+
+    #[test]
+    fn rejects_moves_going_nowhere_or_back() {
+        assert_eq!(
+            error("0,0,0: +X*0"),
+            r#"Line 1: "0,0,0: +X*0": A move of "+X*0" goes nowhere"#
+        );
+        assert_eq!(
+            error("# first\n0,0,0: +X, -X*2"),
+            r#"Line 2: "0,0,0: +X, -X*2": A pipe can't turn right back, "-X*2" goes the way it came"#
+        );
+        assert_eq!(
+            error("0,0,0: +W"),
+            r#"Line 1: "0,0,0: +W": Expected a move like +X, -Y or +Z*3, got "+W""#
+        );
+        assert_eq!(
+            error("0,0,0: +X*many"),
+            r#"Line 1: "0,0,0: +X*many": Expected how many blocks, got "many": invalid digit found in string"#
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_starts() {
+        assert_eq!(
+            error("0,0,0 +X"),
+            r#"Line 1: "0,0,0 +X": Expected where the pipe starts and its moves, like 0,0,0: +X*3, +Y"#
+        );
+        assert_eq!(
+            error("-1,0,0: +X"),
+            r#"Line 1: "-1,0,0: +X": Expected a block coordinate, got "-1": invalid digit found in string"#
+        );
+        assert_eq!(
+            error("4294967296,0,0: +X"),
+            r#"Line 1: "4294967296,0,0: +X": Expected a block coordinate, got "4294967296": number too large to fit in target type"#
+        );
+        assert_eq!(
+            error("0,0: +X"),
+            r#"Line 1: "0,0: +X": Expected x, y and z of the block the pipe starts in, got "0,0""#
+        );
+    }
+}
@@ -1,60 +1,279 @@
+#[cfg(feature = "audio")]
+mod audio;
+mod blit;
+mod build;
 mod camera;
+mod caps;
+mod chunks;
+mod clipboard;
+mod clock;
+mod config;
+mod exposure;
+mod fixed_step;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod generator;
+mod gif;
+mod gizmos;
+mod gpu;
+mod gpu_world;
+mod graph;
+pub mod headless;
+mod http;
+mod i18n;
+mod identity;
 mod instance;
+mod keybindings;
+mod layout;
 mod light;
+mod logging;
+mod material;
 mod models;
+mod outline;
+mod palette;
+mod particles;
+mod picking;
+pub mod plugin;
+mod profiler;
+mod quality;
+mod recipe;
+mod region;
+mod remote;
+mod renderer;
 mod resources;
+mod rhythm;
+#[cfg(feature = "scripting")]
+mod script;
+mod session;
+mod simulation;
+mod split_screen;
+mod stacked;
+mod taa;
+mod text;
 mod texture;
+mod touch;
+#[cfg(feature = "tray")]
+mod tray;
+mod tubes;
+mod wallpaper;
 mod world;
 
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
 
-use cgmath::prelude::*;
-use log::{debug, error};
-use wgpu::util::DeviceExt;
+use anyhow::{Context, bail};
+use clap::Parser;
+use log::{error, info, warn};
 use winit::application::ApplicationHandler;
-use winit::event::{KeyEvent, WindowEvent};
-use winit::event_loop::{ActiveEventLoop, EventLoop};
-use winit::keyboard;
-use winit::keyboard::PhysicalKey;
-use winit::window::{Window, WindowId};
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, KeyEvent, MouseButton, Touch, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+use winit::keyboard::{ModifiersState, PhysicalKey};
+use winit::window::{Fullscreen, Window, WindowId, WindowLevel};
 
-use crate::models::Vertex;
-use crate::world::{Direction, PipeType, World};
+use crate::build::BuildTool;
+use crate::caps::Capabilities;
+use crate::chunks::{Bounds, Chunks};
+pub use crate::config::Config;
+use crate::config::{Cli, DayNight, MonitorMode, PipeStyle, Rgba, Scene, Theme, TimeOfDay};
+use crate::fixed_step::FixedStep;
+use crate::gizmos::GizmoLines;
+use crate::gpu_world::GpuWorld;
+use crate::graph::GraphFormat;
+use crate::i18n::{Language, Messages};
+use crate::instance::InstanceRaw;
+use crate::keybindings::{Action, KeyBindings};
+use crate::light::{DEFAULT_GROUND, DEFAULT_SKY};
+use crate::material::Materials;
+use crate::picking::{Ray, Selection};
+use crate::quality::QualityController;
+use crate::renderer::{CLEAR_COLOR, Renderer};
+use crate::session::{CameraPose, Session};
+use crate::simulation::{Command, SimulationHandle, TeamStats, WorldEvent};
+use crate::stacked::StackedSimulation;
+use crate::tubes::Tessellation;
+use crate::world::{Direction, WorldStats};
+
+// longer frames are clamped, so a stalled or paused window doesn't catch up all at once
+const MAX_FRAME_TIME: f32 = 0.25;
+#[cfg(feature = "gamepad")]
+const GAMEPAD_POLL_INTERVAL: Duration = Duration::from_millis(16);
+const REMOTE_POLL_INTERVAL: Duration = Duration::from_millis(16);
+#[cfg(feature = "tray")]
+const TRAY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+// how long an HTTP stats request waits for the simulation thread
+const STATS_TIMEOUT: Duration = Duration::from_secs(1);
+// frames of a turntable GIF for one turn of the camera, and hundredths of a second each shows for
+const TURNTABLE_FRAMES: u32 = 60;
+const TURNTABLE_DELAY: u16 = 5;
+#[cfg(feature = "audio")]
+const AUDIO_POLL_INTERVAL: Duration = Duration::from_millis(16);
+// how much brighter the light gets at full loudness
+#[cfg(feature = "audio")]
+const AUDIO_LIGHT_PULSE: f32 = 0.8;
+// segments grown at once on every beat
+#[cfg(feature = "audio")]
+const BEAT_BURST: u32 = 5;
+// beats between palette changes
+#[cfg(feature = "audio")]
+const BEATS_PER_PALETTE: u32 = 32;
+// ambient light a key press adds or takes, and the most it adds up to, see `Config::ambient`
+const AMBIENT_STEP: f32 = 0.5;
+const MAX_AMBIENT: f32 = 10.0;
+// rim lights the key cycles through after none
+const RIM_LIGHTS: [f32; 3] = [0.25, 0.5, 1.0];
+// stops of exposure a key press adds or takes, see `Config::exposure`
+const EXPOSURE_STEP: f32 = 0.5;
+// how often the day/night theme is checked against the clock
+const THEME_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// the title of every window, numbered when there are several
+pub(crate) const TITLE: &str = "XPipe";
+// how long the winner of a race of teams is shown after the world filled
+const WIN_SHOWN_FOR: Duration = Duration::from_secs(5);
+// how often the window title's stats are updated
+const TITLE_INTERVAL: Duration = Duration::from_secs(1);
+// how often solid pipes are sorted front to back while the camera moves or pipes grow
+const DEPTH_SORT_INTERVAL: Duration = Duration::from_millis(500);
+// Backgrounds the B key cycles through after the configured one
+const BACKGROUND_PRESETS: [wgpu::Color; 3] = [
+    wgpu::Color::BLACK,
+    wgpu::Color {
+        r: 0.002,
+        g: 0.004,
+        b: 0.02,
+        a: 1.0,
+    },
+    wgpu::Color {
+        r: 0.2,
+        g: 0.2,
+        b: 0.2,
+        a: 1.0,
+    },
+];
 
 pub struct State {
     window: Arc<Window>,
-    surface: wgpu::Surface<'static>,
+    // kept to create the surface again after a suspend
+    instance: wgpu::Instance,
+    // gone while the app is suspended, where the platform takes the window's surface away like Android
+    surface: Option<wgpu::Surface<'static>>,
     is_surface_configured: bool,
     surface_config: wgpu::SurfaceConfiguration,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    render_pipeline: wgpu::RenderPipeline,
-    light_render_pipeline: wgpu::RenderPipeline,
-    depth_texture: texture::Texture,
+    renderer: Renderer,
+    backgrounds: Vec<wgpu::Color>,
+    background_index: usize,
+    day_night: Option<DayNight>,
+    theme: Option<Theme>,
+    last_theme_check: Option<Instant>,
+    // brightness of the light set by the theme, before any pulses
+    light_intensity: f32,
+    // the built-in scenes and the config's own, and the one switched to last, see `Action::NextScene`
+    scenes: Vec<Scene>,
+    scene_index: Option<usize>,
+    // what new pipes are made of, scenes change only some of it
+    materials: Materials,
+    // seconds between shuffles, see `Config::shuffle_minutes`, and how long the world grew since the last
+    shuffle_seconds: Option<f32>,
+    since_shuffle: f32,
 
-    world: World,
+    simulation: SimulationHandle,
+    // worlds growing along with the main one, see `Config::stacked_worlds`
+    stacked_simulations: Vec<StackedSimulation>,
+    chunks: Chunks,
+    // how finely the chunks' tubes are swept, if they follow any
+    tessellation: Tessellation,
+    instances_dirty: bool,
+    is_glass: bool,
+    // where the camera was when instances were last sorted, and how many there were
+    sorted_from: cgmath::Point3<f32>,
+    sorted_segments: usize,
+    last_depth_sort: Option<Instant>,
+    // how long the last depth sort took, for the overlay
+    depth_sort_time: Duration,
+    last_update: Instant,
+    // steps the animations move on by, see `fixed_step`
+    fixed_step: FixedStep,
+    fps_cap: Option<f32>,
+    // lowers the render scale while frames are slow, see `Config::target_fps`
+    quality: Option<QualityController>,
+    idle_fps: Option<f32>,
+    // windows which never get focus, like wallpapers, would otherwise be idle all the time
+    idle_when_unfocused: bool,
+    last_frame: Instant,
+    is_focused: bool,
+    is_occluded: bool,
+    // sized 0×0, with no surface to draw to until restored
+    is_minimized: bool,
+    // growth and light animation are stopped, frames are only drawn on input
+    is_paused: bool,
+    // the simulation stops while the window is hidden, see `Config::pause_when_hidden`
+    pause_when_hidden: bool,
+    // nothing moves by itself but slowly growing pipes, see `Config::reduced_motion`
+    is_motion_reduced: bool,
+    // how long pipes drain away on a reset, unless motion is reduced or replaying
+    drain_seconds: f32,
+    // the world stopped growing to replay how it grew, see `Action::ToggleReplay`
+    is_replaying: bool,
+    // everything stands still with nothing drawn over the scene, for screenshots at `photo_scale`, see
+    // `Action::TogglePhotoMode`
+    is_photo_mode: bool,
+    photo_scale: u32,
+    turntable_width: u32,
+    // scrub keys held down
+    is_scrubbing_backward: bool,
+    is_scrubbing_forward: bool,
+    // something changed outside of the animation, e.g. a resize, which needs one more frame
+    needs_redraw: bool,
 
     camera: camera::Camera,
-    camera_uniform: camera::CameraUniform,
-    camera_bind_group: wgpu::BindGroup,
-    camera_buffer: wgpu::Buffer,
     camera_controller: camera::CameraController,
-
-    light_uniform: light::LightUniform,
-    light_bind_group: wgpu::BindGroup,
-    light_buffer: wgpu::Buffer,
-
-    instance_I_buffer: wgpu::Buffer,
-    instance_L_buffer: wgpu::Buffer,
-
-    pipe_model_I: models::Model,
-    pipe_model_L: models::Model,
+    split_screen: split_screen::SplitScreen,
+    touch_tracker: touch::TouchTracker,
+    #[cfg(feature = "scripting")]
+    script: Option<script::Script>,
+    // listed in the help overlay
+    keys: KeyBindings,
+    messages: Messages,
+    is_help_shown: bool,
+    // what the pipe graph is exported as
+    graph_format: GraphFormat,
+    // how much of the world each racing team took, shown in the overlay, and when the last winner was
+    // declared, see `Config::teams`
+    team_stats: Option<TeamStats>,
+    last_win: Option<(usize, Instant)>,
+    // where the mouse is over the window, and the segment last clicked on, shown in the overlay
+    cursor: Option<PhysicalPosition<f64>>,
+    selection: Option<Selection>,
+    // the world's bounds and axes are drawn, and where its pipes head if `has_gizmo_headings`, see
+    // `Action::ToggleGizmos`
+    has_gizmos: bool,
+    has_gizmo_headings: bool,
+    // placing pipes by hand while the world stops growing, see `Action::ToggleBuildMode`
+    build: Option<BuildTool>,
+    world_size: u32,
+    // blocks along X, Y and Z, stretched to the window's shape if `is_fit_to_aspect`, see
+    // `Config::fit_aspect`
+    world_extents: [u32; 3],
+    is_fit_to_aspect: bool,
+    cell_size: f32,
+    heads: Vec<((u32, u32, u32), Direction)>,
+    // held while a model file is dropped, Shift replaces the elbow rather than the straight pipe
+    modifiers: ModifiersState,
+    // the window's own title and the format it is shown in with live stats, see `Config::title`
+    title: String,
+    title_format: String,
+    frames_since_title: u32,
+    last_title_update: Instant,
+    // stats the title waits for from the simulation, with the frame rate when they were asked for
+    pending_title_stats: Option<(Receiver<WorldStats>, f32)>,
 }
 
 impl State {
-    pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
-        let size = window.inner_size();
-
+    /// Render into `window`, whose own title is `title`, on a device of its own
+    pub async fn new(window: Arc<Window>, title: &str, config: &Config) -> anyhow::Result<Self> {
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
             backends: wgpu::Backends::PRIMARY,
             ..Default::default()
@@ -70,21 +289,69 @@ impl State {
             })
             .await?;
 
+        let capabilities = Capabilities::of(&adapter);
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: Some("Device"),
-                required_features: wgpu::Features::empty(),
+                required_features: capabilities.required_features(config),
+                required_limits: capabilities.required_limits(),
                 ..Default::default()
             })
             .await?;
+        // only needed to find an adapter which can draw to the window, `with_device` makes its own
+        drop(surface);
+        Self::with_device(window, title, config, instance, &adapter, device, queue).await
+    }
+
+    /// Render into `window`, whose own title is `title`, on a device the caller requested from `adapter`,
+    /// e.g. to share it with an application embedding XPipe or to negotiate features and limits itself.
+    /// `adapter` has to be able to draw to surfaces of `window` created with `instance`. Features the
+    /// device lacks are left out, like GPU profiling without timestamp queries.
+    pub async fn with_device(
+        window: Arc<Window>,
+        title: &str,
+        config: &Config,
+        instance: wgpu::Instance,
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+    ) -> anyhow::Result<Self> {
+        let size = window.inner_size();
+        info!("Adapter: {:?}", adapter.get_info());
+        logging::record(&format!("{:?} adapter", window.id()), adapter.get_info());
 
-        let surface_capabilities = surface.get_capabilities(&adapter);
+        let surface = instance.create_surface(window.clone())?;
+        let surface_capabilities = surface.get_capabilities(adapter);
+        if surface_capabilities.formats.is_empty() {
+            bail!("{:?} cannot draw to the window", adapter.get_info().name);
+        }
         let surface_format = surface_capabilities
             .formats
             .iter()
             .find(|f| f.is_srgb())
             .copied()
             .unwrap_or(surface_capabilities.formats[0]);
+        if !surface_format.is_srgb() {
+            info!(
+                "No sRGB surface format, encoding gamma in the shader for {:?}",
+                surface_format
+            );
+        }
+
+        let alpha_mode = alpha_mode(config.alpha_mode, config.transparent, &surface_capabilities.alpha_modes);
+        info!("Alpha mode: {:?}", alpha_mode);
+        let supports_transparency = matches!(
+            alpha_mode,
+            wgpu::CompositeAlphaMode::PreMultiplied | wgpu::CompositeAlphaMode::PostMultiplied
+        );
+        let mut backgrounds = vec![config.background.map_or(CLEAR_COLOR, config::Rgba::to_linear)];
+        backgrounds.extend(BACKGROUND_PRESETS);
+        if supports_transparency {
+            backgrounds.push(wgpu::Color::TRANSPARENT);
+        }
+        for color in &mut backgrounds {
+            *color = clear_color(*color, alpha_mode);
+        }
 
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -93,400 +360,2234 @@ impl State {
             height: size.height,
             present_mode: surface_capabilities.present_modes[0],
             desired_maximum_frame_latency: 2,
-            alpha_mode: surface_capabilities.alpha_modes[0],
+            alpha_mode,
             view_formats: vec![],
         };
 
-        let camera = camera::Camera::new(size.width as f32, size.height as f32);
-        let mut camera_uniform = camera::CameraUniform::new();
-        camera_uniform.update_view_projection(&camera);
-
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("CameraBuffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        let mut camera = camera::Camera::new(size.width as f32, size.height as f32);
+        if config.clock {
+            clock::frame(&mut camera);
+        }
+        let camera_controller = camera::CameraController::new(0.01);
+        let mut split_screen = split_screen::SplitScreen::new(config.views as usize);
+        split_screen.set_stereo(config.stereo.then_some(config.ipd));
+        split_screen.set_reduced_motion(config.reduced_motion);
 
-        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("CameraBindGroupLayout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
+        let mut renderer = Renderer::new(
+            device,
+            queue,
+            surface_config.format,
+            config.depth(),
+            surface_config.width,
+            surface_config.height,
+            backgrounds[0],
+        )
+        .await?;
+        renderer.update_camera(&camera);
+        renderer.set_scale_factor(window.scale_factor() as f32);
+        renderer.set_glass(config.glass);
+        renderer.set_shading(config.shading);
+        renderer.set_render_scale(config.render_scale);
+        if let Err(e) = renderer.set_antialiasing(config.aa) {
+            warn!("Leaving edges jagged: {:?}", e);
+        }
+        renderer.set_floor(config.floor);
+        renderer.set_world_bounds(Some(Bounds::of_world(config.world_size, config.cell_size)));
+        renderer.set_pipe_radius(config.pipe_radius / config.cell_size);
+        renderer.set_tessellation(config.tessellation());
+        renderer.set_sparks(config.sparks);
+        renderer.set_depth_sorted(config.depth_sort);
+        renderer.set_depth_prepass(config.depth_prepass);
+        if let Err(e) = renderer.set_instance_storage(config.instance_storage) {
+            warn!("Reading instances from vertex attributes instead: {:?}", e);
+        }
+        renderer.set_parallel_encoding(config.parallel_encoding);
+        renderer.set_light_orbit(config.light_orbit);
+        renderer.set_ambient(config.ambient);
+        renderer.set_rim_light(config.rim_light);
+        renderer.set_sky_light(config.sky_light);
+        renderer.set_sky_colors(
+            config.sky_color.map_or(DEFAULT_SKY, config::Rgba::to_linear_rgb),
+            config.ground_color.map_or(DEFAULT_GROUND, config::Rgba::to_linear_rgb),
+        );
+        renderer.set_exposure(config.exposure);
+        if let Err(e) = renderer.set_auto_exposure(config.auto_exposure) {
+            warn!("Keeping the exposure as set: {:?}", e);
+        }
+        renderer.set_aging(config.aging);
+        renderer.set_reset_transition(config.reset_transition);
+        renderer.set_drain_seconds(if config.reduced_motion {
+            0.0
+        } else {
+            config.drain_seconds
         });
+        if let Err(e) = renderer.set_post_effect(config.post_effect.as_deref().and_then(blit::find_post_effect)) {
+            warn!("Post effect disabled: {:?}", e);
+        }
+        let gpu_world = if !config.gpu_generation {
+            None
+        } else if !GpuWorld::is_supported(adapter) {
+            warn!("Growing on the GPU is unavailable, the adapter can't run compute shaders or indirect draws");
+            None
+        } else {
+            GpuWorld::new(
+                &renderer.device,
+                config.world_size,
+                config.palette(),
+                config.opacity(),
+                config.materials(),
+                config.turn_probability,
+                config.max_pipe_length,
+            )
+            .inspect_err(|e| warn!("Growing on the CPU instead of the GPU: {:?}", e))
+            .ok()
+            .map(|mut gpu_world| {
+                gpu_world.set_cell_size(config.cell_size);
+                gpu_world
+            })
+        };
+        let is_grown_on_gpu = gpu_world.is_some();
+        let mut chunks = Chunks::new(config.world_size, config.cell_size);
+        chunks.set_tubes(config.pipe_style == PipeStyle::Tubes);
+        renderer.set_gpu_world(gpu_world);
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("CameraBindGroup"),
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-        });
+        let scenes = config.scenes();
+        let scene_index = (config.scene.as_ref()).and_then(|name| scenes.iter().position(|scene| scene.name == *name));
+        let mut state = Self {
+            window,
+            instance,
+            surface: Some(surface),
+            is_surface_configured: false,
+            surface_config,
+            renderer,
+            backgrounds,
+            background_index: 0,
+            day_night: config.day_night.clone(),
+            theme: None,
+            last_theme_check: None,
+            light_intensity: 1.0,
 
-        let camera_controller = camera::CameraController::new(0.01);
+            simulation: SimulationHandle::spawn(config, is_grown_on_gpu),
+            stacked_simulations: vec![],
+            chunks,
+            tessellation: config.tessellation(),
+            instances_dirty: false,
+            is_glass: config.glass,
+            sorted_from: camera.eye(),
+            sorted_segments: 0,
+            last_depth_sort: None,
+            depth_sort_time: Duration::ZERO,
+            last_update: Instant::now(),
+            fixed_step: FixedStep::default(),
+            fps_cap: config.fps_cap,
+            quality: (config.target_fps).map(|fps| {
+                let fps = config.fps_cap.map_or(fps, |cap| cap.min(fps));
+                QualityController::new(fps, config.render_scale)
+            }),
+            idle_fps: config.idle_fps,
+            idle_when_unfocused: true,
+            last_frame: Instant::now(),
+            is_focused: true,
+            is_occluded: false,
+            is_minimized: false,
+            is_paused: false,
+            pause_when_hidden: config.pause_when_hidden,
+            is_motion_reduced: config.reduced_motion,
+            drain_seconds: config.drain_seconds,
+            is_replaying: false,
+            is_photo_mode: false,
+            photo_scale: config.photo_scale,
+            turntable_width: config.turntable_width,
+            is_scrubbing_backward: false,
+            is_scrubbing_forward: false,
+            needs_redraw: true,
 
-        let light_uniform = light::LightUniform {
-            position: [2.0, 2.0, 2.0],
-            _padding1: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
+            camera,
+            camera_controller,
+            split_screen,
+            touch_tracker: touch::TouchTracker::new(),
+            #[cfg(feature = "scripting")]
+            script: config.script.as_deref().and_then(|path| {
+                script::Script::load(path)
+                    .inspect_err(|e| warn!("Script disabled: {:?}", e))
+                    .ok()
+            }),
+            keys: config.keys.clone(),
+            messages: Messages::new(config.language.unwrap_or_else(Language::system)),
+            is_help_shown: false,
+            graph_format: config.graph_format,
+            team_stats: None,
+            last_win: None,
+            cursor: None,
+            selection: None,
+            has_gizmos: false,
+            has_gizmo_headings: config.gizmo_headings,
+            build: None,
+            world_size: config.world_size,
+            world_extents: [config.world_size; 3],
+            is_fit_to_aspect: config.fit_aspect && !is_grown_on_gpu,
+            cell_size: config.cell_size,
+            heads: vec![],
+            modifiers: ModifiersState::empty(),
+            title: title.to_string(),
+            title_format: config.title.clone(),
+            frames_since_title: 0,
+            last_title_update: Instant::now(),
+            pending_title_stats: None,
+            scenes,
+            scene_index: None,
+            materials: config.materials(),
+            shuffle_seconds: config.shuffle_minutes.map(|minutes| minutes * 60.0),
+            since_shuffle: 0.0,
         };
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("LightBuffer"),
-            contents: bytemuck::cast_slice(&[light_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-        let light_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("LightBindGroupLayout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-        });
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("LightBindGroup"),
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-        });
+        state.fit_world_to_window();
+        // around the middle of the world once it's stretched
+        state.stacked_simulations = config
+            .stacked_worlds
+            .iter()
+            .map(|stacked| StackedSimulation::spawn(stacked, config, state.world_extents))
+            .collect();
+        if let Some(index) = scene_index {
+            state.switch_scene(index);
+        }
+        Ok(state)
+    }
 
-        let mut world = World::new();
-        for _ in 0..50 {
-            world.add_pipe();
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let was_hidden = self.is_hidden();
+        self.is_minimized = width == 0 || height == 0;
+        self.visibility_changed(was_hidden);
+        if self.is_minimized {
+            // There is nothing to draw to until the window is restored, and a surface can't be 0×0
+            self.is_surface_configured = false;
+        } else {
+            self.surface_config.width = width;
+            self.surface_config.height = height;
+            self.renderer.resize(width, height);
+            let Some(surface) = &self.surface else {
+                return;
+            };
+            surface.configure(&self.renderer.device, &self.surface_config);
+            logging::record(&format!("{:?} surface", self.window.id()), &self.surface_config);
+            self.is_surface_configured = true;
+            self.fit_world_to_window();
         }
+    }
 
-        let instance_data_I = world.get_I_pipe_instances().iter().map(instance::Instance::to_raw).collect::<Vec<_>>();
-        let instance_data_L = world.get_L_pipe_instances().iter().map(instance::Instance::to_raw).collect::<Vec<_>>();
+    /// Stretch the world to the window's shape, if it's fit to it, see `Config::fit_aspect`
+    fn fit_world_to_window(&mut self) {
+        if !self.is_fit_to_aspect {
+            return;
+        }
+        let aspect = self.surface_config.width as f32 / self.surface_config.height as f32;
+        let extents = world::extents_for_aspect(self.world_size, aspect);
+        if extents == self.world_extents {
+            return;
+        }
+        self.world_extents = extents;
+        self.simulation.send(Command::SetExtents(extents));
+        self.renderer
+            .set_world_bounds(Some(Bounds::of_extents(extents, self.cell_size)));
+        self.update_gizmos();
+    }
 
-        let instance_I_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("InstanceIBuffer"),
-            contents: bytemuck::cast_slice(&instance_data_I),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let instance_L_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("InstanceLBuffer"),
-            contents: bytemuck::cast_slice(&instance_data_L),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+    /// Follow the window onto a monitor of another DPI: size the overlay by the new scale factor, and the
+    /// surface and depth texture by the physical size the window has there
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        info!("Scale factor: {}", scale_factor);
+        self.renderer.set_scale_factor(scale_factor as f32);
+        let size = self.window.inner_size();
+        self.resize(size.width, size.height);
+        self.needs_redraw = true;
+    }
 
-        let depth_texture = texture::Texture::create_depth_texture(&device, &surface_config);
+    /// Let go of the surface, which the platform is about to take away, until `resume`
+    pub fn suspend(&mut self) {
+        self.surface = None;
+        self.is_surface_configured = false;
+    }
 
-        let render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("RenderPipelineLayout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-            Self::create_render_pipeline(
-                &device,
-                &layout,
-                surface_config.format,
-                &[models::ModelVertex::layout(), instance::InstanceRaw::layout()],
-                wgpu::include_wgsl!("shader.wgsl"),
-            )
-        };
+    /// Replace a surface the platform lost, e.g. over sleep and wake or a GPU reset
+    pub fn recreate_surface(&mut self) -> anyhow::Result<()> {
+        self.suspend();
+        self.resume()
+    }
 
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("LightRenderPipelineLayout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-            Self::create_render_pipeline(
-                &device,
-                &layout,
-                surface_config.format,
-                &[models::ModelVertex::layout()],
-                wgpu::include_wgsl!("light.wgsl"),
-            )
-        };
+    /// Draw to the window again after `suspend`, on a new surface
+    pub fn resume(&mut self) -> anyhow::Result<()> {
+        if self.surface.is_none() {
+            self.surface = Some(self.instance.create_surface(self.window.clone())?);
+            let size = self.window.inner_size();
+            self.resize(size.width, size.height);
+            self.needs_redraw = true;
+        }
+        Ok(())
+    }
 
-        let pipe_model_I = models::Model::load_model("pipe.obj", &device).await?;
-        let pipe_model_L = models::Model::load_model("curve.obj", &device).await?;
+    fn upload_instances(&mut self) {
+        self.renderer.upload_chunks(&mut self.chunks);
+        self.instances_dirty = false;
+    }
 
-        Ok(Self {
-            window,
-            surface,
-            is_surface_configured: false,
-            surface_config,
-            device,
-            queue,
-            render_pipeline,
-            light_render_pipeline,
-            depth_texture,
+    /// Apply everything the simulation thread grew since the last call
+    pub fn receive_world_events(&mut self) {
+        while let Some(event) = self.simulation.try_recv() {
+            match event {
+                WorldEvent::Reset => {
+                    self.chunks.clear();
+                    // the stacked worlds were cleared with it, so they start over as well
+                    for stacked in &self.stacked_simulations {
+                        stacked.simulation.send(Command::Reset);
+                    }
+                    self.select(None);
+                    self.set_heads(vec![]);
+                    #[cfg(feature = "scripting")]
+                    self.run_script(|script| script.on_reset());
+                }
+                WorldEvent::Appended {
+                    mut i_instances,
+                    mut l_instances,
+                } => {
+                    let now = self.renderer.time();
+                    for instance in i_instances.iter_mut().chain(&mut l_instances) {
+                        instance.set_birth(now);
+                    }
+                    if let Some(head) = i_instances.last().or(l_instances.last()) {
+                        self.split_screen.follow(head.position());
+                        // Sparks flash, which is what reduced motion spares people from
+                        if !self.is_motion_reduced {
+                            self.renderer.emit_sparks(head.position(), head.color());
+                        }
+                    }
+                    #[cfg(feature = "scripting")]
+                    for (i, instance) in i_instances.iter().chain(&l_instances).enumerate() {
+                        let segments = self.chunks.len() + i + 1;
+                        self.run_script(|script| script.on_pipe_added(instance.position(), segments));
+                    }
+                    self.chunks.append(i_instances, l_instances);
+                }
+                WorldEvent::Erased(blocks) => {
+                    self.chunks.erase(&blocks);
+                    self.select(None);
+                }
+                WorldEvent::Heads(heads) => self.set_heads(heads),
+                WorldEvent::Teams(stats) => {
+                    if let Some(winner) = stats.winner {
+                        self.last_win = Some((winner, Instant::now()));
+                    }
+                    self.team_stats = Some(stats);
+                    self.needs_redraw = true;
+                }
+            }
+            self.instances_dirty = true;
+        }
+        let now = self.renderer.time();
+        for (layer, stacked) in (1..).zip(&self.stacked_simulations) {
+            while let Some(event) = stacked.simulation.try_recv() {
+                match event {
+                    WorldEvent::Reset => self.chunks.clear_layer(layer),
+                    WorldEvent::Appended {
+                        i_instances,
+                        l_instances,
+                    } => {
+                        let place = |instance: &InstanceRaw| {
+                            let mut instance = stacked.place(instance);
+                            instance.set_birth(now);
+                            instance
+                        };
+                        let i_instances = i_instances.iter().map(place).collect();
+                        let l_instances = l_instances.iter().map(place).collect();
+                        self.chunks.append_to_layer(layer, i_instances, l_instances);
+                    }
+                    // nobody edits stacked worlds, and they have no teams
+                    WorldEvent::Erased(_) | WorldEvent::Teams(_) | WorldEvent::Heads(_) => continue,
+                }
+                self.instances_dirty = true;
+            }
+        }
+    }
 
-            world,
+    /// Render `other`'s world instead of this window's own one, without any stacked worlds
+    pub fn share_world_with(&mut self, other: &State) {
+        // Dropping the old handles stops this window's own simulation threads
+        self.simulation = other.simulation.subscribe();
+        self.stacked_simulations.clear();
+        self.chunks.clear();
+        self.instances_dirty = true;
+    }
 
-            camera,
-            camera_uniform,
-            camera_bind_group,
-            camera_buffer,
-            camera_controller,
+    /// Shift the camera in its view plane by a number of pixels of this window, e.g. to line up
+    /// windows of a multi-monitor setup
+    pub fn offset_camera(&mut self, dx: f32, dy: f32) {
+        self.camera.pan_pixels(dx, dy, self.surface_config.height as f32);
+    }
+
+    /// Lower the frame rate cap to `fps`, unless the configured one is lower already
+    pub fn limit_frame_rate(&mut self, fps: f32) {
+        self.fps_cap = Some(self.fps_cap.map_or(fps, |cap| cap.min(fps)));
+    }
 
-            light_uniform,
-            light_bind_group,
-            light_buffer,
+    pub fn set_idle_when_unfocused(&mut self, idle_when_unfocused: bool) {
+        self.idle_when_unfocused = idle_when_unfocused;
+    }
 
-            instance_I_buffer,
-            instance_L_buffer,
+    pub fn set_occluded(&mut self, is_occluded: bool) {
+        let was_hidden = self.is_hidden();
+        self.is_occluded = is_occluded;
+        self.visibility_changed(was_hidden);
+        self.needs_redraw = true;
+    }
 
-            pipe_model_I,
-            pipe_model_L,
-        })
+    /// Whether nobody can see the window, minimized or covered up
+    fn is_hidden(&self) -> bool {
+        self.is_minimized || self.is_occluded
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
-        if width > 0 && height > 0 {
-            self.surface_config.width = width;
-            self.surface_config.height = height;
-            self.surface.configure(&self.device, &self.surface_config);
-            self.is_surface_configured = true;
-            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.surface_config);
+    /// Whether the simulation stands still, by the user's pause or while hidden
+    fn is_simulation_paused(&self) -> bool {
+        self.is_frozen() || self.build.is_some() || (self.pause_when_hidden && self.is_hidden())
+    }
+
+    /// Whether the light, time and everything else animated stand still
+    fn is_frozen(&self) -> bool {
+        self.is_paused || self.is_photo_mode
+    }
+
+    /// Pause or resume the simulation if the window was just hidden or shown again
+    fn visibility_changed(&mut self, was_hidden: bool) {
+        let is_hidden = self.is_hidden();
+        if is_hidden == was_hidden {
+            return;
+        }
+        info!("{:?} {}", self.window.id(), if is_hidden { "hidden" } else { "shown" });
+        if self.pause_when_hidden {
+            self.send_to_every_world(|| Command::SetPaused(self.is_simulation_paused()));
+        }
+        if !is_hidden {
+            // the time spent hidden isn't caught up on, animations go on from where they stopped
+            self.last_update = Instant::now();
+            self.needs_redraw = true;
         }
     }
 
-    pub fn update(&mut self) {
-        // Update the light
-        let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-        self.light_uniform.position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(0.05)) * old_position).into();
-        self.queue
-            .write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light_uniform]));
-        // Update the camera
-        self.camera_controller.update_camera(&mut self.camera);
-        self.camera_uniform.update_view_projection(&self.camera);
-        self.queue
-            .write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+    fn is_idle(&self) -> bool {
+        self.is_occluded || (self.idle_when_unfocused && !self.is_focused)
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.window.request_redraw();
+    fn frame_interval(&self) -> Option<Duration> {
+        let fps = if self.is_idle() {
+            self.idle_fps.or(self.fps_cap)
+        } else {
+            self.fps_cap
+        };
+        fps.filter(|fps| *fps > 0.0)
+            .map(|fps| Duration::from_secs_f32(1.0 / fps))
+    }
 
-        if !self.is_surface_configured {
-            return Ok(());
+    /// Whether the next frame would look different from the last one
+    fn wants_frame(&self) -> bool {
+        self.needs_redraw
+            || !self.is_frozen()
+            || self.camera_controller.is_active()
+            || self.is_scrubbing()
+            || self.instances_dirty
+    }
+
+    /// When the next frame should be drawn, `None` if nothing is going to change or there is nowhere to draw
+    /// it, suspended or minimized
+    pub fn next_frame_time(&self) -> Option<Instant> {
+        if !self.wants_frame() || !self.is_surface_configured {
+            return None;
         }
+        Some(match self.frame_interval() {
+            Some(interval) => self.last_frame + interval,
+            None => self.last_frame,
+        })
+    }
+
+    /// Count the frame as drawn without drawing anything
+    fn skip_frame(&mut self) {
+        self.last_frame = Instant::now();
+    }
 
-        let output = self.surface.get_current_texture()?;
+    pub fn request_redraw(&mut self) {
+        self.needs_redraw = true;
+    }
 
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+    pub fn toggle_pause(&mut self) {
+        self.is_paused = !self.is_paused;
+        self.send_to_every_world(|| Command::SetPaused(self.is_simulation_paused()));
+        self.needs_redraw = true;
+        info!("{}", if self.is_paused { "Paused" } else { "Resumed" });
+    }
 
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("RenderEncoder"),
-        });
+    pub fn toggle_replay(&mut self) {
+        self.is_replaying = !self.is_replaying;
+        self.simulation.send(Command::SetReplay(self.is_replaying));
+        self.update_drain();
+        self.needs_redraw = true;
+        info!("Replay: {}", if self.is_replaying { "on" } else { "off" });
+    }
+
+    /// Whether the action scrubs through the replay, which it does for as long as its key is held
+    pub fn handle_scrub_action(&mut self, action: Action, is_pressed: bool) -> bool {
+        match action {
+            Action::ScrubBackward => self.is_scrubbing_backward = is_pressed,
+            Action::ScrubForward => self.is_scrubbing_forward = is_pressed,
+            _ => return false,
+        }
+        true
+    }
+
+    fn is_scrubbing(&self) -> bool {
+        self.is_replaying && self.is_scrubbing_backward != self.is_scrubbing_forward
+    }
+
+    /// Scrubbing back rebuilds the world every frame, which mustn't drain it each time
+    fn update_drain(&mut self) {
+        let is_drained = !self.is_motion_reduced && !self.is_replaying;
+        self.renderer
+            .set_drain_seconds(if is_drained { self.drain_seconds } else { 0.0 });
+    }
+
+    pub fn cycle_background(&mut self) {
+        self.background_index = (self.background_index + 1) % self.backgrounds.len();
+        self.renderer.set_clear_color(self.backgrounds[self.background_index]);
+        self.needs_redraw = true;
+    }
+
+    /// Switch to the scene after the one switched to last, or the first one
+    pub fn next_scene(&mut self) {
+        let index = self.scene_index.map_or(0, |index| (index + 1) % self.scenes.len());
+        self.switch_scene(index);
+    }
 
+    /// Change every setting `scenes[index]` has, leaving the others as they are
+    fn switch_scene(&mut self, index: usize) {
+        let scene = self.scenes[index].clone();
+        if let Some(probability) = scene.turn_probability {
+            self.simulation.send(Command::SetTurnProbability(probability));
+        }
+        if let Some(palette) = scene.palette() {
+            self.simulation.send(Command::SetPalette(palette));
+        }
+        if let Some(background) = scene.background {
+            self.set_background(background);
+        }
+        if scene.material.is_some() || scene.accent_material.is_some() || scene.accent_chance.is_some() {
+            let materials = &mut self.materials;
+            materials.material = scene.material.unwrap_or(materials.material);
+            materials.accent = scene.accent_material.unwrap_or(materials.accent);
+            materials.accent_chance = scene.accent_chance.unwrap_or(materials.accent_chance);
+            self.simulation.send(Command::SetMaterials(*materials));
+        }
+        if let Some(views) = scene.views {
+            self.split_screen.set_view_count(views as usize);
+        }
+        if let Some(shading) = scene.shading {
+            self.renderer.set_shading(shading);
+        }
+        if let Some(exposure) = scene.exposure {
+            self.renderer.set_exposure(exposure);
+        }
+        if let Some(ambient) = scene.ambient {
+            self.renderer.set_ambient(ambient);
+        }
+        if let Some(rim_light) = scene.rim_light {
+            self.renderer.set_rim_light(rim_light);
+        }
+        if let Some(has_floor) = scene.floor {
+            self.renderer.set_floor(has_floor);
+        }
+        if let Some(has_sparks) = scene.sparks {
+            self.renderer.set_sparks(has_sparks);
+        }
+        if let Some(effect) = scene.post_effect()
+            && let Err(e) = self.renderer.set_post_effect(effect)
         {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("RenderPass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    depth_slice: None,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.01,
-                            g: 0.01,
-                            b: 0.01,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
+            warn!("{:#}", e);
+        }
+        self.scene_index = Some(index);
+        self.needs_redraw = true;
+        info!("Scene: {}", scene.name);
+    }
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.light_bind_group, &[]);
-
-            if self.instance_L_buffer.size() > 0 {
-                let pipe_mesh = &self.pipe_model_L.meshes[0];
-                render_pass.set_vertex_buffer(0, pipe_mesh.vertex_buffer.slice(..));
-                render_pass.set_vertex_buffer(1, self.instance_L_buffer.slice(..));
-                render_pass.set_index_buffer(pipe_mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                render_pass.draw_indexed(
-                    0..pipe_mesh.num_elements,
-                    0,
-                    0..self.world.get_L_pipe_instances().len() as u32,
-                );
-            }
+    /// Lower or raise the render scale by how long the last frame took, if there is a target frame rate
+    fn adapt_quality(&mut self, frame_time: Duration) {
+        // Frames held back on purpose say nothing about how fast they could come
+        if self.is_idle() || !self.wants_frame() {
+            return;
+        }
+        let Some(quality) = &mut self.quality else {
+            return;
+        };
+        if let Some(scale) = quality.frame(frame_time) {
+            self.renderer.set_render_scale(scale);
+            info!("Render scale: {:.2}", scale);
+        }
+    }
 
-            if self.instance_I_buffer.size() > 0 {
-                let pipe_mesh = &self.pipe_model_I.meshes[0];
-                render_pass.set_vertex_buffer(0, pipe_mesh.vertex_buffer.slice(..));
-                render_pass.set_vertex_buffer(1, self.instance_I_buffer.slice(..));
-                render_pass.set_index_buffer(pipe_mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                render_pass.draw_indexed(
-                    0..pipe_mesh.num_elements,
-                    0,
-                    0..self.world.get_I_pipe_instances().len() as u32,
-                );
-            }
+    /// Shuffle once the world grew for `shuffle_seconds` since the last time
+    fn update_shuffle(&mut self, dt: f32) {
+        let Some(interval) = self.shuffle_seconds else {
+            return;
+        };
+        if self.is_simulation_paused() {
+            return;
+        }
+        self.since_shuffle += dt;
+        if self.since_shuffle >= interval {
+            self.since_shuffle = 0.0;
+            self.shuffle();
+        }
+    }
 
-            render_pass.set_pipeline(&self.light_render_pipeline);
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.light_bind_group, &[]);
-            let pipe_mesh = &self.pipe_model_L.meshes[0];
-            render_pass.set_vertex_buffer(0, pipe_mesh.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(pipe_mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            render_pass.draw_indexed(0..pipe_mesh.num_elements, 0, 0..1);
+    /// Switch to a random scene other than the current one, paint it with a random palette and start over
+    /// from a random seed, the old pipes draining away
+    fn shuffle(&mut self) {
+        let mut index = rand::random_range(0..self.scenes.len());
+        if self.scenes.len() > 1 && Some(index) == self.scene_index {
+            index = (index + 1) % self.scenes.len();
         }
+        self.switch_scene(index);
+        let palettes = palette::all();
+        let palette = palettes[rand::random_range(0..palettes.len())];
+        self.simulation.send(Command::SetPalette(palette));
+        info!("Palette: {}", palette.name);
+        self.start_over(Command::Reseed(rand::random()));
+    }
 
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    /// Clear to `background` from now on, unless the window is see-through, which a background would
+    /// cover up whatever is behind
+    fn set_background(&mut self, background: Rgba) {
+        if self.backgrounds[0].a < 1.0 {
+            return;
+        }
+        self.backgrounds[0] = clear_color(background.to_linear(), self.surface_config.alpha_mode);
+        self.background_index = 0;
+        self.renderer.set_clear_color(self.backgrounds[0]);
+    }
 
-        Ok(())
+    pub fn toggle_shading(&mut self) {
+        self.renderer.set_shading(self.renderer.shading().next());
+        self.needs_redraw = true;
     }
 
-    fn create_render_pipeline(
-        device: &wgpu::Device,
-        layout: &wgpu::PipelineLayout,
-        color_format: wgpu::TextureFormat,
-        vertex_layouts: &[wgpu::VertexBufferLayout],
-        shader: wgpu::ShaderModuleDescriptor,
-    ) -> wgpu::RenderPipeline {
-        let shader = device.create_shader_module(shader);
-
-        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("RenderPipeline"),
-            layout: Some(layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                compilation_options: Default::default(),
-                buffers: vertex_layouts,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                compilation_options: Default::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: color_format,
-                    blend: Some(wgpu::BlendState {
-                        alpha: wgpu::BlendComponent::REPLACE,
-                        color: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: Default::default(),
-                bias: Default::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
-        })
+    /// Change the ambient light by `steps` of `AMBIENT_STEP`, within 0 to `MAX_AMBIENT`
+    pub fn change_ambient(&mut self, steps: f32) {
+        let ambient = (self.renderer.ambient() + steps * AMBIENT_STEP).clamp(0.0, MAX_AMBIENT);
+        self.renderer.set_ambient(ambient);
+        self.needs_redraw = true;
     }
-}
 
-pub struct App {
-    state: Option<State>,
-}
+    /// Change the exposure by `steps` of `EXPOSURE_STEP`, within `exposure::MAX_STOPS` either way
+    pub fn change_exposure(&mut self, steps: f32) {
+        let max = exposure::MAX_STOPS;
+        let exposure = (self.renderer.exposure() + steps * EXPOSURE_STEP).clamp(-max, max);
+        self.renderer.set_exposure(exposure);
+        self.needs_redraw = true;
+    }
 
-impl App {
-    pub fn new() -> Self {
-        Self { state: None }
+    pub fn toggle_auto_exposure(&mut self) {
+        let has_auto_exposure = !self.renderer.has_auto_exposure();
+        if let Err(e) = self.renderer.set_auto_exposure(has_auto_exposure) {
+            warn!("{:#}", e);
+        }
+        self.needs_redraw = true;
     }
-}
 
-impl ApplicationHandler<State> for App {
-    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window_attributes = Window::default_attributes();
-        let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
+    /// Light the pipes' silhouettes with the next of `RIM_LIGHTS` stronger than now, or none after the
+    /// strongest
+    pub fn cycle_rim_light(&mut self) {
+        let rim = self.renderer.rim_light();
+        let next = RIM_LIGHTS.into_iter().find(|strength| *strength > rim).unwrap_or(0.0);
+        self.renderer.set_rim_light(next);
+        self.needs_redraw = true;
+    }
 
-        self.state = Some(pollster::block_on(State::new(window)).unwrap());
+    pub fn set_reduced_motion(&mut self, is_motion_reduced: bool) {
+        self.is_motion_reduced = is_motion_reduced;
+        self.split_screen.set_reduced_motion(is_motion_reduced);
+        self.update_drain();
+        self.send_to_every_world(|| Command::SetReducedMotion(is_motion_reduced));
+        self.needs_redraw = true;
+        info!("Reduced motion: {}", if is_motion_reduced { "on" } else { "off" });
     }
 
-    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: State) {
-        self.state = Some(event)
+    /// Sweep every tube again this finely, see `Config::tube_sides`
+    pub fn set_tessellation(&mut self, tessellation: Tessellation) {
+        self.tessellation = tessellation;
+        self.renderer.set_tessellation(tessellation);
+        if let Some(tubes) = self.chunks.tubes_mut() {
+            tubes.mark_all_changed();
+            self.instances_dirty = true;
+        }
+        self.needs_redraw = true;
     }
 
-    fn window_event(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
-        let state = match &mut self.state {
-            None => return,
-            Some(s) => s,
+    pub fn toggle_depth_sort(&mut self) {
+        let is_depth_sorted = !self.renderer.is_depth_sorted();
+        self.renderer.set_depth_sorted(is_depth_sorted);
+        // Sort right away rather than after the interval
+        self.sorted_segments = usize::MAX;
+        self.last_depth_sort = None;
+        self.needs_redraw = true;
+        info!("Depth sorting: {}", if is_depth_sorted { "on" } else { "off" });
+    }
+
+    pub fn toggle_sparks(&mut self) {
+        self.renderer.set_sparks(!self.renderer.has_sparks());
+        self.needs_redraw = true;
+    }
+
+    pub fn toggle_floor(&mut self) {
+        self.renderer.set_floor(!self.renderer.has_floor());
+        self.needs_redraw = true;
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let fullscreen = match self.window.fullscreen() {
+            Some(_) => None,
+            None => Some(Fullscreen::Borderless(None)),
         };
+        self.window.set_fullscreen(fullscreen);
+    }
 
-        match event {
-            WindowEvent::CloseRequested => event_loop.exit(),
-            WindowEvent::Resized(size) => state.resize(size.width, size.height),
-            WindowEvent::RedrawRequested => {
-                state.update();
-                match state.render() {
-                    Ok(_) => {}
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        let size = state.window.inner_size();
-                        state.resize(size.width, size.height);
-                    }
-                    Err(e) => error!("Cannot render window: {:?}", e),
-                }
+    /// Orbit the camera once around the world as it stands and save that as a looping GIF in the pictures
+    /// directory, `turntable_width` wide
+    pub fn export_turntable(&mut self) {
+        let result = self.render_turntable().and_then(|gif| {
+            let dir = dirs::picture_dir()
+                .or_else(dirs::home_dir)
+                .context("No directory to save turntables in")?;
+            let path = timestamped_path(&dir, "-turntable", "gif");
+            std::fs::write(&path, gif).with_context(|| format!("Cannot write {:?}", path))?;
+            Ok(path)
+        });
+        // the next update puts the views and the overlay back
+        self.update_gizmos();
+        self.needs_redraw = true;
+        match result {
+            Ok(path) => info!("Turntable saved to {:?}", path),
+            Err(e) => error!("Cannot save turntable: {:?}", e),
+        }
+    }
+
+    fn render_turntable(&mut self) -> anyhow::Result<Vec<u8>> {
+        let width = self.turntable_width;
+        let aspect = self.surface_config.height as f32 / self.surface_config.width.max(1) as f32;
+        let height = ((width as f32 * aspect).round() as u32).max(1);
+        let mut gif = gif::Gif::new(width as u16, height as u16, TURNTABLE_DELAY);
+        // only the main view turns, whatever the split screen shows
+        let mut camera = self.camera.clone();
+        self.renderer.set_overlay_text(None);
+        self.renderer.set_gizmos(None);
+        for _ in 0..TURNTABLE_FRAMES {
+            if self.is_glass {
+                self.chunks.sort_back_to_front(camera.eye());
+                self.sorted_from = camera.eye();
+                self.upload_instances();
             }
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        physical_key: PhysicalKey::Code(code),
-                        state: key_state,
-                        ..
-                    },
-                ..
-            } => {
-                let is_pressed = key_state.is_pressed();
-                if code == keyboard::KeyCode::Escape && is_pressed {
-                    event_loop.exit();
-                } else {
-                    state.camera_controller.handle_key(code, is_pressed);
-                }
+            self.renderer.update_cameras(&[&camera]);
+            gif.add_frame(&self.renderer.render_to_image_sized(width, height)?);
+            camera.orbit(std::f32::consts::TAU / TURNTABLE_FRAMES as f32, 0.0);
+        }
+        Ok(gif.finish())
+    }
+
+    /// Freeze everything and hide what is drawn over the scene, for screenshots at `photo_scale`, or bring
+    /// it all back
+    pub fn toggle_photo_mode(&mut self) {
+        self.is_photo_mode = !self.is_photo_mode;
+        self.send_to_every_world(|| Command::SetPaused(self.is_simulation_paused()));
+        self.update_gizmos();
+        match self.is_photo_mode {
+            true => {
+                self.renderer.set_selection(None);
+                self.renderer.set_outlined_pipe(None);
             }
-            _ => {}
+            false => self.select(self.selection),
         }
+        self.needs_redraw = true;
+        info!("Photo mode: {}", if self.is_photo_mode { "on" } else { "off" });
     }
-}
 
-pub fn run() -> anyhow::Result<()> {
-    env_logger::init();
-    let event_loop = EventLoop::with_user_event().build()?;
-    let mut app = App::new();
-    event_loop.run_app(&mut app)?;
+    /// Save the frame as a PNG in the pictures directory, in photo mode at `photo_scale` times the window's
+    /// resolution and named with the world's seed to grow it again
+    pub fn save_screenshot(&mut self) {
+        let (scale, name) = match self.is_photo_mode {
+            true => (
+                self.photo_scale,
+                self.seed().map(|seed| format!("-seed-{}", seed)).unwrap_or_default(),
+            ),
+            false => (1, String::new()),
+        };
+        let result = self.renderer.render_to_image_scaled(scale).and_then(|image| {
+            let dir = dirs::picture_dir()
+                .or_else(dirs::home_dir)
+                .context("No directory to save screenshots in")?;
+            let path = timestamped_path(&dir, &name, "png");
+            image.save(&path).with_context(|| format!("Cannot write {:?}", path))?;
+            Ok(path)
+        });
+        match result {
+            Ok(path) => info!("Screenshot saved to {:?}", path),
+            Err(e) => error!("Cannot save screenshot: {:?}", e),
+        }
+    }
+
+    /// Save the graph of this window's pipes in the documents directory, see `graph::Graph`
+    pub fn export_graph(&self) {
+        let (sender, receiver) = mpsc::channel();
+        self.simulation.send(Command::QueryGraph(sender));
+        let result = receiver
+            .recv_timeout(STATS_TIMEOUT)
+            .context("Simulation doesn't answer")
+            .and_then(|graph| {
+                let dir = dirs::document_dir()
+                    .or_else(dirs::home_dir)
+                    .context("No directory to save graphs in")?;
+                let path = timestamped_path(&dir, "", self.graph_format.extension());
+                std::fs::write(&path, graph.write(self.graph_format))
+                    .with_context(|| format!("Cannot write {:?}", path))?;
+                Ok((path, graph))
+            });
+        match result {
+            Ok((path, graph)) => info!(
+                "Graph of {} nodes and {} edges saved to {:?}",
+                graph.nodes.len(),
+                graph.edges.len(),
+                path
+            ),
+            Err(e) => error!("Cannot save the graph: {:?}", e),
+        }
+    }
+
+    /// Copy a share string of this window's world, see `recipe::Recipe`. `clipboard` is kept open for the
+    /// whole run, as on some platforms the text is gone along with it.
+    pub fn copy_recipe(&self, clipboard: &mut Option<clipboard::Clipboard>) {
+        let (sender, receiver) = mpsc::channel();
+        self.simulation.send(Command::QueryRecipe(sender));
+        let result = receiver
+            .recv_timeout(STATS_TIMEOUT)
+            .context("Simulation doesn't answer")
+            .and_then(|recipe| {
+                let share = recipe.to_string();
+                let clipboard = match clipboard {
+                    Some(clipboard) => clipboard,
+                    None => clipboard.insert(clipboard::Clipboard::new()?),
+                };
+                clipboard.set_text(&share)?;
+                Ok(share)
+            });
+        match result {
+            Ok(share) => info!("Copied {}, grow the same world with --from-share {}", share, share),
+            Err(e) => error!("Cannot copy the share string: {:?}", e),
+        }
+    }
+
+    /// Draw straight pipes, or elbows for `is_elbow`, with the model in an OBJ or glTF file
+    pub fn load_pipe_model(&mut self, path: &Path, is_elbow: bool) {
+        match models::Model::load_file(path, &self.renderer.device) {
+            Ok(model) => {
+                self.renderer.set_pipe_model(is_elbow, model);
+                self.needs_redraw = true;
+                info!("{} model: {:?}", if is_elbow { "Elbow" } else { "Pipe" }, path);
+            }
+            Err(e) => error!("Cannot load model {:?}: {:?}", path, e),
+        }
+    }
+
+    pub fn toggle_help(&mut self) {
+        self.is_help_shown = !self.is_help_shown;
+        self.needs_redraw = true;
+    }
+
+    /// Every bound key and what it does, then the current settings
+    fn help_text(&self) -> String {
+        let messages = &self.messages;
+        let mut text = format!("{}\n", messages.get("keys"));
+        for (action, keys) in self.keys.iter().filter(|(_, keys)| !keys.is_empty()) {
+            let ctrl = if action.is_with_ctrl() { "Ctrl+" } else { "" };
+            let keys = keys
+                .iter()
+                .map(|key| format!("{}{}", ctrl, keybindings::key_name(*key)))
+                .collect::<Vec<_>>();
+            text += &format!("  {:<16} {}\n", keys.join(", "), messages.get(action.message_id()));
+        }
+
+        let on_off = |is_on| messages.get(if is_on { "on" } else { "off" }).to_string();
+        text += &format!("\n{}\n", messages.get("settings"));
+        let scene = self.scene_index.map_or("-", |index| self.scenes[index].name.as_str());
+        for (id, value) in [
+            ("setting-scene", scene.to_string()),
+            (
+                "setting-shuffle",
+                self.shuffle_seconds
+                    .map_or_else(|| on_off(false), |seconds| format!("{} min", seconds / 60.0)),
+            ),
+            ("setting-shading", format!("{:?}", self.renderer.shading())),
+            ("setting-aa", format!("{:?}", self.renderer.antialiasing())),
+            ("setting-render-scale", format!("{:.2}", self.renderer.render_scale())),
+            ("setting-views", self.split_screen.view_count().to_string()),
+            ("setting-glass", on_off(self.is_glass)),
+            ("setting-floor", on_off(self.renderer.has_floor())),
+            ("setting-sparks", on_off(self.renderer.has_sparks())),
+            ("setting-ambient", format!("{:.2}", self.renderer.ambient())),
+            ("setting-rim-light", format!("{:.2}", self.renderer.rim_light())),
+            ("setting-sky-light", format!("{:.2}", self.renderer.sky_light())),
+            (
+                "setting-exposure",
+                format!(
+                    "{:+.1} EV (×{:.2})",
+                    self.renderer.exposure(),
+                    self.renderer.exposure_scale()
+                ),
+            ),
+            ("setting-auto-exposure", on_off(self.renderer.has_auto_exposure())),
+            ("setting-reduced-motion", on_off(self.is_motion_reduced)),
+            ("setting-paused", on_off(self.is_paused)),
+            ("setting-replay", on_off(self.is_replaying)),
+        ] {
+            text += &format!("  {:<16} {}\n", messages.get(id), value);
+        }
+
+        // What depth sorting costs on the CPU and what the GPU spends drawing, to compare it on and off
+        let sort_time = self.depth_sort_time.as_secs_f64() * 1000.0;
+        let depth_sort = match self.renderer.is_depth_sorted() {
+            true => format!("{} ({:.2} ms)", on_off(true), sort_time),
+            false => on_off(false),
+        };
+        text += &format!("  {:<16} {}\n", messages.get("setting-depth-sort"), depth_sort);
+        let depth_prepass = on_off(self.renderer.has_depth_prepass());
+        text += &format!("  {:<16} {}\n", messages.get("setting-depth-prepass"), depth_prepass);
+        let instance_storage = on_off(self.renderer.is_instance_storage());
+        text += &format!(
+            "  {:<16} {}\n",
+            messages.get("setting-instance-storage"),
+            instance_storage
+        );
+        // Like depth sorting, with what encoding the last frame cost on the CPU
+        let encode_time = self.renderer.encode_time().as_secs_f64() * 1000.0;
+        text += &format!(
+            "  {:<16} {} ({:.2} ms)\n",
+            messages.get("setting-parallel-encoding"),
+            on_off(self.renderer.is_parallel_encoding()),
+            encode_time
+        );
+        let upload_kib = self.renderer.upload_bytes() as f64 / 1024.0;
+        text += &format!("  {:<16} {:.1} KiB\n", messages.get("uploads"), upload_kib);
+        for (pass, ms) in self.renderer.pass_times() {
+            text += &format!("  {:<16} {:.3} ms\n", format!("GPU {}", pass), ms);
+        }
+        text
+    }
+
+    /// How much of the world each racing team took, and who won the last race for a while
+    fn team_text(&self) -> Option<String> {
+        let stats = self.team_stats.as_ref()?;
+        let team = self.messages.get("team");
+        let mut text = String::new();
+        for (i, fill) in stats.fills.iter().enumerate() {
+            text += &format!("{} {:<4} {:5.1}%\n", team, i + 1, fill * 100.0);
+        }
+        if let Some((winner, at)) = self.last_win
+            && at.elapsed() < WIN_SHOWN_FOR
+        {
+            text += &format!("\n{} {} {}\n", team, winner + 1, self.messages.get("team-wins"));
+        }
+        Some(text)
+    }
+
+    pub fn toggle_gizmos(&mut self) {
+        self.has_gizmos = !self.has_gizmos;
+        self.update_gizmos();
+        self.needs_redraw = true;
+    }
+
+    fn set_heads(&mut self, heads: Vec<((u32, u32, u32), Direction)>) {
+        if heads != self.heads {
+            self.heads = heads;
+            if self.has_gizmos && self.has_gizmo_headings {
+                self.update_gizmos();
+            }
+        }
+    }
+
+    fn update_gizmos(&mut self) {
+        // the world's bounds help to see where in it a pipe is built
+        let is_shown = (self.has_gizmos || self.build.is_some()) && !self.is_photo_mode;
+        let lines = is_shown.then(|| {
+            let mut lines = GizmoLines::world(self.world_extents, self.cell_size);
+            if self.has_gizmos && self.has_gizmo_headings {
+                lines.add_headings(&self.heads);
+            }
+            if let Some(build) = self.build
+                && let Some(target) = build.target()
+            {
+                lines.add_build_target(target, build.direction);
+            }
+            lines
+        });
+        self.renderer.set_gizmos(lines.as_ref());
+    }
+
+    /// Start or stop placing pipes by hand, which stops the world growing by itself meanwhile
+    pub fn toggle_build_mode(&mut self) {
+        self.build = match self.build {
+            Some(_) => None,
+            None => Some(BuildTool::default()),
+        };
+        self.send_to_every_world(|| Command::SetPaused(self.is_simulation_paused()));
+        self.aim_build_tool();
+        self.update_gizmos();
+        self.needs_redraw = true;
+        info!("Build mode: {}", if self.build.is_some() { "on" } else { "off" });
+    }
+
+    /// Change what build mode places, if building
+    pub fn change_build_tool(&mut self, change: impl FnOnce(&mut BuildTool)) {
+        let Some(build) = &mut self.build else {
+            return;
+        };
+        change(build);
+        self.update_gizmos();
+        self.needs_redraw = true;
+    }
+
+    /// Place a pipe at the block build mode aims at, if building
+    pub fn build_pipe(&mut self) {
+        if let Some(command) = self.build.and_then(|build| build.command()) {
+            self.simulation.send(command);
+        }
+    }
+
+    /// Step back the latest change to the world, pausing it so that it doesn't grow right back
+    pub fn undo(&mut self) {
+        if !self.is_simulation_paused() {
+            self.toggle_pause();
+        }
+        self.simulation.send(Command::Undo);
+    }
+
+    /// Aim build mode through the middle of the view, if building
+    fn aim_build_tool(&mut self) {
+        let Some(build) = &mut self.build else {
+            return;
+        };
+        let ray = self.camera.ray(0.0, 0.0);
+        if build.aim(&self.chunks, &ray, self.world_extents) {
+            self.update_gizmos();
+        }
+    }
+
+    /// Select the segment under the cursor in whichever view it is over, or nothing if there is none
+    pub fn pick_at_cursor(&mut self) {
+        let Some(ray) = self.cursor_ray() else {
+            return;
+        };
+        let selection = self.chunks.pick(&ray);
+        match &selection {
+            Some(selection) => info!("Selected {}", selection.to_string().replace('\n', ", ")),
+            None => info!("Selected nothing"),
+        }
+        self.select(selection);
+    }
+
+    /// Take the segment under the cursor out of the world, or its whole pipe if `is_whole_pipe`
+    pub fn erase_at_cursor(&mut self, is_whole_pipe: bool) {
+        let Some(selection) = self.cursor_ray().and_then(|ray| self.chunks.pick(&ray)) else {
+            return;
+        };
+        let blocks = match is_whole_pipe {
+            true => {
+                let (i_instances, l_instances) = self.chunks.pipe_instances(&selection);
+                i_instances
+                    .iter()
+                    .map(|i| (i, false))
+                    .chain(l_instances.iter().map(|l| (l, true)))
+                    .map(|(instance, is_elbow)| Selection::of_instance(instance, is_elbow, self.cell_size).block)
+                    .collect()
+            }
+            false => vec![selection.block],
+        };
+        let blocks = blocks
+            .into_iter()
+            .map(|(x, y, z)| (x as u32, y as u32, z as u32))
+            .collect();
+        self.simulation.send(Command::Erase(blocks));
+    }
+
+    /// The ray under the cursor in whichever view it is over, if it is over one
+    fn cursor_ray(&self) -> Option<Ray> {
+        let cursor = self.cursor?;
+        let (x, y) = (cursor.x as f32, cursor.y as f32);
+        let viewports = split_screen::layout(
+            self.split_screen.view_count(),
+            self.surface_config.width,
+            self.surface_config.height,
+        );
+        let cameras = self.split_screen.cameras(&self.camera);
+        let (viewport, camera) = viewports.iter().zip(cameras).find(|(viewport, _)| {
+            (viewport.x as f32..(viewport.x + viewport.width) as f32).contains(&x)
+                && (viewport.y as f32..(viewport.y + viewport.height) as f32).contains(&y)
+        })?;
+        Some(camera.ray(
+            (x - viewport.x as f32) / viewport.width as f32 * 2.0 - 1.0,
+            1.0 - (y - viewport.y as f32) / viewport.height as f32 * 2.0,
+        ))
+    }
+
+    fn select(&mut self, selection: Option<Selection>) {
+        self.renderer.set_selection(selection.as_ref());
+        let pipe = selection
+            .as_ref()
+            .map(|selection| self.chunks.pipe_instances(selection));
+        (self.renderer).set_outlined_pipe(pipe.as_ref().map(|(i, l)| (i.as_slice(), l.as_slice())));
+        self.selection = selection;
+        self.needs_redraw = true;
+    }
+
+    pub fn cycle_views(&mut self) {
+        self.split_screen.cycle();
+        self.needs_redraw = true;
+        info!("Views: {}", self.split_screen.view_count());
+    }
+
+    pub fn handle_remote(&mut self, command: &remote::RemoteCommand) {
+        match command {
+            remote::RemoteCommand::AddPipes(count) => self.simulation.send(Command::Grow(*count)),
+            remote::RemoteCommand::Reset => self.reset_world(),
+            remote::RemoteCommand::SetTurnProbability(probability) => {
+                self.simulation.send(Command::SetTurnProbability(*probability))
+            }
+            remote::RemoteCommand::SetDirectionWeights(weights) => {
+                self.simulation.send(Command::SetDirectionWeights(*weights))
+            }
+            remote::RemoteCommand::SetPalette(name) => match palette::find(name) {
+                Some(palette) => self.simulation.send(Command::SetPalette(palette)),
+                None => warn!("Unknown palette {:?}", name),
+            },
+            remote::RemoteCommand::MoveLight(degrees) => self.renderer.orbit_light(cgmath::Deg(*degrees)),
+        }
+        self.needs_redraw = true;
+    }
+
+    /// Run a hook of the script, if there is one, and do what it asks for. A failing script is dropped,
+    /// rather than failing again every frame.
+    #[cfg(feature = "scripting")]
+    fn run_script(&mut self, hook: impl FnOnce(&mut script::Script) -> anyhow::Result<Vec<script::ScriptAction>>) {
+        let Some(script) = &mut self.script else {
+            return;
+        };
+        let actions = match hook(script) {
+            Ok(actions) => actions,
+            Err(e) => {
+                error!("Script disabled: {:?}", e);
+                self.script = None;
+                return;
+            }
+        };
+        for action in actions {
+            match action {
+                script::ScriptAction::AddPipes(count) => self.simulation.send(Command::Grow(count)),
+                script::ScriptAction::Reset => self.reset_world(),
+                script::ScriptAction::SetPalette(name) => match palette::find(&name) {
+                    Some(palette) => self.simulation.send(Command::SetPalette(palette)),
+                    None => warn!("Script asked for unknown palette {:?}", name),
+                },
+                script::ScriptAction::SetTurnProbability(probability) => {
+                    self.simulation.send(Command::SetTurnProbability(probability))
+                }
+                script::ScriptAction::ColorAllPipes(color) => {
+                    for instance in self.chunks.instances_mut() {
+                        instance.set_color(color);
+                    }
+                    self.instances_dirty = true;
+                }
+                script::ScriptAction::OrbitCamera { yaw, pitch } => self.camera.orbit(yaw, pitch),
+                script::ScriptAction::ZoomCamera(factor) => self.camera.zoom(factor),
+                script::ScriptAction::MoveLight(degrees) => self.renderer.orbit_light(cgmath::Deg(degrees)),
+                script::ScriptAction::SetLightIntensity(intensity) => {
+                    self.light_intensity = intensity;
+                    self.renderer.set_light_intensity(intensity);
+                }
+            }
+        }
+        self.needs_redraw = true;
+    }
+
+    /// The seed the world grows from, `None` if the simulation doesn't answer
+    fn seed(&self) -> Option<u64> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.simulation.send(Command::QueryStats(sender));
+        Some(receiver.recv_timeout(STATS_TIMEOUT).ok()?.seed)
+    }
+
+    /// The world's stats as JSON, `None` if the simulation doesn't answer
+    fn stats(&self) -> Option<String> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.simulation.send(Command::QueryStats(sender));
+        let stats = receiver.recv_timeout(STATS_TIMEOUT).ok()?;
+        Some(format!(
+            r#"{{"pipes": {}, "segments": {}, "turns": {}, "fill": {}, "seed": {}, "paused": {}}}"#,
+            stats.pipes, stats.segments, stats.turns, stats.fill, stats.seed, self.is_paused
+        ))
+    }
+
+    /// Everything to pick up where this window is now with `--resume`, `None` if the simulation doesn't
+    /// answer. Settings changed while running are saved as they are now.
+    fn session(&self, config: &Config) -> Option<Session> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.simulation.send(Command::Snapshot(sender));
+        let world = receiver.recv_timeout(STATS_TIMEOUT).ok()?;
+        let mut settings = config.clone();
+        settings.shading = self.renderer.shading();
+        settings.aa = self.renderer.antialiasing();
+        settings.floor = self.renderer.has_floor();
+        settings.sparks = self.renderer.has_sparks();
+        settings.ambient = self.renderer.ambient();
+        settings.rim_light = self.renderer.rim_light();
+        settings.sky_light = self.renderer.sky_light();
+        settings.exposure = self.renderer.exposure();
+        settings.auto_exposure = self.renderer.has_auto_exposure();
+        settings.depth_sort = self.renderer.is_depth_sorted();
+        settings.depth_prepass = self.renderer.has_depth_prepass();
+        settings.instance_storage = self.renderer.is_instance_storage();
+        settings.parallel_encoding = self.renderer.is_parallel_encoding();
+        settings.reduced_motion = self.is_motion_reduced;
+        let (eye, target) = (self.camera.eye(), self.camera.target());
+        Some(Session {
+            settings,
+            camera: CameraPose {
+                eye: eye.into(),
+                target: target.into(),
+            },
+            world,
+        })
+    }
+
+    fn restore(&mut self, session: &Session) {
+        self.is_replaying = false;
+        self.update_drain();
+        self.simulation.send(Command::Restore(Box::new(session.world.clone())));
+        self.camera
+            .look_at(session.camera.eye.into(), session.camera.target.into());
+        self.needs_redraw = true;
+    }
+
+    /// Draw the current frame into a PNG file's bytes
+    fn snapshot_png(&mut self) -> anyhow::Result<Vec<u8>> {
+        let image = self.renderer.render_to_image()?;
+        let mut png = std::io::Cursor::new(vec![]);
+        image.write_to(&mut png, image::ImageFormat::Png)?;
+        Ok(png.into_inner())
+    }
+
+    /// Change a setting by its name in the config, from text
+    fn configure(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        let parse_bool = |value: &str| {
+            value
+                .parse::<bool>()
+                .with_context(|| format!("Expected true or false for {}, got {:?}", name, value))
+        };
+        match name {
+            "palette" => {
+                let palette = palette::find(value).with_context(|| format!("Unknown palette {:?}", value))?;
+                self.simulation.send(Command::SetPalette(palette));
+            }
+            "turn_probability" => {
+                let probability = value
+                    .parse()
+                    .with_context(|| format!("Expected a number for {}, got {:?}", name, value))?;
+                self.simulation.send(Command::SetTurnProbability(probability));
+            }
+            "direction_weights" => self.simulation.send(Command::SetDirectionWeights(value.parse()?)),
+            "shading" => {
+                let shading = <config::Shading as clap::ValueEnum>::from_str(value, true)
+                    .map_err(|e| anyhow::anyhow!("Invalid shading {:?}: {}", value, e))?;
+                self.renderer.set_shading(shading);
+            }
+            "aa" => {
+                let aa = <config::Antialiasing as clap::ValueEnum>::from_str(value, true)
+                    .map_err(|e| anyhow::anyhow!("Invalid anti-aliasing {:?}: {}", value, e))?;
+                self.renderer.set_antialiasing(aa)?;
+            }
+            "floor" => self.renderer.set_floor(parse_bool(value)?),
+            "sparks" => self.renderer.set_sparks(parse_bool(value)?),
+            "ambient" | "rim_light" | "sky_light" => {
+                let strength: f32 = value
+                    .parse()
+                    .with_context(|| format!("Expected a number for {}, got {:?}", name, value))?;
+                if strength < 0.0 {
+                    bail!("{} must not be negative, got {}", name, strength);
+                }
+                match name {
+                    "ambient" => self.renderer.set_ambient(strength),
+                    "rim_light" => self.renderer.set_rim_light(strength),
+                    _ => self.renderer.set_sky_light(strength),
+                }
+            }
+            "exposure" => {
+                let stops: f32 = value
+                    .parse()
+                    .with_context(|| format!("Expected a number of stops for exposure, got {:?}", value))?;
+                if stops.abs() > exposure::MAX_STOPS {
+                    bail!(
+                        "Exposure must be within {} stops either way, got {}",
+                        exposure::MAX_STOPS,
+                        stops
+                    );
+                }
+                self.renderer.set_exposure(stops);
+            }
+            "auto_exposure" => self.renderer.set_auto_exposure(parse_bool(value)?)?,
+            "depth_sort" => self.renderer.set_depth_sorted(parse_bool(value)?),
+            "depth_prepass" => self.renderer.set_depth_prepass(parse_bool(value)?),
+            "instance_storage" => self.renderer.set_instance_storage(parse_bool(value)?)?,
+            "parallel_encoding" => self.renderer.set_parallel_encoding(parse_bool(value)?),
+            "tube_sides" | "tube_segments_per_unit" => {
+                let count: u32 = value
+                    .parse()
+                    .with_context(|| format!("Expected a whole number for {}, got {:?}", name, value))?;
+                let mut tessellation = self.tessellation;
+                match name {
+                    "tube_sides" => tessellation.sides = count.clamp(tubes::MIN_SIDES, tubes::MAX_SIDES),
+                    _ => tessellation.segments_per_unit = count.clamp(1, tubes::MAX_SEGMENTS_PER_UNIT),
+                }
+                self.set_tessellation(tessellation);
+            }
+            "reduced_motion" => self.set_reduced_motion(parse_bool(value)?),
+            "paused" => {
+                if parse_bool(value)? != self.is_paused {
+                    self.toggle_pause();
+                }
+            }
+            _ => bail!(
+                "Unknown setting {:?}, available: palette, turn_probability, direction_weights, shading, aa, floor, sparks, \
+                 ambient, rim_light, sky_light, exposure, auto_exposure, depth_sort, depth_prepass, instance_storage, parallel_encoding, tube_sides, \
+                 tube_segments_per_unit, reduced_motion, paused",
+                name
+            ),
+        }
+        self.needs_redraw = true;
+        Ok(())
+    }
+
+    /// Show the latest frame rate and world stats in the window title, every `TITLE_INTERVAL`
+    fn update_title(&mut self, now: Instant) {
+        if self.title_format.is_empty() {
+            return;
+        }
+        self.frames_since_title += 1;
+        if let Some((received, fps)) = self
+            .pending_title_stats
+            .as_ref()
+            .map(|(receiver, fps)| (receiver.try_recv(), *fps))
+        {
+            match received {
+                Ok(stats) => {
+                    self.window
+                        .set_title(&format_title(&self.title_format, &self.title, fps, &stats));
+                    self.pending_title_stats = None;
+                }
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => self.pending_title_stats = None,
+            }
+        }
+
+        let elapsed = now - self.last_title_update;
+        if elapsed < TITLE_INTERVAL {
+            return;
+        }
+        // Asked for without waiting, the answer is picked up on a later frame
+        let (sender, receiver) = mpsc::channel();
+        self.simulation.send(Command::QueryStats(sender));
+        let fps = self.frames_since_title as f32 / elapsed.as_secs_f32();
+        self.pending_title_stats = Some((receiver, fps));
+        self.frames_since_title = 0;
+        self.last_title_update = now;
+    }
+
+    /// Switch to the day or night theme, if it is time to
+    fn update_theme(&mut self) {
+        let Some(day_night) = &self.day_night else {
+            return;
+        };
+        if self
+            .last_theme_check
+            .is_some_and(|time| time.elapsed() < THEME_CHECK_INTERVAL)
+        {
+            return;
+        }
+        self.last_theme_check = Some(Instant::now());
+
+        let (hour, minute) = clock::now();
+        let theme = day_night.theme_at(TimeOfDay::new(hour, minute));
+        if self.theme.as_ref() == Some(theme) {
+            return;
+        }
+        let theme = theme.clone();
+        info!("Theme: {:?}", theme);
+        self.simulation.send(Command::SetPalette(theme.palette()));
+        if let Some(background) = theme.background {
+            self.set_background(background);
+        }
+        self.light_intensity = theme.light;
+        self.renderer.set_light_intensity(theme.light);
+        self.theme = Some(theme);
+        self.needs_redraw = true;
+    }
+
+    pub fn reset_world(&mut self) {
+        self.start_over(Command::Reset);
+    }
+
+    /// Have the world start over by `command`
+    fn start_over(&mut self, command: Command) {
+        // The world starting over ends the replay as well
+        self.is_replaying = false;
+        self.update_drain();
+        self.simulation.send(command);
+        self.renderer.reset_gpu_world();
+    }
+
+    /// Send the command `command` makes to the main world's simulation and to every stacked world's
+    fn send_to_every_world(&self, command: impl Fn() -> Command) {
+        self.simulation.send(command());
+        for stacked in &self.stacked_simulations {
+            stacked.simulation.send(command());
+        }
+    }
+
+    pub fn scale_growth_speed(&mut self, factor: f32) {
+        self.send_to_every_world(|| Command::ScaleGrowthSpeed(factor));
+    }
+
+    /// Pulse the light with the loudness, grow a burst of pipes on every beat and change palettes
+    /// every few beats
+    #[cfg(feature = "audio")]
+    fn react_to_audio(&mut self, frame: &audio::AudioFrame) {
+        // Pulses, bursts and palette changes are all sudden
+        if self.is_motion_reduced {
+            return;
+        }
+        self.renderer
+            .set_light_intensity(self.light_intensity * (1.0 + frame.loudness * AUDIO_LIGHT_PULSE));
+        if frame.is_beat && !self.is_frozen() {
+            self.simulation.send(Command::Grow(BEAT_BURST));
+            if frame.beat_count % BEATS_PER_PALETTE == 0 {
+                self.simulation.send(Command::NextPalette);
+            }
+        }
+        self.needs_redraw = true;
+    }
+
+    pub fn handle_touch(&mut self, touch: &Touch) {
+        // Normalize by the window height so gestures feel the same at any resolution
+        let height = self.surface_config.height.max(1) as f32;
+        for gesture in self.touch_tracker.handle(touch) {
+            match gesture {
+                touch::Gesture::Orbit { dx, dy } => self
+                    .camera_controller
+                    .orbit_by(dx / height * std::f32::consts::PI, dy / height * std::f32::consts::PI),
+                touch::Gesture::Pinch { scale } => self.camera_controller.zoom_by(1.0 / scale),
+                touch::Gesture::Pan { dx, dy } => self.camera_controller.pan_by(dx / height, dy / height),
+            }
+        }
+    }
+
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let dt = (now - self.last_update).as_secs_f32().min(MAX_FRAME_TIME);
+        // Whole steps of animation, so that the light, time and GPU growth are where they would be at any
+        // refresh rate
+        let step_time = self.fixed_step.steps(now - self.last_update) as f32 * fixed_step::STEP_SECONDS;
+        self.adapt_quality(now - self.last_update);
+        self.last_update = now;
+        self.last_frame = now;
+
+        self.update_theme();
+        self.update_shuffle(dt);
+        self.update_title(now);
+        #[cfg(feature = "scripting")]
+        {
+            let segments = self.chunks.len();
+            self.run_script(|script| script.on_frame(dt, segments));
+        }
+        if !self.is_frozen() {
+            if !self.is_motion_reduced {
+                let speed = self.renderer.light_orbit().speed;
+                self.renderer.orbit_light(cgmath::Deg(speed * step_time));
+            }
+            self.renderer.advance_time(step_time);
+            self.renderer.grow_gpu_world(step_time);
+        }
+        if self.is_scrubbing() {
+            let direction = if self.is_scrubbing_forward { 1.0 } else { -1.0 };
+            self.simulation.send(Command::Scrub(direction * step_time));
+        }
+        self.camera_controller.update_camera(&mut self.camera);
+        self.split_screen.update(
+            dt,
+            self.surface_config.width,
+            self.surface_config.height,
+            &mut self.camera,
+        );
+        self.renderer.update_cameras(&self.split_screen.cameras(&self.camera));
+        let overlay = match self.is_help_shown {
+            _ if self.is_photo_mode => None,
+            true => Some(self.help_text()),
+            false => match (&self.selection, &self.build) {
+                (Some(selection), _) => Some(selection.to_string()),
+                (None, Some(build)) => Some(build.to_string()),
+                (None, None) => self.team_text(),
+            },
+        };
+        self.renderer.set_overlay_text(overlay.as_deref());
+
+        self.receive_world_events();
+        self.aim_build_tool();
+        let eye = self.camera.eye();
+        if self.is_glass && (self.instances_dirty || eye != self.sorted_from) {
+            self.chunks.sort_back_to_front(eye);
+            self.sorted_from = eye;
+            self.instances_dirty = true;
+        } else if !self.is_glass
+            && self.renderer.is_depth_sorted()
+            && self
+                .last_depth_sort
+                .is_none_or(|last| now - last >= DEPTH_SORT_INTERVAL)
+            && (eye != self.sorted_from || self.chunks.len() != self.sorted_segments)
+        {
+            // Pipes grown in between are drawn in any order until the next sort, which is only a bit slower
+            let sort_start = Instant::now();
+            self.chunks.sort_front_to_back(eye);
+            self.depth_sort_time = sort_start.elapsed();
+            self.last_depth_sort = Some(now);
+            self.sorted_from = eye;
+            self.sorted_segments = self.chunks.len();
+            self.instances_dirty = true;
+        }
+        if self.instances_dirty {
+            self.upload_instances();
+        }
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.needs_redraw = false;
+
+        let Some(surface) = self.surface.as_ref().filter(|_| self.is_surface_configured) else {
+            return Ok(());
+        };
+
+        let output = surface.get_current_texture()?;
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.renderer.render(&view);
+        output.present();
+
+        Ok(())
+    }
+}
+
+/// `format` with the placeholders of `Cli::title` filled in
+/// A file in `dir` named after the current time, which doesn't exist yet
+fn timestamped_path(dir: &Path, suffix: &str, extension: &str) -> PathBuf {
+    let name = format!("xpipe-{}{}", jiff::Zoned::now().strftime("%Y%m%d-%H%M%S"), suffix);
+    // Every window saves its own within the same second when asked all at once, e.g. from the tray
+    (1..)
+        .map(|i| match i {
+            1 => dir.join(format!("{}.{}", name, extension)),
+            _ => dir.join(format!("{}-{}.{}", name, i, extension)),
+        })
+        .find(|path| !path.exists())
+        .expect("Endless file names")
+}
+
+fn format_title(format: &str, title: &str, fps: f32, stats: &WorldStats) -> String {
+    format
+        .replace("{fps}", &format!("{:.0}", fps))
+        .replace("{pipes}", &stats.pipes.to_string())
+        .replace("{segments}", &stats.segments.to_string())
+        .replace("{seed}", &stats.seed.to_string())
+        .replace("{title}", title)
+}
+
+/// The alpha mode to configure a surface supporting `supported` with, `preference` if it can be and else
+/// the best for a window which is `transparent` or not
+fn alpha_mode(
+    preference: config::AlphaMode,
+    transparent: bool,
+    supported: &[wgpu::CompositeAlphaMode],
+) -> wgpu::CompositeAlphaMode {
+    let preferred = match preference {
+        config::AlphaMode::Auto => None,
+        config::AlphaMode::Opaque => Some(wgpu::CompositeAlphaMode::Opaque),
+        config::AlphaMode::Premultiplied => Some(wgpu::CompositeAlphaMode::PreMultiplied),
+        config::AlphaMode::Postmultiplied => Some(wgpu::CompositeAlphaMode::PostMultiplied),
+        config::AlphaMode::Inherit => Some(wgpu::CompositeAlphaMode::Inherit),
+    };
+    if let Some(mode) = preferred {
+        if supported.contains(&mode) {
+            return mode;
+        }
+        warn!(
+            "Surface doesn't support alpha mode {:?}, supported: {:?}",
+            mode, supported
+        );
+    }
+    // Transparent windows need a see-through mode, Opaque would hide the desktop. Opaque windows shouldn't
+    // get one, or glass pipes would let the desktop show through them. Inherit leaves it up to the
+    // platform, which composites the window as it was created.
+    let candidates: &[wgpu::CompositeAlphaMode] = match transparent {
+        true => &[
+            wgpu::CompositeAlphaMode::PreMultiplied,
+            wgpu::CompositeAlphaMode::PostMultiplied,
+            wgpu::CompositeAlphaMode::Inherit,
+        ],
+        false => &[wgpu::CompositeAlphaMode::Opaque, wgpu::CompositeAlphaMode::Inherit],
+    };
+    candidates
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or_else(|| {
+            if transparent {
+                warn!("Surface doesn't support transparency, alpha modes: {:?}", supported);
+            }
+            supported[0]
+        })
+}
+
+/// `color` the way a surface with `alpha_mode` expects to be cleared to
+fn clear_color(color: wgpu::Color, alpha_mode: wgpu::CompositeAlphaMode) -> wgpu::Color {
+    if alpha_mode == wgpu::CompositeAlphaMode::PreMultiplied {
+        // The compositor expects color already scaled by alpha
+        wgpu::Color {
+            r: color.r * color.a,
+            g: color.g * color.a,
+            b: color.b * color.a,
+            a: color.a,
+        }
+    } else {
+        color
+    }
+}
+
+pub struct App {
+    cli: Cli,
+    config: Config,
+    states: HashMap<WindowId, State>,
+    #[cfg(feature = "gamepad")]
+    gamepad: Option<gamepad::Gamepad>,
+    remote: Option<remote::RemoteControl>,
+    http: Option<http::HttpServer>,
+    #[cfg(feature = "audio")]
+    audio: Option<audio::AudioListener>,
+    // created once the event loop runs, which some platforms need for it
+    #[cfg(feature = "tray")]
+    tray: Option<tray::Tray>,
+    // the session to resume, until the windows are open
+    session: Option<Session>,
+    // opened on the first copy, see `State::copy_recipe`
+    clipboard: Option<clipboard::Clipboard>,
+}
+
+impl App {
+    pub fn new(cli: Cli, config: Config, session: Option<Session>) -> Self {
+        #[cfg(not(feature = "audio"))]
+        if config.audio.is_some() {
+            warn!("Audio-reactive mode is unavailable, this build lacks the audio feature");
+        }
+        #[cfg(not(feature = "scripting"))]
+        if config.script.is_some() {
+            warn!("Scripts are unavailable, this build lacks the scripting feature");
+        }
+        #[cfg(not(feature = "tray"))]
+        if config.tray {
+            warn!("The tray icon is unavailable, this build lacks the tray feature");
+        }
+        Self {
+            #[cfg(feature = "audio")]
+            audio: config.audio.and_then(|source| {
+                audio::AudioListener::new(source)
+                    .inspect_err(|e| warn!("Audio disabled: {:?}", e))
+                    .ok()
+            }),
+            remote: config.remote.and_then(|address| {
+                remote::RemoteControl::bind(address)
+                    .inspect_err(|e| warn!("Remote control disabled: {:?}", e))
+                    .ok()
+            }),
+            http: config.http.and_then(|address| {
+                http::HttpServer::bind(address)
+                    .inspect_err(|e| warn!("HTTP control API disabled: {:?}", e))
+                    .ok()
+            }),
+            config,
+            cli,
+            states: HashMap::new(),
+            #[cfg(feature = "tray")]
+            tray: None,
+            session,
+            clipboard: None,
+            #[cfg(feature = "gamepad")]
+            gamepad: gamepad::Gamepad::new()
+                .inspect_err(|e| log::warn!("Gamepad disabled: {:?}", e))
+                .ok(),
+        }
+    }
+
+    /// Ask for a redraw of every window whose next frame is due and sleep until the earliest of the others
+    fn schedule_redraws(&mut self, event_loop: &ActiveEventLoop) {
+        let now = Instant::now();
+        let mut wake_up: Option<Instant> = None;
+        for state in self.states.values_mut() {
+            state.receive_world_events();
+            match state.next_frame_time() {
+                Some(time) if time <= now => state.window.request_redraw(),
+                Some(time) => wake_up = Some(wake_up.map_or(time, |t| t.min(time))),
+                None => {}
+            }
+        }
+
+        #[cfg(feature = "gamepad")]
+        if self.gamepad.is_some() {
+            // Gamepads don't wake the event loop, poll them regularly instead
+            let poll_time = now + GAMEPAD_POLL_INTERVAL;
+            wake_up = Some(wake_up.map_or(poll_time, |t| t.min(poll_time)));
+        }
+
+        if self.remote.is_some() || self.http.is_some() {
+            // Neither does the network
+            let poll_time = now + REMOTE_POLL_INTERVAL;
+            wake_up = Some(wake_up.map_or(poll_time, |t| t.min(poll_time)));
+        }
+
+        #[cfg(feature = "audio")]
+        if self.audio.is_some() {
+            let poll_time = now + AUDIO_POLL_INTERVAL;
+            wake_up = Some(wake_up.map_or(poll_time, |t| t.min(poll_time)));
+        }
+
+        #[cfg(feature = "tray")]
+        if self.tray.is_some() {
+            let poll_time = now + TRAY_POLL_INTERVAL;
+            wake_up = Some(wake_up.map_or(poll_time, |t| t.min(poll_time)));
+        }
+
+        event_loop.set_control_flow(match wake_up {
+            Some(time) => ControlFlow::WaitUntil(time),
+            None => ControlFlow::Wait,
+        });
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn handle_gamepad(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(gamepad) = &mut self.gamepad else {
+            return;
+        };
+        let actions = gamepad.poll();
+        let axes = gamepad.axes();
+        // A single gamepad drives every window
+        for state in self.states.values_mut() {
+            for action in &actions {
+                match action {
+                    gamepad::GamepadAction::ResetWorld => state.reset_world(),
+                    gamepad::GamepadAction::GrowFaster => state.scale_growth_speed(2.0),
+                    gamepad::GamepadAction::GrowSlower => state.scale_growth_speed(0.5),
+                    gamepad::GamepadAction::Exit => event_loop.exit(),
+                }
+            }
+            state
+                .camera_controller
+                .set_analog_input(axes.yaw, axes.pitch, axes.zoom);
+        }
+    }
+
+    #[cfg(feature = "tray")]
+    fn handle_tray(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(tray) = &mut self.tray else {
+            return;
+        };
+        for action in tray.poll() {
+            match action {
+                tray::TrayAction::TogglePause => self.states.values_mut().for_each(State::toggle_pause),
+                tray::TrayAction::ResetWorld => self.states.values_mut().for_each(State::reset_world),
+                tray::TrayAction::Screenshot => self.states.values_mut().for_each(State::save_screenshot),
+                tray::TrayAction::OpenConfig => {
+                    if let Some(path) = &self.cli.config
+                        && let Err(e) = open::that(path)
+                    {
+                        warn!("Cannot open {:?}: {:?}", path, e);
+                    }
+                }
+                tray::TrayAction::Exit => event_loop.exit(),
+            }
+        }
+    }
+
+    fn handle_remote(&mut self) {
+        let Some(remote) = &mut self.remote else {
+            return;
+        };
+        let commands = remote.poll();
+        for state in self.states.values_mut() {
+            for command in &commands {
+                state.handle_remote(command);
+            }
+        }
+    }
+
+    fn handle_http(&mut self) {
+        let Some(http) = &mut self.http else {
+            return;
+        };
+        for request in http.poll() {
+            match &request.endpoint {
+                // Every window gets the changes, any one of them answers the queries
+                http::Endpoint::Stats => match self.states.values().next().and_then(State::stats) {
+                    Some(stats) => request.respond("200 OK", "application/json", stats.as_bytes()),
+                    None => request.respond("503 Service Unavailable", "text/plain", b"No world to query"),
+                },
+                http::Endpoint::Snapshot => match self.states.values_mut().next().map(State::snapshot_png) {
+                    Some(Ok(png)) => request.respond("200 OK", "image/png", &png),
+                    Some(Err(e)) => {
+                        request.respond("500 Internal Server Error", "text/plain", format!("{:?}", e).as_bytes())
+                    }
+                    None => request.respond("503 Service Unavailable", "text/plain", b"No window to render"),
+                },
+                http::Endpoint::Reset => {
+                    self.states.values_mut().for_each(State::reset_world);
+                    request.respond("200 OK", "text/plain", b"OK");
+                }
+                http::Endpoint::Configure(settings) => {
+                    let result = self.states.values_mut().try_for_each(|state| {
+                        settings
+                            .iter()
+                            .try_for_each(|(name, value)| state.configure(name, value))
+                    });
+                    match result {
+                        Ok(()) => request.respond("200 OK", "text/plain", b"OK"),
+                        Err(e) => request.respond("400 Bad Request", "text/plain", format!("{:?}", e).as_bytes()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Save where `state` is at, for the next run to resume
+    fn save_session(&self, state: &State) {
+        let Some(session) = state.session(&self.config) else {
+            warn!("Session not saved, the world doesn't answer");
+            return;
+        };
+        match session.save() {
+            Ok(()) => info!("Session saved"),
+            Err(e) => warn!("Cannot save session: {:?}", e),
+        }
+    }
+
+    #[cfg(feature = "audio")]
+    fn handle_audio(&mut self) {
+        let Some(audio) = &mut self.audio else {
+            return;
+        };
+        let frame = audio.poll();
+        // Every window dances to the same music
+        for state in self.states.values_mut() {
+            state.react_to_audio(&frame);
+        }
+    }
+}
+
+impl App {
+    fn open_windows(&mut self, event_loop: &ActiveEventLoop) {
+        let count = self.cli.windows.max(1);
+        for i in 0..count {
+            let title = if count == 1 {
+                TITLE.to_string()
+            } else {
+                format!("{} {}", TITLE, i + 1)
+            };
+            let window_attributes = identity::window_attributes(&title).with_transparent(self.config.transparent);
+            let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
+            let state = pollster::block_on(State::new(window, &title, &self.config)).unwrap();
+            self.states.insert(state.window.id(), state);
+        }
+    }
+
+    /// A transparent, click-through window floating above everything else
+    fn open_overlay_window(&mut self, event_loop: &ActiveEventLoop) {
+        let window_attributes = identity::window_attributes(TITLE)
+            .with_transparent(true)
+            .with_decorations(false)
+            .with_maximized(true)
+            .with_window_level(WindowLevel::AlwaysOnTop);
+        let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
+        if let Err(e) = window.set_cursor_hittest(false) {
+            warn!("Overlay cannot be made click-through: {:?}", e);
+        }
+        let mut state = pollster::block_on(State::new(window, TITLE, &self.config)).unwrap();
+        state.set_idle_when_unfocused(false);
+        self.states.insert(state.window.id(), state);
+    }
+
+    /// One wallpaper window per monitor, each with its own world
+    fn open_wallpaper_windows(&mut self, event_loop: &ActiveEventLoop) {
+        let mut monitors = event_loop.available_monitors().map(Some).collect::<Vec<_>>();
+        if monitors.is_empty() {
+            monitors.push(None);
+        }
+        for monitor in monitors {
+            let window_attributes = wallpaper::window_attributes(event_loop, monitor);
+            let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
+            if let Err(e) = wallpaper::attach(&window) {
+                warn!("Cannot attach to the desktop, running as a regular window: {:?}", e);
+            }
+            let mut state = pollster::block_on(State::new(window, TITLE, &self.config)).unwrap();
+            state.limit_frame_rate(wallpaper::WALLPAPER_FPS);
+            state.set_idle_when_unfocused(false);
+            self.states.insert(state.window.id(), state);
+        }
+    }
+
+    /// One borderless fullscreen window per monitor
+    fn open_monitor_windows(&mut self, event_loop: &ActiveEventLoop, mode: MonitorMode) {
+        let monitors = event_loop.available_monitors().collect::<Vec<_>>();
+        if monitors.is_empty() {
+            warn!("No monitor found, falling back to windowed mode");
+            self.open_windows(event_loop);
+            return;
+        }
+
+        // The center of the whole desktop, cameras are offset relative to it in shared mode
+        let (min_x, max_x, min_y, max_y) = monitors.iter().fold(
+            (i32::MAX, i32::MIN, i32::MAX, i32::MIN),
+            |(min_x, max_x, min_y, max_y), m| {
+                let (p, s) = (m.position(), m.size());
+                (
+                    min_x.min(p.x),
+                    max_x.max(p.x + s.width as i32),
+                    min_y.min(p.y),
+                    max_y.max(p.y + s.height as i32),
+                )
+            },
+        );
+        let desktop_center = ((min_x + max_x) as f32 / 2.0, (min_y + max_y) as f32 / 2.0);
+
+        let mut primary: Option<WindowId> = None;
+        for (i, monitor) in monitors.into_iter().enumerate() {
+            info!("Opening window on monitor {:?}", monitor.name());
+            let (position, size) = (monitor.position(), monitor.size());
+            let title = format!("{} {}", TITLE, i + 1);
+            let window_attributes = identity::window_attributes(&title)
+                .with_transparent(self.config.transparent)
+                .with_fullscreen(Some(Fullscreen::Borderless(Some(monitor))));
+            let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
+            let mut state = pollster::block_on(State::new(window, &title, &self.config)).unwrap();
+
+            if mode == MonitorMode::Shared {
+                if let Some(primary) = primary.and_then(|id| self.states.get(&id)) {
+                    state.share_world_with(primary);
+                } else {
+                    primary = Some(state.window.id());
+                }
+                let center = (
+                    position.x as f32 + size.width as f32 / 2.0,
+                    position.y as f32 + size.height as f32 / 2.0,
+                );
+                state.offset_camera(center.0 - desktop_center.0, center.1 - desktop_center.1);
+            }
+            self.states.insert(state.window.id(), state);
+        }
+    }
+}
+
+impl ApplicationHandler<State> for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if !self.states.is_empty() {
+            // Back from `suspended`, with the same windows
+            for state in self.states.values_mut() {
+                if let Err(e) = state.resume() {
+                    error!("Cannot draw to window again: {:?}", e);
+                }
+            }
+            return;
+        }
+
+        #[cfg(feature = "tray")]
+        if self.config.tray && self.tray.is_none() {
+            self.tray = tray::Tray::new(self.cli.config.is_some())
+                .inspect_err(|e| warn!("Tray icon disabled: {:?}", e))
+                .ok();
+        }
+
+        if self.cli.wallpaper {
+            self.open_wallpaper_windows(event_loop);
+        } else if self.cli.overlay {
+            self.open_overlay_window(event_loop);
+        } else {
+            match self.cli.monitors {
+                Some(mode) => self.open_monitor_windows(event_loop, mode),
+                None => self.open_windows(event_loop),
+            }
+        }
+
+        // The first window picks up where the last run left off, any others start over
+        if let Some(session) = self.session.take()
+            && let Some(state) = self.states.values_mut().next()
+        {
+            state.restore(&session);
+        }
+    }
+
+    fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
+        for state in self.states.values_mut() {
+            state.suspend();
+        }
+        // Suspended apps may be killed without another word on mobile platforms
+        if let Some(state) = self.states.values().next() {
+            self.save_session(state);
+        }
+    }
+
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: State) {
+        self.states.insert(event.window.id(), event);
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        #[cfg(feature = "gamepad")]
+        self.handle_gamepad(event_loop);
+        #[cfg(feature = "audio")]
+        self.handle_audio();
+        #[cfg(feature = "tray")]
+        self.handle_tray(event_loop);
+        self.handle_remote();
+        self.handle_http();
+
+        self.schedule_redraws(event_loop);
+    }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        // Windows closed one by one saved the last of them already
+        if let Some(state) = self.states.values().next() {
+            self.save_session(state);
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
+        let state = match self.states.get_mut(&window_id) {
+            None => return,
+            Some(s) => s,
+        };
+
+        match event {
+            WindowEvent::CloseRequested => {
+                let closed = self.states.remove(&window_id);
+                logging::forget(&format!("{:?} adapter", window_id));
+                logging::forget(&format!("{:?} surface", window_id));
+                if self.states.is_empty() {
+                    if let Some(closed) = closed {
+                        self.save_session(&closed);
+                    }
+                    event_loop.exit();
+                }
+            }
+            WindowEvent::Resized(size) => {
+                state.resize(size.width, size.height);
+                state.request_redraw();
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                state.set_scale_factor(scale_factor);
+                state.request_redraw();
+            }
+            WindowEvent::Focused(is_focused) => state.is_focused = is_focused,
+            WindowEvent::ModifiersChanged(modifiers) => state.modifiers = modifiers.state(),
+            WindowEvent::DroppedFile(path) => state.load_pipe_model(&path, state.modifiers.shift_key()),
+            WindowEvent::Occluded(is_occluded) => state.set_occluded(is_occluded),
+            WindowEvent::RedrawRequested => {
+                if state.is_minimized {
+                    // Nothing to draw to, the platform may still ask for frames
+                    state.skip_frame();
+                    return;
+                }
+                if self.cli.wallpaper && (state.is_occluded || wallpaper::is_covered_by_fullscreen_app(&state.window)) {
+                    // Nobody can see the wallpaper, keep checking at the paced rate without rendering
+                    state.skip_frame();
+                    return;
+                }
+                state.update();
+                match state.render() {
+                    Ok(_) => {}
+                    Err(wgpu::SurfaceError::Outdated) => {
+                        let size = state.window.inner_size();
+                        state.resize(size.width, size.height);
+                    }
+                    Err(wgpu::SurfaceError::Lost) => {
+                        warn!("Surface lost, creating it again");
+                        if let Err(e) = state.recreate_surface() {
+                            error!("Cannot draw to window again: {:?}", e);
+                        }
+                    }
+                    Err(wgpu::SurfaceError::Timeout) => {
+                        // Usually the compositor holding on to frames while the display sleeps, try again
+                        state.request_redraw();
+                    }
+                    Err(wgpu::SurfaceError::OutOfMemory) => {
+                        error!("Out of GPU memory, exiting");
+                        event_loop.exit();
+                    }
+                    Err(e) => error!("Cannot render window: {:?}", e),
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => state.cursor = Some(position),
+            WindowEvent::CursorLeft { .. } => state.cursor = None,
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                // Building, clicks erase rather than select, Shift the whole pipe
+                match state.build.is_some() {
+                    true => state.erase_at_cursor(state.modifiers.shift_key()),
+                    false => state.pick_at_cursor(),
+                }
+                state.request_redraw();
+            }
+            WindowEvent::Touch(touch) => {
+                state.handle_touch(&touch);
+                state.request_redraw();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(code),
+                        state: key_state,
+                        repeat,
+                        ..
+                    },
+                ..
+            } => {
+                let Some(action) = self.config.keys.action(code) else {
+                    return;
+                };
+                if action.is_with_ctrl() && !state.modifiers.control_key() {
+                    return;
+                }
+                let is_pressed = key_state.is_pressed();
+                // Holding a key down moves the camera, but only toggles everything else once
+                if !state.camera_controller.handle_action(action, is_pressed)
+                    && !state.handle_scrub_action(action, is_pressed)
+                    && is_pressed
+                    && !repeat
+                {
+                    match action {
+                        Action::Exit => event_loop.exit(),
+                        Action::Pause => state.toggle_pause(),
+                        Action::Reset => state.reset_world(),
+                        Action::Screenshot => state.save_screenshot(),
+                        Action::TogglePhotoMode => state.toggle_photo_mode(),
+                        Action::ExportTurntable => state.export_turntable(),
+                        Action::NextScene => state.next_scene(),
+                        Action::CopyRecipe => state.copy_recipe(&mut self.clipboard),
+                        Action::ExportGraph => state.export_graph(),
+                        Action::ToggleFullscreen => state.toggle_fullscreen(),
+                        Action::CycleBackground => state.cycle_background(),
+                        Action::CycleShading => state.toggle_shading(),
+                        Action::CycleViews => state.cycle_views(),
+                        Action::ToggleFloor => state.toggle_floor(),
+                        Action::ToggleSparks => state.toggle_sparks(),
+                        Action::BrightenAmbient => state.change_ambient(1.0),
+                        Action::DimAmbient => state.change_ambient(-1.0),
+                        Action::CycleRimLight => state.cycle_rim_light(),
+                        Action::BrightenExposure => state.change_exposure(1.0),
+                        Action::DarkenExposure => state.change_exposure(-1.0),
+                        Action::ToggleAutoExposure => state.toggle_auto_exposure(),
+                        Action::ToggleReducedMotion => state.set_reduced_motion(!state.is_motion_reduced),
+                        Action::ToggleDepthSort => state.toggle_depth_sort(),
+                        Action::ToggleReplay => state.toggle_replay(),
+                        Action::ToggleHelp => state.toggle_help(),
+                        Action::ToggleGizmos => state.toggle_gizmos(),
+                        Action::ToggleBuildMode => state.toggle_build_mode(),
+                        Action::ToggleBuildPipeType => state.change_build_tool(BuildTool::toggle_pipe_type),
+                        Action::NextBuildDirection => state.change_build_tool(BuildTool::next_direction),
+                        Action::Build => state.build_pipe(),
+                        Action::Undo => state.undo(),
+                        Action::Redo => state.simulation.send(Command::Redo),
+                        Action::CameraForward
+                        | Action::CameraBackward
+                        | Action::CameraLeft
+                        | Action::CameraRight
+                        | Action::ScrubBackward
+                        | Action::ScrubForward => {}
+                    }
+                }
+                state.request_redraw();
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn run() -> anyhow::Result<()> {
+    logging::init();
+    let cli = Cli::parse();
+    if cli.print_caps {
+        // before the event loop, which needs a display
+        return print_capabilities();
+    }
+    run_app(cli, EventLoop::with_user_event().build()?)
+}
+
+/// Entry point of the Android app, which has no command line and takes the defaults and config file only
+#[cfg(target_os = "android")]
+#[unsafe(no_mangle)]
+fn android_main(app: winit::platform::android::activity::AndroidApp) {
+    use winit::platform::android::EventLoopBuilderExtAndroid;
+
+    logging::init();
+    resources::set_android_app(app.clone());
+    let cli = Cli::parse_from([TITLE]);
+    let event_loop = EventLoop::with_user_event().with_android_app(app).build();
+    if let Err(e) = event_loop
+        .map_err(anyhow::Error::from)
+        .and_then(|event_loop| run_app(cli, event_loop))
+    {
+        error!("{:?}", e);
+    }
+}
+
+fn run_app(cli: Cli, event_loop: EventLoop<State>) -> anyhow::Result<()> {
+    let session = if cli.resume {
+        Session::load()
+            .inspect_err(|e| warn!("Cannot resume: {:?}", e))
+            .ok()
+            .flatten()
+            .or_else(|| {
+                info!("No session to resume, starting over");
+                None
+            })
+    } else {
+        None
+    };
+    let config = Config::load(&cli, session.as_ref().map(|session| session.settings.clone()))?;
+    let mut app = App::new(cli, config, session);
+    event_loop.run_app(&mut app)?;
+    Ok(())
+}
+
+/// Print what the GPU which windows would draw with can do, for `--print-caps`
+fn print_capabilities() -> anyhow::Result<()> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::PRIMARY,
+        ..Default::default()
+    });
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::LowPower,
+        force_fallback_adapter: false,
+        compatible_surface: None,
+    }))?;
+    print!("{}", Capabilities::of(&adapter).report());
     Ok(())
 }
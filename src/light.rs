@@ -1,8 +1,94 @@
+use cgmath::{Deg, InnerSpace, Point3, Rad, Vector3};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Ambient light every shading gets by default, scaled by `LightUniform::ambient`
+pub const DEFAULT_AMBIENT: f32 = 1.0;
+/// The sky around the world overhead, as a share of the light's color, and the ground below it, see
+/// `LightUniform::sky`
+pub const DEFAULT_SKY: [f32; 3] = [0.6; 3];
+pub const DEFAULT_GROUND: [f32; 3] = [0.02; 3];
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
     pub position: [f32; 3],
-    pub _padding1: u32,
+    /// Scales the light every side of the pipes gets, however they face the light, which each shading
+    /// has a little of. Brightens the sides facing away from the light without moving it.
+    pub ambient: f32,
     pub color: [f32; 3],
-    pub _padding2: u32,
+    /// Strength of the light around the pipes' silhouettes, as if from behind them, 0 for none
+    pub rim: f32,
+    /// Linear colors of the gradient around the world, from the ground below to the sky overhead, which
+    /// metals mirror and sky lighting comes from. The sky is lit by the light, so is a share of its color.
+    pub sky: [f32; 3],
+    /// Strength of the light the pipes get from the sky and ground around them, which tints the sides
+    /// facing up like the sky and those facing down like the ground, 0 for none
+    pub sky_light: f32,
+    pub ground: [f32; 3],
+    /// Multiple of the light everything lit in the scene is shown with, like a camera's exposure, 1 for as
+    /// it is
+    pub exposure: f32,
+}
+
+/// The path the light travels around the world. Configured as the `[light_orbit]` table, e.g.
+/// `center = [15.0, 20.0, 15.0]`, `radius = 25.0` and `path = "lissajous"` to sweep over a world 30 blocks
+/// wide. The defaults circle just above the corner of the world the pipes start growing from.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightOrbit {
+    /// Point the light travels around
+    pub center: [f32; 3],
+    /// Distance of the light from the center
+    pub radius: f32,
+    /// Axis the light turns around, it starts off on the side of the center towards +X and +Z
+    pub axis: [f32; 3],
+    /// Degrees the light turns a second, negative ones clockwise seen from along the axis
+    pub speed: f32,
+    pub path: OrbitPath,
+}
+
+impl Default for LightOrbit {
+    fn default() -> Self {
+        Self {
+            center: [0.0, 2.0, 0.0],
+            radius: 2.0 * std::f32::consts::SQRT_2,
+            axis: [0.0, 1.0, 0.0],
+            speed: 3.0,
+            path: OrbitPath::Circle,
+        }
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrbitPath {
+    /// Around the axis at the same distance all the time
+    #[default]
+    Circle,
+    /// A figure across the circle, three times along one side for every two along the other, so the light
+    /// passes near the center and out to the rim in turns
+    Lissajous,
+}
+
+impl LightOrbit {
+    /// Where the light is `angle` into its orbit
+    pub fn position(&self, angle: Deg<f32>) -> Point3<f32> {
+        let axis = Vector3::from(self.axis).normalize();
+        // the start is the way along the plane across the axis closest to +X and +Z
+        let mut start = Vector3::new(1.0, 0.0, 1.0);
+        start -= axis * start.dot(axis);
+        if start.magnitude2() < 1e-6 {
+            start = Vector3::unit_x() - axis * axis.x;
+        }
+        let start = start.normalize();
+        let side = axis.cross(start);
+
+        let Rad(angle) = angle.into();
+        let (along, across) = match self.path {
+            OrbitPath::Circle => (angle.cos(), angle.sin()),
+            OrbitPath::Lissajous => ((3.0 * angle).cos(), (2.0 * angle).sin()),
+        };
+        Point3::from(self.center) + (start * along + side * across) * self.radius
+    }
 }
@@ -8,7 +8,13 @@ var<uniform> camera: Camera;
 
 struct Light {
     position: vec3<f32>,
-    color: vec3<f32>
+    ambient: f32,
+    color: vec3<f32>,
+    rim: f32,
+    sky: vec3<f32>,
+    sky_light: f32,
+    ground: vec3<f32>,
+    exposure: f32,
 }
 
 @group(1) @binding(0)
@@ -37,5 +43,5 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+    return vec4<f32>(in.color * light.exposure, 1.0);
 }
@@ -0,0 +1,150 @@
+#![cfg_attr(target_os = "android", allow(dead_code, unused_imports))]
+//! Android logs to logcat only, the file logging below is for every other platform
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+use anyhow::Context;
+use log::{error, info, warn};
+
+const LOG_FILE: &str = "xpipe.log";
+// the file of a run starts over once it grows past this, the older part is kept as the first old log
+const MAX_LOG_SIZE: u64 = 4 * 1024 * 1024;
+// logs of earlier runs kept next to the current one, as xpipe.1.log, xpipe.2.log, ...
+const OLD_LOGS: u32 = 3;
+const DEFAULT_FILTER: &str = "warn,XPipe=info";
+
+/// What was going on when a panic happens, by e.g. window or world
+static CONTEXT: LazyLock<Mutex<BTreeMap<String, String>>> = LazyLock::new(Default::default);
+
+/// Log to stderr and to a file in the platform's log directory, rotated every run, and log panics along
+/// with everything recorded so far. `RUST_LOG` filters both as usual.
+#[cfg(not(target_os = "android"))]
+pub fn init() {
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(DEFAULT_FILTER));
+    let path = LogFile::open().map(|file| {
+        let path = file.path.clone();
+        builder.target(env_logger::Target::Pipe(Box::new(Tee(file))));
+        path
+    });
+    builder.init();
+    match path {
+        Ok(path) => info!("Logging to {:?}", path),
+        Err(e) => warn!("Logging to stderr only: {:?}", e),
+    }
+    set_panic_hook();
+}
+
+/// Log to logcat, where Android apps have nobody reading their stderr, and log panics like elsewhere
+#[cfg(target_os = "android")]
+pub fn init() {
+    android_logger::init_once(
+        android_logger::Config::default()
+            .with_max_level(log::LevelFilter::Info)
+            .with_tag(crate::TITLE),
+    );
+    set_panic_hook();
+}
+
+fn set_panic_hook() {
+    std::panic::set_hook(Box::new(|panic| {
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        // The panic may have come from inside `record`, never wait for the lock here
+        let context = match CONTEXT.try_lock() {
+            Ok(context) => context
+                .iter()
+                .map(|(key, value)| format!("\n  {}: {}", key, value))
+                .collect::<String>(),
+            Err(_) => " unavailable".to_string(),
+        };
+        let thread = std::thread::current();
+        error!(
+            "Thread {:?} {}\nContext:{}\nBacktrace:\n{}",
+            thread.name().unwrap_or("unnamed"),
+            panic,
+            context,
+            backtrace
+        );
+    }));
+}
+
+/// Remember `value` to report if anything panics, replacing what was recorded under `key` before
+pub fn record(key: &str, value: impl Debug) {
+    let value = format!("{:?}", value);
+    CONTEXT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key.to_string(), value);
+}
+
+/// Drop whatever was recorded under `key`, e.g. once a window is closed
+pub fn forget(key: &str) {
+    CONTEXT.lock().unwrap_or_else(|e| e.into_inner()).remove(key);
+}
+
+/// Writes every log line to stderr as well as the file
+struct Tee(LogFile);
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A broken stderr must not cost the file its logs, nor the other way around
+        let _ = io::stderr().write_all(buf);
+        self.0.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let _ = io::stderr().flush();
+        self.0.flush()
+    }
+}
+
+/// The log file of this run, which moves older logs out of the way
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open() -> anyhow::Result<Self> {
+        let dir = dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .context("No log directory on this platform")?
+            .join("xpipe");
+        fs::create_dir_all(&dir).with_context(|| format!("Cannot create {:?}", dir))?;
+        let path = dir.join(LOG_FILE);
+        rotate(&path);
+        let file = File::create(&path).with_context(|| format!("Cannot create {:?}", path))?;
+        Ok(Self { path, file, size: 0 })
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.size + buf.len() as u64 > MAX_LOG_SIZE {
+            rotate(&self.path);
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Shift `xpipe.log` to `xpipe.1.log`, that one to `xpipe.2.log` and so on, dropping the oldest
+fn rotate(path: &Path) {
+    let old = |i: u32| path.with_extension(format!("{}.log", i));
+    for i in (1..OLD_LOGS).rev() {
+        // Missing ones are fine, there may not have been that many runs yet
+        let _ = fs::rename(old(i), old(i + 1));
+    }
+    let _ = fs::rename(path, old(1));
+}
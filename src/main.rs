@@ -1,4 +1,4 @@
-use XPipe::run;
+use xpipe::run;
 
 fn main() {
     run().unwrap()
@@ -0,0 +1,123 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+/// What a pipe is made of, one for the whole pipe. The pipe shaders look up its `MaterialRaw` by index.
+#[derive(ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Material {
+    /// Smooth and a little glossy
+    #[default]
+    Plastic,
+    /// Rough, with hardly any highlight
+    Matte,
+    /// Polished metal mirroring the sky
+    Chrome,
+    /// Shining with its own color, lit or not
+    Lamp,
+}
+
+impl Material {
+    /// Every material, at its index in the shaders' material buffer
+    pub const ALL: [Material; 4] = [Material::Plastic, Material::Matte, Material::Chrome, Material::Lamp];
+
+    /// Index of the material in the shaders' material buffer
+    pub fn index(self) -> u32 {
+        self as u32
+    }
+
+    fn to_raw(self) -> MaterialRaw {
+        let (shininess, specular, metallic, emissive) = match self {
+            Material::Plastic => (32.0, 1.0, 0.0, 0.0),
+            Material::Matte => (8.0, 0.1, 0.0, 0.0),
+            Material::Chrome => (128.0, 1.0, 1.0, 0.0),
+            Material::Lamp => (32.0, 0.5, 0.0, 0.8),
+        };
+        MaterialRaw {
+            shininess,
+            specular,
+            metallic,
+            emissive,
+        }
+    }
+}
+
+/// What new pipes are made of: `material`, except for a share of `accent_chance` made of `accent`, e.g.
+/// one chrome pipe among matte ones
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Materials {
+    pub material: Material,
+    pub accent: Material,
+    pub accent_chance: f32,
+}
+
+impl Default for Materials {
+    fn default() -> Self {
+        Self {
+            material: Material::Plastic,
+            accent: Material::Plastic,
+            accent_chance: 0.0,
+        }
+    }
+}
+
+/// See `Material` in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialRaw {
+    // sharpness of highlights, the exponent of Blinn-Phong
+    shininess: f32,
+    // brightness of highlights
+    specular: f32,
+    // 1 for metals, which have no diffuse color but tint their highlights and reflections with it
+    metallic: f32,
+    // light of its own, added to the pipe's glow
+    emissive: f32,
+}
+
+/// Every material in a storage buffer, in the order of `Material::ALL`
+pub fn create_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    let materials = Material::ALL.map(Material::to_raw);
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("MaterialBuffer"),
+        contents: bytemuck::cast_slice(&materials),
+        usage: wgpu::BufferUsages::STORAGE,
+    })
+}
+
+/// Binding 0 of a bind group, where fragment shaders find the buffer of `create_buffer`
+pub fn layout_entry() -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// Layout of a bind group holding nothing but the materials
+pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("MaterialBindGroupLayout"),
+        entries: &[layout_entry()],
+    })
+}
+
+pub fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("MaterialBindGroup"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    })
+}
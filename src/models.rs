@@ -1,9 +1,15 @@
 use std::io::{BufReader, Cursor};
+use std::path::Path;
 
+use anyhow::{Context, bail};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Transform, Vector3, Zero};
 use wgpu::util::DeviceExt;
 
 use crate::resources::load_string;
 
+/// Radius of the built-in pipe meshes, whose sides touch those of the next block's
+pub const MODELED_RADIUS: f32 = 0.5;
+
 pub trait Vertex {
     fn layout() -> wgpu::VertexBufferLayout<'static>;
 }
@@ -41,6 +47,8 @@ pub struct Mesh {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
+    // as loaded, to thicken or thin again from
+    vertices: Vec<ModelVertex>,
 }
 
 pub struct Model {
@@ -53,6 +61,7 @@ impl Model {
         let obj_cursor = Cursor::new(obj_text);
         let mut obj_reader = BufReader::new(obj_cursor);
 
+        #[allow(deprecated)]
         let (models, _) = tobj::load_obj_buf_async(
             &mut obj_reader,
             &tobj::LoadOptions {
@@ -103,26 +112,224 @@ impl Model {
                     })
                     .collect::<Vec<_>>();
 
-                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{:?}VertexBuffer", file_name)),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsages::VERTEX,
-                });
-                let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{:?}IndexBuffer", file_name)),
-                    contents: bytemuck::cast_slice(&m.mesh.indices),
-                    usage: wgpu::BufferUsages::INDEX,
-                });
-
-                Mesh {
-                    name: file_name.to_string(),
-                    vertex_buffer,
-                    index_buffer,
-                    num_elements: m.mesh.indices.len() as u32,
-                }
+                Mesh::new(device, file_name, &vertices, &m.mesh.indices)
             })
             .collect::<Vec<_>>();
 
         Ok(Model { meshes })
     }
+
+    /// A model from an OBJ or glTF file anywhere, merged into one mesh and fit into a block like the
+    /// built-in pipes: centered on the origin with its longest side 1 long. Straight pipes run along Y,
+    /// elbows joining +Y and +X, like `res/pipe.obj` and `res/curve.obj`.
+    pub fn load_file(path: &Path, device: &wgpu::Device) -> anyhow::Result<Model> {
+        let extension = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+        let (mut vertices, indices) = match extension.as_deref() {
+            Some("obj") => read_obj(path)?,
+            Some("gltf" | "glb") => read_gltf(path)?,
+            _ => bail!("Unsupported model {:?}, expected an OBJ or glTF file", path),
+        };
+        if indices.is_empty() {
+            bail!("Model {:?} has no triangles", path);
+        }
+        fit_into_block(&mut vertices);
+        let name = path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        Ok(Model {
+            meshes: vec![Mesh::new(device, &name, &vertices, &indices)],
+        })
+    }
+
+    /// Thicken or thin the pipe by `scale` from how it was loaded, around the line it runs along: the Y
+    /// axis for straight pipes, and for elbows the quarter circle from +Y to +X around (0.5, 0.5)
+    pub fn set_radius_scale(&mut self, device: &wgpu::Device, scale: f32, is_elbow: bool) {
+        for mesh in &mut self.meshes {
+            let vertices: Vec<_> = mesh
+                .vertices
+                .iter()
+                .map(|vertex| scale_around_center_line(vertex, scale, is_elbow))
+                .collect();
+            mesh.vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?}VertexBuffer", mesh.name)),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        }
+    }
+}
+
+/// Move a vertex towards or away from the nearest point on the pipe's center line, keeping its normal,
+/// which points away from that line on a round pipe anyway
+fn scale_around_center_line(vertex: &ModelVertex, scale: f32, is_elbow: bool) -> ModelVertex {
+    let position = Vector3::from(vertex.position);
+    let center = if is_elbow {
+        let bend = Vector3::new(0.5, 0.5, 0.0);
+        let mut outwards = Vector3::new(position.x - bend.x, position.y - bend.y, 0.0);
+        // the inside of the built-in elbow pinches into the bend, where only the normal tells which
+        // way the center line is
+        if outwards.magnitude2() < 1e-6 {
+            outwards = Vector3::new(-vertex.normal[0], -vertex.normal[1], 0.0);
+        }
+        if outwards.magnitude2() < 1e-6 {
+            return *vertex;
+        }
+        // half a block from the bend, where the elbow meets the middle of the block's faces
+        bend + outwards.normalize() * 0.5
+    } else {
+        Vector3::new(0.0, position.y, 0.0)
+    };
+    ModelVertex {
+        position: (center + (position - center) * scale).into(),
+        normal: vertex.normal,
+    }
+}
+
+impl Mesh {
+    pub fn new(device: &wgpu::Device, name: &str, vertices: &[ModelVertex], indices: &[u32]) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?}VertexBuffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?}IndexBuffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Mesh {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            vertices: vertices.to_vec(),
+        }
+    }
+}
+
+/// Every object of an OBJ file as one mesh, its materials are of no use to pipes
+fn read_obj(path: &Path) -> anyhow::Result<(Vec<ModelVertex>, Vec<u32>)> {
+    let (models, _) = tobj::load_obj(
+        path,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+    )
+    .with_context(|| format!("Cannot read {:?}", path))?;
+
+    let (mut vertices, mut indices) = (vec![], vec![]);
+    for model in models {
+        let mesh = model.mesh;
+        let mut part: Vec<_> = mesh
+            .positions
+            .chunks_exact(3)
+            .map(|p| ModelVertex {
+                position: [p[0], p[1], p[2]],
+                normal: [0.0; 3],
+            })
+            .collect();
+        if mesh.normals.len() == mesh.positions.len() {
+            for (vertex, n) in part.iter_mut().zip(mesh.normals.chunks_exact(3)) {
+                vertex.normal = [n[0], n[1], n[2]];
+            }
+        } else {
+            smooth_normals(&mut part, &mesh.indices);
+        }
+        append(&mut vertices, &mut indices, part, &mesh.indices);
+    }
+    Ok((vertices, indices))
+}
+
+/// Every triangle mesh in the default scene of a glTF file as one mesh, placed as the scene places them
+fn read_gltf(path: &Path) -> anyhow::Result<(Vec<ModelVertex>, Vec<u32>)> {
+    let (document, buffers, _) = gltf::import(path).with_context(|| format!("Cannot read {:?}", path))?;
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .context("No scene in the file")?;
+
+    let (mut vertices, mut indices) = (vec![], vec![]);
+    let mut nodes: Vec<_> = scene.nodes().map(|node| (node, Matrix4::identity())).collect();
+    while let Some((node, parent_transform)) = nodes.pop() {
+        let transform = parent_transform * Matrix4::from(node.transform().matrix());
+        nodes.extend(node.children().map(|child| (child, transform)));
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+        let normal_transform =
+            Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate())
+                .invert()
+                .map_or(Matrix3::identity(), |inverse| inverse.transpose());
+
+        for primitive in mesh.primitives().filter(|p| p.mode() == gltf::mesh::Mode::Triangles) {
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let mut part: Vec<_> = positions
+                .map(|p| ModelVertex {
+                    position: transform.transform_point(Point3::from(p)).into(),
+                    normal: [0.0; 3],
+                })
+                .collect();
+            let part_indices: Vec<u32> = match reader.read_indices() {
+                Some(read) => read.into_u32().collect(),
+                None => (0..part.len() as u32).collect(),
+            };
+            match reader.read_normals() {
+                Some(normals) => {
+                    for (vertex, normal) in part.iter_mut().zip(normals) {
+                        vertex.normal = (normal_transform * Vector3::from(normal)).normalize().into();
+                    }
+                }
+                None => smooth_normals(&mut part, &part_indices),
+            }
+            append(&mut vertices, &mut indices, part, &part_indices);
+        }
+    }
+    Ok((vertices, indices))
+}
+
+fn append(vertices: &mut Vec<ModelVertex>, indices: &mut Vec<u32>, part: Vec<ModelVertex>, part_indices: &[u32]) {
+    let offset = vertices.len() as u32;
+    indices.extend(part_indices.iter().map(|i| i + offset));
+    vertices.extend(part);
+}
+
+/// Normals of a mesh without any, averaged over the triangles around every vertex
+fn smooth_normals(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut normals = vec![Vector3::zero(); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(vertices[triangle[i] as usize].position));
+        // Not normalized, so larger triangles weigh more
+        let normal = (b - a).cross(c - a);
+        for i in triangle {
+            normals[*i as usize] += normal;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        if normal.magnitude2() > 0.0 {
+            vertex.normal = normal.normalize().into();
+        }
+    }
+}
+
+/// Center the mesh on the origin and scale it so its longest side is 1, the size of a block
+fn fit_into_block(vertices: &mut [ModelVertex]) {
+    let (min, max) = vertices
+        .iter()
+        .fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), vertex| {
+            (
+                [0, 1, 2].map(|i| min[i].min(vertex.position[i])),
+                [0, 1, 2].map(|i| max[i].max(vertex.position[i])),
+            )
+        });
+    let longest = (0..3).map(|i| max[i] - min[i]).fold(0.0, f32::max);
+    let scale = if longest > 0.0 { 1.0 / longest } else { 1.0 };
+    for vertex in vertices {
+        vertex.position = [0, 1, 2].map(|i| (vertex.position[i] - (min[i] + max[i]) / 2.0) * scale);
+    }
 }
@@ -0,0 +1,288 @@
+use wgpu::util::DeviceExt;
+
+use crate::gpu;
+use crate::instance::InstanceRaw;
+use crate::models::{self, Vertex};
+
+// pixels between the neighbors each flood pass looks at, halving down to the next ones. A jump flood
+// reaches as far as they add up to, which the outline's width in outline.wgsl must not exceed.
+const STEPS: [u32; 3] = [4, 2, 1];
+// what the seeds are cleared to, a pixel farther off than any outline reaches
+const NO_SEED: f64 = u16::MAX as f64;
+// the pixel of the pipe nearest to each pixel
+const SEED_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Uint;
+
+/// Outlines the selected pipe as a whole, wherever it shows, even behind other pipes: its instances are
+/// drawn into a mask of which pixels it covers, a jump flood finds the nearest of those for the pixels
+/// around, and the ones near enough are drawn over the scene in the outline's color.
+pub struct Outline {
+    // the pipes' own vertex shader, so that the mask lines up with them
+    mask_pipeline: wgpu::RenderPipeline,
+    // one for each of `STEPS`
+    flood_pipelines: Vec<wgpu::RenderPipeline>,
+    outline_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // the pipe's straight pipes and caps, and its elbows, with their counts. None while nothing is
+    // selected, or the pipe has none of them.
+    instances: [Option<(wgpu::Buffer, u32)>; 2],
+    width: u32,
+    height: u32,
+    // the seeds flooded back and forth between, only kept while there is a pipe to outline
+    seeds: Option<Seeds>,
+}
+
+struct Seeds {
+    views: [wgpu::TextureView; 2],
+    // reading each of the views
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+impl Outline {
+    /// Outlines drawn over a scene in `color_format`, with the mask drawn by `pipe_shader`'s vertex shader
+    /// through `pipe_layout`
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        pipe_layout: &wgpu::PipelineLayout,
+        pipe_shader: &wgpu::ShaderModule,
+    ) -> anyhow::Result<Self> {
+        let mask_pipeline = gpu::validated(device, "OutlineMaskPipeline", || {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("OutlineMaskPipeline"),
+                layout: Some(pipe_layout),
+                vertex: wgpu::VertexState {
+                    module: pipe_shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[models::ModelVertex::layout(), InstanceRaw::layout()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: pipe_shader,
+                    entry_point: Some("fs_outline_mask"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(SEED_FORMAT.into())],
+                }),
+                primitive: Default::default(),
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            })
+        })?;
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("OutlineBindGroupLayout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Uint,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("OutlinePipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        // A module of its own for every step rather than an override, which some backends share between
+        // the pipelines of a module
+        let create_pipeline = |label, step: u32, entry_point, target: wgpu::ColorTargetState| {
+            let source = format!("const STEP: i32 = {};\n{}", step, include_str!("outline.wgsl"));
+            let shader = gpu::create_shader_module(device, "OutlineShader", &source)?;
+            gpu::validated(device, label, || {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(label),
+                    layout: Some(&layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: Some("vs_main"),
+                        compilation_options: Default::default(),
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: Some(entry_point),
+                        compilation_options: Default::default(),
+                        targets: &[Some(target)],
+                    }),
+                    primitive: Default::default(),
+                    depth_stencil: None,
+                    multisample: Default::default(),
+                    multiview: None,
+                    cache: None,
+                })
+            })
+        };
+        let flood_pipelines = STEPS
+            .iter()
+            .map(|&step| create_pipeline("OutlineFloodPipeline", step, "fs_flood", SEED_FORMAT.into()))
+            .collect::<anyhow::Result<_>>()?;
+        let outline_pipeline = create_pipeline(
+            "OutlinePipeline",
+            // which the outline itself doesn't look at
+            1,
+            "fs_outline",
+            wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            },
+        )?;
+
+        Ok(Self {
+            mask_pipeline,
+            flood_pipelines,
+            outline_pipeline,
+            bind_group_layout,
+            instances: [None, None],
+            width: 1,
+            height: 1,
+            seeds: None,
+        })
+    }
+
+    /// Outline the pipe of `i_instances` and `l_instances` from now on, or nothing for `None`
+    pub fn set_pipe(&mut self, device: &wgpu::Device, pipe: Option<(&[InstanceRaw], &[InstanceRaw])>) {
+        let (i_instances, l_instances) = pipe.unwrap_or_default();
+        self.instances = [i_instances, l_instances].map(|instances| {
+            (!instances.is_empty()).then(|| {
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("OutlineInstanceBuffer"),
+                    contents: bytemuck::cast_slice(instances),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+                (buffer, instances.len() as u32)
+            })
+        });
+        self.update_seeds(device);
+    }
+
+    /// Outline over a scene `width` by `height` from now on
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.update_seeds(device);
+    }
+
+    fn update_seeds(&mut self, device: &wgpu::Device) {
+        if self.instances.iter().all(Option::is_none) {
+            self.seeds = None;
+            return;
+        }
+        let view = |label| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: self.width,
+                        height: self.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: SEED_FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let views = [view("OutlineSeeds0"), view("OutlineSeeds1")];
+        let bind_group = |view| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("OutlineBindGroup"),
+                layout: &self.bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                }],
+            })
+        };
+        let bind_groups = [bind_group(&views[0]), bind_group(&views[1])];
+        self.seeds = Some(Seeds { views, bind_groups });
+    }
+
+    /// Draw the outline over `target`, if there is a pipe to outline. `draw_views` draws the mask of every
+    /// view into the render pass it is given, with its camera, light and time bound, by `draw_mask`.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        draw_views: impl FnOnce(&mut wgpu::RenderPass),
+    ) {
+        let Some(seeds) = &self.seeds else {
+            return;
+        };
+        {
+            let mut render_pass = begin_pass(
+                encoder,
+                "OutlineMaskPass",
+                &seeds.views[0],
+                wgpu::LoadOp::Clear(wgpu::Color {
+                    r: NO_SEED,
+                    g: NO_SEED,
+                    b: 0.0,
+                    a: 0.0,
+                }),
+            );
+            render_pass.set_pipeline(&self.mask_pipeline);
+            draw_views(&mut render_pass);
+        }
+        // back and forth between the two, each pass reading the seeds the last one wrote
+        for (i, pipeline) in self.flood_pipelines.iter().enumerate() {
+            let mut render_pass = begin_pass(
+                encoder,
+                "OutlineFloodPass",
+                &seeds.views[(i + 1) % 2],
+                wgpu::LoadOp::Load,
+            );
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &seeds.bind_groups[i % 2], &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        let mut render_pass = begin_pass(encoder, "OutlinePass", target, wgpu::LoadOp::Load);
+        render_pass.set_pipeline(&self.outline_pipeline);
+        render_pass.set_bind_group(0, &seeds.bind_groups[STEPS.len() % 2], &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    /// Draw the pipe into the mask, with the meshes of straight pipes and elbows
+    pub fn draw_mask(&self, render_pass: &mut wgpu::RenderPass, meshes: [&models::Mesh; 2]) {
+        for (instances, mesh) in self.instances.iter().zip(meshes) {
+            let Some((buffer, count)) = instances else {
+                continue;
+            };
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.num_elements, 0, 0..*count);
+        }
+    }
+}
+
+fn begin_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            depth_slice: None,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    })
+}
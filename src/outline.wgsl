@@ -0,0 +1,56 @@
+// Outline around the selected pipe: the pixels it covers are spread to those around them by a jump
+// flood, a pass for each step, and those near enough are then drawn in the outline's color. See
+// `outline::Outline`.
+
+// the pixel of the selected pipe nearest to each pixel, far off for none
+@group(0) @binding(0)
+var seeds: texture_2d<u32>;
+
+// STEP, the pixels between the neighbors a flood pass looks at, is put in front for each pass
+
+// in pixels, must be at most the sum of the steps in outline.rs
+const WIDTH: f32 = 3.0;
+// the same as the highlight of the selected segments in shader.wgsl
+const COLOR: vec3<f32> = vec3<f32>(1.0, 0.85, 0.2);
+
+// One triangle covering the whole target, like the blitter's
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+fn pixel_distance(a: vec2<u32>, b: vec2<u32>) -> f32 {
+    return distance(vec2<f32>(a), vec2<f32>(b));
+}
+
+@fragment
+fn fs_flood(@builtin(position) position: vec4<f32>) -> @location(0) vec2<u32> {
+    let size = vec2<i32>(textureDimensions(seeds));
+    let pixel = vec2<i32>(position.xy);
+    var nearest = textureLoad(seeds, pixel, 0).xy;
+    let center = vec2<u32>(position.xy);
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbor = pixel + vec2<i32>(x, y) * STEP;
+            if any(neighbor < vec2<i32>(0)) || any(neighbor >= size) {
+                continue;
+            }
+            let seed = textureLoad(seeds, neighbor, 0).xy;
+            if pixel_distance(seed, center) < pixel_distance(nearest, center) {
+                nearest = seed;
+            }
+        }
+    }
+    return nearest;
+}
+
+@fragment
+fn fs_outline(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let distance = pixel_distance(textureLoad(seeds, vec2<i32>(position.xy), 0).xy, vec2<u32>(position.xy));
+    // the pipe itself is its own nearest pixel, and left as it is
+    if distance < 0.5 {
+        return vec4<f32>(0.0);
+    }
+    return vec4<f32>(COLOR, 1.0 - smoothstep(WIDTH - 1.0, WIDTH, distance));
+}
@@ -0,0 +1,193 @@
+use crate::plugin;
+
+macro_rules! rgb {
+    ($r:expr, $g:expr, $b:expr) => {
+        [($r as f32) / 256.0, ($g as f32) / 256.0, ($b as f32) / 256.0]
+    };
+}
+
+/// The colors new pipes are painted with
+#[derive(Debug)]
+pub struct Palette {
+    pub name: &'static str,
+    pub colors: &'static [[f32; 3]],
+    /// Chance of a new pipe to glow
+    pub glow_chance: f32,
+    /// How bright glowing pipes are regardless of the light, 1 shows their full color even in the dark
+    pub glow: f32,
+}
+
+pub const CLASSIC: Palette = Palette {
+    name: "classic",
+    colors: &[
+        rgb!(116, 222, 215),
+        rgb!(255, 0, 0),
+        rgb!(247, 104, 31),
+        rgb!(75, 151, 160),
+        rgb!(254, 211, 86),
+        rgb!(250, 231, 231),
+        rgb!(132, 123, 14),
+        rgb!(251, 155, 72),
+        rgb!(14, 169, 30),
+        rgb!(158, 235, 189),
+        rgb!(2, 143, 146),
+    ],
+    glow_chance: 0.0,
+    glow: 0.0,
+};
+
+/// Saturated light tubes, best on a black background
+pub const NEON: Palette = Palette {
+    name: "neon",
+    colors: &[
+        rgb!(0, 255, 255),
+        rgb!(255, 0, 200),
+        rgb!(255, 140, 0),
+        rgb!(120, 0, 255),
+        rgb!(0, 255, 90),
+    ],
+    glow_chance: 0.5,
+    glow: 1.0,
+};
+
+/// Colors people without red cones, as well as everyone else, tell apart easily
+pub const PROTANOPIA: Palette = Palette {
+    name: "protanopia",
+    colors: &[
+        rgb!(238, 17, 34),
+        rgb!(204, 0, 221),
+        rgb!(119, 255, 102),
+        rgb!(204, 221, 0),
+        rgb!(170, 34, 0),
+        rgb!(68, 238, 255),
+    ],
+    glow_chance: 0.0,
+    glow: 0.0,
+};
+
+/// Colors people without green cones, as well as everyone else, tell apart easily
+pub const DEUTERANOPIA: Palette = Palette {
+    name: "deuteranopia",
+    colors: &[
+        rgb!(34, 68, 238),
+        rgb!(136, 170, 255),
+        rgb!(238, 255, 102),
+        rgb!(136, 51, 51),
+        rgb!(238, 238, 0),
+        rgb!(68, 68, 0),
+    ],
+    glow_chance: 0.0,
+    glow: 0.0,
+};
+
+/// Colors people without blue cones, as well as everyone else, tell apart easily
+pub const TRITANOPIA: Palette = Palette {
+    name: "tritanopia",
+    colors: &[
+        rgb!(221, 0, 255),
+        rgb!(238, 17, 0),
+        rgb!(102, 51, 255),
+        rgb!(255, 119, 17),
+        rgb!(0, 85, 17),
+        rgb!(153, 255, 0),
+    ],
+    glow_chance: 0.0,
+    glow: 0.0,
+};
+
+pub const PALETTES: &[&Palette] = &[&CLASSIC, &NEON, &PROTANOPIA, &DEUTERANOPIA, &TRITANOPIA];
+
+// CIE76 color difference below which pipes of two colors are easily mistaken for each other
+const MIN_COLOR_DIFFERENCE: f32 = 20.0;
+
+/// Ways people see colors, to check palettes for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorVision {
+    Normal,
+    /// No red cones
+    Protanopia,
+    /// No green cones
+    Deuteranopia,
+    /// No blue cones
+    Tritanopia,
+}
+
+impl ColorVision {
+    pub const ALL: [ColorVision; 4] = [
+        ColorVision::Normal,
+        ColorVision::Protanopia,
+        ColorVision::Deuteranopia,
+        ColorVision::Tritanopia,
+    ];
+
+    /// How a linear RGB color looks, after Machado, Oliveira and Fernandes 2009 at full severity
+    fn simulate(self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+        let matrix = match self {
+            ColorVision::Normal => return [r, g, b],
+            ColorVision::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            ColorVision::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            ColorVision::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        };
+        matrix.map(|row| (row[0] * r + row[1] * g + row[2] * b).clamp(0.0, 1.0))
+    }
+}
+
+/// Pairs of colors, by their index, which people with `vision` can hardly tell apart, and how different
+/// they look, from 0 for the same color to 100 for black and white
+pub fn similar_colors(colors: &[[f32; 3]], vision: ColorVision) -> Vec<(usize, usize, f32)> {
+    let seen: Vec<_> = colors.iter().map(|color| to_lab(vision.simulate(*color))).collect();
+    let mut similar = vec![];
+    for (i, a) in seen.iter().enumerate() {
+        for (j, b) in seen.iter().enumerate().skip(i + 1) {
+            let difference = a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum::<f32>().sqrt();
+            if difference < MIN_COLOR_DIFFERENCE {
+                similar.push((i, j, difference));
+            }
+        }
+    }
+    similar
+}
+
+/// CIELAB of a linear RGB color, seen in D65 daylight like a screen
+fn to_lab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// The palette of the given name, built in or registered by a plugin
+pub fn find(name: &str) -> Option<&'static Palette> {
+    all()
+        .into_iter()
+        .find(|palette| palette.name.eq_ignore_ascii_case(name))
+}
+
+pub fn names() -> Vec<&'static str> {
+    all().into_iter().map(|palette| palette.name).collect()
+}
+
+/// The built-in palettes followed by the registered ones
+pub fn all() -> Vec<&'static Palette> {
+    PALETTES.iter().copied().chain(plugin::palettes()).collect()
+}
@@ -0,0 +1,120 @@
+use rand::Rng;
+use wgpu::util::DeviceExt;
+
+use crate::gpu::Uploader;
+
+const MAX_SPARKS: usize = 4096;
+const SPARKS_PER_EMIT: usize = 12;
+const MIN_SPEED: f32 = 1.0;
+const MAX_SPEED: f32 = 3.0;
+// sparks are tinted towards this, so dark pipes still throw visible ones
+const SPARK_TINT: [f32; 3] = [1.0, 0.8, 0.5];
+
+/// Where and when a spark was thrown, the shader works out where it is now
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Spark {
+    origin: [f32; 3],
+    birth: f32,
+    velocity: [f32; 3],
+    color: [f32; 3],
+}
+
+impl Spark {
+    const DEAD: Spark = Spark {
+        origin: [0.0; 3],
+        // long enough ago to have burnt out whatever the time is
+        birth: -1000.0,
+        velocity: [0.0; 3],
+        color: [0.0; 3],
+    };
+
+    pub fn layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<Spark>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 7]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+/// Sparks thrown off the heads of growing pipes. They only move on the GPU: the CPU writes each spark
+/// once into a ring buffer, and the shader animates it from the time since then.
+pub struct ParticleSystem {
+    spark_buffer: wgpu::Buffer,
+    // slot the next spark overwrites, the oldest one
+    next_slot: usize,
+}
+
+impl ParticleSystem {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let spark_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SparkBuffer"),
+            contents: bytemuck::cast_slice(&[Spark::DEAD; MAX_SPARKS]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            spark_buffer,
+            next_slot: 0,
+        }
+    }
+
+    /// Throw a burst of sparks from `origin` at `time` seconds, tinted like the pipe they come from
+    pub fn emit(&mut self, uploader: &mut Uploader, origin: cgmath::Point3<f32>, color: [f32; 3], time: f32) {
+        let mut rng = rand::rng();
+        let color = std::array::from_fn(|i| (color[i] + SPARK_TINT[i]) / 2.0);
+        let sparks = (0..SPARKS_PER_EMIT)
+            .map(|_| {
+                // mostly upwards, like sparks off a weld
+                let direction = cgmath::Vector3::new(
+                    rng.random_range(-1.0..1.0),
+                    rng.random_range(0.2..1.0),
+                    rng.random_range(-1.0..1.0),
+                );
+                let speed = rng.random_range(MIN_SPEED..MAX_SPEED);
+                Spark {
+                    origin: origin.into(),
+                    birth: time,
+                    velocity: (cgmath::InnerSpace::normalize(direction) * speed).into(),
+                    color,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Write in up to two parts, where the ring buffer wraps around
+        let first = sparks.len().min(MAX_SPARKS - self.next_slot);
+        let offset = (self.next_slot * size_of::<Spark>()) as wgpu::BufferAddress;
+        uploader.write(&self.spark_buffer, offset, bytemuck::cast_slice(&sparks[..first]));
+        if first < sparks.len() {
+            uploader.write(&self.spark_buffer, 0, bytemuck::cast_slice(&sparks[first..]));
+        }
+        self.next_slot = (self.next_slot + sparks.len()) % MAX_SPARKS;
+    }
+
+    /// Draw every spark with the particle pipeline, whichever are burnt out are dropped in the shader
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_vertex_buffer(0, self.spark_buffer.slice(..));
+        render_pass.draw(0..6, 0..MAX_SPARKS as u32);
+    }
+}
@@ -0,0 +1,72 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Time {
+    seconds: f32,
+    aging: f32,
+}
+
+@group(2) @binding(0)
+var<uniform> time: Time;
+
+const LIFETIME: f32 = 1.2;
+const GRAVITY: vec3<f32> = vec3<f32>(0.0, -4.0, 0.0);
+const SPARK_SIZE: f32 = 0.08;
+
+struct SparkInput {
+    @location(0) origin: vec3<f32>,
+    @location(1) birth: f32,
+    @location(2) velocity: vec3<f32>,
+    @location(3) color: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // position within the quad, from -1 to 1
+    @location(0) offset: vec2<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+// A quad facing the eye, wherever the spark has flown by now
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, spark: SparkInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    var out: VertexOutput;
+    let age = time.seconds - spark.birth;
+    if age < 0.0 || age > LIFETIME {
+        // Burnt out, outside of the clip volume
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+
+    let position = spark.origin + spark.velocity * age + 0.5 * GRAVITY * age * age;
+    let forward = normalize(position - camera.view_pos.xyz);
+    let right = normalize(cross(forward, vec3<f32>(0.0, 1.0, 0.0)));
+    let up = cross(right, forward);
+    let corner = corners[index];
+    let world_position = position + (right * corner.x + up * corner.y) * SPARK_SIZE;
+
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.offset = corner;
+    // Cooling down as it falls
+    out.color = spark.color * (1.0 - age / LIFETIME);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let glow = 1.0 - smoothstep(0.0, 1.0, length(in.offset));
+    return vec4<f32>(in.color * glow, glow);
+}
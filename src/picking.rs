@@ -0,0 +1,172 @@
+use std::fmt;
+
+use cgmath::{InnerSpace, Matrix3, Point3, Quaternion, Vector3};
+
+use crate::chunks::Bounds;
+use crate::instance::InstanceRaw;
+use crate::world::Direction;
+
+/// A half line from the eye into the world, to find what is under the cursor
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    /// Of length 1
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    /// How far along the ray it enters `bounds`, 0 if it starts inside them, `None` if it misses them
+    pub fn hit(&self, bounds: &Bounds) -> Option<f32> {
+        let (near, far) = self.span(bounds);
+        (near <= far).then_some(near)
+    }
+
+    /// How far along the ray it leaves `bounds`, `None` if it misses them
+    pub fn leave(&self, bounds: &Bounds) -> Option<f32> {
+        let (near, far) = self.span(bounds);
+        (near <= far).then_some(far)
+    }
+
+    // from where the ray enters `bounds`, or starts inside them, to where it leaves them, the other way
+    // round if it misses them
+    fn span(&self, bounds: &Bounds) -> (f32, f32) {
+        let (mut near, mut far) = (0.0f32, f32::INFINITY);
+        for axis in 0..3 {
+            // parallel to the axis the inverse is infinite, and the slab either contains the ray or not
+            let inverse = 1.0 / self.direction[axis];
+            let a = (bounds.min[axis] - self.origin[axis]) * inverse;
+            let b = (bounds.max[axis] - self.origin[axis]) * inverse;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+        (near, far)
+    }
+}
+
+/// What a pipe segment in a block is
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SegmentKind {
+    Straight,
+    Elbow,
+    /// The flange closing a pipe off
+    Cap,
+}
+
+/// A pipe segment picked with the mouse, to see how it sits in its block
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Selection {
+    /// The block the segment is in, snapped to the grid
+    pub block: (i32, i32, i32),
+    pub kind: SegmentKind,
+    /// Which way the pipe leaves the block
+    pub direction: Direction,
+    /// Which way the pipe goes as it comes into an elbow, the same as `direction` for the others
+    pub entry: Direction,
+    /// How the segment's mesh is turned from its own axes to the world's
+    pub rotation: Quaternion<f32>,
+    /// Distance between the centers of neighboring blocks, see `Config::cell_size`
+    pub cell_size: f32,
+    /// Blocks before this one in its pipe
+    pub segment: u32,
+}
+
+impl Selection {
+    /// Read the segment back from an instance, so that it shows what is drawn rather than what the world
+    /// meant to draw
+    pub fn of_instance(instance: &InstanceRaw, is_elbow: bool, cell_size: f32) -> Self {
+        let model = instance.model();
+        let position = instance.position() / cell_size;
+        let (x, y) = (model.x.truncate(), model.y.truncate());
+        let kind = match is_elbow {
+            true => SegmentKind::Elbow,
+            // caps are straight pipes squashed along the pipe
+            false if y.magnitude() < 0.99 * cell_size => SegmentKind::Cap,
+            false => SegmentKind::Straight,
+        };
+        // Straight meshes run along their local Y axis, elbows from their local +Y end to their +X end
+        let (direction, entry) = match kind {
+            SegmentKind::Elbow => (nearest_direction(x), nearest_direction(-y)),
+            _ => {
+                let direction = nearest_direction(y * instance.flow_axis());
+                (direction, direction)
+            }
+        };
+        let z = model.z.truncate();
+        let rotation = Quaternion::from(Matrix3::from_cols(x.normalize(), y.normalize(), z.normalize()));
+        Self {
+            block: (
+                position.x.round() as i32,
+                position.y.round() as i32,
+                position.z.round() as i32,
+            ),
+            kind,
+            direction,
+            entry,
+            rotation,
+            cell_size,
+            segment: instance.flow_phase() as u32,
+        }
+    }
+
+    /// Center of the block in the scene, around which the shaders highlight the segments
+    pub fn block_center(&self) -> Point3<f32> {
+        let (x, y, z) = self.block;
+        Point3::new(x as f32, y as f32, z as f32) * self.cell_size
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (x, y, z) = self.block;
+        writeln!(f, "Block {}, {}, {}", x, y, z)?;
+        match self.kind {
+            SegmentKind::Elbow => writeln!(
+                f,
+                "Elbow in {} out {}",
+                direction_name(self.entry),
+                direction_name(self.direction)
+            )?,
+            SegmentKind::Straight => writeln!(f, "Straight {}", direction_name(self.direction))?,
+            SegmentKind::Cap => writeln!(f, "Cap {}", direction_name(self.direction))?,
+        }
+        // as an angle around an axis, which reads easier than the quaternion itself
+        let rotation = match self.rotation.s < 0.0 {
+            true => -self.rotation,
+            false => self.rotation,
+        };
+        let angle = 2.0 * rotation.s.clamp(-1.0, 1.0).acos();
+        if rotation.v.magnitude() < 1e-4 {
+            write!(f, "Not rotated")
+        } else {
+            let axis = rotation.v.normalize();
+            write!(
+                f,
+                "Rotated {:.0}° around {:.2}, {:.2}, {:.2}",
+                angle.to_degrees(),
+                axis.x,
+                axis.y,
+                axis.z
+            )
+        }
+    }
+}
+
+/// The direction closest to `vector`
+fn nearest_direction(vector: Vector3<f32>) -> Direction {
+    Direction::ALL
+        .into_iter()
+        .max_by(|a, b| a.vector().dot(vector).total_cmp(&b.vector().dot(vector)))
+        .unwrap()
+}
+
+/// Like in layouts, see `layout::Layout`
+pub fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::X => "+X",
+        Direction::Y => "+Y",
+        Direction::Z => "+Z",
+        Direction::_X => "-X",
+        Direction::_Y => "-Y",
+        Direction::_Z => "-Z",
+    }
+}
@@ -0,0 +1,85 @@
+//! Registry of pipe generators, palettes and post effects from outside of XPipe, selectable by name from
+//! the config like the built-in ones. Register them before calling `run`:
+//!
+//! ```no_run
+//! use xpipe::plugin::{self, Direction, PipeGenerator, Step};
+//!
+//! struct Upwards;
+//!
+//! impl PipeGenerator for Upwards {
+//!     fn next_direction(&mut self, _step: Step, _rng: &mut rand::rngs::StdRng) -> Direction {
+//!         Direction::Y
+//!     }
+//! }
+//!
+//! plugin::register_generator("upwards", || Box::new(Upwards));
+//! xpipe::run().unwrap();
+//! ```
+
+use std::sync::{LazyLock, RwLock};
+
+pub use crate::generator::{Constraints, CreateGenerator, DirectionWeights, PipeGenerator, Step, Turn};
+pub use crate::palette::Palette;
+pub use crate::world::Direction;
+
+/// A full-screen pass over the finished frame. `wgsl` defines
+/// `fn effect(color: vec4<f32>, uv: vec2<f32>) -> vec4<f32>`, which gets the color of every pixel and its
+/// position from (0, 0) at the top left to (1, 1) at the bottom right, and returns the new color.
+#[derive(Copy, Clone, Debug)]
+pub struct PostEffect {
+    pub name: &'static str,
+    pub wgsl: &'static str,
+}
+
+#[derive(Default)]
+struct Registry {
+    generators: Vec<(&'static str, CreateGenerator)>,
+    palettes: Vec<&'static Palette>,
+    post_effects: Vec<PostEffect>,
+}
+
+static REGISTRY: LazyLock<RwLock<Registry>> = LazyLock::new(Default::default);
+
+fn registry() -> std::sync::RwLockReadGuard<'static, Registry> {
+    // Registering can't leave the registry half-changed, so a panic while holding it doesn't matter
+    REGISTRY.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn registry_mut() -> std::sync::RwLockWriteGuard<'static, Registry> {
+    REGISTRY.write().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Make a generator selectable as `generator = "<name>"`, built-in ones of the same name take precedence
+pub fn register_generator(name: &'static str, create: CreateGenerator) {
+    registry_mut().generators.push((name, create));
+}
+
+/// Make a palette selectable by its name, built-in ones of the same name take precedence
+pub fn register_palette(palette: &'static Palette) {
+    registry_mut().palettes.push(palette);
+}
+
+/// Make a post effect selectable as `post_effect = "<name>"`, built-in ones of the same name take precedence
+pub fn register_post_effect(effect: PostEffect) {
+    registry_mut().post_effects.push(effect);
+}
+
+pub(crate) fn create_generator(name: &str) -> Option<Box<dyn PipeGenerator>> {
+    registry()
+        .generators
+        .iter()
+        .find(|(registered, _)| registered.eq_ignore_ascii_case(name))
+        .map(|(_, create)| create())
+}
+
+pub(crate) fn generator_names() -> Vec<String> {
+    registry().generators.iter().map(|(name, _)| name.to_string()).collect()
+}
+
+pub(crate) fn palettes() -> Vec<&'static Palette> {
+    registry().palettes.clone()
+}
+
+pub(crate) fn post_effects() -> Vec<PostEffect> {
+    registry().post_effects.clone()
+}
@@ -0,0 +1,187 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+const MAX_PASSES: u32 = 8;
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Measures how long each render pass takes on the GPU with timestamp queries, and logs a rolling
+/// average every few seconds. Needs `wgpu::Features::TIMESTAMP_QUERY`.
+pub struct GpuProfiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    timestamp_period: f32, // nanoseconds per tick
+
+    // passes which wrote timestamps in the current frame, in query order
+    frame_passes: Vec<&'static str>,
+    // passes whose timestamps are being copied to, or read back from, `readback_buffer`
+    readback_passes: Vec<&'static str>,
+    is_copy_pending: bool,
+    is_mapping: bool,
+    // how mapping `readback_buffer` went, `None` while the GPU is still at it
+    map_result: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+
+    // total milliseconds and sample count per pass since the last report
+    totals: Vec<(&'static str, f64, u32)>,
+    // average milliseconds per pass at the last report
+    averages: Vec<(&'static str, f64)>,
+    last_report: Instant,
+}
+
+impl GpuProfiler {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let query_count = MAX_PASSES * 2;
+        let buffer_size = (query_count as usize * size_of::<u64>()) as wgpu::BufferAddress;
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("TimestampQuerySet"),
+                ty: wgpu::QueryType::Timestamp,
+                count: query_count,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("TimestampResolveBuffer"),
+                size: buffer_size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("TimestampReadbackBuffer"),
+                size: buffer_size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            timestamp_period: queue.get_timestamp_period(),
+            frame_passes: vec![],
+            readback_passes: vec![],
+            is_copy_pending: false,
+            is_mapping: false,
+            map_result: Arc::new(Mutex::new(None)),
+            totals: vec![],
+            averages: vec![],
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Queries for a render pass named `name`, to get its timestamp writes with, `None` once every query of
+    /// the frame is used. Reserved apart from the writes so that passes can be encoded on other threads.
+    pub fn reserve_pass(&mut self, name: &'static str) -> Option<u32> {
+        let index = self.frame_passes.len() as u32 * 2;
+        if index >= MAX_PASSES * 2 {
+            return None;
+        }
+        self.frame_passes.push(name);
+        Some(index)
+    }
+
+    /// Timestamp writes for the pass `reserve_pass` gave `index`
+    pub fn timestamp_writes(&self, index: u32) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(index),
+            end_of_pass_write_index: Some(index + 1),
+        }
+    }
+
+    /// Copy this frame's timestamps to the readback buffer, unless it still holds an older frame
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let passes = std::mem::take(&mut self.frame_passes);
+        if passes.is_empty() || self.is_mapping {
+            return;
+        }
+        let query_count = passes.len() as u32 * 2;
+        encoder.resolve_query_set(&self.query_set, 0..query_count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            (query_count as usize * size_of::<u64>()) as wgpu::BufferAddress,
+        );
+        self.readback_passes = passes;
+        self.is_copy_pending = true;
+    }
+
+    /// Start reading back the copied timestamps, must be called after the frame is submitted
+    pub fn after_submit(&mut self) {
+        if !self.is_copy_pending {
+            return;
+        }
+        self.is_copy_pending = false;
+        self.is_mapping = true;
+        let map_result = self.map_result.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *map_result.lock().unwrap() = Some(result);
+            });
+    }
+
+    /// Pick up timestamps of an earlier frame once the GPU is done with them
+    pub fn collect(&mut self, device: &wgpu::Device) {
+        if !self.is_mapping {
+            return;
+        }
+        let _ = device.poll(wgpu::PollType::Poll);
+        let map_result = self.map_result.lock().unwrap().take();
+        match map_result {
+            None => return,
+            Some(Ok(())) => {}
+            Some(Err(e)) => {
+                // the frame's timestamps are lost, the next frame's are read back instead
+                warn!("Cannot read back GPU timestamps: {}", e);
+                self.readback_buffer.unmap();
+                self.is_mapping = false;
+                return;
+            }
+        }
+
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+            for (i, name) in self.readback_passes.iter().enumerate() {
+                let (begin, end) = (timestamps[i * 2], timestamps[i * 2 + 1]);
+                if end < begin {
+                    continue;
+                }
+                let ms = (end - begin) as f64 * self.timestamp_period as f64 / 1_000_000.0;
+                match self.totals.iter_mut().find(|(n, _, _)| n == name) {
+                    Some((_, total, count)) => {
+                        *total += ms;
+                        *count += 1;
+                    }
+                    None => self.totals.push((name, ms, 1)),
+                }
+            }
+        }
+        self.readback_buffer.unmap();
+        self.is_mapping = false;
+
+        if self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.report();
+        }
+    }
+
+    /// Average milliseconds of every pass as of the last report
+    pub fn averages(&self) -> &[(&'static str, f64)] {
+        &self.averages
+    }
+
+    fn report(&mut self) {
+        self.averages = self
+            .totals
+            .iter()
+            .map(|(name, total, count)| (*name, total / *count as f64))
+            .collect();
+        let report = self
+            .averages
+            .iter()
+            .map(|(name, ms)| format!("{}: {:.3} ms", name, ms))
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!("GPU pass times (avg over {}s) {}", REPORT_INTERVAL.as_secs(), report);
+        self.totals.clear();
+        self.last_report = Instant::now();
+    }
+}
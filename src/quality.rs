@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+// lowest render scale the controller goes down to, below which pipes blur into each other
+const MIN_SCALE: f32 = 0.35;
+// factor the render scale changes by in a step, down or back up
+const SCALE_STEP: f32 = 0.85;
+// weight of the newest frame in the smoothed frame time
+const SMOOTHING: f32 = 0.05;
+// frames to wait after a change before judging the new scale, while the smoothed time catches up
+const SETTLE_FRAMES: u32 = 60;
+// frames at the target before trying a step back up, doubled every time that turns out too slow
+const PROBE_FRAMES: u32 = 300;
+const MAX_PROBE_FRAMES: u32 = PROBE_FRAMES * 16;
+// frames this much slower than the target are missing it, a vsync'd frame is a little late at times
+const SLOW: f32 = 1.15;
+// frames this much faster than the target leave room for a finer scale right away
+const FAST: f32 = 0.7;
+// frames taking this much longer than the target were held up by something else, e.g. a pause
+const STALL: f32 = 4.0;
+
+/// Lowers the render scale while frames come slower than a target frame rate, and raises it back towards
+/// the configured one once there is headroom. Frames drawn in step with the display take as long as the
+/// target however fast the GPU is, so it tries a step up once in a while, and waits longer before the next
+/// try whenever that turns out too slow.
+pub struct QualityController {
+    // seconds a frame may take
+    target: f32,
+    max_scale: f32,
+    scale: f32,
+    // smoothed seconds a frame took, `None` until the first one after a change or stall
+    frame_time: Option<f32>,
+    frames_since_change: u32,
+    probe_frames: u32,
+    // whether the last change was a step up on trial
+    is_probing: bool,
+}
+
+impl QualityController {
+    /// Keep `target_fps` with a render scale of at most `max_scale`
+    pub fn new(target_fps: f32, max_scale: f32) -> Self {
+        Self {
+            target: 1.0 / target_fps,
+            max_scale,
+            scale: max_scale,
+            frame_time: None,
+            frames_since_change: 0,
+            probe_frames: PROBE_FRAMES,
+            is_probing: false,
+        }
+    }
+
+    /// Take in how long the last frame took, and the render scale to switch to if it should change
+    pub fn frame(&mut self, duration: Duration) -> Option<f32> {
+        let seconds = duration.as_secs_f32();
+        if seconds > self.target * STALL {
+            self.frame_time = None;
+            return None;
+        }
+        let frame_time = self
+            .frame_time
+            .map_or(seconds, |average| average + (seconds - average) * SMOOTHING);
+        self.frame_time = Some(frame_time);
+        self.frames_since_change += 1;
+        if self.frames_since_change < SETTLE_FRAMES {
+            return None;
+        }
+        if self.is_probing && frame_time <= self.target * SLOW {
+            // the step up held
+            self.is_probing = false;
+            self.probe_frames = PROBE_FRAMES;
+        }
+
+        let scale = if frame_time > self.target * SLOW && self.scale > MIN_SCALE {
+            if self.is_probing {
+                self.probe_frames = (self.probe_frames * 2).min(MAX_PROBE_FRAMES);
+            }
+            self.is_probing = false;
+            (self.scale * SCALE_STEP).max(MIN_SCALE)
+        } else if frame_time < self.target * FAST && self.scale < self.max_scale {
+            self.probe_frames = PROBE_FRAMES;
+            self.is_probing = false;
+            (self.scale / SCALE_STEP).min(self.max_scale)
+        } else if frame_time <= self.target * SLOW
+            && self.scale < self.max_scale
+            && self.frames_since_change >= self.probe_frames
+        {
+            self.is_probing = true;
+            (self.scale / SCALE_STEP).min(self.max_scale)
+        } else {
+            return None;
+        };
+        self.scale = scale;
+        self.frames_since_change = 0;
+        Some(scale)
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Context;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+
+use crate::generator::{Constraints, DirectionWeights};
+use crate::region::Region;
+use crate::world;
+
+// marks share strings, and their version should the recipe ever change
+const PREFIX: &str = "xpipe1.";
+
+/// What a world grows from, to share as a string like `xpipe1.c2VlZCA9IDQy...` and grow the same pipes
+/// from with `--from-share`. The pipes come out the same as long as none of this changed while they grew.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Recipe {
+    pub seed: u64,
+    pub palette: String,
+    pub generator: String,
+    pub turn_probability: f32,
+    /// 1 for solid pipes, less for glass
+    pub opacity: f32,
+    // missing from share strings of before worlds could grow larger
+    #[serde(default = "default_world_size")]
+    pub world_size: u32,
+    #[serde(default)]
+    pub max_pipe_length: Option<u32>,
+    #[serde(default)]
+    pub direction_weights: DirectionWeights,
+    // share strings of before there were constraints grew without any
+    #[serde(default = "no_constraints")]
+    pub constraints: Constraints,
+    #[serde(default)]
+    pub region: Option<Region>,
+    #[serde(default)]
+    pub look_ahead: u32,
+    #[serde(default)]
+    pub teams: u32,
+}
+
+fn default_world_size() -> u32 {
+    world::DEFAULT_SIZE
+}
+
+fn no_constraints() -> Constraints {
+    Constraints::NONE
+}
+
+impl fmt::Display for Recipe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let toml = toml::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}{}", PREFIX, URL_SAFE_NO_PAD.encode(toml))
+    }
+}
+
+impl FromStr for Recipe {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let encoded = s
+            .trim()
+            .strip_prefix(PREFIX)
+            .with_context(|| format!("Expected a share string starting with {:?}", PREFIX))?;
+        let toml = URL_SAFE_NO_PAD
+            .decode(encoded)
+            .context("Share string is cut off or mistyped")?;
+        let toml = String::from_utf8(toml).context("Share string is cut off or mistyped")?;
+        toml::from_str(&toml).context("Share string is from another version of XPipe")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn share_strings_decode_to_the_recipe_they_encode() {
+        let recipe = Recipe {
+            seed: 42,
+            palette: "sunset".to_string(),
+            generator: "straight".to_string(),
+            turn_probability: 0.25,
+            opacity: 0.5,
+            world_size: 24,
+            max_pipe_length: Some(100),
+            direction_weights: DirectionWeights {
+                y: 4.0,
+                neg_y: 0.0,
+                ..DirectionWeights::EVEN
+            },
+            constraints: Constraints {
+                no_reversals: true,
+                avoid_spirals: true,
+            },
+            region: Some(Region::Ellipsoid { radii: [4.0, 8.0, 6.0] }),
+            look_ahead: 3,
+            teams: 2,
+        };
+        let share = recipe.to_string();
+        assert!(share.starts_with(PREFIX));
+        assert_eq!(share.parse::<Recipe>().unwrap(), recipe);
+    }
+
+    #[test]
+    fn rejects_malformed_share_strings() {
+        let error = |s: &str| s.parse::<Recipe>().unwrap_err().to_string();
+        assert_eq!(
+            error("c2VlZCA9IDQy"),
+            r#"Expected a share string starting with "xpipe1.""#
+        );
+        assert_eq!(error("xpipe1.c2VlZCA9I!Qy"), "Share string is cut off or mistyped");
+        let share = format!("{}{}", PREFIX, URL_SAFE_NO_PAD.encode("seed = 42"));
+        assert_eq!(error(&share), "Share string is from another version of XPipe");
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+// samples along each side of the world at most, when estimating how much of it a region takes
+const SHARE_SAMPLES: u32 = 64;
+
+/// A shape pipes only grow inside of, around the center of the world and measured in blocks, e.g.
+/// `region = { shape = "sphere", radius = 8 }` for a ball of pipes rather than a box. Blocks count as
+/// inside when their centers are.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "kebab-case")]
+pub enum Region {
+    Sphere {
+        radius: f32,
+    },
+    /// A column standing upright, along Y
+    Cylinder {
+        radius: f32,
+    },
+    /// A ball stretched to a radius along each of X, Y and Z
+    Ellipsoid {
+        radii: [f32; 3],
+    },
+    /// Everything behind a plane, the side its `normal` points away from, with the plane `offset` blocks
+    /// from the center along the normal
+    HalfSpace {
+        normal: [f32; 3],
+        offset: f32,
+    },
+}
+
+impl Region {
+    pub fn check(&self) -> anyhow::Result<()> {
+        match *self {
+            Region::Sphere { radius } | Region::Cylinder { radius } if radius <= 0.0 => {
+                bail!("Region radius must be positive, got {}", radius)
+            }
+            Region::Ellipsoid { radii } if radii.iter().any(|radius| *radius <= 0.0) => {
+                bail!("Region radii must be positive, got {:?}", radii)
+            }
+            Region::HalfSpace { normal, .. } if normal == [0.0; 3] => bail!("Region normal must not be 0"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the block at `position` is inside, in a world of `extents` blocks along X, Y and Z
+    pub fn contains(&self, position: (u32, u32, u32), extents: [u32; 3]) -> bool {
+        let from_center = |p: u32, extent: u32| p as f32 - extent as f32 / 2.0;
+        let (x, y, z) = (
+            from_center(position.0, extents[0]),
+            from_center(position.1, extents[1]),
+            from_center(position.2, extents[2]),
+        );
+        match *self {
+            Region::Sphere { radius } => x * x + y * y + z * z <= radius * radius,
+            Region::Cylinder { radius } => x * x + z * z <= radius * radius,
+            Region::Ellipsoid { radii: [a, b, c] } => (x / a).powi(2) + (y / b).powi(2) + (z / c).powi(2) <= 1.0,
+            Region::HalfSpace {
+                normal: [a, b, c],
+                offset,
+            } => {
+                let length = (a * a + b * b + c * c).sqrt();
+                (x * a + y * b + z * c) / length <= offset
+            }
+        }
+    }
+
+    /// Share of the blocks of a world of `extents` blocks along X, Y and Z which are inside, from 0 to 1.
+    /// Large worlds are only sampled, which comes close enough for how full the world is.
+    pub fn share(&self, extents: [u32; 3]) -> f32 {
+        let [xs, ys, zs] = extents.map(|extent| {
+            let blocks = extent + 1;
+            let step = blocks.div_ceil(SHARE_SAMPLES).max(1);
+            (0..blocks).step_by(step as usize).collect::<Vec<_>>()
+        });
+        let mut inside = 0;
+        for &x in &xs {
+            for &y in &ys {
+                inside += zs.iter().filter(|&&z| self.contains((x, y, z), extents)).count();
+            }
+        }
+        inside as f32 / (xs.len() * ys.len() * zs.len()) as f32
+    }
+}
@@ -0,0 +1,154 @@
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+
+use anyhow::{Context, bail};
+use log::{info, warn};
+
+use crate::generator::DirectionWeights;
+
+// large enough for any message this understands, longer ones are cut off and fail to parse
+const MAX_PACKET_SIZE: usize = 1024;
+const BUNDLE_TAG: &[u8] = b"#bundle\0";
+
+/// What a remote controller asked for
+#[derive(Clone, Debug, PartialEq)]
+pub enum RemoteCommand {
+    AddPipes(u32),
+    Reset,
+    SetTurnProbability(f32),
+    SetDirectionWeights(DirectionWeights),
+    SetPalette(String),
+    /// Orbit the light by this many degrees
+    MoveLight(f32),
+}
+
+/// Listens for OSC messages over UDP, so that VJ software and home automation can drive the visuals.
+/// Understood addresses, with their arguments:
+/// - `/add_pipe [count: int]`
+/// - `/reset`
+/// - `/set_turn_probability probability: float`
+/// - `/set_direction_weights weights: string`, like `y=4,neg_y=0.5`
+/// - `/set_palette name: string`
+/// - `/move_light degrees: float`
+pub struct RemoteControl {
+    socket: UdpSocket,
+}
+
+impl RemoteControl {
+    pub fn bind(address: SocketAddr) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(address).with_context(|| format!("Cannot listen on {}", address))?;
+        // Polled from the event loop, which must never wait on the network
+        socket.set_nonblocking(true)?;
+        info!("Listening for OSC messages on {}", address);
+        Ok(Self { socket })
+    }
+
+    /// Every command received since the last poll
+    pub fn poll(&mut self) -> Vec<RemoteCommand> {
+        let mut commands = vec![];
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((size, sender)) => {
+                    if let Err(e) = parse_packet(&buffer[..size], &mut commands) {
+                        warn!("Invalid OSC packet from {}: {:?}", sender, e);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return commands,
+                Err(e) => {
+                    warn!("Cannot receive OSC packets: {:?}", e);
+                    return commands;
+                }
+            }
+        }
+    }
+}
+
+enum Argument {
+    Int(i32),
+    Float(f32),
+    String(String),
+}
+
+impl Argument {
+    fn to_f32(&self) -> anyhow::Result<f32> {
+        match self {
+            Argument::Int(i) => Ok(*i as f32),
+            Argument::Float(f) => Ok(*f),
+            Argument::String(s) => bail!("Expected a number, got {:?}", s),
+        }
+    }
+}
+
+/// Append the commands in a message or a bundle of them to `commands`
+fn parse_packet(packet: &[u8], commands: &mut Vec<RemoteCommand>) -> anyhow::Result<()> {
+    let Some(mut elements) = packet.strip_prefix(BUNDLE_TAG) else {
+        commands.push(parse_message(packet)?);
+        return Ok(());
+    };
+    // Everything runs as soon as it arrives, so the time tag is of no use
+    elements = elements.get(8..).context("Bundle without time tag")?;
+    while !elements.is_empty() {
+        let (size, rest) = read_i32(elements)?;
+        let element = rest.get(..size as usize).context("Truncated bundle element")?;
+        parse_packet(element, commands)?;
+        elements = &rest[size as usize..];
+    }
+    Ok(())
+}
+
+fn parse_message(packet: &[u8]) -> anyhow::Result<RemoteCommand> {
+    let (address, mut rest) = read_string(packet)?;
+    let mut arguments = vec![];
+    // Very old senders leave out the type tags of messages without arguments
+    if !rest.is_empty() {
+        let tags;
+        (tags, rest) = read_string(rest)?;
+        let tags = tags.strip_prefix(',').context("Missing type tags")?;
+        for tag in tags.chars() {
+            let argument;
+            (argument, rest) = match tag {
+                'i' => read_i32(rest).map(|(i, rest)| (Argument::Int(i), rest))?,
+                'f' => read_i32(rest).map(|(i, rest)| (Argument::Float(f32::from_bits(i as u32)), rest))?,
+                's' => read_string(rest).map(|(s, rest)| (Argument::String(s), rest))?,
+                _ => bail!("Unsupported argument type {:?}", tag),
+            };
+            arguments.push(argument);
+        }
+    }
+
+    let first = arguments.first();
+    let number = || first.context("Missing argument").and_then(Argument::to_f32);
+    Ok(match address.as_str() {
+        "/add_pipe" => RemoteCommand::AddPipes(match first {
+            Some(argument) => argument.to_f32()?.max(0.0) as u32,
+            None => 1,
+        }),
+        "/reset" => RemoteCommand::Reset,
+        "/set_turn_probability" => RemoteCommand::SetTurnProbability(number()?),
+        "/set_direction_weights" => match first {
+            Some(Argument::String(weights)) => RemoteCommand::SetDirectionWeights(weights.parse()?),
+            _ => bail!("Expected direction weights"),
+        },
+        "/set_palette" => match first {
+            Some(Argument::String(name)) => RemoteCommand::SetPalette(name.clone()),
+            _ => bail!("Expected a palette name"),
+        },
+        "/move_light" => RemoteCommand::MoveLight(number()?),
+        _ => bail!("Unknown address {:?}", address),
+    })
+}
+
+/// A big-endian integer, and whatever follows it
+fn read_i32(data: &[u8]) -> anyhow::Result<(i32, &[u8])> {
+    let (bytes, rest) = data.split_first_chunk::<4>().context("Truncated number")?;
+    Ok((i32::from_be_bytes(*bytes), rest))
+}
+
+/// A null-terminated string padded to a multiple of 4 bytes, and whatever follows it
+fn read_string(data: &[u8]) -> anyhow::Result<(String, &[u8])> {
+    let length = data.iter().position(|b| *b == 0).context("Unterminated string")?;
+    let string = std::str::from_utf8(&data[..length])?.to_string();
+    let padded = (length / 4 + 1) * 4;
+    Ok((string, data.get(padded..).unwrap_or_default()))
+}
@@ -0,0 +1,1865 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, bail};
+use cgmath::prelude::*;
+
+use crate::blit::Blitter;
+use crate::camera::{Camera, CameraUniform};
+use crate::chunks::{Bounds, ChunkChange, ChunkKey, Chunks};
+use crate::config::{Antialiasing, ResetTransition, Shading};
+use crate::exposure::AutoExposure;
+use crate::gizmos::{GizmoLines, Gizmos};
+use crate::gpu::{self, UniformBuffer, Uploader};
+use crate::gpu_world::GpuWorld;
+use crate::instance::InstanceRaw;
+use crate::light::{DEFAULT_AMBIENT, DEFAULT_GROUND, DEFAULT_SKY, LightOrbit, LightUniform};
+use crate::material;
+use crate::models::{self, Vertex};
+use crate::outline::Outline;
+use crate::particles::{ParticleSystem, Spark};
+use crate::picking::Selection;
+use crate::plugin::PostEffect;
+use crate::profiler::GpuProfiler;
+use crate::split_screen;
+use crate::taa::{self, Taa, TaaHistory};
+use crate::text::TextOverlay;
+use crate::texture;
+use crate::tubes;
+
+// Height of the floor below the world, and where and how far it reaches, must match floor.wgsl
+const FLOOR_Y: f32 = -1.0;
+const FLOOR_CENTER: [f32; 2] = [15.0, 15.0];
+const FLOOR_RADIUS: f32 = 60.0;
+
+const BYTES_PER_PIXEL: u32 = 4;
+// bytes of the staging buffers uploads are written through, more than a frame usually uploads
+const UPLOAD_CHUNK_SIZE: wgpu::BufferAddress = 1 << 20;
+
+pub const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.01,
+    g: 0.01,
+    b: 0.01,
+    a: 1.0,
+};
+
+/// Seconds of animation so far, which stand still while paused
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TimeUniform {
+    seconds: f32,
+    // seconds pipes take to fade, 0 keeps them vivid
+    aging: f32,
+    // when the pipes drawn with this started draining away and for how long, 0 while they aren't
+    drain_start: f32,
+    drain_duration: f32,
+    // births of the newest and the oldest of them, which drain first and last
+    drain_newest: f32,
+    drain_oldest: f32,
+    // 1 if they fade out all at once rather than shrink, see `ResetTransition`
+    drain_fade: f32,
+    _padding: f32,
+    // center of the block whose segments are highlighted and w its size, 0 if there is none, see
+    // `picking::Selection`
+    selected: [f32; 4],
+}
+
+/// How pipes are drawn over what is in the depth buffer already
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum PipePass {
+    Solid,
+    /// Blended, without writing depth
+    Glass,
+    /// Solid, shading only the nearest fragments the depth pre-pass left
+    AfterPrepass,
+}
+
+/// Settings in which pipelines differ from each other
+struct PipelineOptions<'a> {
+    label: &'a str,
+    shader: &'a wgpu::ShaderModule,
+    vertex_entry: &'a str,
+    fragment_entry: &'a str,
+    // nothing for depth only passes, which still need a target like the pass they are in
+    color_writes: wgpu::ColorWrites,
+    vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+    blend: wgpu::BlendState,
+    depth_write_enabled: bool,
+    // for depth going from 0 near to 1 far, turned around for reversed depth
+    depth_compare: wgpu::CompareFunction,
+    cull_mode: Option<wgpu::Face>,
+}
+
+/// Every pipeline pipes are drawn with, for one way of reading their instances
+struct PipePipelines {
+    // one for every shading, solid and glass
+    shaded: HashMap<(Shading, PipePass), wgpu::RenderPipeline>,
+    // writes the depth of solid pipes ahead of shading them, see `set_depth_prepass`
+    depth_prepass: wgpu::RenderPipeline,
+    // dark shell around toon shaded pipes
+    outline: wgpu::RenderPipeline,
+}
+
+/// Pipes reading their instances from storage buffers, see `Renderer::set_instance_storage`
+struct InstanceStorage {
+    // the materials, then the instances of one buffer
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipelines: PipePipelines,
+}
+
+/// A chunk's instances on the GPU, see `chunks::Chunks`. The buffers grow in powers of two, so that
+/// appending to a chunk mostly writes into the buffers it has.
+struct ChunkBuffers {
+    i_buffer: wgpu::Buffer,
+    i_count: u32,
+    l_buffer: wgpu::Buffer,
+    l_count: u32,
+    // bind the buffers for pipelines reading instances from storage, where the GPU can
+    i_bind_group: Option<wgpu::BindGroup>,
+    l_bind_group: Option<wgpu::BindGroup>,
+    bounds: Bounds,
+}
+
+/// A tube's mesh and its one instance on the GPU, see `tubes::Tube`
+struct TubeBuffers {
+    mesh: models::Mesh,
+    instance_buffer: wgpu::Buffer,
+    bind_group: Option<wgpu::BindGroup>,
+    bounds: Bounds,
+}
+
+/// Offscreen target the scene is drawn to when rendering at another resolution than the output's
+struct ScaledTarget {
+    view: wgpu::TextureView,
+    blit_bind_group: wgpu::BindGroup,
+    // to measure the frames drawn into it with, while auto-exposure is on
+    exposure_bind_group: Option<wgpu::BindGroup>,
+    // what the frames drawn into it are blended into while TAA is on, with the bind groups to blit each
+    // of the two histories
+    taa_history: Option<(TaaHistory, [wgpu::BindGroup; 2])>,
+    width: u32,
+    height: u32,
+}
+
+/// Camera of one split-screen view
+struct View {
+    camera: UniformBuffer<CameraUniform>,
+    // the same camera looking at the world mirrored by the floor
+    reflection: UniformBuffer<CameraUniform>,
+    // the reflection only shows from above
+    is_above_floor: bool,
+    // the camera and reflection as updated, which TAA jitters every frame
+    unjittered: (CameraUniform, CameraUniform),
+}
+
+/// Draws the pipes into any color target, be it a window surface or an offscreen texture
+pub struct Renderer {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    color_format: wgpu::TextureFormat,
+    // what the scene is drawn in, see `scene_format`
+    scene_format: wgpu::TextureFormat,
+    depth: texture::Depth,
+    // size of the output, the scene itself is drawn at this times `render_scale`
+    width: u32,
+    height: u32,
+    render_scale: f32,
+    scaled_target: Option<ScaledTarget>,
+    blitter: Blitter,
+    // the scene goes through the blitter even at full scale, for the effect or to encode gamma
+    has_post_effect: bool,
+    // drawn over everything else, at the output resolution
+    text: TextOverlay,
+    // debug lines in the scene, none unless shown
+    gizmos: Gizmos,
+    // around the selected pipe, drawn over the scene
+    outline: Outline,
+    // pipes reading their instances from vertex attributes, which every GPU can
+    pipe_pipelines: PipePipelines,
+    // or why it's unavailable
+    instance_storage: anyhow::Result<InstanceStorage>,
+    is_instance_storage: bool,
+    has_depth_prepass: bool,
+    shading: Shading,
+    // glass pipes are blended over each other, and expect to be sorted back to front
+    is_glass: bool,
+    // solid pipes are drawn near to far, so fewer fragments are shaded only to be covered
+    is_depth_sorted: bool,
+    light_render_pipeline: wgpu::RenderPipeline,
+    // glossy floor, blended over the reflected pipes
+    floor_pipeline: wgpu::RenderPipeline,
+    // soft dark blobs on the floor below every pipe
+    shadow_pipeline: wgpu::RenderPipeline,
+    // sparks off the heads of growing pipes, added onto whatever is behind them
+    particle_pipeline: wgpu::RenderPipeline,
+    particles: ParticleSystem,
+    has_sparks: bool,
+    has_floor: bool,
+    // the world's extent, which the cameras' near and far planes are fit around, see `set_world_bounds`
+    world_bounds: Option<Bounds>,
+    depth_texture: texture::Texture,
+    clear_color: wgpu::Color,
+    profiler: Option<GpuProfiler>,
+    // every write to a buffer, submitted ahead of the frame
+    uploader: Uploader,
+    // the scene encoded on a worker thread while this one encodes what goes over it
+    is_parallel_encoding: bool,
+    // CPU time the last frame took to encode, to compare parallel encoding on and off
+    encode_time: Duration,
+    // stops the frames are brightened or darkened by on top of auto-exposure, see `set_exposure`
+    exposure: f32,
+    // adapts the exposure to the frames while it is on, which draws them into the scaled target
+    auto_exposure: Option<AutoExposure>,
+    // blends the frames while TAA is on, which draws them into the scaled target
+    taa: Option<Taa>,
+    // frames blended since the history was last started over
+    taa_frame: u32,
+
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    // one per split-screen view, laid out by `split_screen::layout`
+    views: Vec<View>,
+
+    light: UniformBuffer<LightUniform>,
+    light_orbit: LightOrbit,
+    // how far into its orbit the light is
+    light_angle: cgmath::Deg<f32>,
+    time: UniformBuffer<TimeUniform>,
+    // the time of draining chunks, which is the same but for the drain
+    draining_time: UniformBuffer<TimeUniform>,
+    // every material, which pipes look up by the index in their instance
+    material_buffer: wgpu::Buffer,
+    materials: wgpu::BindGroup,
+
+    chunk_buffers: BTreeMap<ChunkKey, ChunkBuffers>,
+    // births of the oldest and the newest instance uploaded since the chunks were last cleared
+    birth_range: Option<(f32, f32)>,
+    // the chunks of the world before the last clear, shrinking away until the drain is over
+    draining_chunks: Vec<ChunkBuffers>,
+    // drawn instead of the chunks' instances when the chunks follow their pipes as tubes, which still cast
+    // the chunks' shadows
+    tube_buffers: Option<Vec<TubeBuffers>>,
+    tessellation: tubes::Tessellation,
+    drain_seconds: f32,
+    reset_transition: ResetTransition,
+    // grown in a compute shader rather than uploaded, drawn along with the chunks
+    gpu_world: Option<GpuWorld>,
+    // bind its straight pipe and elbow buffers, like the chunks'
+    gpu_world_bind_groups: Option<[wgpu::BindGroup; 2]>,
+
+    i_pipe_model: models::Model,
+    l_pipe_model: models::Model,
+    // how much thicker pipes are drawn than they were modeled, see `set_pipe_radius`
+    pipe_radius_scale: f32,
+}
+
+impl Renderer {
+    pub async fn new(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        depth: texture::Depth,
+        width: u32,
+        height: u32,
+        clear_color: wgpu::Color,
+    ) -> anyhow::Result<Self> {
+        let profiler = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| GpuProfiler::new(&device, &queue));
+        let uploader = Uploader::new(&device, UPLOAD_CHUNK_SIZE);
+        let scene_format = scene_format(color_format);
+
+        let camera_bind_group_layout = gpu::uniform_layout(&device, "Camera");
+        let views = vec![Self::create_view(&device, &camera_bind_group_layout)];
+
+        let light_bind_group_layout = gpu::uniform_layout(&device, "Light");
+        let light_orbit = LightOrbit::default();
+        let light = UniformBuffer::new(
+            &device,
+            &light_bind_group_layout,
+            "Light",
+            LightUniform {
+                position: light_orbit.position(cgmath::Deg(0.0)).into(),
+                ambient: DEFAULT_AMBIENT,
+                color: [1.0, 1.0, 1.0],
+                rim: 0.0,
+                sky: DEFAULT_SKY,
+                sky_light: 0.0,
+                ground: DEFAULT_GROUND,
+                exposure: 1.0,
+            },
+        );
+
+        let time_bind_group_layout = gpu::uniform_layout(&device, "Time");
+        let time = UniformBuffer::new(&device, &time_bind_group_layout, "Time", bytemuck::Zeroable::zeroed());
+        let draining_time = UniformBuffer::new(
+            &device,
+            &time_bind_group_layout,
+            "DrainingTime",
+            bytemuck::Zeroable::zeroed(),
+        );
+
+        let material_bind_group_layout = material::bind_group_layout(&device);
+        let material_buffer = material::create_buffer(&device);
+        let materials = material::create_bind_group(&device, &material_bind_group_layout, &material_buffer);
+
+        let depth_texture = texture::Texture::create_depth_texture(&device, depth, width, height);
+
+        let scene_bind_group_layouts = [
+            &camera_bind_group_layout,
+            &light_bind_group_layout,
+            &time_bind_group_layout,
+        ];
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("RenderPipelineLayout"),
+            bind_group_layouts: &scene_bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipe_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipeRenderPipelineLayout"),
+            bind_group_layouts: &[
+                &camera_bind_group_layout,
+                &light_bind_group_layout,
+                &time_bind_group_layout,
+                &material_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipe_shader = gpu::create_shader_module(&device, "PipeShader", include_str!("shader.wgsl"))?;
+        let pipe_pipelines = Self::create_pipe_pipelines(
+            &device,
+            &pipe_layout,
+            scene_format,
+            depth,
+            &pipe_shader,
+            ["vs_main", "vs_outline"],
+            &[models::ModelVertex::layout(), InstanceRaw::layout()],
+        )?;
+        let instance_storage = Self::create_instance_storage(&device, scene_format, depth, scene_bind_group_layouts);
+
+        let light_render_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("LightRenderPipelineLayout"),
+                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+            let shader = gpu::create_shader_module(&device, "LightShader", include_str!("light.wgsl"))?;
+            Self::create_render_pipeline(
+                &device,
+                &layout,
+                scene_format,
+                depth,
+                &PipelineOptions {
+                    label: "LightRenderPipeline",
+                    shader: &shader,
+                    vertex_entry: "vs_main",
+                    fragment_entry: "fs_main",
+                    vertex_layouts: &[models::ModelVertex::layout()],
+                    blend: wgpu::BlendState::REPLACE,
+                    color_writes: wgpu::ColorWrites::ALL,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    cull_mode: None,
+                },
+            )?
+        };
+
+        let floor_shader = gpu::create_shader_module(&device, "FloorShader", include_str!("floor.wgsl"))?;
+        let floor_pipeline = Self::create_render_pipeline(
+            &device,
+            &layout,
+            scene_format,
+            depth,
+            &PipelineOptions {
+                label: "FloorRenderPipeline",
+                shader: &floor_shader,
+                vertex_entry: "vs_main",
+                fragment_entry: "fs_main",
+                vertex_layouts: &[],
+                blend: wgpu::BlendState::ALPHA_BLENDING,
+                color_writes: wgpu::ColorWrites::ALL,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                cull_mode: None,
+            },
+        )?;
+
+        let shadow_pipeline = Self::create_render_pipeline(
+            &device,
+            &layout,
+            scene_format,
+            depth,
+            &PipelineOptions {
+                label: "ShadowRenderPipeline",
+                shader: &floor_shader,
+                vertex_entry: "vs_shadow",
+                fragment_entry: "fs_shadow",
+                vertex_layouts: &[InstanceRaw::layout()],
+                blend: wgpu::BlendState::ALPHA_BLENDING,
+                color_writes: wgpu::ColorWrites::ALL,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                cull_mode: None,
+            },
+        )?;
+
+        let particles = ParticleSystem::new(&device);
+        let particle_pipeline = {
+            let shader = gpu::create_shader_module(&device, "ParticleShader", include_str!("particles.wgsl"))?;
+            let additive = wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            };
+            Self::create_render_pipeline(
+                &device,
+                &layout,
+                scene_format,
+                depth,
+                &PipelineOptions {
+                    label: "ParticleRenderPipeline",
+                    shader: &shader,
+                    vertex_entry: "vs_main",
+                    fragment_entry: "fs_main",
+                    vertex_layouts: &[Spark::layout()],
+                    blend: wgpu::BlendState {
+                        color: additive,
+                        alpha: wgpu::BlendComponent::OVER,
+                    },
+                    color_writes: wgpu::ColorWrites::ALL,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    cull_mode: None,
+                },
+            )?
+        };
+
+        let i_pipe_model = models::Model::load_model("pipe.obj", &device).await?;
+        let l_pipe_model = models::Model::load_model("curve.obj", &device).await?;
+
+        let mut renderer = Self {
+            blitter: Blitter::new(&device, color_format, None, scene_format != color_format)?,
+            has_post_effect: false,
+            text: TextOverlay::new(&device, color_format)?,
+            gizmos: Gizmos::new(&device, scene_format, depth, &camera_bind_group_layout)?,
+            outline: Outline::new(&device, scene_format, &pipe_layout, &pipe_shader)?,
+            device,
+            queue,
+            color_format,
+            scene_format,
+            depth,
+            width,
+            height,
+            render_scale: 1.0,
+            scaled_target: None,
+            pipe_pipelines,
+            instance_storage,
+            is_instance_storage: false,
+            has_depth_prepass: false,
+            shading: Shading::Phong,
+            is_glass: false,
+            is_depth_sorted: false,
+            light_render_pipeline,
+            floor_pipeline,
+            shadow_pipeline,
+            particle_pipeline,
+            particles,
+            has_sparks: false,
+            has_floor: false,
+            world_bounds: None,
+            depth_texture,
+            clear_color,
+            profiler,
+            uploader,
+            is_parallel_encoding: false,
+            encode_time: Duration::ZERO,
+            exposure: 0.0,
+            auto_exposure: None,
+            taa: None,
+            taa_frame: 0,
+
+            camera_bind_group_layout,
+            views,
+
+            light,
+            light_orbit,
+            light_angle: cgmath::Deg(0.0),
+            time,
+            draining_time,
+            material_buffer,
+            materials,
+
+            chunk_buffers: BTreeMap::new(),
+            birth_range: None,
+            draining_chunks: vec![],
+            tube_buffers: None,
+            tessellation: tubes::Tessellation::default(),
+            drain_seconds: 0.0,
+            reset_transition: ResetTransition::Drain,
+            gpu_world: None,
+            gpu_world_bind_groups: None,
+
+            i_pipe_model,
+            l_pipe_model,
+            pipe_radius_scale: 1.0,
+        };
+        // for the offscreen target the scene is drawn to if the blitter encodes gamma
+        renderer.resize(width, height);
+        Ok(renderer)
+    }
+
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = color;
+    }
+
+    /// Blend pipes instead of drawing them solid, instances should then be uploaded back to front. Chunks
+    /// are drawn back to front then too.
+    pub fn set_glass(&mut self, is_glass: bool) {
+        self.is_glass = is_glass;
+    }
+
+    pub fn has_floor(&self) -> bool {
+        self.has_floor
+    }
+
+    /// Draw straight pipes, or elbows for `is_elbow`, with `model` from now on
+    pub fn set_pipe_model(&mut self, is_elbow: bool, mut model: models::Model) {
+        if self.pipe_radius_scale != 1.0 {
+            model.set_radius_scale(&self.device, self.pipe_radius_scale, is_elbow);
+        }
+        if is_elbow {
+            self.l_pipe_model = model;
+        } else {
+            self.i_pipe_model = model;
+        }
+        if let Some(gpu_world) = &mut self.gpu_world {
+            gpu_world.set_meshes(&self.i_pipe_model, &self.l_pipe_model);
+        }
+    }
+
+    /// Draw pipes `radius` thick in blocks, rather than the `models::MODELED_RADIUS` they are modeled with
+    pub fn set_pipe_radius(&mut self, radius: f32) {
+        self.pipe_radius_scale = radius / models::MODELED_RADIUS;
+        self.i_pipe_model
+            .set_radius_scale(&self.device, self.pipe_radius_scale, false);
+        self.l_pipe_model
+            .set_radius_scale(&self.device, self.pipe_radius_scale, true);
+    }
+
+    /// Sweep tubes uploaded from now on this finely, see `tubes::Tubes::mark_all_changed` to sweep the
+    /// others again
+    pub fn set_tessellation(&mut self, tessellation: tubes::Tessellation) {
+        self.tessellation = tessellation;
+    }
+
+    /// Grow pipes on the GPU as well as drawing the chunks, or stop and drop them for `None`
+    pub fn set_gpu_world(&mut self, gpu_world: Option<GpuWorld>) {
+        self.gpu_world_bind_groups = gpu_world.as_ref().and_then(|gpu_world| {
+            let [i_buffer, l_buffer] = gpu_world.instance_buffers();
+            self.instance_bind_group(i_buffer)
+                .zip(self.instance_bind_group(l_buffer))
+                .map(|(i, l)| [i, l])
+        });
+        self.gpu_world = gpu_world;
+        if let Some(gpu_world) = &mut self.gpu_world {
+            gpu_world.set_meshes(&self.i_pipe_model, &self.l_pipe_model);
+        }
+    }
+
+    /// Grow the GPU world for `dt` more seconds with the next frame, if there is one
+    pub fn grow_gpu_world(&mut self, dt: f32) {
+        if let Some(gpu_world) = &mut self.gpu_world {
+            gpu_world.grow(dt);
+        }
+    }
+
+    /// Start the GPU world over with the next frame, if there is one
+    pub fn reset_gpu_world(&mut self) {
+        if let Some(gpu_world) = &mut self.gpu_world {
+            gpu_world.reset();
+        }
+    }
+
+    /// Show a glossy floor under the world, with a grid, reflections and shadows of the pipes
+    pub fn set_floor(&mut self, has_floor: bool) {
+        self.has_floor = has_floor;
+    }
+
+    /// Fit the near and far planes of the cameras updated from now on around a world within `bounds`, and
+    /// the floor and its reflection if shown, rather than leaving them where they were set. Keeps large
+    /// worlds from being clipped and far pipes from z-fighting.
+    pub fn set_world_bounds(&mut self, bounds: Option<Bounds>) {
+        self.world_bounds = bounds;
+    }
+
+    /// Around everything the cameras may see, `None` if unknown
+    fn scene_bounds(&self) -> Option<Bounds> {
+        let world = self.world_bounds?;
+        if !self.has_floor {
+            return Some(world);
+        }
+        let reflection = Bounds {
+            min: cgmath::Point3::new(world.min.x, 2.0 * FLOOR_Y - world.max.y, world.min.z),
+            max: cgmath::Point3::new(world.max.x, 2.0 * FLOOR_Y - world.min.y, world.max.z),
+        };
+        let [x, z] = FLOOR_CENTER;
+        let floor = Bounds {
+            min: cgmath::Point3::new(x - FLOOR_RADIUS, FLOOR_Y, z - FLOOR_RADIUS),
+            max: cgmath::Point3::new(x + FLOOR_RADIUS, FLOOR_Y, z + FLOOR_RADIUS),
+        };
+        Some(world.union(reflection).union(floor))
+    }
+
+    pub fn has_depth_prepass(&self) -> bool {
+        self.has_depth_prepass
+    }
+
+    /// Lay down the depth of solid pipes in a pass of its own before shading them, so that each pixel is
+    /// shaded once however many pipes overlap there. Costs drawing the pipes twice.
+    pub fn set_depth_prepass(&mut self, has_depth_prepass: bool) {
+        self.has_depth_prepass = has_depth_prepass;
+    }
+
+    pub fn is_instance_storage(&self) -> bool {
+        self.is_instance_storage
+    }
+
+    /// Read pipe instances from storage buffers by their index rather than from vertex attributes, which
+    /// leaves the attributes' limits behind and lets compute shaders write instances as they are drawn.
+    /// Fails on GPUs whose vertex shaders can't read storage buffers, like many OpenGL ES ones, which keep
+    /// reading attributes.
+    pub fn set_instance_storage(&mut self, is_instance_storage: bool) -> anyhow::Result<()> {
+        if let Err(e) = &self.instance_storage
+            && is_instance_storage
+        {
+            bail!("Cannot read instances from storage buffers: {:#}", e);
+        }
+        self.is_instance_storage = is_instance_storage;
+        Ok(())
+    }
+
+    /// The pipelines pipes are drawn with, reading their instances whichever way is set
+    fn pipes(&self) -> &PipePipelines {
+        match &self.instance_storage {
+            Ok(storage) if self.is_instance_storage => &storage.pipelines,
+            _ => &self.pipe_pipelines,
+        }
+    }
+
+    /// A bind group of the materials and `buffer`'s instances for pipelines reading instances from storage,
+    /// `None` where the GPU can't
+    fn instance_bind_group(&self, buffer: &wgpu::Buffer) -> Option<wgpu::BindGroup> {
+        let storage = self.instance_storage.as_ref().ok()?;
+        Some(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("InstanceStorageBindGroup"),
+            layout: &storage.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        }))
+    }
+
+    fn pipe_pass(&self) -> PipePass {
+        if self.is_glass {
+            PipePass::Glass
+        } else if self.has_depth_prepass {
+            PipePass::AfterPrepass
+        } else {
+            PipePass::Solid
+        }
+    }
+
+    pub fn is_depth_sorted(&self) -> bool {
+        self.is_depth_sorted
+    }
+
+    /// Draw chunks of solid pipes from the nearest to the farthest, their instances should then be
+    /// uploaded front to back
+    pub fn set_depth_sorted(&mut self, is_depth_sorted: bool) {
+        self.is_depth_sorted = is_depth_sorted;
+    }
+
+    pub fn is_parallel_encoding(&self) -> bool {
+        self.is_parallel_encoding
+    }
+
+    /// Encode the scene on a worker thread while the blit and overlay are encoded on this one, see
+    /// `Config::parallel_encoding`
+    pub fn set_parallel_encoding(&mut self, is_parallel_encoding: bool) {
+        self.is_parallel_encoding = is_parallel_encoding;
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Brighten the frames by `stops`, or darken them for negative ones, as a camera's exposure would.
+    /// With auto-exposure on it corrects what that picks.
+    pub fn set_exposure(&mut self, stops: f32) {
+        self.exposure = stops;
+        self.update_exposure();
+    }
+
+    pub fn has_auto_exposure(&self) -> bool {
+        self.auto_exposure.is_some()
+    }
+
+    /// Adapt the exposure to how bright the frames are, see `exposure::AutoExposure`. Fails on GPUs without
+    /// compute shaders, which keep the exposure as set.
+    pub fn set_auto_exposure(&mut self, has_auto_exposure: bool) -> anyhow::Result<()> {
+        if has_auto_exposure == self.auto_exposure.is_some() {
+            return Ok(());
+        }
+        self.auto_exposure = match has_auto_exposure {
+            true => Some(AutoExposure::new(&self.device).context("Cannot adapt the exposure")?),
+            false => None,
+        };
+        self.update_exposure();
+        // for the scaled target the frames are measured in
+        self.resize(self.width, self.height);
+        Ok(())
+    }
+
+    /// Multiple of the light the frames are exposed with, as set and adapted
+    pub fn exposure_scale(&self) -> f32 {
+        self.light.exposure
+    }
+
+    pub fn antialiasing(&self) -> Antialiasing {
+        match self.taa {
+            Some(_) => Antialiasing::Taa,
+            None => Antialiasing::None,
+        }
+    }
+
+    /// Smooth the edges of pipes with `aa`, see `taa::Taa`. Fails if its shader doesn't compile, which
+    /// leaves the edges as they were.
+    pub fn set_antialiasing(&mut self, aa: Antialiasing) -> anyhow::Result<()> {
+        if aa == self.antialiasing() {
+            return Ok(());
+        }
+        self.taa = match aa {
+            Antialiasing::Taa => Some(Taa::new(&self.device, self.scene_format).context("Cannot set up TAA")?),
+            Antialiasing::None => None,
+        };
+        if self.taa.is_none() {
+            for view in &mut self.views {
+                let (camera, reflection) = view.unjittered;
+                view.camera.update(&mut self.uploader, |uniform| *uniform = camera);
+                view.reflection
+                    .update(&mut self.uploader, |uniform| *uniform = reflection);
+            }
+        }
+        // for the scaled target and the history
+        self.resize(self.width, self.height);
+        Ok(())
+    }
+
+    /// Shift every view's cameras by this frame's TAA jitter, a fraction of a pixel of its viewport
+    fn jitter_cameras(&mut self) {
+        let (x, y) = taa::jitter(self.taa_frame);
+        let size = self.depth_texture.texture.size();
+        let viewports = split_screen::layout(self.views.len(), size.width, size.height);
+        for (view, viewport) in self.views.iter_mut().zip(viewports) {
+            let offset = cgmath::vec2(2.0 * x / viewport.width as f32, 2.0 * y / viewport.height as f32);
+            let (camera, reflection) = view.unjittered;
+            view.camera.update(&mut self.uploader, |uniform| {
+                *uniform = camera;
+                uniform.jitter(offset);
+            });
+            view.reflection.update(&mut self.uploader, |uniform| {
+                *uniform = reflection;
+                uniform.jitter(offset);
+            });
+        }
+    }
+
+    fn update_exposure(&mut self) {
+        let adapted = self.auto_exposure.as_ref().map_or(1.0, AutoExposure::exposure);
+        let exposure = self.exposure.exp2() * adapted;
+        if exposure != self.light.exposure {
+            self.light.update(&mut self.uploader, |light| light.exposure = exposure);
+        }
+    }
+
+    /// CPU time the last frame took to encode, from the compute pass to finishing the last command buffer
+    pub fn encode_time(&self) -> Duration {
+        self.encode_time
+    }
+
+    /// Bytes written to buffers for the last frame, like instances, cameras and sparks
+    pub fn upload_bytes(&self) -> u64 {
+        self.uploader.last_frame_bytes()
+    }
+
+    /// Milliseconds each render pass took on the GPU on average, as of the profiler's last report. Empty
+    /// without `Config::gpu_profiling`.
+    pub fn pass_times(&self) -> &[(&'static str, f64)] {
+        self.profiler.as_ref().map_or(&[], |profiler| profiler.averages())
+    }
+
+    pub fn has_sparks(&self) -> bool {
+        self.has_sparks
+    }
+
+    /// Throw sparks off the heads of growing pipes
+    pub fn set_sparks(&mut self, has_sparks: bool) {
+        self.has_sparks = has_sparks;
+    }
+
+    pub fn emit_sparks(&mut self, origin: cgmath::Point3<f32>, color: [f32; 3]) {
+        if self.has_sparks {
+            self.particles
+                .emit(&mut self.uploader, origin, color, self.time.seconds);
+        }
+    }
+
+    /// Seconds of animation so far, what instance births are measured in
+    pub fn time(&self) -> f32 {
+        self.time.seconds
+    }
+
+    /// Move animations like sparks, flowing bands and draining pipes on by `dt` seconds
+    pub fn advance_time(&mut self, dt: f32) {
+        self.time.update(&mut self.uploader, |time| time.seconds += dt);
+        let seconds = self.time.seconds;
+        if self.draining_chunks.is_empty() {
+            return;
+        }
+        if seconds >= self.draining_time.drain_start + self.draining_time.drain_duration {
+            self.draining_chunks.clear();
+        } else {
+            self.draining_time
+                .update(&mut self.uploader, |time| time.seconds = seconds);
+        }
+    }
+
+    /// Fade pipes to gray over `seconds` after their birth, or never for 0
+    pub fn set_aging(&mut self, seconds: f32) {
+        self.time.update(&mut self.uploader, |time| time.aging = seconds);
+        self.draining_time
+            .update(&mut self.uploader, |time| time.aging = seconds);
+    }
+
+    /// Highlight the segments in the selected block, or none for `None`
+    pub fn set_selection(&mut self, selection: Option<&Selection>) {
+        let selected = selection.map_or([0.0; 4], |selection| {
+            let center = selection.block_center();
+            [center.x, center.y, center.z, selection.cell_size]
+        });
+        self.time.update(&mut self.uploader, |time| time.selected = selected);
+    }
+
+    /// Outline the pipe of `i_instances` and `l_instances` as a whole, see `chunks::Chunks::pipe_instances`,
+    /// or none for `None`. It is outlined as it is now, however it grows on.
+    pub fn set_outlined_pipe(&mut self, pipe: Option<(&[InstanceRaw], &[InstanceRaw])>) {
+        self.outline.set_pipe(&self.device, pipe);
+    }
+
+    /// Shrink the pipes away over `seconds` when the chunks are cleared, the newest first, rather than
+    /// dropping them at once for 0
+    /// Whether the pipes drain away or crossfade on the next clear
+    pub fn set_reset_transition(&mut self, transition: ResetTransition) {
+        self.reset_transition = transition;
+    }
+
+    pub fn set_drain_seconds(&mut self, seconds: f32) {
+        self.drain_seconds = seconds;
+        if seconds <= 0.0 {
+            self.draining_chunks.clear();
+        }
+    }
+
+    /// Keep drawing the chunks which were just cleared until they drained away, if there are any
+    fn start_drain(&mut self, chunk_buffers: BTreeMap<ChunkKey, ChunkBuffers>) {
+        let Some((oldest, newest)) = self.birth_range.take().filter(|_| self.drain_seconds > 0.0) else {
+            self.draining_chunks.clear();
+            return;
+        };
+        self.draining_chunks = chunk_buffers.into_values().collect();
+        let (seconds, aging, duration) = (self.time.seconds, self.time.aging, self.drain_seconds);
+        self.draining_time.update(&mut self.uploader, |time| {
+            *time = TimeUniform {
+                seconds,
+                aging,
+                drain_start: seconds,
+                drain_duration: duration,
+                drain_newest: newest,
+                drain_oldest: oldest,
+                drain_fade: match self.reset_transition {
+                    ResetTransition::Drain => 0.0,
+                    ResetTransition::Crossfade => 1.0,
+                },
+                _padding: 0.0,
+                selected: [0.0; 4],
+            }
+        });
+    }
+
+    pub fn shading(&self) -> Shading {
+        self.shading
+    }
+
+    pub fn set_shading(&mut self, shading: Shading) {
+        self.shading = shading;
+    }
+
+    /// Draw the scene at `scale` times the output resolution, then filter it to fit the output
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    pub fn set_render_scale(&mut self, scale: f32) {
+        self.render_scale = scale;
+        self.resize(self.width, self.height);
+    }
+
+    /// Show text over the frame, e.g. help, or none for `None`
+    pub fn set_overlay_text(&mut self, text: Option<&str>) {
+        self.text.set_text(&self.device, &self.queue, text);
+    }
+
+    /// Draw debug lines into the scene, or none for `None`
+    pub fn set_gizmos(&mut self, lines: Option<&GizmoLines>) {
+        self.gizmos.set_lines(&self.device, lines);
+    }
+
+    /// Physical pixels per logical pixel of the output, which the overlay text is sized by
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.text.set_scale_factor(&self.device, &self.queue, scale_factor);
+    }
+
+    /// Pass finished frames through `effect`, or show them as they are for `None`. Keeps the effect there
+    /// was if the new one's shader doesn't compile.
+    pub fn set_post_effect(&mut self, effect: Option<PostEffect>) -> anyhow::Result<()> {
+        self.blitter = Blitter::new(&self.device, self.color_format, effect, self.encodes_gamma())?;
+        self.has_post_effect = effect.is_some();
+        self.resize(self.width, self.height);
+        Ok(())
+    }
+
+    /// Whether the output isn't sRGB, so that the blitter has to encode the scene's linear colors itself
+    fn encodes_gamma(&self) -> bool {
+        self.scene_format != self.color_format
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        let max_size = self.device.limits().max_texture_dimension_2d;
+        let scale = |size: u32| ((size as f32 * self.render_scale).round() as u32).clamp(1, max_size);
+        let (scaled_width, scaled_height) = (scale(width), scale(height));
+
+        self.depth_texture =
+            texture::Texture::create_depth_texture(&self.device, self.depth, scaled_width, scaled_height);
+        self.outline.resize(&self.device, scaled_width, scaled_height);
+        let is_scaled = self.render_scale != 1.0 || self.has_post_effect || self.encodes_gamma();
+        self.scaled_target = (is_scaled || self.auto_exposure.is_some() || self.taa.is_some()).then(|| {
+            let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("ScaledTarget"),
+                size: wgpu::Extent3d {
+                    width: scaled_width,
+                    height: scaled_height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.scene_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let taa_history = self.taa.as_ref().map(|taa| {
+                let history = taa.history(&self.device, &view, scaled_width, scaled_height);
+                let blit = |index| self.blitter.bind_group(&self.device, history.view(index));
+                let blit_bind_groups = [blit(0), blit(1)];
+                (history, blit_bind_groups)
+            });
+            ScaledTarget {
+                blit_bind_group: self.blitter.bind_group(&self.device, &view),
+                exposure_bind_group: (self.auto_exposure.as_ref()).map(|auto| auto.bind_group(&self.device, &view)),
+                taa_history,
+                view,
+                width: scaled_width,
+                height: scaled_height,
+            }
+        });
+        // what was blended so far is gone with the old target
+        self.taa_frame = 0;
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, label: &str, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity.max(1) * size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Write `instances` from `from` on to `buffer`, the ones before are in it already. Replaces it with a
+    /// larger buffer holding all of them if they don't fit.
+    fn write_instances(
+        &mut self,
+        buffer: &mut wgpu::Buffer,
+        label: &str,
+        instances: &[InstanceRaw],
+        from: usize,
+    ) -> u32 {
+        let from = if size_of_val(instances) as wgpu::BufferAddress > buffer.size() {
+            *buffer = Self::create_instance_buffer(&self.device, label, instances.len().next_power_of_two());
+            0
+        } else {
+            from
+        };
+        let offset = (from * size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+        self.uploader
+            .write(buffer, offset, bytemuck::cast_slice(&instances[from..]));
+        instances.len() as u32
+    }
+
+    /// Upload the chunks which changed since the last upload
+    pub fn upload_chunks(&mut self, chunks: &mut Chunks) {
+        let (is_cleared, removed, changed) = chunks.take_changes();
+        if is_cleared {
+            let chunk_buffers = std::mem::take(&mut self.chunk_buffers);
+            self.start_drain(chunk_buffers);
+        }
+        for key in removed {
+            self.chunk_buffers.remove(&key);
+        }
+        for ChunkChange {
+            key,
+            chunk,
+            i_from,
+            l_from,
+        } in changed
+        {
+            for instance in chunk.i_instances[i_from..].iter().chain(&chunk.l_instances[l_from..]) {
+                let birth = instance.birth();
+                let (oldest, newest) = self.birth_range.get_or_insert((birth, birth));
+                *oldest = oldest.min(birth);
+                *newest = newest.max(birth);
+            }
+            let mut buffers = self.chunk_buffers.remove(&key).unwrap_or_else(|| ChunkBuffers {
+                i_buffer: Self::create_instance_buffer(&self.device, "InstanceIBuffer", chunk.i_instances.len()),
+                i_count: 0,
+                l_buffer: Self::create_instance_buffer(&self.device, "InstanceLBuffer", chunk.l_instances.len()),
+                l_count: 0,
+                i_bind_group: None,
+                l_bind_group: None,
+                bounds: chunk.bounds,
+            });
+            buffers.i_count =
+                self.write_instances(&mut buffers.i_buffer, "InstanceIBuffer", &chunk.i_instances, i_from);
+            buffers.l_count =
+                self.write_instances(&mut buffers.l_buffer, "InstanceLBuffer", &chunk.l_instances, l_from);
+            // the buffers may have been replaced by larger ones
+            buffers.i_bind_group = self.instance_bind_group(&buffers.i_buffer);
+            buffers.l_bind_group = self.instance_bind_group(&buffers.l_buffer);
+            buffers.bounds = chunk.bounds;
+            self.chunk_buffers.insert(key, buffers);
+        }
+        self.upload_tubes(chunks);
+    }
+
+    /// Sweep the tubes which grew since the last upload into meshes again, whole
+    fn upload_tubes(&mut self, chunks: &mut Chunks) {
+        let cell_size = chunks.cell_size();
+        let Some(tubes) = chunks.tubes_mut() else {
+            self.tube_buffers = None;
+            return;
+        };
+        let (is_cleared, changed) = tubes.take_changes();
+        let mut tube_buffers = self.tube_buffers.take().unwrap_or_default();
+        if is_cleared {
+            tube_buffers.clear();
+        }
+        let radius = models::MODELED_RADIUS * self.pipe_radius_scale * cell_size;
+        for (index, tube) in changed {
+            let center_line = tube.center_line(cell_size, self.tessellation);
+            let (vertices, indices) = tubes::sweep(&center_line, radius, self.tessellation.sides);
+            let instance_buffer = Self::create_instance_buffer(&self.device, "TubeInstanceBuffer", 1);
+            self.uploader
+                .write(&instance_buffer, 0, bytemuck::cast_slice(&[tube.instance]));
+            let buffers = TubeBuffers {
+                mesh: models::Mesh::new(&self.device, "Tube", &vertices, &indices),
+                bind_group: self.instance_bind_group(&instance_buffer),
+                instance_buffer,
+                bounds: tube.bounds,
+            };
+            match tube_buffers.get_mut(index) {
+                Some(old) => *old = buffers,
+                None => tube_buffers.push(buffers),
+            }
+        }
+        self.tube_buffers = Some(tube_buffers);
+    }
+
+    /// Chunks any of which `camera` sees, back to front for glass pipes and front to back for solid ones
+    /// when depth sorted
+    fn visible_chunks(&self, camera: &CameraUniform) -> Vec<&ChunkBuffers> {
+        let frustum = camera.frustum();
+        let mut visible: Vec<_> = self
+            .chunk_buffers
+            .values()
+            .filter(|chunk| frustum.intersects(&chunk.bounds))
+            .collect();
+        let eye = camera.eye();
+        let distance = |chunk: &ChunkBuffers| eye.distance2(chunk.bounds.center());
+        if self.is_glass {
+            visible.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+        } else if self.is_depth_sorted {
+            visible.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        }
+        visible
+    }
+
+    fn create_view(device: &wgpu::Device, camera_bind_group_layout: &wgpu::BindGroupLayout) -> View {
+        View {
+            camera: UniformBuffer::new(device, camera_bind_group_layout, "Camera", CameraUniform::new()),
+            reflection: UniformBuffer::new(
+                device,
+                camera_bind_group_layout,
+                "ReflectionCamera",
+                CameraUniform::new(),
+            ),
+            is_above_floor: true,
+            unjittered: (CameraUniform::new(), CameraUniform::new()),
+        }
+    }
+
+    pub fn update_camera(&mut self, camera: &Camera) {
+        self.update_cameras(&[camera]);
+    }
+
+    /// Split the target into one view per camera, see `split_screen::layout`
+    pub fn update_cameras(&mut self, cameras: &[&Camera]) {
+        self.views.truncate(cameras.len().max(1));
+        while self.views.len() < cameras.len() {
+            let view = Self::create_view(&self.device, &self.camera_bind_group_layout);
+            self.views.push(view);
+        }
+        let is_reversed_z = self.depth.reversed;
+        let scene_bounds = self.scene_bounds();
+        for (view, camera) in self.views.iter_mut().zip(cameras) {
+            let mut camera = (*camera).clone();
+            if let Some(bounds) = &scene_bounds {
+                camera.fit_depth_range(bounds);
+            }
+            let camera = &camera;
+            view.camera.update(&mut self.uploader, |uniform| {
+                uniform.update_view_projection(camera);
+                if is_reversed_z {
+                    uniform.reverse_depth();
+                }
+            });
+            view.reflection.update(&mut self.uploader, |uniform| {
+                uniform.update_reflected(camera, FLOOR_Y);
+                if is_reversed_z {
+                    uniform.reverse_depth();
+                }
+            });
+            view.is_above_floor = camera.eye().y > FLOOR_Y;
+            view.unjittered = (*view.camera, *view.reflection);
+        }
+    }
+
+    pub fn light_orbit(&self) -> &LightOrbit {
+        &self.light_orbit
+    }
+
+    /// Have the light travel `orbit` from now on, from its start
+    pub fn set_light_orbit(&mut self, orbit: LightOrbit) {
+        self.light_orbit = orbit;
+        self.light_angle = cgmath::Deg(0.0);
+        self.orbit_light(cgmath::Deg(0.0));
+    }
+
+    /// Move the light `angle` further along its orbit
+    pub fn orbit_light(&mut self, angle: cgmath::Deg<f32>) {
+        self.light_angle = (self.light_angle + angle).normalize();
+        let position = self.light_orbit.position(self.light_angle);
+        self.light
+            .update(&mut self.uploader, |light| light.position = position.into());
+    }
+
+    pub fn ambient(&self) -> f32 {
+        self.light.ambient
+    }
+
+    /// Scale the ambient light of every shading, see `LightUniform::ambient`
+    pub fn set_ambient(&mut self, ambient: f32) {
+        self.light.update(&mut self.uploader, |light| light.ambient = ambient);
+    }
+
+    pub fn rim_light(&self) -> f32 {
+        self.light.rim
+    }
+
+    /// Light the pipes' silhouettes this strongly, see `LightUniform::rim`
+    pub fn set_rim_light(&mut self, rim: f32) {
+        self.light.update(&mut self.uploader, |light| light.rim = rim);
+    }
+
+    pub fn sky_light(&self) -> f32 {
+        self.light.sky_light
+    }
+
+    /// Light the pipes from the sky and ground around them this strongly, see `LightUniform::sky_light`
+    pub fn set_sky_light(&mut self, strength: f32) {
+        self.light
+            .update(&mut self.uploader, |light| light.sky_light = strength);
+    }
+
+    /// Colors of the sky and the ground around the world, in linear space, see `LightUniform::sky`
+    pub fn set_sky_colors(&mut self, sky: [f32; 3], ground: [f32; 3]) {
+        self.light.update(&mut self.uploader, |light| {
+            light.sky = sky;
+            light.ground = ground;
+        });
+    }
+
+    /// Scale the light's brightness, 1 is plain white
+    pub fn set_light_intensity(&mut self, intensity: f32) {
+        self.light
+            .update(&mut self.uploader, |light| light.color = [intensity; 3]);
+    }
+
+    /// Render a frame `scale` times as wide and high as the window into an image, as large as textures can
+    /// be at most
+    pub fn render_to_image_scaled(&mut self, scale: u32) -> anyhow::Result<image::RgbaImage> {
+        let (width, height) = (self.width, self.height);
+        let max_scale = self.device.limits().max_texture_dimension_2d / width.max(height).max(1);
+        let scale = scale.min(max_scale).max(1);
+        if scale == 1 {
+            return self.render_to_image();
+        }
+        self.render_to_image_sized(width * scale, height * scale)
+    }
+
+    /// Render a frame `width` by `height` into an image, offscreen at that size whatever the window's is
+    pub fn render_to_image_sized(&mut self, width: u32, height: u32) -> anyhow::Result<image::RgbaImage> {
+        let max_size = self.device.limits().max_texture_dimension_2d;
+        if width > max_size || height > max_size {
+            bail!(
+                "Cannot render {}x{}, textures are {} wide at most",
+                width,
+                height,
+                max_size
+            );
+        }
+        let size = (self.width, self.height);
+        self.resize(width, height);
+        let image = self.render_to_image();
+        self.resize(size.0, size.1);
+        image
+    }
+
+    /// Draw a frame into a texture of its own and read it back from the GPU, e.g. for screenshots
+    pub fn render_to_image(&mut self) -> anyhow::Result<image::RgbaImage> {
+        let is_bgra = match self.color_format {
+            wgpu::TextureFormat::Rgba8UnormSrgb | wgpu::TextureFormat::Rgba8Unorm => false,
+            wgpu::TextureFormat::Bgra8UnormSrgb | wgpu::TextureFormat::Bgra8Unorm => true,
+            format => bail!("Cannot read back frames in {:?}", format),
+        };
+        let target = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ImageTarget"),
+            size: wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.color_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        self.render(&target.create_view(&wgpu::TextureViewDescriptor::default()));
+
+        // Rows of a texture copy must be aligned, the padding is cut off again below
+        let unpadded_bytes_per_row = self.width * BYTES_PER_PIXEL;
+        let padded_bytes_per_row = unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let device = &self.device;
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ReadbackBuffer"),
+            size: (padded_bytes_per_row * self.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ReadbackEncoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            target.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            target.size(),
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        receiver.recv()??;
+
+        let mut pixels = {
+            let data = slice.get_mapped_range();
+            data.chunks(padded_bytes_per_row as usize)
+                .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        if is_bgra {
+            for pixel in pixels.chunks_exact_mut(BYTES_PER_PIXEL as usize) {
+                pixel.swap(0, 2);
+            }
+        }
+        readback_buffer.unmap();
+        image::RgbaImage::from_raw(self.width, self.height, pixels).context("Rendered image has the wrong size")
+    }
+
+    /// Draw a frame into `view` and submit it. The compute pass, the scene and what goes over it are each
+    /// encoded into a command buffer of their own, which are submitted together in that order.
+    pub fn render(&mut self, view: &wgpu::TextureView) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.collect(&self.device);
+        }
+        if let Some(auto_exposure) = &mut self.auto_exposure {
+            auto_exposure.collect(&self.device);
+        }
+        let encode_start = Instant::now();
+
+        // Encoded first, as growing the GPU world changes it
+        let mut compute_encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ComputeEncoder"),
+        });
+        if let Some(gpu_world) = &mut self.gpu_world {
+            gpu_world.dispatch(&mut self.uploader, &mut compute_encoder, self.time.seconds);
+        }
+        // the last frame is still in the scaled target, drawn with the exposure before this one's
+        if let Some(auto_exposure) = &mut self.auto_exposure
+            && let Some(target) = &self.scaled_target
+            && let Some(bind_group) = &target.exposure_bind_group
+        {
+            let size = (target.width, target.height);
+            auto_exposure.dispatch(&mut compute_encoder, bind_group, size, self.light.exposure);
+        }
+        self.update_exposure();
+        if self.taa.is_some() {
+            self.jitter_cameras();
+        }
+        let main_pass = self.profiler.as_mut().and_then(|p| p.reserve_pass("main"));
+
+        let (scene, mut overlay_encoder) = if self.is_parallel_encoding {
+            // a scoped thread a frame costs far less than encoding a dense world takes
+            std::thread::scope(|scope| {
+                let scene = scope.spawn(|| self.encode_scene(view, main_pass));
+                let overlay_encoder = self.encode_overlay(view);
+                let scene = scene.join().unwrap_or_else(|e| std::panic::resume_unwind(e));
+                (scene, overlay_encoder)
+            })
+        } else {
+            (self.encode_scene(view, main_pass), self.encode_overlay(view))
+        };
+        if let Some(profiler) = &mut self.profiler {
+            profiler.resolve(&mut overlay_encoder);
+        }
+        // the uploads go first, as everything after may read what they write
+        let command_buffers: Vec<_> = (self.uploader.finish().into_iter())
+            .chain([compute_encoder.finish(), scene, overlay_encoder.finish()])
+            .collect();
+        self.encode_time = encode_start.elapsed();
+
+        self.queue.submit(command_buffers);
+        self.uploader.recall();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.after_submit();
+        }
+        if let Some(auto_exposure) = &mut self.auto_exposure {
+            auto_exposure.after_submit();
+        }
+        if self.taa.is_some() {
+            self.taa_frame = self.taa_frame.wrapping_add(1);
+        }
+    }
+
+    /// The render pass drawing every view of the scene, into the scaled target if there is one, with the
+    /// profiler's queries `main_pass`
+    fn encode_scene(&self, view: &wgpu::TextureView, main_pass: Option<u32>) -> wgpu::CommandBuffer {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SceneEncoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("RenderPass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: self.scaled_target.as_ref().map_or(view, |target| &target.view),
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.clear_color),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.depth.clear_value()),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: self
+                    .profiler
+                    .as_ref()
+                    .zip(main_pass)
+                    .map(|(profiler, index)| profiler.timestamp_writes(index)),
+            });
+
+            let size = self.depth_texture.texture.size();
+            let viewports = split_screen::layout(self.views.len(), size.width, size.height);
+            for (view, viewport) in self.views.iter().zip(viewports) {
+                render_pass.set_viewport(
+                    viewport.x as f32,
+                    viewport.y as f32,
+                    viewport.width as f32,
+                    viewport.height as f32,
+                    0.0,
+                    1.0,
+                );
+                render_pass.set_scissor_rect(viewport.x, viewport.y, viewport.width, viewport.height);
+                render_pass.set_bind_group(1, self.light.bind_group(), &[]);
+                render_pass.set_bind_group(2, self.time.bind_group(), &[]);
+                self.draw_view(&mut render_pass, view);
+            }
+        }
+        let target = self.scaled_target.as_ref().map_or(view, |target| &target.view);
+        self.outline.encode(&mut encoder, target, |render_pass| {
+            let size = self.depth_texture.texture.size();
+            let viewports = split_screen::layout(self.views.len(), size.width, size.height);
+            for (view, viewport) in self.views.iter().zip(viewports) {
+                render_pass.set_viewport(
+                    viewport.x as f32,
+                    viewport.y as f32,
+                    viewport.width as f32,
+                    viewport.height as f32,
+                    0.0,
+                    1.0,
+                );
+                render_pass.set_scissor_rect(viewport.x, viewport.y, viewport.width, viewport.height);
+                render_pass.set_bind_group(0, view.camera.bind_group(), &[]);
+                render_pass.set_bind_group(1, self.light.bind_group(), &[]);
+                render_pass.set_bind_group(2, self.time.bind_group(), &[]);
+                render_pass.set_bind_group(3, &self.materials, &[]);
+                self.outline.draw_mask(
+                    render_pass,
+                    [&self.i_pipe_model.meshes[0], &self.l_pipe_model.meshes[0]],
+                );
+            }
+        });
+        encoder.finish()
+    }
+
+    /// The scaled target blitted to `view` and the text drawn over it, left unfinished for the profiler to
+    /// resolve its queries into
+    fn encode_overlay(&self, view: &wgpu::TextureView) -> wgpu::CommandEncoder {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("OverlayEncoder"),
+        });
+        if let Some(target) = &self.scaled_target {
+            match (&self.taa, &target.taa_history) {
+                (Some(taa), Some((history, blit_bind_groups))) => {
+                    // the two histories take turns, the one written this frame is the one shown
+                    let index = (self.taa_frame % 2) as usize;
+                    taa.resolve(&mut encoder, history, index, self.taa_frame == 0);
+                    self.blitter.blit(&mut encoder, &blit_bind_groups[index], view);
+                }
+                _ => self.blitter.blit(&mut encoder, &target.blit_bind_group, view),
+            }
+        }
+        self.text.draw(&mut encoder, view, self.width, self.height);
+        encoder
+    }
+
+    /// Draw the whole scene from the camera of `view`
+    fn draw_view(&self, render_pass: &mut wgpu::RenderPass, view: &View) {
+        let pipe_pass = self.pipe_pass();
+        if pipe_pass == PipePass::AfterPrepass {
+            // First thing, so that the reflection and floor hidden behind pipes aren't shaded either
+            render_pass.set_bind_group(0, view.camera.bind_group(), &[]);
+            render_pass.set_pipeline(&self.pipes().depth_prepass);
+            self.draw_pipes(render_pass, &view.camera);
+        }
+
+        if self.has_floor {
+            // The mirrored pipes lie below the floor, which is then blended over them
+            if view.is_above_floor {
+                let reflection_pass = if self.is_glass {
+                    PipePass::Glass
+                } else {
+                    PipePass::Solid
+                };
+                render_pass.set_bind_group(0, view.reflection.bind_group(), &[]);
+                render_pass.set_pipeline(&self.pipes().shaded[&(self.shading, reflection_pass)]);
+                self.draw_pipes(render_pass, &view.reflection);
+            }
+            render_pass.set_bind_group(0, view.camera.bind_group(), &[]);
+            render_pass.set_pipeline(&self.floor_pipeline);
+            render_pass.draw(0..6, 0..1);
+
+            render_pass.set_pipeline(&self.shadow_pipeline);
+            // Shadows of pipes out of view may still fall into it, so every chunk casts them
+            let draw_shadows = |render_pass: &mut wgpu::RenderPass, chunk: &ChunkBuffers| {
+                for (buffer, count) in [(&chunk.l_buffer, chunk.l_count), (&chunk.i_buffer, chunk.i_count)] {
+                    if count > 0 {
+                        render_pass.set_vertex_buffer(0, buffer.slice(..));
+                        render_pass.draw(0..6, 0..count);
+                    }
+                }
+            };
+            for chunk in self.chunk_buffers.values() {
+                draw_shadows(render_pass, chunk);
+            }
+            if !self.draining_chunks.is_empty() {
+                render_pass.set_bind_group(2, self.draining_time.bind_group(), &[]);
+                for chunk in &self.draining_chunks {
+                    draw_shadows(render_pass, chunk);
+                }
+                render_pass.set_bind_group(2, self.time.bind_group(), &[]);
+            }
+            if let Some(gpu_world) = &self.gpu_world {
+                gpu_world.draw_shadows(render_pass);
+            }
+        }
+        render_pass.set_bind_group(0, view.camera.bind_group(), &[]);
+
+        // The light goes first, as blended pipes must be drawn after everything solid
+        render_pass.set_pipeline(&self.light_render_pipeline);
+        let pipe_mesh = &self.l_pipe_model.meshes[0];
+        render_pass.set_vertex_buffer(0, pipe_mesh.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(pipe_mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..pipe_mesh.num_elements, 0, 0..1);
+
+        if self.shading == Shading::Toon {
+            render_pass.set_pipeline(&self.pipes().outline);
+            self.draw_pipes(render_pass, &view.camera);
+        }
+
+        render_pass.set_pipeline(&self.pipes().shaded[&(self.shading, pipe_pass)]);
+        self.draw_pipes(render_pass, &view.camera);
+        self.gizmos.draw(render_pass);
+
+        if self.has_sparks {
+            render_pass.set_pipeline(&self.particle_pipeline);
+            self.particles.draw(render_pass);
+        }
+    }
+
+    /// Draw the pipe instances of every chunk `camera` sees, and of the GPU world, with whatever pipe
+    /// pipeline is set
+    fn draw_pipes(&self, render_pass: &mut wgpu::RenderPass, camera: &CameraUniform) {
+        let is_instance_storage = self.is_instance_storage;
+        if !is_instance_storage {
+            render_pass.set_bind_group(3, &self.materials, &[]);
+        }
+        if let Some(tube_buffers) = &self.tube_buffers {
+            let frustum = camera.frustum();
+            for tube in tube_buffers.iter().filter(|tube| frustum.intersects(&tube.bounds)) {
+                self.draw_tube(render_pass, tube);
+            }
+            return;
+        }
+        for chunk in self.visible_chunks(camera) {
+            self.draw_chunk(render_pass, chunk);
+        }
+        // What is left of the world before the last clear, shrinking on its own time
+        if !self.draining_chunks.is_empty() {
+            let frustum = camera.frustum();
+            render_pass.set_bind_group(2, self.draining_time.bind_group(), &[]);
+            for chunk in self
+                .draining_chunks
+                .iter()
+                .filter(|chunk| frustum.intersects(&chunk.bounds))
+            {
+                self.draw_chunk(render_pass, chunk);
+            }
+            render_pass.set_bind_group(2, self.time.bind_group(), &[]);
+        }
+        // Everything grown on the GPU at once, as nobody knows where it is
+        if let Some(gpu_world) = &self.gpu_world {
+            let bind_groups = self.gpu_world_bind_groups.as_ref().filter(|_| is_instance_storage);
+            gpu_world.draw(render_pass, &self.i_pipe_model, &self.l_pipe_model, bind_groups);
+        }
+    }
+
+    /// Draw the straight pipes and elbows of `chunk` with the pipeline set
+    fn draw_chunk(&self, render_pass: &mut wgpu::RenderPass, chunk: &ChunkBuffers) {
+        let meshes = [
+            (&self.l_pipe_model, &chunk.l_buffer, &chunk.l_bind_group, chunk.l_count),
+            (&self.i_pipe_model, &chunk.i_buffer, &chunk.i_bind_group, chunk.i_count),
+        ];
+        for (model, buffer, bind_group, count) in meshes {
+            if count == 0 {
+                continue;
+            }
+            let pipe_mesh = &model.meshes[0];
+            render_pass.set_vertex_buffer(0, pipe_mesh.vertex_buffer.slice(..));
+            match bind_group {
+                Some(bind_group) if self.is_instance_storage => render_pass.set_bind_group(3, bind_group, &[]),
+                _ => render_pass.set_vertex_buffer(1, buffer.slice(..)),
+            }
+            render_pass.set_index_buffer(pipe_mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..pipe_mesh.num_elements, 0, 0..count);
+        }
+    }
+
+    /// Draw `tube` with the pipeline set
+    fn draw_tube(&self, render_pass: &mut wgpu::RenderPass, tube: &TubeBuffers) {
+        render_pass.set_vertex_buffer(0, tube.mesh.vertex_buffer.slice(..));
+        match &tube.bind_group {
+            Some(bind_group) if self.is_instance_storage => render_pass.set_bind_group(3, bind_group, &[]),
+            _ => render_pass.set_vertex_buffer(1, tube.instance_buffer.slice(..)),
+        }
+        render_pass.set_index_buffer(tube.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..tube.mesh.num_elements, 0, 0..1);
+    }
+
+    /// Pipelines drawing pipes in every shading and pass with `shader`, whose vertex entries are
+    /// `vertex_entries`, one for shaded pipes and one for outlines
+    fn create_pipe_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth: texture::Depth,
+        shader: &wgpu::ShaderModule,
+        [vertex_entry, outline_vertex_entry]: [&str; 2],
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+    ) -> anyhow::Result<PipePipelines> {
+        let mut shaded = HashMap::new();
+        // every shading is built up front, so that switching between them is instant
+        for shading in Shading::ALL {
+            for pass in [PipePass::Solid, PipePass::Glass, PipePass::AfterPrepass] {
+                let options = PipelineOptions {
+                    label: "PipeRenderPipeline",
+                    shader,
+                    vertex_entry,
+                    fragment_entry: match shading {
+                        Shading::Phong => "fs_main",
+                        Shading::Lambert => "fs_lambert",
+                        Shading::Pbr => "fs_pbr",
+                        Shading::Toon => "fs_toon",
+                        Shading::Flow => "fs_flow",
+                        Shading::Unlit => "fs_unlit",
+                    },
+                    vertex_layouts,
+                    blend: if pass == PipePass::Glass {
+                        wgpu::BlendState::ALPHA_BLENDING
+                    } else {
+                        wgpu::BlendState::REPLACE
+                    },
+                    color_writes: wgpu::ColorWrites::ALL,
+                    // Glass pipes don't write depth, so those behind them still get drawn. After the
+                    // pre-pass the depth is there already.
+                    depth_write_enabled: pass == PipePass::Solid,
+                    depth_compare: if pass == PipePass::AfterPrepass {
+                        wgpu::CompareFunction::Equal
+                    } else {
+                        wgpu::CompareFunction::Less
+                    },
+                    cull_mode: None,
+                };
+                let pipeline = Self::create_render_pipeline(device, layout, color_format, depth, &options)?;
+                shaded.insert((shading, pass), pipeline);
+            }
+        }
+
+        // Depth of the nearest pipes only, so that the color pass shades each pixel once
+        let depth_prepass_pipeline = Self::create_render_pipeline(
+            device,
+            layout,
+            color_format,
+            depth,
+            &PipelineOptions {
+                label: "DepthPrepassPipeline",
+                shader,
+                vertex_entry,
+                fragment_entry: "fs_depth_only",
+                vertex_layouts,
+                blend: wgpu::BlendState::REPLACE,
+                color_writes: wgpu::ColorWrites::empty(),
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                cull_mode: None,
+            },
+        )?;
+
+        // An inverted hull: the pipes blown up a little, with only their inner side visible
+        let outline_pipeline = Self::create_render_pipeline(
+            device,
+            layout,
+            color_format,
+            depth,
+            &PipelineOptions {
+                label: "OutlineRenderPipeline",
+                shader,
+                vertex_entry: outline_vertex_entry,
+                fragment_entry: "fs_outline",
+                vertex_layouts,
+                blend: wgpu::BlendState::REPLACE,
+                color_writes: wgpu::ColorWrites::ALL,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                cull_mode: Some(wgpu::Face::Front),
+            },
+        )?;
+
+        Ok(PipePipelines {
+            shaded,
+            depth_prepass: depth_prepass_pipeline,
+            outline: outline_pipeline,
+        })
+    }
+
+    /// Pipelines reading pipe instances from storage buffers, where the GPU's vertex shaders can. Their
+    /// pipe bind group comes after `scene_bind_group_layouts`.
+    fn create_instance_storage(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        depth: texture::Depth,
+        scene_bind_group_layouts: [&wgpu::BindGroupLayout; 3],
+    ) -> anyhow::Result<InstanceStorage> {
+        let bind_group_layout = gpu::validated(device, "InstanceStorageBindGroupLayout", || {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("InstanceStorageBindGroupLayout"),
+                entries: &[
+                    material::layout_entry(),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            })
+        })?;
+        let [camera, light, time] = scene_bind_group_layouts;
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("InstanceStorageRenderPipelineLayout"),
+            bind_group_layouts: &[camera, light, time, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = gpu::create_shader_module(
+            device,
+            "PipeStorageShader",
+            concat!(include_str!("shader.wgsl"), include_str!("instance_storage.wgsl")),
+        )?;
+        let pipelines = Self::create_pipe_pipelines(
+            device,
+            &layout,
+            color_format,
+            depth,
+            &shader,
+            ["vs_storage_main", "vs_storage_outline"],
+            &[models::ModelVertex::layout()],
+        )?;
+        Ok(InstanceStorage {
+            bind_group_layout,
+            pipelines,
+        })
+    }
+
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth: texture::Depth,
+        options: &PipelineOptions,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        gpu::validated(device, options.label, || {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(options.label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: options.shader,
+                    entry_point: Some(options.vertex_entry),
+                    compilation_options: Default::default(),
+                    buffers: options.vertex_layouts,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: options.shader,
+                    entry_point: Some(options.fragment_entry),
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(options.blend),
+                        write_mask: options.color_writes,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: options.cull_mode,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: depth.format,
+                    depth_write_enabled: options.depth_write_enabled,
+                    depth_compare: depth.compare(options.depth_compare),
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            })
+        })
+    }
+}
+
+/// The format to draw the scene in for output in `color_format`. Shaders write linear colors, which only
+/// sRGB targets encode for display, and float ones are linear anyway. Any other output, like the plain
+/// Unorm surfaces of some GL and Android drivers, would show them washed out, so the scene is drawn into
+/// an sRGB texture of its own instead, which the blitter encodes onto the output.
+fn scene_format(color_format: wgpu::TextureFormat) -> wgpu::TextureFormat {
+    match color_format {
+        format if format.is_srgb() => format,
+        wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float => color_format,
+        format if format.add_srgb_suffix() != format => format.add_srgb_suffix(),
+        // e.g. 10 bit formats, which have no sRGB twin
+        _ => wgpu::TextureFormat::Rgba16Float,
+    }
+}
@@ -1,21 +1,56 @@
 use std::path::PathBuf;
 
+use anyhow::Context;
 use log::debug;
 
+#[cfg(target_os = "android")]
+static ANDROID_APP: std::sync::OnceLock<winit::platform::android::activity::AndroidApp> = std::sync::OnceLock::new();
+
+/// Android has no resource directory, the resources are assets in the APK read through this app
+#[cfg(target_os = "android")]
+pub fn set_android_app(app: winit::platform::android::activity::AndroidApp) {
+    let _ = ANDROID_APP.set(app);
+}
+
+#[cfg(not(target_os = "android"))]
 fn res_dir() -> anyhow::Result<PathBuf> {
+    // The binary lives in target/<profile>, test binaries one level deeper in target/<profile>/deps
     let current_exe = std::env::current_exe()?;
-    Ok(current_exe
-        .parent()
-        .unwrap()
-        .parent()
-        .unwrap()
-        .parent()
-        .unwrap()
-        .join("res"))
+    current_exe
+        .ancestors()
+        .map(|dir| dir.join("res"))
+        .find(|dir| dir.is_dir())
+        .context("Resource directory not found")
 }
 
+#[cfg(not(target_os = "android"))]
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     debug!("Loading resource: {:?}", file_name);
     let path = res_dir()?.join(file_name);
     Ok(std::fs::read_to_string(path)?)
 }
+
+#[cfg(target_os = "android")]
+pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
+    use std::io::Read;
+
+    debug!("Loading asset: {:?}", file_name);
+    let app = ANDROID_APP.get().context("Android app not set")?;
+    let mut asset = app
+        .asset_manager()
+        .open(&std::ffi::CString::new(file_name)?)
+        .with_context(|| format!("Asset not found: {:?}", file_name))?;
+    let mut text = String::new();
+    asset.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Where XPipe keeps what it saves between runs, e.g. `~/.local/share/xpipe` on Linux and the app's
+/// internal storage on Android
+pub fn data_dir() -> anyhow::Result<PathBuf> {
+    #[cfg(target_os = "android")]
+    let dir = ANDROID_APP.get().and_then(|app| app.internal_data_path());
+    #[cfg(not(target_os = "android"))]
+    let dir = dirs::data_dir().map(|dir| dir.join("xpipe"));
+    dir.context("No data directory on this platform")
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+
+use crate::fixed_step;
+
+/// When pipes grow: steadily at the growth speed, or in bursts of segments every so often. Configured as
+/// the `[rhythm]` table, e.g. `burst_segments = 30`, `burst_interval = 4.0` and `easing = "ease_out"`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rhythm {
+    /// Segments grown in every burst, 0 to grow steadily instead
+    pub burst_segments: u32,
+    /// Seconds from the start of one burst to the start of the next
+    pub burst_interval: f32,
+    /// How the segments of a burst spread over it. A burst takes as long as growing its segments at the
+    /// growth speed would, up to the interval.
+    pub easing: Easing,
+    /// Seconds a new pipe waits after its first segment before growing on, so that pipes take turns
+    /// rather than running into each other
+    pub stagger: f32,
+}
+
+impl Default for Rhythm {
+    fn default() -> Self {
+        Self {
+            burst_segments: 0,
+            burst_interval: 2.0,
+            easing: Easing::Linear,
+            stagger: 0.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    /// Evenly over the burst
+    #[default]
+    Linear,
+    /// Slow at first, then faster and faster
+    EaseIn,
+    /// Fast at first, slowing down towards the end
+    EaseOut,
+    /// Slow at either end, fastest halfway
+    EaseInOut,
+}
+
+impl Easing {
+    /// Share of a burst's segments grown at `t`, from 0 at its start to 1 at its end
+    fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Where growth is in its rhythm, handing out the segments due each fixed step
+#[derive(Debug)]
+pub struct RhythmClock {
+    rhythm: Rhythm,
+    // segments of steady growth which didn't add up to a whole one yet
+    accumulator: f32,
+    // seconds into the current burst, and its segments grown so far
+    burst_time: f32,
+    burst_grown: u32,
+    // seconds until the newest pipe grows on
+    stagger_left: f32,
+}
+
+impl RhythmClock {
+    pub fn new(rhythm: Rhythm) -> Self {
+        Self {
+            rhythm,
+            accumulator: 0.0,
+            burst_time: 0.0,
+            burst_grown: 0,
+            stagger_left: 0.0,
+        }
+    }
+
+    /// Start over with the first burst, e.g. for a new world
+    pub fn restart(&mut self) {
+        *self = Self::new(self.rhythm.clone());
+    }
+
+    /// Segments due after one more step of `fixed_step::STEP_SECONDS` at `speed` segments per second
+    pub fn step(&mut self, speed: f32) -> u32 {
+        let dt = fixed_step::STEP_SECONDS;
+        if self.stagger_left > 0.0 {
+            self.stagger_left -= dt;
+            return 0;
+        }
+        let segments = self.rhythm.burst_segments;
+        if segments == 0 {
+            self.accumulator += dt * speed;
+            let count = self.accumulator as u32;
+            self.accumulator -= count as f32;
+            return count;
+        }
+
+        self.burst_time += dt;
+        let interval = self.rhythm.burst_interval;
+        if self.burst_time >= interval {
+            self.burst_time -= interval;
+            self.burst_grown = 0;
+        }
+        let duration = (segments as f32 / speed).min(interval);
+        let target = (segments as f32 * self.rhythm.easing.apply(self.burst_time / duration)) as u32;
+        let count = target.saturating_sub(self.burst_grown);
+        self.burst_grown += count;
+        count
+    }
+
+    /// A new pipe just started, which waits for the stagger before growing on
+    pub fn on_new_pipe(&mut self) {
+        self.stagger_left = self.rhythm.stagger;
+    }
+
+    pub fn is_staggered(&self) -> bool {
+        self.rhythm.stagger > 0.0
+    }
+}
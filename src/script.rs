@@ -0,0 +1,140 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::anyhow;
+use rhai::{AST, CallFnOptions, Dynamic, Engine, FuncArgs, Scope};
+
+/// Something a script asked for, applied by its window once the hook returns
+#[derive(Clone, Debug)]
+pub enum ScriptAction {
+    AddPipes(u32),
+    Reset,
+    SetPalette(String),
+    SetTurnProbability(f32),
+    /// Repaint every pipe grown so far
+    ColorAllPipes([f32; 3]),
+    /// Orbit the camera by radians
+    OrbitCamera {
+        yaw: f32,
+        pitch: f32,
+    },
+    ZoomCamera(f32),
+    /// Orbit the light by degrees
+    MoveLight(f32),
+    SetLightIntensity(f32),
+}
+
+/// A Rhai script driving a window. It may define any of these hooks:
+/// - `on_frame(dt, segments)`, every frame with its length in seconds and the segments grown so far
+/// - `on_pipe_added(x, y, z, segments)`, for every new segment
+/// - `on_reset()`, when the world starts over
+///
+/// and call these functions, with floats where a number isn't a count:
+/// - world: `add_pipes(count)`, `reset()`, `set_palette(name)`, `set_turn_probability(p)`,
+///   `color_all_pipes(r, g, b)`
+/// - camera: `orbit_camera(yaw, pitch)`, `zoom_camera(factor)`
+/// - light: `move_light(degrees)`, `set_light_intensity(intensity)`
+///
+/// Top-level statements run once when the script is loaded.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    actions: Rc<RefCell<Vec<ScriptAction>>>,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut engine = Engine::new();
+        let actions = Rc::new(RefCell::new(vec![]));
+        let push = |actions: &Rc<RefCell<Vec<ScriptAction>>>| {
+            let actions = actions.clone();
+            move |action: ScriptAction| actions.borrow_mut().push(action)
+        };
+
+        let action = push(&actions);
+        engine.register_fn("add_pipes", move |count: i64| {
+            action(ScriptAction::AddPipes(count.max(0) as u32))
+        });
+        let action = push(&actions);
+        engine.register_fn("reset", move || action(ScriptAction::Reset));
+        let action = push(&actions);
+        engine.register_fn("set_palette", move |name: &str| {
+            action(ScriptAction::SetPalette(name.to_string()))
+        });
+        let action = push(&actions);
+        engine.register_fn("set_turn_probability", move |probability: f64| {
+            action(ScriptAction::SetTurnProbability(probability as f32))
+        });
+        let action = push(&actions);
+        engine.register_fn("color_all_pipes", move |r: f64, g: f64, b: f64| {
+            action(ScriptAction::ColorAllPipes([r as f32, g as f32, b as f32]))
+        });
+        let action = push(&actions);
+        engine.register_fn("orbit_camera", move |yaw: f64, pitch: f64| {
+            action(ScriptAction::OrbitCamera {
+                yaw: yaw as f32,
+                pitch: pitch as f32,
+            })
+        });
+        let action = push(&actions);
+        engine.register_fn("zoom_camera", move |factor: f64| {
+            action(ScriptAction::ZoomCamera(factor as f32))
+        });
+        let action = push(&actions);
+        engine.register_fn("move_light", move |degrees: f64| {
+            action(ScriptAction::MoveLight(degrees as f32))
+        });
+        let action = push(&actions);
+        engine.register_fn("set_light_intensity", move |intensity: f64| {
+            action(ScriptAction::SetLightIntensity(intensity as f32))
+        });
+
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| anyhow!("Cannot compile script {:?}: {}", path, e))?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| anyhow!("Script {:?} failed: {}", path, e))?;
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            actions,
+        })
+    }
+
+    pub fn on_frame(&mut self, dt: f32, segments: usize) -> anyhow::Result<Vec<ScriptAction>> {
+        self.call("on_frame", (dt as f64, segments as i64))
+    }
+
+    pub fn on_pipe_added(
+        &mut self,
+        position: cgmath::Point3<f32>,
+        segments: usize,
+    ) -> anyhow::Result<Vec<ScriptAction>> {
+        let (x, y, z) = (position.x as f64, position.y as f64, position.z as f64);
+        self.call("on_pipe_added", (x, y, z, segments as i64))
+    }
+
+    pub fn on_reset(&mut self) -> anyhow::Result<Vec<ScriptAction>> {
+        self.call("on_reset", ())
+    }
+
+    /// Run the hook `name` if the script has it, and take whatever it asked for, along with anything
+    /// asked for since the last hook
+    fn call(&mut self, name: &str, args: impl FuncArgs) -> anyhow::Result<Vec<ScriptAction>> {
+        if self.ast.iter_functions().any(|function| function.name == name) {
+            // The top-level statements ran when loading, not again for every hook
+            let options = CallFnOptions::new().eval_ast(false);
+            // Hooks ask for things through the functions they call, whatever they return goes unused
+            let _ = self
+                .engine
+                .call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, args)
+                .map_err(|e| anyhow!("Script hook {} failed: {}", name, e))?;
+        }
+        Ok(self.actions.borrow_mut().drain(..).collect())
+    }
+}
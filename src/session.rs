@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::resources;
+use crate::world::WorldSnapshot;
+
+const FILE_NAME: &str = "session.toml";
+
+/// Where the camera was, as plain arrays to save
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct CameraPose {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+}
+
+/// What a run leaves behind for `--resume`, so a long-grown world survives a restart
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub settings: Config,
+    pub camera: CameraPose,
+    pub world: WorldSnapshot,
+}
+
+impl Session {
+    /// The last saved session, `None` if there is none
+    pub fn load() -> anyhow::Result<Option<Self>> {
+        let path = path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path).with_context(|| format!("Cannot read session {:?}", path))?;
+        let session = toml::from_str(&text).with_context(|| format!("Invalid session {:?}", path))?;
+        Ok(Some(session))
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Cannot create {:?}", dir))?;
+        }
+        let text = toml::to_string(self).context("Cannot serialize session")?;
+        // Write next to it first, so a crash halfway through doesn't destroy the last session
+        let temporary = path.with_extension("toml.tmp");
+        std::fs::write(&temporary, text).with_context(|| format!("Cannot write session {:?}", temporary))?;
+        std::fs::rename(&temporary, &path).with_context(|| format!("Cannot write session {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// The session file in the platform's data directory, see `resources::data_dir`
+fn path() -> anyhow::Result<PathBuf> {
+    Ok(resources::data_dir()?.join(FILE_NAME))
+}
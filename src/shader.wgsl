@@ -10,18 +10,60 @@ var<uniform> camera: CameraUniform;
 
 struct Light {
     position: vec3<f32>,
-    color: vec3<f32>
+    // scales the ambient light of every shading
+    ambient: f32,
+    color: vec3<f32>,
+    // strength of the light around silhouettes
+    rim: f32,
+    // the gradient around the world, the sky a share of the light's color
+    sky: vec3<f32>,
+    // strength of the light from the sky and the ground
+    sky_light: f32,
+    ground: vec3<f32>,
+    // multiple of the light everything is shown with, like a camera's exposure
+    exposure: f32,
 }
 
 @group(1) @binding(0)
 var<uniform> light: Light;
 
+struct Time {
+    seconds: f32,
+    // how long pipes take to fade, 0 keeps them vivid
+    aging: f32,
+    // when these pipes started draining away and for how long, 0 while they aren't
+    drain_start: f32,
+    drain_duration: f32,
+    // births of the newest and the oldest of them
+    drain_newest: f32,
+    drain_oldest: f32,
+    // 1 if they fade out all at once rather than shrink
+    drain_fade: f32,
+    // center of the block picked with the mouse, w is 0 while none is
+    selected: vec4<f32>,
+}
+
+@group(2) @binding(0)
+var<uniform> time: Time;
+
+// See `MaterialRaw` in material.rs
+struct Material {
+    shininess: f32,
+    specular: f32,
+    metallic: f32,
+    emissive: f32,
+}
+
+@group(3) @binding(0)
+var<storage, read> materials: array<Material>;
+
 struct VertextInput {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
 }
 
 struct InstanceInput {
+    @location(2) material: u32,
     @location(5) model_matrix_0: vec4<f32>,
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
@@ -29,59 +71,394 @@ struct InstanceInput {
     @location(9) normal_matrix_0: vec3<f32>,
     @location(10) normal_matrix_1: vec3<f32>,
     @location(11) normal_matrix_2: vec3<f32>,
-    @location(12) color: vec3<f32>,
+    @location(12) color: vec4<f32>,
+    @location(13) emissive: f32,
+    // segment index along the pipe, and which way the pipe runs through the mesh
+    @location(14) flow: vec2<f32>,
+    @location(15) birth: f32,
 };
 
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // computed the same in every pipeline, so the depth pre-pass matches the color pass exactly
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) world_normal: vec3<f32>,
     @location(1) world_position: vec3<f32>,
-    @location(2) color: vec3<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) emissive: f32,
+    // distance along the pipe, in segments
+    @location(4) flow: f32,
+    @location(5) @interpolate(flat) material: u32,
 };
 
-@vertex
-fn vs_main(
-    model: VertextInput,
-    instance: InstanceInput
-) -> VertexOutput {
-    let model_matrix = mat4x4<f32>(
+fn instance_model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
         instance.model_matrix_0,
         instance.model_matrix_1,
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
-    let normal_matrix = mat3x3<f32>(
+}
+
+fn instance_normal_matrix(instance: InstanceInput) -> mat3x3<f32> {
+    return mat3x3<f32>(
         instance.normal_matrix_0,
         instance.normal_matrix_1,
         instance.normal_matrix_2,
     );
+}
+
+const PI: f32 = 3.14159265;
+// How dark and gray fully aged pipes get
+const AGED_BRIGHTNESS: f32 = 0.5;
+
+// Old pipes lose their color and darken, so that the growing ones stand out
+fn aged_color(color: vec4<f32>, birth: f32) -> vec4<f32> {
+    if time.aging <= 0.0 {
+        return color;
+    }
+    let age = clamp((time.seconds - birth) / time.aging, 0.0, 1.0);
+    let gray = vec3<f32>(dot(color.rgb, vec3<f32>(0.299, 0.587, 0.114)) * AGED_BRIGHTNESS);
+    return vec4<f32>(mix(color.rgb, gray, age), color.a);
+}
+
+// Share of the drain each segment takes to shrink away, so that a few shrink at once
+const DRAIN_SHRINK: f32 = 0.2;
+
+// Size of a segment while its pipes drain away, from 1 down to 0. The newest go first, in reverse of how
+// they grew.
+fn drained_scale(birth: f32) -> f32 {
+    if time.drain_duration <= 0.0 || time.drain_fade > 0.0 {
+        return 1.0;
+    }
+    let order = (time.drain_newest - birth) / max(time.drain_newest - time.drain_oldest, 1e-6);
+    let progress = (time.seconds - time.drain_start) / time.drain_duration;
+    let start = order * (1.0 - DRAIN_SHRINK);
+    return 1.0 - smoothstep(start, start + DRAIN_SHRINK, progress);
+}
+
+// Share of a pipe still showing while its pipes crossfade away, from 1 down to 0, all of them at once
+fn faded_opacity() -> f32 {
+    if time.drain_duration <= 0.0 || time.drain_fade <= 0.0 {
+        return 1.0;
+    }
+    return 1.0 - smoothstep(0.0, 1.0, (time.seconds - time.drain_start) / time.drain_duration);
+}
+
+// Throw away as large a share of the pixels of crossfading pipes as they faded, in interleaved gradient
+// noise fine enough to pass for transparency, so that solid pipes fade without blending or sorting
+fn fade_out(in: VertexOutput) {
+    let opacity = faded_opacity();
+    if opacity >= 1.0 {
+        return;
+    }
+    let noise = fract(52.9829189 * fract(dot(in.clip_position.xy, vec2<f32>(0.06711056, 0.00583715))));
+    if noise >= opacity {
+        discard;
+    }
+}
+
+// What the segments of the picked block are lit up with
+const SELECTED_COLOR: vec3<f32> = vec3<f32>(1.0, 0.85, 0.2);
+const SELECTED_GLOW: f32 = 0.6;
+
+// Whether the instance is in the picked block. Caps sit off its center, but not by half a block.
+fn is_selected(instance: InstanceInput) -> bool {
+    return all(abs(instance.model_matrix_3.xyz - time.selected.xyz) < vec3<f32>(0.5 * time.selected.w));
+}
+
+fn flow_position(model_position: vec3<f32>, instance: InstanceInput) -> f32 {
+    var along: f32;
+    if instance.flow.y == 0.0 {
+        // Curves bend a quarter circle around their local (0.5, 0.5), from the +Y end to the +X end
+        let angle = atan2(0.5 - model_position.y, 0.5 - model_position.x);
+        along = clamp(angle / (PI / 2.0), 0.0, 1.0);
+    } else {
+        along = model_position.y * instance.flow.y + 0.5;
+    }
+    return instance.flow.x + along;
+}
+
+@vertex
+fn vs_main(
+    model: VertextInput,
+    instance: InstanceInput
+) -> VertexOutput {
+    return vertex(model, instance);
+}
+
+// The vertex shader of the shaded pipes, whichever way their instances are read
+fn vertex(model: VertextInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = instance_model_matrix(instance);
+    let normal_matrix = instance_normal_matrix(instance);
     var out: VertexOutput;
-    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    let position = model.position * drained_scale(instance.birth);
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(position, 1.0);
     out.clip_position = camera.view_proj * world_position;
-    out.world_normal = normal_matrix * model.normal;
+    out.world_normal = normalize(normal_matrix * model.normal);
     out.world_position = world_position.xyz;
-    out.color = instance.color;
+    out.color = aged_color(instance.color, instance.birth);
+    out.emissive = instance.emissive;
+    if is_selected(instance) {
+        out.color = vec4<f32>(mix(out.color.rgb, SELECTED_COLOR, 0.6), 1.0);
+        out.emissive += SELECTED_GLOW;
+    }
+    out.flow = flow_position(model.position, instance);
+    out.material = instance.material;
     return out;
 }
 
+// Glowing pipes give off light of their own color, regardless of where the light is
+fn emissive_color(in: VertexOutput) -> vec3<f32> {
+    return (in.emissive + materials[in.material].emissive) * in.color.rgb;
+}
+
+// how fast rim light fades from the silhouette inwards
+const RIM_POWER: f32 = 3.0;
+
+// Light around the silhouette, strongest where the surface faces away from the eye, as if from behind
+fn rim_color(normal: vec3<f32>, view_dir: vec3<f32>) -> vec3<f32> {
+    return light.color * light.rim * pow(1.0 - max(dot(normal, view_dir), 0.0), RIM_POWER);
+}
+
+// What metals mirror, as there is nothing around the pipes to mirror: the ground below a sky lit like the
+// light
+fn sky(direction: vec3<f32>) -> vec3<f32> {
+    return mix(light.ground, light.color * light.sky, smoothstep(-0.2, 0.6, direction.y));
+}
+
+// Light from all of the sky and ground onto a surface facing `normal`. Diffuse surfaces blur the gradient
+// so much that mixing the ground and the sky by how far up the surface faces comes close.
+fn sky_irradiance(normal: vec3<f32>) -> vec3<f32> {
+    return mix(light.ground, light.color * light.sky, 0.5 + 0.5 * normal.y) * light.sky_light;
+}
+
+
+// `color` as the camera shows it
+fn exposed(color: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(color.rgb * light.exposure, color.a);
+}
 
 @fragment
 fn fs_main(
     in: VertexOutput,
 ) -> @location(0) vec4<f32> {
-    let ambient_strength = 0.1;
+    fade_out(in);
+    return exposed(phong(in));
+}
+
+fn phong(in: VertexOutput) -> vec4<f32> {
+    let ambient_strength = 0.1 * light.ambient;
     let ambient_color = light.color * ambient_strength;
 
     let light_dir = normalize(light.position - in.world_position);
     let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
     let diffuse_color = light.color * diffuse_strength;
 
+    let material = materials[in.material];
     let view_dir = normalize(camera.view_pos.xyz - in.world_position);
     let half_dir = normalize(view_dir + light_dir);
-    let specular_strength = pow(max(dot(in.world_normal, half_dir), 0.0), 32.0);
+    let specular_strength = pow(max(dot(in.world_normal, half_dir), 0.0), material.shininess) * material.specular;
     let specular_color = specular_strength * light.color;
 
-    let result = (ambient_color + diffuse_color + specular_color) * in.color;
+    // Metals have no diffuse color of their own, they mirror the sky in theirs instead
+    let reflection = sky(reflect(-view_dir, normalize(in.world_normal))) * material.metallic;
+    let diffuse_weight = 1.0 - material.metallic;
+
+    let rim = rim_color(normalize(in.world_normal), view_dir);
+    let sky_color = sky_irradiance(normalize(in.world_normal));
+
+    let result = ((ambient_color + sky_color + diffuse_color) * diffuse_weight + specular_color + reflection + rim) * in.color.rgb
+        + emissive_color(in);
+
+    return vec4<f32>(result, in.color.a);
+}
+
+// Lambert shading
+
+@fragment
+fn fs_lambert(
+    in: VertexOutput,
+) -> @location(0) vec4<f32> {
+    fade_out(in);
+    let ambient_strength = 0.1 * light.ambient;
+    let ambient_color = light.color * ambient_strength;
+
+    // Diffuse light only, as if every pipe were chalk
+    let normal = normalize(in.world_normal);
+    let light_dir = normalize(light.position - in.world_position);
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let diffuse_color = light.color * diffuse_strength;
+    let rim = rim_color(normal, normalize(camera.view_pos.xyz - in.world_position));
+
+    let result = (ambient_color + sky_irradiance(normal) + diffuse_color + rim) * in.color.rgb + emissive_color(in);
+
+    return exposed(vec4<f32>(result, in.color.a));
+}
+
+// Physically based shading, every pipe a dielectric like plastic or paint unless its material is metallic
+
+// reflectance of dielectrics seen head on
+const PBR_REFLECTANCE: f32 = 0.04;
+const PBR_AMBIENT: f32 = 0.05;
+
+@fragment
+fn fs_pbr(
+    in: VertexOutput,
+) -> @location(0) vec4<f32> {
+    fade_out(in);
+    let normal = normalize(in.world_normal);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let light_dir = normalize(light.position - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let n_dot_l = max(dot(normal, light_dir), 0.0);
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+    let n_dot_h = max(dot(normal, half_dir), 0.0);
+
+    // The roughness a Blinn-Phong highlight of the material's shininess has, by the Beckmann distribution
+    let material = materials[in.material];
+    let roughness = sqrt(2.0 / (material.shininess + 2.0));
+    // Metals reflect in their own color, and scatter nothing below their surface
+    let reflectance = mix(vec3<f32>(PBR_REFLECTANCE), in.color.rgb, material.metallic);
+
+    // Cook-Torrance: GGX distribution, Smith geometry and Schlick's Fresnel
+    let alpha = roughness * roughness;
+    let alpha2 = alpha * alpha;
+    let d_denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    let distribution = alpha2 / (PI * d_denominator * d_denominator);
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let geometry = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+    let fresnel = reflectance + (1.0 - reflectance) * pow(1.0 - max(dot(half_dir, view_dir), 0.0), 5.0);
+    let specular = distribution * geometry * fresnel / max(4.0 * n_dot_v * n_dot_l, 0.0001) * material.specular;
+
+    // What isn't reflected off the surface is scattered below it
+    let diffuse = (1.0 - fresnel) * (1.0 - material.metallic) * in.color.rgb / PI;
+    // The light is as bright as in the other shadings when it shines head on
+    let radiance = light.color * PI;
+    let lit = (diffuse + specular) * radiance * n_dot_l;
+
+    let ambient_color = (PBR_AMBIENT * light.ambient * light.color + sky_irradiance(normal)) * in.color.rgb
+        * (1.0 - material.metallic);
+    let reflection = sky(reflect(-view_dir, normal)) * reflectance * material.metallic;
+    let rim = rim_color(normal, view_dir) * in.color.rgb;
+
+    return exposed(vec4<f32>(lit + ambient_color + reflection + rim + emissive_color(in), in.color.a));
+}
+
+// Toon shading
+
+const TOON_BANDS: f32 = 3.0;
+const OUTLINE_WIDTH: f32 = 0.04;
+
+@fragment
+fn fs_toon(
+    in: VertexOutput,
+) -> @location(0) vec4<f32> {
+    fade_out(in);
+    let ambient_strength = 0.2 * light.ambient;
+    let ambient_color = light.color * ambient_strength;
+
+    // Flat bands of light instead of a smooth falloff
+    let normal = normalize(in.world_normal);
+    let light_dir = normalize(light.position - in.world_position);
+    let diffuse_strength = ceil(max(dot(normal, light_dir), 0.0) * TOON_BANDS) / TOON_BANDS;
+    let diffuse_color = light.color * diffuse_strength;
+
+    // A hard-edged highlight
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let specular_strength = step(0.95, max(dot(normal, half_dir), 0.0)) * materials[in.material].specular;
+    let specular_color = specular_strength * light.color * 0.5;
+
+    // A hard-edged rim too, a band along the silhouette
+    let rim_strength = step(0.6, 1.0 - max(dot(normal, view_dir), 0.0));
+    let rim = light.color * light.rim * rim_strength;
+
+    let result = (ambient_color + sky_irradiance(normal) + diffuse_color + specular_color + rim) * in.color.rgb
+        + emissive_color(in);
+
+    return exposed(vec4<f32>(result, in.color.a));
+}
+
+@vertex
+fn vs_outline(
+    model: VertextInput,
+    instance: InstanceInput
+) -> VertexOutput {
+    return outline_vertex(model, instance);
+}
 
-    return vec4<f32>(result, 1.0);
+fn outline_vertex(model: VertextInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = instance_model_matrix(instance);
+    let normal_matrix = instance_normal_matrix(instance);
+    let world_normal = normalize(normal_matrix * model.normal);
+    let scale = drained_scale(instance.birth);
+    let world_position = (model_matrix * vec4<f32>(model.position * scale, 1.0)).xyz + world_normal * OUTLINE_WIDTH * scale;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.world_normal = world_normal;
+    out.world_position = world_position;
+    out.color = aged_color(instance.color, instance.birth);
+    out.emissive = instance.emissive;
+    out.flow = 0.0;
+    out.material = instance.material;
+    return out;
+}
+
+@fragment
+fn fs_outline(
+    in: VertexOutput,
+) -> @location(0) vec4<f32> {
+    fade_out(in);
+    return vec4<f32>(0.0, 0.0, 0.0, in.color.a);
+}
+
+// Flow shading
+
+const FLOW_SPEED: f32 = 2.0; // segments per second
+const FLOW_SPACING: f32 = 4.0; // segments from one band to the next
+const FLOW_WIDTH: f32 = 0.08; // share of the spacing a band takes up
+
+@fragment
+fn fs_flow(
+    in: VertexOutput,
+) -> @location(0) vec4<f32> {
+    fade_out(in);
+    let lit = phong(in);
+
+    // Bands of light moving from the start of each pipe towards its head
+    let wave = fract((in.flow - time.seconds * FLOW_SPEED) / FLOW_SPACING);
+    let band = 1.0 - smoothstep(0.0, FLOW_WIDTH, abs(wave - 0.5));
+    let band_color = mix(in.color.rgb, vec3<f32>(1.0), 0.5) * band;
+
+    return exposed(vec4<f32>(lit.rgb + band_color, lit.a));
+}
+
+// Unlit shading, the palette's colors as they are
+
+@fragment
+fn fs_unlit(
+    in: VertexOutput,
+) -> @location(0) vec4<f32> {
+    fade_out(in);
+    return exposed(in.color);
+}
+
+// Depth pre-pass, which writes no color at all
+
+@fragment
+fn fs_depth_only(
+    in: VertexOutput,
+) -> @location(0) vec4<f32> {
+    fade_out(in);
+    return vec4<f32>(0.0);
+}
+
+// Where the selected pipe covers the screen, as the pixel itself, which `outline::Outline` floods from
+
+@fragment
+fn fs_outline_mask(
+    in: VertexOutput,
+) -> @location(0) vec2<u32> {
+    return vec2<u32>(in.clip_position.xy);
 }
@@ -0,0 +1,578 @@
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use crate::clock;
+use crate::config::Config;
+use crate::fixed_step::{self, FixedStep};
+use crate::generator::{self, DirectionWeights};
+use crate::graph::Graph;
+use crate::instance::{Instance, InstanceRaw};
+use crate::layout::Layout;
+use crate::logging;
+use crate::material::Materials;
+use crate::palette::{self, Palette};
+use crate::recipe::Recipe;
+use crate::rhythm::{Rhythm, RhythmClock};
+use crate::world::{Change, Direction, PipeType, World, WorldSnapshot, WorldStats};
+
+const INITIAL_PIPES: u32 = 50;
+const GROWTH_SPEED: f32 = 10.0; // segments per second
+const MIN_GROWTH_SPEED: f32 = 0.5;
+const MAX_GROWTH_SPEED: f32 = 500.0;
+// share of the growth speed left with reduced motion
+const REDUCED_MOTION_GROWTH: f32 = 0.25;
+// holding a scrub key replays the whole world in about this many seconds, however big it grew
+const SCRUB_SECONDS: f32 = 8.0;
+const MIN_SCRUB_SPEED: f32 = GROWTH_SPEED;
+
+pub enum Command {
+    Reset,
+    /// Start over growing from this seed
+    Reseed(u64),
+    /// Grow this many segments right away, on top of the regular growth
+    Grow(u32),
+    SetTurnProbability(f32),
+    /// Grow pipes in a world this many blocks wide along X, Y and Z from now on, see `World::set_extents`
+    SetExtents([u32; 3]),
+    /// Have new pipes and turns prefer some directions
+    SetDirectionWeights(DirectionWeights),
+    /// Reply with the stats of the world as it is now
+    QueryStats(Sender<WorldStats>),
+    /// Reply with what the world grows from, to grow it again elsewhere
+    QueryRecipe(Sender<Recipe>),
+    /// Reply with the graph of the pipes, to export
+    QueryGraph(Sender<Graph>),
+    /// Paint new pipes with this palette
+    SetPalette(&'static Palette),
+    /// Paint new pipes with the palette after the current one
+    #[cfg_attr(not(feature = "audio"), allow(dead_code))]
+    NextPalette,
+    /// Make new pipes of these materials
+    SetMaterials(Materials),
+    /// Reply with a snapshot of the world to save
+    Snapshot(Sender<WorldSnapshot>),
+    /// Replace the world with a saved one
+    Restore(Box<WorldSnapshot>),
+    ScaleGrowthSpeed(f32),
+    /// Grow slower, on top of the growth speed
+    SetReducedMotion(bool),
+    SetPaused(bool),
+    /// Stop growing to replay how the world grew, showing only the blocks up to the replay's position,
+    /// or show the whole world again and grow on
+    SetReplay(bool),
+    /// Move the replay's position on by this many seconds of scrubbing, back for negative ones
+    Scrub(f32),
+    /// Take the segments in these blocks out of the world, see `World::erase`
+    Erase(Vec<(u32, u32, u32)>),
+    /// Undo the latest edit of the world, grown or by hand, see `World::undo`
+    Undo,
+    /// Redo the latest edit undone, see `World::redo`
+    Redo,
+    /// Place a segment by hand, see `World::build`
+    Build {
+        pipe_type: PipeType,
+        position: (u32, u32, u32),
+        direction: Direction,
+    },
+    Subscribe(Sender<WorldEvent>),
+}
+
+/// Changes to the world's instances, in the order they happened
+pub enum WorldEvent {
+    Reset,
+    Appended {
+        i_instances: Vec<InstanceRaw>,
+        l_instances: Vec<InstanceRaw>,
+    },
+    /// The segments in these blocks were taken out of the world
+    Erased(Vec<(u32, u32, u32)>),
+    /// How the teams racing to fill the world are doing, after every change while they race
+    Teams(TeamStats),
+    /// The block each growing pipe ended at and which way it goes on, after every change, see
+    /// `World::heads`
+    Heads(Vec<((u32, u32, u32), Direction)>),
+}
+
+/// Share of the world each team took, from 0 to 1, and the team which took the most once the world filled
+#[derive(Clone, Debug, Default)]
+pub struct TeamStats {
+    pub fills: Vec<f32>,
+    pub winner: Option<usize>,
+}
+
+/// A window's connection to a simulation thread. The thread stops once every handle is dropped.
+pub struct SimulationHandle {
+    commands: Sender<Command>,
+    events: Receiver<WorldEvent>,
+}
+
+impl SimulationHandle {
+    /// Start growing a new world on its own thread, which only grows pipes it is asked for when
+    /// `is_grown_on_gpu`, see `gpu_world::GpuWorld`
+    pub fn spawn(config: &Config, is_grown_on_gpu: bool) -> Self {
+        let mut world = config.seed.map_or_else(World::new, World::with_seed);
+        world.set_size(config.world_size);
+        world.set_max_pipe_length(config.max_pipe_length);
+        world.set_turn_probability(config.turn_probability);
+        world.set_direction_weights(config.direction_weights);
+        world.set_constraints(config.constraints);
+        world.set_region(config.region);
+        world.set_look_ahead(config.look_ahead);
+        world.set_teams(config.teams);
+        world.set_palette(config.palette());
+        world.set_opacity(config.opacity());
+        world.set_cell_size(config.cell_size);
+        world.set_jitter(config.jitter);
+        world.set_color_jitter(config.color_jitter);
+        world.set_materials(config.materials());
+        world.set_generator(generator::find(&config.generator).unwrap_or_else(|| Box::new(generator::Classic)));
+        if let Some(path) = &config.layout
+            && let Err(e) = Layout::load(path).and_then(|layout| world.set_layout(Some(&layout)))
+        {
+            warn!("Growing random pipes instead of the layout: {:?}", e);
+        }
+        let is_clock = config.clock;
+        let is_motion_reduced = config.reduced_motion;
+        let generator = config.generator.clone();
+        let rhythm = config.rhythm.clone();
+        let (commands, command_receiver) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("simulation".to_string())
+            .spawn(move || {
+                let mut simulation = Simulation::new(event_sender, world, is_clock, !is_grown_on_gpu);
+                simulation.is_motion_reduced = is_motion_reduced;
+                simulation.generator = generator;
+                simulation.rhythm = RhythmClock::new(rhythm);
+                simulation.run(command_receiver)
+            })
+            .expect("Cannot spawn simulation thread");
+
+        Self { commands, events }
+    }
+
+    /// Another handle to the same world, which gets its own copy of every event
+    pub fn subscribe(&self) -> Self {
+        let (event_sender, events) = mpsc::channel();
+        self.send(Command::Subscribe(event_sender));
+        Self {
+            commands: self.commands.clone(),
+            events,
+        }
+    }
+
+    pub fn send(&self, command: Command) {
+        if self.commands.send(command).is_err() {
+            error!("Simulation thread is gone");
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<WorldEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+struct Simulation {
+    world: World,
+    subscribers: Vec<Sender<WorldEvent>>,
+    growth_speed: f32,
+    rhythm: RhythmClock,
+    // steps of `fixed_step::STEP` the world grows in, so it grows the same however the thread is woken
+    fixed_step: FixedStep,
+    is_paused: bool,
+    // grows over time by itself, rather than only on `Command::Grow`
+    is_growing: bool,
+    is_motion_reduced: bool,
+    // blocks shown while replaying, None while growing
+    replay_position: Option<usize>,
+    // blocks scrubbed over which didn't add up to a whole one yet
+    scrub_accumulator: f32,
+    // the hour and minute the pipes spell in clock mode, None otherwise
+    clock_time: Option<(u8, u8)>,
+    // what the world's stats are recorded under for crash reports
+    context_key: String,
+    // name of the world's generator, for its recipe
+    generator: String,
+}
+
+impl Simulation {
+    fn new(subscriber: Sender<WorldEvent>, world: World, is_clock: bool, is_growing: bool) -> Self {
+        let mut simulation = Self {
+            world,
+            subscribers: vec![subscriber],
+            growth_speed: GROWTH_SPEED,
+            rhythm: RhythmClock::new(Rhythm::default()),
+            fixed_step: FixedStep::default(),
+            is_paused: false,
+            is_growing,
+            is_motion_reduced: false,
+            replay_position: None,
+            scrub_accumulator: 0.0,
+            clock_time: None,
+            context_key: format!("world {:?}", thread::current().id()),
+            generator: "classic".to_string(),
+        };
+        if is_clock {
+            simulation.update_clock();
+        }
+        if is_growing {
+            simulation.add_pipes(INITIAL_PIPES, false);
+        }
+        simulation
+    }
+
+    fn run(mut self, commands: Receiver<Command>) {
+        let mut last_tick = Instant::now();
+        loop {
+            // While paused there is nothing to do until the next command
+            let timeout = if self.is_paused || !self.is_growing {
+                Duration::MAX
+            } else {
+                fixed_step::STEP
+            };
+            match commands.recv_timeout(timeout) {
+                Ok(command) => self.handle(command),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    logging::forget(&self.context_key);
+                    return;
+                }
+            }
+
+            let now = Instant::now();
+            let steps = self.fixed_step.steps(now - last_tick);
+            last_tick = now;
+            if steps == 0 {
+                continue;
+            }
+            if self.clock_time.is_some() {
+                self.update_clock();
+            }
+            if !self.is_paused {
+                self.grow(steps);
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        // A replay only shows what was there before it started, so nothing grows or is edited meanwhile
+        let is_growth_or_edit = matches!(
+            command,
+            Command::Grow(_) | Command::Undo | Command::Redo | Command::Erase(_) | Command::Build { .. }
+        );
+        if is_growth_or_edit && self.replay_position.is_some() {
+            return;
+        }
+        match command {
+            Command::Reset => self.reset(),
+            Command::Reseed(seed) => {
+                self.world.reset_with_seed(seed);
+                self.started_over();
+            }
+            Command::Grow(count) => self.add_pipes(count, false),
+            Command::SetTurnProbability(probability) => self.world.set_turn_probability(probability),
+            Command::SetExtents(extents) => self.world.set_extents(extents),
+            Command::SetDirectionWeights(weights) => self.world.set_direction_weights(weights),
+            Command::SetPalette(palette) => self.world.set_palette(palette),
+            Command::SetMaterials(materials) => self.world.set_materials(materials),
+            Command::QueryStats(reply) => {
+                // Nobody is waiting for the stats anymore when this fails
+                let _ = reply.send(self.world.stats());
+            }
+            Command::QueryRecipe(reply) => {
+                let _ = reply.send(Recipe {
+                    seed: self.world.stats().seed,
+                    palette: self.world.palette().name.to_string(),
+                    generator: self.generator.clone(),
+                    turn_probability: self.world.turn_probability(),
+                    opacity: self.world.opacity(),
+                    world_size: self.world.size(),
+                    max_pipe_length: self.world.max_pipe_length(),
+                    direction_weights: self.world.direction_weights(),
+                    constraints: self.world.constraints(),
+                    region: self.world.region(),
+                    look_ahead: self.world.look_ahead(),
+                    teams: self.world.team_fills().len() as u32,
+                });
+            }
+            Command::QueryGraph(reply) => {
+                let _ = reply.send(Graph::from_blocks(self.world.blocks()));
+            }
+            Command::Snapshot(reply) => {
+                let _ = reply.send(self.world.snapshot());
+            }
+            Command::Restore(snapshot) => {
+                self.world.restore(&snapshot);
+                self.rhythm.restart();
+                self.replay_position = None;
+                self.broadcast_world();
+            }
+            Command::NextPalette => {
+                let palettes = palette::all();
+                let current = palettes
+                    .iter()
+                    .position(|palette| std::ptr::eq(*palette, self.world.palette()))
+                    .unwrap_or(0);
+                let palette = palettes[(current + 1) % palettes.len()];
+                self.world.set_palette(palette);
+                info!("Palette: {}", palette.name);
+            }
+            Command::ScaleGrowthSpeed(factor) => {
+                self.growth_speed = (self.growth_speed * factor).clamp(MIN_GROWTH_SPEED, MAX_GROWTH_SPEED);
+                info!("Growth speed: {:.1} segments/s", self.growth_speed);
+            }
+            Command::SetReducedMotion(is_motion_reduced) => self.is_motion_reduced = is_motion_reduced,
+            Command::SetPaused(is_paused) => self.is_paused = is_paused,
+            Command::SetReplay(is_replaying) => self.set_replay(is_replaying),
+            Command::Scrub(seconds) => self.scrub(seconds),
+            Command::Undo | Command::Redo => {
+                let (change, name) = match command {
+                    Command::Undo => (self.world.undo(), "undo"),
+                    _ => (self.world.redo(), "redo"),
+                };
+                match change {
+                    Some(change) => self.show_change(change),
+                    None => info!("Nothing to {}", name),
+                }
+            }
+            Command::Erase(positions) => {
+                let erased = self.world.erase(&positions);
+                if !erased.is_empty() {
+                    self.show_change(Change::Removed(erased));
+                }
+            }
+            Command::Build {
+                pipe_type,
+                position,
+                direction,
+            } => {
+                let start = self.world.len();
+                if self.world.build(pipe_type, position, direction) {
+                    self.flush(start);
+                } else {
+                    warn!(
+                        "Cannot build a {:?} pipe at {:?} heading {:?}",
+                        pipe_type, position, direction
+                    );
+                }
+            }
+            Command::Subscribe(subscriber) => {
+                // Catch the new subscriber up with everything grown so far, or replayed
+                let shown = self.replay_position.unwrap_or(self.world.len());
+                let (i_instances, l_instances) = self.raw_instances_between(0..shown);
+                let snapshot = WorldEvent::Appended {
+                    i_instances,
+                    l_instances,
+                };
+                if subscriber.send(snapshot).is_ok() {
+                    self.subscribers.push(subscriber);
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.world.reset();
+        self.started_over();
+    }
+
+    fn started_over(&mut self) {
+        self.rhythm.restart();
+        self.replay_position = None;
+        self.broadcast(|| WorldEvent::Reset);
+    }
+
+    fn set_replay(&mut self, is_replaying: bool) {
+        if !is_replaying {
+            // Everything scrubbed back over comes back before growing on
+            if let Some(position) = self.replay_position.take() {
+                self.flush(position);
+            }
+        } else if self.replay_position.is_none() {
+            self.replay_position = Some(self.world.len());
+            self.scrub_accumulator = 0.0;
+        }
+    }
+
+    /// Scrub through the replay at a speed which goes through all of it in `SCRUB_SECONDS`
+    fn scrub(&mut self, seconds: f32) {
+        let Some(position) = self.replay_position else {
+            return;
+        };
+        let len = self.world.len();
+        self.scrub_accumulator += seconds * (len as f32 / SCRUB_SECONDS).max(MIN_SCRUB_SPEED);
+        let blocks = self.scrub_accumulator.trunc();
+        self.scrub_accumulator -= blocks;
+        let target = (position as i64 + blocks as i64).clamp(0, len as i64) as usize;
+        if target > position {
+            let (i_instances, l_instances) = self.raw_instances_between(position..target);
+            self.broadcast(|| WorldEvent::Appended {
+                i_instances: i_instances.clone(),
+                l_instances: l_instances.clone(),
+            });
+        } else if target < position {
+            // Instances can't be taken away one by one, so the shorter log is built up again from the start
+            let (i_instances, l_instances) = self.raw_instances_between(0..target);
+            self.broadcast(|| WorldEvent::Reset);
+            self.broadcast(|| WorldEvent::Appended {
+                i_instances: i_instances.clone(),
+                l_instances: l_instances.clone(),
+            });
+        }
+        self.replay_position = Some(target);
+    }
+
+    /// Start spelling the time over once it changed
+    fn update_clock(&mut self) {
+        let time = clock::now();
+        if self.clock_time == Some(time) {
+            return;
+        }
+        self.clock_time = Some(time);
+        self.world.set_mask(Some(clock::mask(time.0, time.1)));
+        self.reset();
+    }
+
+    fn grow(&mut self, steps: u32) {
+        if self.replay_position.is_some() {
+            return;
+        }
+        let speed = if self.is_motion_reduced {
+            self.growth_speed * REDUCED_MOTION_GROWTH
+        } else {
+            self.growth_speed
+        };
+        // A step at a time, so that the same steps grow the same pipes however they are batched
+        let mut count = 0;
+        for _ in 0..steps {
+            count += self.rhythm.step(speed);
+            if self.rhythm.is_staggered() && count > 0 {
+                self.add_pipes(std::mem::take(&mut count), true);
+            }
+        }
+        self.add_pipes(count, false);
+    }
+
+    /// Grow `count` segments, or fewer when `stops_at_new_pipe` and a new pipe starts, which then waits out
+    /// the rhythm's stagger before growing on
+    fn add_pipes(&mut self, count: u32, stops_at_new_pipe: bool) {
+        let mut start = self.world.len();
+        let pipes = self.world.stats().pipes;
+        for _ in 0..count {
+            if !self.world.add_pipe() {
+                if self.clock_time.is_some() {
+                    // Every digit is filled, keep showing them until the time changes
+                    break;
+                }
+                if self.world.has_layout() {
+                    // The layout is all there, keep showing it
+                    break;
+                }
+                // The world is full, start over
+                self.flush(start);
+                self.declare_winner();
+                self.reset();
+                start = 0;
+            }
+            if stops_at_new_pipe && self.world.stats().pipes != pipes {
+                self.rhythm.on_new_pipe();
+                break;
+            }
+        }
+        self.flush(start);
+        logging::record(&self.context_key, self.world.stats());
+    }
+
+    /// Tell which team took the most of the full world before it starts over, if teams race for it
+    fn declare_winner(&mut self) {
+        let Some(winner) = self.world.leading_team() else {
+            return;
+        };
+        let fills = self.world.team_fills();
+        info!(
+            "Team {} wins with {:.1}% of the world",
+            winner + 1,
+            fills[winner] * 100.0
+        );
+        self.broadcast(|| {
+            WorldEvent::Teams(TeamStats {
+                fills: fills.clone(),
+                winner: Some(winner),
+            })
+        });
+    }
+
+    /// Send out what an edit, or undoing or redoing one, changed
+    fn show_change(&mut self, change: Change) {
+        match change {
+            Change::Added(indices) => {
+                let (i_instances, l_instances) = self.world.instances_of(indices);
+                let (i_instances, l_instances) = (to_raw(&i_instances), to_raw(&l_instances));
+                self.broadcast(|| WorldEvent::Appended {
+                    i_instances: i_instances.clone(),
+                    l_instances: l_instances.clone(),
+                });
+            }
+            Change::Removed(blocks) => self.broadcast(|| WorldEvent::Erased(blocks.clone())),
+            Change::Replaced => self.broadcast_world(),
+        }
+        let heads = self.world.heads();
+        self.broadcast(|| WorldEvent::Heads(heads.clone()));
+    }
+
+    /// Send out the whole world again, replacing what was there
+    fn broadcast_world(&mut self) {
+        let (i_instances, l_instances) = self.raw_instances_since(0);
+        self.broadcast(|| WorldEvent::Reset);
+        self.broadcast(|| WorldEvent::Appended {
+            i_instances: i_instances.clone(),
+            l_instances: l_instances.clone(),
+        });
+    }
+
+    /// Send out the instances of the blocks added since the first `start` ones
+    fn flush(&mut self, start: usize) {
+        if self.world.len() <= start {
+            return;
+        }
+        let (i_instances, l_instances) = self.raw_instances_since(start);
+        self.broadcast(|| WorldEvent::Appended {
+            i_instances: i_instances.clone(),
+            l_instances: l_instances.clone(),
+        });
+        let heads = self.world.heads();
+        self.broadcast(|| WorldEvent::Heads(heads.clone()));
+        let fills = self.world.team_fills();
+        if !fills.is_empty() {
+            self.broadcast(|| {
+                WorldEvent::Teams(TeamStats {
+                    fills: fills.clone(),
+                    winner: None,
+                })
+            });
+        }
+    }
+
+    fn raw_instances_since(&self, start: usize) -> (Vec<InstanceRaw>, Vec<InstanceRaw>) {
+        self.raw_instances_between(start..self.world.len())
+    }
+
+    fn raw_instances_between(&self, blocks: Range<usize>) -> (Vec<InstanceRaw>, Vec<InstanceRaw>) {
+        let (i_instances, l_instances) = self.world.instances_between(blocks);
+        (to_raw(&i_instances), to_raw(&l_instances))
+    }
+
+    fn broadcast(&mut self, event: impl Fn() -> WorldEvent) {
+        // Windows which have been closed drop their receiver
+        self.subscribers.retain(|subscriber| subscriber.send(event()).is_ok());
+    }
+}
+
+fn to_raw(instances: &[Instance]) -> Vec<InstanceRaw> {
+    instances.iter().map(Instance::to_raw).collect()
+}
@@ -0,0 +1,158 @@
+use cgmath::{Point3, Vector3};
+
+use crate::camera::Camera;
+
+pub const MAX_VIEWS: usize = 4;
+// center of the part of the world pipes grow in
+const WORLD_CENTER: Point3<f32> = Point3::new(15.0, 15.0, 15.0);
+const OVERVIEW_DISTANCE: f32 = 60.0;
+// where the chase camera stays relative to the pipe it follows
+const CHASE_OFFSET: Vector3<f32> = Vector3::new(3.0, 2.0, 3.0);
+// share of the distance to the newest pipe the chase camera catches up on per second
+const CHASE_SPEED: f32 = 2.0;
+
+/// A rectangle of the render target in pixels, from the top left
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    pub fn aspect(&self) -> f32 {
+        self.width.max(1) as f32 / self.height.max(1) as f32
+    }
+}
+
+/// Where each of `count` views goes in a `width` x `height` target. The first view gets the most room:
+/// two views are side by side, a third one shares the right half with the second, and four form a grid.
+pub fn layout(count: usize, width: u32, height: u32) -> Vec<Viewport> {
+    let (half_width, half_height) = (width / 2, height / 2);
+    let left = |width| Viewport {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    };
+    let right = |width| Viewport {
+        x: half_width,
+        y: 0,
+        width,
+        height,
+    };
+    let quarter = |column: u32, row: u32| Viewport {
+        x: column * half_width,
+        y: row * half_height,
+        width: if column == 0 { half_width } else { width - half_width },
+        height: if row == 0 { half_height } else { height - half_height },
+    };
+    match count {
+        0 | 1 => vec![left(width)],
+        2 => vec![left(half_width), right(width - half_width)],
+        3 => vec![left(half_width), quarter(1, 0), quarter(1, 1)],
+        _ => vec![quarter(0, 0), quarter(1, 0), quarter(0, 1), quarter(1, 1)],
+    }
+}
+
+/// Views of one window. The first one is the user's free camera, the others in order chase the newest
+/// pipe, look down from the top and look at the side of the world. In stereo there are always two views
+/// instead, for the left and right eye of the free camera.
+pub struct SplitScreen {
+    view_count: usize,
+    // distance between the eyes when rendering in stereo
+    stereo_ipd: Option<f32>,
+    eyes: [Camera; 2],
+    chase: Camera,
+    top: Camera,
+    side: Camera,
+    // the newest pipe, and the point the chase camera looks at on its way there
+    head: Point3<f32>,
+    chase_target: Point3<f32>,
+    // the chase camera stays where it is
+    is_motion_reduced: bool,
+}
+
+impl SplitScreen {
+    pub fn new(view_count: usize) -> Self {
+        let mut top = Camera::new(1.0, 1.0);
+        // slightly off vertical, the camera can't look straight along its up axis
+        top.look_at(WORLD_CENTER + Vector3::new(0.0, OVERVIEW_DISTANCE, 1.0), WORLD_CENTER);
+        let mut side = Camera::new(1.0, 1.0);
+        side.look_at(WORLD_CENTER + Vector3::new(OVERVIEW_DISTANCE, 0.0, 0.0), WORLD_CENTER);
+        Self {
+            view_count: view_count.clamp(1, MAX_VIEWS),
+            stereo_ipd: None,
+            eyes: [Camera::new(1.0, 1.0), Camera::new(1.0, 1.0)],
+            chase: Camera::new(1.0, 1.0),
+            top,
+            side,
+            head: WORLD_CENTER,
+            chase_target: WORLD_CENTER,
+            is_motion_reduced: false,
+        }
+    }
+
+    pub fn view_count(&self) -> usize {
+        if self.stereo_ipd.is_some() { 2 } else { self.view_count }
+    }
+
+    /// Render a side-by-side stereo pair with eyes `ipd` apart, or the regular views for `None`
+    pub fn set_stereo(&mut self, ipd: Option<f32>) {
+        self.stereo_ipd = ipd;
+    }
+
+    /// Show `count` views, from 1 to `MAX_VIEWS`
+    pub fn set_view_count(&mut self, count: usize) {
+        self.view_count = count.clamp(1, MAX_VIEWS);
+    }
+
+    /// Go from one view up to `MAX_VIEWS` and back to one
+    pub fn cycle(&mut self) {
+        self.view_count = self.view_count % MAX_VIEWS + 1;
+    }
+
+    /// Stop the chase camera from moving along with the pipes, or let it again
+    pub fn set_reduced_motion(&mut self, is_motion_reduced: bool) {
+        self.is_motion_reduced = is_motion_reduced;
+    }
+
+    /// Point the chase camera at a newly grown pipe
+    pub fn follow(&mut self, head: Point3<f32>) {
+        self.head = head;
+    }
+
+    /// Move the chase camera along and fit every camera, `main` included, to its part of the target
+    pub fn update(&mut self, dt: f32, width: u32, height: u32, main: &mut Camera) {
+        if !self.is_motion_reduced {
+            let catch_up = (CHASE_SPEED * dt).min(1.0);
+            self.chase_target += (self.head - self.chase_target) * catch_up;
+            self.chase.look_at(self.chase_target + CHASE_OFFSET, self.chase_target);
+        }
+
+        let viewports = layout(self.view_count(), width, height);
+        main.set_aspect(viewports[0].aspect());
+        if let Some(ipd) = self.stereo_ipd {
+            self.eyes = main.stereo_pair(ipd);
+            return;
+        }
+        for (camera, viewport) in [&mut self.chase, &mut self.top, &mut self.side]
+            .into_iter()
+            .zip(&viewports[1..])
+        {
+            camera.set_aspect(viewport.aspect());
+        }
+    }
+
+    /// Camera of every view, in the order of `layout`
+    pub fn cameras<'a>(&'a self, main: &'a Camera) -> Vec<&'a Camera> {
+        if self.stereo_ipd.is_some() {
+            return self.eyes.iter().collect();
+        }
+        [main, &self.chase, &self.top, &self.side]
+            .into_iter()
+            .take(self.view_count)
+            .collect()
+    }
+}
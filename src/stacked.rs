@@ -0,0 +1,36 @@
+use cgmath::{Point3, Vector3};
+
+use crate::chunks::Bounds;
+use crate::config::{Config, StackedWorld};
+use crate::instance::InstanceRaw;
+use crate::simulation::SimulationHandle;
+
+/// A stacked world growing on a simulation thread of its own, see `config::StackedWorld`, and where its
+/// instances go in the main world
+pub struct StackedSimulation {
+    pub simulation: SimulationHandle,
+    offset: Vector3<f32>,
+    scale: f32,
+}
+
+impl StackedSimulation {
+    /// Start growing `stacked` over the main world of `config`, `extents` blocks wide along X, Y and Z
+    pub fn spawn(stacked: &StackedWorld, config: &Config, extents: [u32; 3]) -> Self {
+        let cell_size = config.cell_size;
+        let center = match stacked.center {
+            Some([x, y, z]) => Point3::new(x, y, z) * cell_size,
+            None => Bounds::of_extents(extents, cell_size).center(),
+        };
+        let own_center = Bounds::of_world(stacked.world_size, cell_size).center();
+        Self {
+            simulation: SimulationHandle::spawn(&stacked.config(config), false),
+            offset: center - own_center * stacked.scale,
+            scale: stacked.scale,
+        }
+    }
+
+    /// `instance` of the stacked world where it goes in the main world
+    pub fn place(&self, instance: &InstanceRaw) -> InstanceRaw {
+        instance.transformed(self.offset, self.scale)
+    }
+}
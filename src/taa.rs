@@ -0,0 +1,176 @@
+use crate::gpu;
+
+// frames the jitter goes through before it starts over
+const JITTER_FRAMES: u32 = 8;
+
+/// Where in its pixels frame `frame` is drawn, from -0.5 to 0.5 either way. The Halton sequence in bases 2
+/// and 3 spreads the frames evenly over the pixel, however many of them there are.
+pub fn jitter(frame: u32) -> (f32, f32) {
+    let index = frame % JITTER_FRAMES + 1;
+    (halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let (mut result, mut fraction) = (0.0, 1.0);
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Temporal anti-aliasing, smoother than drawing edges at several samples a pixel and with no more than
+/// one pass over the frame: the scene is drawn with the camera shifted by a fraction of a pixel every
+/// frame, see `jitter`, and blended into a history of the frames before. Whatever moved is clamped to its
+/// neighborhood in the new frame rather than smeared.
+pub struct Taa {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+}
+
+/// The two textures the frames are blended into in turns, one read while the other is written
+pub struct TaaHistory {
+    views: [wgpu::TextureView; 2],
+    // the new frame and the history other than the one written
+    bind_groups: [wgpu::BindGroup; 2],
+    // the new frame as its own history, for a start without one
+    first_bind_group: wgpu::BindGroup,
+}
+
+impl TaaHistory {
+    /// The history written by the `index`th frame, to show it
+    pub fn view(&self, index: usize) -> &wgpu::TextureView {
+        &self.views[index]
+    }
+}
+
+impl Taa {
+    /// Blends frames drawn in `format`
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> anyhow::Result<Self> {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TaaBindGroupLayout"),
+            entries: &[texture_entry(0), texture_entry(1)],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TaaPipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = gpu::create_shader_module(device, "TaaShader", include_str!("taa.wgsl"))?;
+        let pipeline = gpu::validated(device, "TaaPipeline", || {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("TaaPipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: Default::default(),
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            })
+        })?;
+        Ok(Self {
+            pipeline,
+            bind_group_layout,
+            format,
+        })
+    }
+
+    /// A history of frames `width` by `height` drawn into `frame`
+    pub fn history(&self, device: &wgpu::Device, frame: &wgpu::TextureView, width: u32, height: u32) -> TaaHistory {
+        let view = |label| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let views = [view("TaaHistory0"), view("TaaHistory1")];
+        let bind_group = |history: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("TaaBindGroup"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(frame),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(history),
+                    },
+                ],
+            })
+        };
+        TaaHistory {
+            bind_groups: [bind_group(&views[1]), bind_group(&views[0])],
+            first_bind_group: bind_group(frame),
+            views,
+        }
+    }
+
+    /// Blend the new frame into the history the `index`th frame writes, from the other one unless it is
+    /// the first frame of the history
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder, history: &TaaHistory, index: usize, is_first: bool) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("TaaPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &history.views[index],
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        let bind_group = match is_first {
+            true => &history.first_bind_group,
+            false => &history.bind_groups[index],
+        };
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,38 @@
+// Temporal anti-aliasing: every frame is drawn with the camera shifted by a fraction of a pixel, and
+// blended into the ones before. See `taa::Taa`.
+
+@group(0) @binding(0)
+var frame: texture_2d<f32>;
+@group(0) @binding(1)
+var history: texture_2d<f32>;
+
+// share of the frames before kept every frame, the rest is the new one
+const HISTORY_WEIGHT: f32 = 0.9;
+
+// One triangle covering the whole target, like the blitter's
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(frame));
+    let pixel = vec2<i32>(position.xy);
+    let current = textureLoad(frame, pixel, 0);
+
+    // The frames before are only trusted as far as they look like the pixels around in this one, so that
+    // whatever moved or changed leaves no trail behind
+    var low = current;
+    var high = current;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbor = textureLoad(frame, clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), size - 1), 0);
+            low = min(low, neighbor);
+            high = max(high, neighbor);
+        }
+    }
+    let previous = clamp(textureLoad(history, pixel, 0), low, high);
+    return mix(current, previous, HISTORY_WEIGHT);
+}
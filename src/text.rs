@@ -0,0 +1,249 @@
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont, point};
+
+use crate::gpu;
+
+const FONT: &[u8] = include_bytes!("../res/DejaVuSansMono.ttf");
+// in logical pixels, multiplied by the window's scale factor
+const FONT_SIZE: f32 = 15.0;
+const PADDING: f32 = 12.0;
+const MARGIN: f32 = 16.0;
+const BACKGROUND_ALPHA: f32 = 0.7;
+
+/// Text in the top left corner over the finished frame, white on a dark box, e.g. the help overlay. It is
+/// rasterized on the CPU whenever it changes and drawn as a single textured rectangle.
+pub struct TextOverlay {
+    font: FontRef<'static>,
+    scale_factor: f32,
+    text: Option<String>,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    // the rasterized text and its size in pixels, None while there is no text
+    rasterized: Option<(wgpu::BindGroup, u32, u32)>,
+}
+
+impl TextOverlay {
+    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat) -> anyhow::Result<Self> {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TextBindGroupLayout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TextPipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = gpu::create_shader_module(device, "TextShader", include_str!("text.wgsl"))?;
+        let pipeline = gpu::validated(device, "TextPipeline", || {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("TextPipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: target_format,
+                        blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: Default::default(),
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            })
+        })?;
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("TextSampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            // only filtered when the text is shrunk to fit a small window
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            font: FontRef::try_from_slice(FONT).expect("Bundled font is invalid"),
+            scale_factor: 1.0,
+            text: None,
+            pipeline,
+            bind_group_layout,
+            sampler,
+            rasterized: None,
+        })
+    }
+
+    /// Show `text`, lines separated by `\n`, or nothing for `None`
+    pub fn set_text(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, text: Option<&str>) {
+        if self.text.as_deref() == text {
+            return;
+        }
+        self.text = text.map(str::to_string);
+        self.rasterize(device, queue);
+    }
+
+    /// Physical pixels per logical pixel, e.g. 2 on most high DPI screens
+    pub fn set_scale_factor(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, scale_factor: f32) {
+        if self.scale_factor == scale_factor {
+            return;
+        }
+        self.scale_factor = scale_factor;
+        self.rasterize(device, queue);
+    }
+
+    fn rasterize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.rasterized = None;
+        let Some(text) = self.text.as_deref().filter(|text| !text.is_empty()) else {
+            return;
+        };
+
+        let font = self.font.as_scaled(PxScale::from(FONT_SIZE * self.scale_factor));
+        let padding = PADDING * self.scale_factor;
+        let line_height = font.height() + font.line_gap();
+        let lines: Vec<&str> = text.lines().collect();
+        let line_width = |line: &str| line.chars().map(|c| font.h_advance(font.glyph_id(c))).sum::<f32>();
+        let text_width = lines.iter().map(|line| line_width(line)).fold(0.0, f32::max);
+        let width = (text_width + 2.0 * padding).ceil() as u32;
+        let height = (line_height * lines.len() as f32 + 2.0 * padding).ceil() as u32;
+
+        let mut coverage = vec![0.0f32; (width * height) as usize];
+        for (i, line) in lines.iter().enumerate() {
+            let baseline = padding + font.ascent() + line_height * i as f32;
+            let mut x = padding;
+            for c in line.chars() {
+                let mut glyph = font.scaled_glyph(c);
+                glyph.position = point(x, baseline);
+                x += font.h_advance(glyph.id);
+                let Some(outlined) = font.outline_glyph(glyph) else {
+                    continue;
+                };
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, glyph_coverage| {
+                    let (px, py) = (bounds.min.x as i64 + gx as i64, bounds.min.y as i64 + gy as i64);
+                    if (0..width as i64).contains(&px) && (0..height as i64).contains(&py) {
+                        let pixel = &mut coverage[(py * width as i64 + px) as usize];
+                        *pixel = pixel.max(glyph_coverage);
+                    }
+                });
+            }
+        }
+        // White text over the dark box, premultiplied
+        let pixels: Vec<u8> = coverage
+            .iter()
+            .flat_map(|c| {
+                let alpha = c + (1.0 - c) * BACKGROUND_ALPHA;
+                let [color, alpha] = [c, &alpha].map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8);
+                [color, color, color, alpha]
+            })
+            .collect();
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("TextTexture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            &pixels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("TextBindGroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        self.rasterized = Some((bind_group, width, height));
+    }
+
+    /// Draw the text over `target`, which is `width` by `height` pixels. Text too large for it is shrunk.
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, width: u32, height: u32) {
+        let Some((bind_group, text_width, text_height)) = &self.rasterized else {
+            return;
+        };
+        let margin = MARGIN * self.scale_factor;
+        let fit = ((width as f32 - 2.0 * margin) / *text_width as f32)
+            .min((height as f32 - 2.0 * margin) / *text_height as f32)
+            .min(1.0);
+        if fit <= 0.0 {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("TextPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_viewport(
+            margin.floor(),
+            margin.floor(),
+            *text_width as f32 * fit,
+            *text_height as f32 * fit,
+            0.0,
+            1.0,
+        );
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
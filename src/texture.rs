@@ -1,16 +1,53 @@
+/// How the depth buffer stores and compares depths, see `Config::depth_format` and `Config::reversed_z`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Depth {
+    pub format: wgpu::TextureFormat,
+    /// Depth goes from 1 at the near plane to 0 at the far one, rather than the other way round. Float
+    /// depth is most precise near 0, which then makes up for the projection crowding far depths together.
+    pub reversed: bool,
+}
+
+impl Default for Depth {
+    fn default() -> Self {
+        Self {
+            format: wgpu::TextureFormat::Depth32Float,
+            reversed: false,
+        }
+    }
+}
+
+impl Depth {
+    /// What the depth buffer is cleared to, the far plane
+    pub fn clear_value(self) -> f32 {
+        if self.reversed { 0.0 } else { 1.0 }
+    }
+
+    /// `compare` for depths going from 0 near to 1 far, turned around if they go the other way
+    pub fn compare(self, compare: wgpu::CompareFunction) -> wgpu::CompareFunction {
+        use wgpu::CompareFunction::*;
+        match (self.reversed, compare) {
+            (false, compare) => compare,
+            (true, Less) => Greater,
+            (true, LessEqual) => GreaterEqual,
+            (true, Greater) => Less,
+            (true, GreaterEqual) => LessEqual,
+            (true, compare) => compare,
+        }
+    }
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
+    #[allow(dead_code)]
     pub sampler: wgpu::Sampler,
 }
 
 impl Texture {
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
-
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+    pub fn create_depth_texture(device: &wgpu::Device, depth: Depth, width: u32, height: u32) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width.max(1),
-            height: config.height.max(1),
+            width: width.max(1),
+            height: height.max(1),
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -19,7 +56,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
+            format: depth.format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         };
@@ -34,7 +71,7 @@ impl Texture {
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual),
+            compare: Some(depth.compare(wgpu::CompareFunction::LessEqual)),
             lod_min_clamp: 0.0,
             lod_max_clamp: 100.0,
             ..Default::default()
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use winit::dpi::PhysicalPosition;
+use winit::event::{Touch, TouchPhase};
+
+#[derive(Copy, Clone, Debug)]
+pub enum Gesture {
+    /// One-finger drag, in pixels
+    Orbit { dx: f32, dy: f32 },
+    /// Two-finger pinch, ratio of the new finger distance to the old one
+    Pinch { scale: f32 },
+    /// Two-finger drag of the fingers' midpoint, in pixels
+    Pan { dx: f32, dy: f32 },
+}
+
+/// Turns raw winit touch events into camera gestures
+#[derive(Default)]
+pub struct TouchTracker {
+    touches: HashMap<u64, PhysicalPosition<f64>>,
+}
+
+impl TouchTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn handle(&mut self, touch: &Touch) -> Vec<Gesture> {
+        match touch.phase {
+            TouchPhase::Started => {
+                self.touches.insert(touch.id, touch.location);
+                vec![]
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.remove(&touch.id);
+                vec![]
+            }
+            TouchPhase::Moved => {
+                let Some(old) = self.touches.get(&touch.id).copied() else {
+                    return vec![];
+                };
+                let gestures = match self.touches.len() {
+                    1 => vec![Gesture::Orbit {
+                        dx: (touch.location.x - old.x) as f32,
+                        dy: (touch.location.y - old.y) as f32,
+                    }],
+                    2 => self.two_finger_gestures(touch.id, old, touch.location),
+                    // ignore anything with three or more fingers
+                    _ => vec![],
+                };
+                self.touches.insert(touch.id, touch.location);
+                gestures
+            }
+        }
+    }
+
+    fn two_finger_gestures(&self, id: u64, old: PhysicalPosition<f64>, new: PhysicalPosition<f64>) -> Vec<Gesture> {
+        let Some(other) = self
+            .touches
+            .iter()
+            .find(|(other_id, _)| **other_id != id)
+            .map(|(_, p)| *p)
+        else {
+            return vec![];
+        };
+
+        let old_distance = distance(old, other);
+        let new_distance = distance(new, other);
+        let mut gestures = vec![Gesture::Pan {
+            // the midpoint moves by half of the moving finger's delta
+            dx: ((new.x - old.x) / 2.0) as f32,
+            dy: ((new.y - old.y) / 2.0) as f32,
+        }];
+        if old_distance > 0.0 && new_distance > 0.0 {
+            gestures.push(Gesture::Pinch {
+                scale: (new_distance / old_distance) as f32,
+            });
+        }
+        gestures
+    }
+}
+
+fn distance(a: PhysicalPosition<f64>, b: PhysicalPosition<f64>) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
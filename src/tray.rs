@@ -0,0 +1,90 @@
+use tray_icon::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+
+use crate::identity;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrayAction {
+    TogglePause,
+    ResetWorld,
+    Screenshot,
+    OpenConfig,
+    Exit,
+}
+
+// menu items by their id
+const ITEMS: [(TrayAction, &str, &str); 5] = [
+    (TrayAction::TogglePause, "pause", "Pause / resume"),
+    (TrayAction::ResetWorld, "reset", "Start over"),
+    (TrayAction::Screenshot, "screenshot", "Save a screenshot"),
+    (TrayAction::OpenConfig, "config", "Open config"),
+    (TrayAction::Exit, "exit", "Quit"),
+];
+
+/// An icon in the system tray with a menu, for windows without any chrome to click like wallpapers and
+/// overlays. On Linux it lives on a GTK thread of its own, elsewhere on the event loop's thread, which
+/// has to be running when it is created.
+pub struct Tray {
+    #[cfg(not(target_os = "linux"))]
+    _icon: TrayIcon,
+}
+
+impl Tray {
+    /// `has_config` enables opening the config file, there is none to open otherwise
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(has_config: bool) -> anyhow::Result<Self> {
+        Ok(Self {
+            _icon: build(has_config)?,
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn new(has_config: bool) -> anyhow::Result<Self> {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        std::thread::Builder::new().name("tray".to_string()).spawn(move || {
+            let icon = gtk::init().map_err(anyhow::Error::from).and_then(|_| build(has_config));
+            match icon {
+                Ok(_icon) => {
+                    let _ = sender.send(Ok(()));
+                    // Runs until the process exits, the icon along with it
+                    gtk::main();
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                }
+            }
+        })?;
+        receiver.recv()??;
+        Ok(Self {})
+    }
+
+    /// Menu items clicked since the last call
+    pub fn poll(&mut self) -> Vec<TrayAction> {
+        MenuEvent::receiver()
+            .try_iter()
+            .filter_map(|event| {
+                ITEMS
+                    .iter()
+                    .find(|(_, id, _)| event.id == *id)
+                    .map(|(action, _, _)| *action)
+            })
+            .collect()
+    }
+}
+
+fn build(has_config: bool) -> anyhow::Result<TrayIcon> {
+    let menu = Menu::new();
+    for (action, id, text) in ITEMS {
+        if action == TrayAction::Exit {
+            menu.append(&PredefinedMenuItem::separator())?;
+        }
+        let is_enabled = action != TrayAction::OpenConfig || has_config;
+        menu.append(&MenuItem::with_id(id, text, is_enabled, None))?;
+    }
+    let (rgba, width, height) = identity::icon_rgba()?;
+    Ok(TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        .with_tooltip(crate::TITLE)
+        .with_icon(Icon::from_rgba(rgba, width, height)?)
+        .build()?)
+}
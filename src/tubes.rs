@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
+
+use crate::chunks::Bounds;
+use crate::instance::InstanceRaw;
+use crate::models::ModelVertex;
+use crate::picking::{SegmentKind, Selection};
+
+/// Fewest and most sides of a tube, see `Tessellation::sides`
+pub const MIN_SIDES: u32 = 3;
+pub const MAX_SIDES: u32 = 64;
+/// Most pieces of a tube's center line a unit long, see `Tessellation::segments_per_unit`
+pub const MAX_SEGMENTS_PER_UNIT: u32 = 32;
+
+/// How finely tubes are swept, finer ones are smoother but take more vertices
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tessellation {
+    /// Vertices around each ring of a tube
+    pub sides: u32,
+    /// Straight pieces the center line is cut into for every unit of its length, at least 1 a block
+    pub segments_per_unit: u32,
+}
+
+impl Default for Tessellation {
+    fn default() -> Self {
+        Self {
+            sides: 16,
+            segments_per_unit: 8,
+        }
+    }
+}
+
+/// A pipe as one smooth tube through the centers of its blocks, rather than a straight pipe or an elbow
+/// a block
+#[derive(Clone, Debug)]
+pub struct Tube {
+    /// Centers of the blocks the pipe grew through, in order
+    centers: Vec<Point3<f32>>,
+    /// Which way the pipe goes into its first block and out of its last, for the half blocks before and
+    /// after their centers
+    first_direction: Vector3<f32>,
+    last_direction: Vector3<f32>,
+    /// Color, material and birth of the pipe's first segment, with the transform cleared since tubes are
+    /// swept in the scene itself
+    pub instance: InstanceRaw,
+    pub bounds: Bounds,
+}
+
+impl Tube {
+    /// Points along a Catmull-Rom spline through the middles of the faces the pipe passes from block to
+    /// block by, from the face of the first block it comes in by to the face of the last it leaves by.
+    /// Straight runs stay straight, and turns round off about as wide as elbows but flow on into the next.
+    pub fn center_line(&self, cell_size: f32, tessellation: Tessellation) -> Vec<Point3<f32>> {
+        let half = cell_size * 0.5;
+        // every span of the spline is a block long
+        let samples = ((tessellation.segments_per_unit as f32 * cell_size).round() as u32).max(1);
+        let mut controls = Vec::with_capacity(self.centers.len() + 1);
+        controls.push(self.centers[0] - self.first_direction * half);
+        controls.extend(self.centers.windows(2).map(|pair| pair[0].midpoint(pair[1])));
+        controls.push(self.centers[self.centers.len() - 1] + self.last_direction * half);
+
+        // the spline needs a point before the first and after the last, which carry on in a straight line
+        let n = controls.len();
+        let control = |i: isize| match i {
+            -1 => controls[0] + (controls[0] - controls[1]),
+            i if i as usize >= n => controls[n - 1] + (controls[n - 1] - controls[n - 2]),
+            i => controls[i as usize],
+        };
+        let mut points = vec![controls[0]];
+        for span in 0..n as isize - 1 {
+            let (p0, p1, p2, p3) = (control(span - 1), control(span), control(span + 1), control(span + 2));
+            for sample in 1..=samples {
+                let t = sample as f32 / samples as f32;
+                points.push(catmull_rom(p0, p1, p2, p3, t));
+            }
+        }
+        points
+    }
+}
+
+/// The point `t` of the way from `p1` to `p2` on a uniform Catmull-Rom spline
+fn catmull_rom(p0: Point3<f32>, p1: Point3<f32>, p2: Point3<f32>, p3: Point3<f32>, t: f32) -> Point3<f32> {
+    let (t2, t3) = (t * t, t * t * t);
+    let weights = [
+        -0.5 * t3 + t2 - 0.5 * t,
+        1.5 * t3 - 2.5 * t2 + 1.0,
+        -1.5 * t3 + 2.0 * t2 + 0.5 * t,
+        0.5 * t3 - 0.5 * t2,
+    ];
+    let mut point = Vector3::new(0.0, 0.0, 0.0);
+    for (p, weight) in [p0, p1, p2, p3].into_iter().zip(weights) {
+        point += Vector3::new(p.x, p.y, p.z) * weight;
+    }
+    Point3::new(point.x, point.y, point.z)
+}
+
+/// A tube `radius` thick and `sides` sided around `center_line`, closed at both ends. The rings around the
+/// line are turned along with it by parallel transport, so that they don't twist where the line turns.
+pub fn sweep(center_line: &[Point3<f32>], radius: f32, sides: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    if center_line.len() < 2 {
+        return (vertices, indices);
+    }
+    let last = center_line.len() - 1;
+    let tangent = |i: usize| (center_line[(i + 1).min(last)] - center_line[i.saturating_sub(1)]).normalize();
+
+    // any direction across the first tangent starts the rings off
+    let first = tangent(0);
+    let across = match first.x.abs() < 0.9 {
+        true => Vector3::unit_x(),
+        false => Vector3::unit_y(),
+    };
+    let mut normal = (across - first * across.dot(first)).normalize();
+    for (i, center) in center_line.iter().enumerate() {
+        let tangent = tangent(i);
+        normal = (normal - tangent * normal.dot(tangent)).normalize();
+        let binormal = tangent.cross(normal);
+        for side in 0..sides {
+            let angle = side as f32 / sides as f32 * std::f32::consts::TAU;
+            let outwards = normal * angle.cos() + binormal * angle.sin();
+            vertices.push(ModelVertex {
+                position: (center + outwards * radius).into(),
+                normal: outwards.into(),
+            });
+        }
+    }
+    for ring in 0..last as u32 {
+        for side in 0..sides {
+            let next_side = (side + 1) % sides;
+            let (a, b) = (ring * sides + side, ring * sides + next_side);
+            let (c, d) = (a + sides, b + sides);
+            indices.extend_from_slice(&[a, b, c, b, d, c]);
+        }
+    }
+
+    // flat disks over both ends, with vertices of their own to face along the line
+    for (end, facing) in [(0, -tangent(0)), (last, tangent(last))] {
+        let center_index = vertices.len() as u32;
+        vertices.push(ModelVertex {
+            position: center_line[end].into(),
+            normal: facing.into(),
+        });
+        for side in 0..sides {
+            let mut vertex = vertices[end * sides as usize + side as usize];
+            vertex.normal = facing.into();
+            vertices.push(vertex);
+        }
+        for side in 0..sides {
+            let (a, b) = (center_index + 1 + side, center_index + 1 + (side + 1) % sides);
+            match end == 0 {
+                true => indices.extend_from_slice(&[center_index, b, a]),
+                false => indices.extend_from_slice(&[center_index, a, b]),
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+/// Pipes followed block by block from the instances of their segments, to draw as tubes, see
+/// `Config::pipe_style`
+#[derive(Clone, Debug, Default)]
+pub struct Tubes {
+    tubes: Vec<Tube>,
+    // the tube which grows on into each block, by the block and the segment it would be there. Teams grow
+    // several pipes at once, which may head for the same block.
+    heads: HashMap<((i32, i32, i32), u32), usize>,
+    // tubes which grew since the last upload
+    changed: Vec<usize>,
+    is_cleared: bool,
+}
+
+impl Tubes {
+    pub fn clear(&mut self) {
+        self.tubes.clear();
+        self.heads.clear();
+        self.changed.clear();
+        self.is_cleared = true;
+    }
+
+    /// Follow the pipes through the straight pipes and elbows grown since the last call, in blocks
+    /// `cell_size` apart
+    pub fn append(&mut self, i_instances: &[InstanceRaw], l_instances: &[InstanceRaw], cell_size: f32) {
+        let mut segments: Vec<_> = i_instances
+            .iter()
+            .map(|i| (i, false))
+            .chain(l_instances.iter().map(|l| (l, true)))
+            .map(|(instance, is_elbow)| (instance, Selection::of_instance(instance, is_elbow, cell_size)))
+            .filter(|(_, selection)| selection.kind != SegmentKind::Cap)
+            .collect();
+        // every pipe's segments in the order they grew, even if straight pipes and elbows come apart
+        segments.sort_by(|(a, _), (b, _)| a.flow_phase().total_cmp(&b.flow_phase()));
+        for (instance, selection) in segments {
+            let segment = instance.flow_phase() as u32;
+            let center = selection.block_center();
+            let mut direction = selection.direction.vector();
+            let tube = match self.heads.remove(&(selection.block, segment)) {
+                Some(index) if segment > 0 => {
+                    let tube = &mut self.tubes[index];
+                    // elbows look the same turned either way, so which of their open ends is the way out
+                    // depends on the way in
+                    if selection.kind == SegmentKind::Elbow && direction.dot(tube.last_direction) < -0.5 {
+                        direction = -selection.entry.vector();
+                    }
+                    tube.centers.push(center);
+                    tube.last_direction = direction;
+                    tube.bounds = tube.bounds.union(block_bounds(center, cell_size));
+                    index
+                }
+                _ => {
+                    self.tubes.push(Tube {
+                        centers: vec![center],
+                        first_direction: selection.entry.vector(),
+                        last_direction: direction,
+                        instance: instance.without_transform(),
+                        bounds: block_bounds(center, cell_size),
+                    });
+                    self.tubes.len() - 1
+                }
+            };
+            let (x, y, z) = selection.block;
+            let next = (x + direction.x as i32, y + direction.y as i32, z + direction.z as i32);
+            self.heads.insert((next, segment + 1), tube);
+            if !self.changed.contains(&tube) {
+                self.changed.push(tube);
+            }
+        }
+    }
+
+    /// Have every tube swept again on the next upload, e.g. more finely
+    pub fn mark_all_changed(&mut self) {
+        self.changed = (0..self.tubes.len()).collect();
+    }
+
+    /// Whether all tubes went away since the last call, and the tubes which grew since then with their
+    /// index
+    pub fn take_changes(&mut self) -> (bool, Vec<(usize, &Tube)>) {
+        let is_cleared = std::mem::take(&mut self.is_cleared);
+        let changed = std::mem::take(&mut self.changed);
+        (is_cleared, changed.into_iter().map(|i| (i, &self.tubes[i])).collect())
+    }
+}
+
+/// Around a block whose center is at `center`
+fn block_bounds(center: Point3<f32>, cell_size: f32) -> Bounds {
+    let half = Vector3::new(1.0, 1.0, 1.0) * cell_size * 0.5;
+    Bounds {
+        min: center - half,
+        max: center + half,
+    }
+}
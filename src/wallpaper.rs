@@ -0,0 +1,170 @@
+use winit::event_loop::ActiveEventLoop;
+use winit::monitor::MonitorHandle;
+use winit::window::{Fullscreen, Window, WindowAttributes};
+
+use crate::identity;
+
+/// Wallpapers don't need to be smooth, keep them cheap
+pub const WALLPAPER_FPS: f32 = 15.0;
+
+/// Attributes of a borderless fullscreen window meant to sit below the desktop icons
+pub fn window_attributes(event_loop: &ActiveEventLoop, monitor: Option<MonitorHandle>) -> WindowAttributes {
+    let attributes = identity::window_attributes(crate::TITLE)
+        .with_decorations(false)
+        .with_fullscreen(Some(Fullscreen::Borderless(monitor)));
+    platform::window_attributes(event_loop, attributes)
+}
+
+/// Move an already created window behind the desktop icons, where the platform needs it
+pub fn attach(window: &Window) -> anyhow::Result<()> {
+    platform::attach(window)
+}
+
+/// Whether another application is running fullscreen on top of the wallpaper, in which case rendering
+/// is a waste of power. Platforms without a check rely on `WindowEvent::Occluded` instead.
+pub fn is_covered_by_fullscreen_app(window: &Window) -> bool {
+    platform::is_covered_by_fullscreen_app(window)
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use anyhow::{Context, bail};
+    use windows_sys::Win32::Foundation::{HWND, LPARAM, RECT};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        EnumWindows, FindWindowExW, FindWindowW, GetDesktopWindow, GetForegroundWindow, GetShellWindow, GetWindowRect,
+        SMTO_NORMAL, SendMessageTimeoutW, SetParent,
+    };
+    use windows_sys::core::BOOL;
+    use winit::event_loop::ActiveEventLoop;
+    use winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
+    use winit::window::{Window, WindowAttributes};
+
+    /// Undocumented message asking Progman to spawn a WorkerW window between the icons and the wallpaper
+    const SPAWN_WORKERW: u32 = 0x052C;
+
+    pub fn window_attributes(_event_loop: &ActiveEventLoop, attributes: WindowAttributes) -> WindowAttributes {
+        attributes
+    }
+
+    pub fn attach(window: &Window) -> anyhow::Result<()> {
+        let hwnd = hwnd_of(window)?;
+        let progman_class = wide("Progman");
+        unsafe {
+            let progman = FindWindowW(progman_class.as_ptr(), std::ptr::null());
+            if progman.is_null() {
+                bail!("Progman window not found");
+            }
+            let mut result = 0;
+            SendMessageTimeoutW(progman, SPAWN_WORKERW, 0, 0, SMTO_NORMAL, 1000, &mut result);
+
+            let mut worker_w: HWND = std::ptr::null_mut();
+            EnumWindows(Some(find_worker_w), &mut worker_w as *mut HWND as LPARAM);
+            // Newer Windows 11 builds host the WorkerW inside Progman instead
+            let parent = if worker_w.is_null() { progman } else { worker_w };
+            if SetParent(hwnd, parent).is_null() {
+                bail!("Cannot attach window to the desktop");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_covered_by_fullscreen_app(window: &Window) -> bool {
+        let Ok(hwnd) = hwnd_of(window) else {
+            return false;
+        };
+        unsafe {
+            let foreground = GetForegroundWindow();
+            if foreground.is_null()
+                || foreground == hwnd
+                || foreground == GetDesktopWindow()
+                || foreground == GetShellWindow()
+            {
+                return false;
+            }
+            let mut ours: RECT = std::mem::zeroed();
+            let mut theirs: RECT = std::mem::zeroed();
+            if GetWindowRect(hwnd, &mut ours) == 0 || GetWindowRect(foreground, &mut theirs) == 0 {
+                return false;
+            }
+            theirs.left <= ours.left
+                && theirs.top <= ours.top
+                && theirs.right >= ours.right
+                && theirs.bottom >= ours.bottom
+        }
+    }
+
+    unsafe extern "system" fn find_worker_w(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let shell_view_class = wide("SHELLDLL_DefView");
+        let worker_w_class = wide("WorkerW");
+        unsafe {
+            let shell_view = FindWindowExW(hwnd, std::ptr::null_mut(), shell_view_class.as_ptr(), std::ptr::null());
+            if !shell_view.is_null() {
+                // The WorkerW we want is the sibling right after the one holding the icons
+                *(lparam as *mut HWND) =
+                    FindWindowExW(std::ptr::null_mut(), hwnd, worker_w_class.as_ptr(), std::ptr::null());
+                return 0;
+            }
+        }
+        1
+    }
+
+    fn hwnd_of(window: &Window) -> anyhow::Result<HWND> {
+        match window.window_handle().context("Window handle unavailable")?.as_raw() {
+            RawWindowHandle::Win32(handle) => Ok(handle.hwnd.get() as HWND),
+            _ => bail!("Not a Win32 window"),
+        }
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))))]
+mod platform {
+    use log::warn;
+    use winit::event_loop::ActiveEventLoop;
+    use winit::platform::x11::{ActiveEventLoopExtX11, WindowAttributesExtX11, WindowType};
+    use winit::window::{Window, WindowAttributes};
+
+    pub fn window_attributes(event_loop: &ActiveEventLoop, attributes: WindowAttributes) -> WindowAttributes {
+        if event_loop.is_x11() {
+            // Window managers keep desktop windows below everything else, icons included
+            attributes.with_x11_window_type(vec![WindowType::Desktop])
+        } else {
+            warn!("Layer-shell backgrounds are not available through winit, running as a regular fullscreen window");
+            attributes
+        }
+    }
+
+    pub fn attach(_window: &Window) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    pub fn is_covered_by_fullscreen_app(_window: &Window) -> bool {
+        false
+    }
+}
+
+#[cfg(not(any(
+    target_os = "windows",
+    all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")))
+)))]
+mod platform {
+    use log::warn;
+    use winit::event_loop::ActiveEventLoop;
+    use winit::window::{Window, WindowAttributes};
+
+    pub fn window_attributes(_event_loop: &ActiveEventLoop, attributes: WindowAttributes) -> WindowAttributes {
+        warn!("Wallpaper mode is not supported on this platform, running as a regular fullscreen window");
+        attributes
+    }
+
+    pub fn attach(_window: &Window) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    pub fn is_covered_by_fullscreen_app(_window: &Window) -> bool {
+        false
+    }
+}
@@ -1,40 +1,27 @@
-use std::collections::HashSet;
-use rand::seq::IndexedRandom;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::ops::Range;
+
+use anyhow::bail;
 use cgmath::Rotation3;
+use rand::rngs::StdRng;
+use rand::seq::IndexedRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
+use crate::generator::{self, Constraints, DirectionWeights, PipeGenerator, Step, Turn};
 use crate::instance::Instance;
+use crate::layout::Layout;
+use crate::material::{Material, Materials};
+use crate::palette::{self, Palette};
+use crate::region::Region;
 
-
-macro_rules! rgb {
-    ($r:expr, $g:expr, $b:expr) => {[ ($r as f32) / 256.0, ($g as f32) / 256.0, ($b as f32) / 256.0 ]};
-}
-
-const COLOR: &[[f32; 3]] = &[
-    rgb!(116, 222, 215),
-    rgb!(255, 0, 0),
-    rgb!(247, 104, 31),
-    rgb!(75, 151, 160),
-    rgb!(254, 211, 86),
-    rgb!(250, 231, 231),
-    rgb!(132, 123, 14),
-    rgb!(251, 155, 72),
-    rgb!(14, 169, 30),
-    rgb!(158, 235, 189),
-    rgb!(2, 143, 146)
-];
-
-fn random_color() -> &'static [f32; 3] {
-    let mut rng = rand::rng();
-    COLOR.choose(&mut rng).unwrap()
-}
-
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum PipeType {
     I,
     L,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Direction {
     X,
     Y,
@@ -44,38 +31,261 @@ pub enum Direction {
     _Z,
 }
 
-const ALL_DIRECTIONS: [Direction; 6] = [Direction::X, Direction::Y, Direction::Z, Direction::_X, Direction::_Y, Direction::_Z];
+const ALL_DIRECTIONS: [Direction; 6] = [
+    Direction::X,
+    Direction::Y,
+    Direction::Z,
+    Direction::_X,
+    Direction::_Y,
+    Direction::_Z,
+];
 const PERPENDICULAR_X: [Direction; 4] = [Direction::Y, Direction::_Y, Direction::Z, Direction::_Z];
 const PERPENDICULAR_Y: [Direction; 4] = [Direction::X, Direction::_X, Direction::Z, Direction::_Z];
 const PERPENDICULAR_Z: [Direction; 4] = [Direction::Y, Direction::_Y, Direction::X, Direction::_X];
 
 impl Direction {
-    fn random() -> Direction {
-        let mut rng = rand::rng();
-        *ALL_DIRECTIONS.choose(&mut rng).unwrap()
+    pub const ALL: [Direction; 6] = ALL_DIRECTIONS;
+
+    /// Any of the four directions at a right angle to this one
+    pub fn random_perpendicular(self, rng: &mut StdRng) -> Direction {
+        *self.perpendiculars().choose(rng).unwrap()
     }
 
-    fn random_perpendicular(self) -> Direction {
+    /// The four directions at a right angle to this one
+    pub fn perpendiculars(self) -> &'static [Direction] {
         use Direction::*;
-        let options = match self {
+        match self {
             X | _X => &PERPENDICULAR_X,
             Y | _Y => &PERPENDICULAR_Y,
             Z | _Z => &PERPENDICULAR_Z,
-        };
-        let mut rng = rand::rng();
-        *options.choose(&mut rng).unwrap()
+        }
+    }
+
+    /// The block next to `position` this way, out of any world when that would be below 0
+    pub fn neighbor(self, position: (u32, u32, u32)) -> (u32, u32, u32) {
+        use Direction::*;
+        let (x, y, z) = position;
+        match self {
+            X => (x + 1, y, z),
+            Y => (x, y + 1, z),
+            Z => (x, y, z + 1),
+            _X => (x.wrapping_sub(1), y, z),
+            _Y => (x, y.wrapping_sub(1), z),
+            _Z => (x, y, z.wrapping_sub(1)),
+        }
+    }
+
+    /// Whether both run along the same axis, either way
+    pub fn is_parallel_to(self, other: Direction) -> bool {
+        use Direction::*;
+        matches!((self, other), (X | _X, X | _X) | (Y | _Y, Y | _Y) | (Z | _Z, Z | _Z))
+    }
+
+    pub fn opposite(self) -> Direction {
+        use Direction::*;
+        match self {
+            X => _X,
+            Y => _Y,
+            Z => _Z,
+            _X => X,
+            _Y => Y,
+            _Z => Z,
+        }
+    }
+
+    pub fn vector(self) -> cgmath::Vector3<f32> {
+        use Direction::*;
+        match self {
+            X => cgmath::Vector3::unit_x(),
+            Y => cgmath::Vector3::unit_y(),
+            Z => cgmath::Vector3::unit_z(),
+            _X => -cgmath::Vector3::unit_x(),
+            _Y => -cgmath::Vector3::unit_y(),
+            _Z => -cgmath::Vector3::unit_z(),
+        }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-struct Block {
+// Kept for every block grown, so as small as it gets: positions fit in 16 bits as worlds are at most
+// MAX_SIZE blocks wide, and the enums in a byte each
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Block {
     pipe_type: PipeType,
     direction: Direction, // direction of output pipe
-    position: (u32, u32, u32),
+    position: (u16, u16, u16),
     color: [f32; 3],
+    emissive: f32,
+    segment: u32, // number of blocks before this one in its pipe
+    #[serde(default)]
+    material: Material,
+    // the last block of a pipe which reached the longest pipes may grow, closed with a cap
+    #[serde(default)]
+    is_capped: bool,
+    // index of the team the block's pipe belongs to, when teams race to fill the world
+    #[serde(default)]
+    team: Option<u8>,
+    // direction the pipe comes into the block by, which an elbow turns from. Missing from blocks of
+    // snapshots of before it was kept, which all continue the block placed before them.
+    #[serde(default)]
+    entry: Option<Direction>,
+    // taken out of the world again, see `World::erase`
+    #[serde(default)]
+    is_erased: bool,
 }
 
+impl Block {
+    pub fn position(&self) -> (u32, u32, u32) {
+        let p = self.position;
+        (p.0 as u32, p.1 as u32, p.2 as u32)
+    }
+
+    pub fn pipe_type(&self) -> PipeType {
+        self.pipe_type
+    }
+
+    /// Direction the pipe leaves the block in
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Blocks before this one in its pipe
+    pub fn segment(&self) -> u32 {
+        self.segment
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        self.color
+    }
+
+    /// Whether the pipe ends here with a cap
+    pub fn is_capped(&self) -> bool {
+        self.is_capped
+    }
+
+    /// Whether the segment was taken out of the world again, see `World::erase`
+    pub fn is_erased(&self) -> bool {
+        self.is_erased
+    }
+}
+
+// Blocks along each side of an occupancy bitmap, 4096 blocks in 64 words
+const OCCUPANCY_CHUNK: u32 = 16;
+
+/// Which blocks are taken, as bitmaps of `OCCUPANCY_CHUNK`³ blocks each. A bitmap is only allocated once a
+/// block of it is taken, so big worlds cost memory only where pipes grow.
+#[derive(Default)]
+struct Occupancy {
+    chunks: HashMap<(u32, u32, u32), Box<[u64; 64]>>,
+    len: usize,
+}
+
+impl Occupancy {
+    fn locate(position: &(u32, u32, u32)) -> ((u32, u32, u32), usize) {
+        let c = OCCUPANCY_CHUNK;
+        let key = (position.0 / c, position.1 / c, position.2 / c);
+        let bit = (position.0 % c + position.1 % c * c + position.2 % c * c * c) as usize;
+        (key, bit)
+    }
+
+    fn contains(&self, position: &(u32, u32, u32)) -> bool {
+        let (key, bit) = Self::locate(position);
+        self.chunks
+            .get(&key)
+            .is_some_and(|bits| bits[bit / 64] & 1 << (bit % 64) != 0)
+    }
+
+    fn insert(&mut self, position: (u32, u32, u32)) {
+        let (key, bit) = Self::locate(&position);
+        let word = &mut self.chunks.entry(key).or_insert_with(|| Box::new([0; 64]))[bit / 64];
+        if *word & 1 << (bit % 64) == 0 {
+            *word |= 1 << (bit % 64);
+            self.len += 1;
+        }
+    }
+
+    fn remove(&mut self, position: &(u32, u32, u32)) {
+        let (key, bit) = Self::locate(position);
+        if let Some(bits) = self.chunks.get_mut(&key)
+            && bits[bit / 64] & 1 << (bit % 64) != 0
+        {
+            bits[bit / 64] &= !(1 << (bit % 64));
+            self.len -= 1;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.chunks.clear();
+        self.len = 0;
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// One of the pipe networks racing to fill the world, see `World::set_teams`
+#[derive(Clone, Debug, Default)]
+struct Team {
+    // the last block of the pipe the team grows on, where it grows from on its next turn
+    head: Option<Block>,
+    last_turn: Option<Turn>,
+    // blocks the team took
+    segments: u32,
+    // the team's blocks which may still have a free neighbor to branch a new pipe off to
+    frontier: Vec<(u32, u32, u32)>,
+    // hemmed in by the others, with no free block left next to its network
+    is_out: bool,
+}
+
+/// Everything to grow a world on from where it was, e.g. after a restart
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    /// Every block grown since the last reset, in the order they grew
+    blocks: Vec<Block>,
+    pipe_count: u32,
+    /// The rng is reseeded with this when taking the snapshot, so the restored world grows on the same
+    seed: u64,
+    turn_probability: f32,
+    palette: String,
+    opacity: f32,
+}
+
+/// A change to the world which can be undone and redone, see `World::undo`
 #[derive(Clone, Debug)]
+enum Edit {
+    /// This block was added at the end of the history
+    AddBlock(Block),
+    /// The blocks at these indices of the history were erased
+    RemoveBlock(Vec<usize>),
+    /// The world started over from this one, which is only kept until the reset is undone
+    Reset(Option<Box<WorldSnapshot>>),
+}
+
+/// What undoing or redoing an edit changed in the world, to show
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// The blocks at these indices of the history are there again
+    Added(Vec<usize>),
+    /// The segments in these blocks went away
+    Removed(Vec<(u32, u32, u32)>),
+    /// The whole world is another one
+    Replaced,
+}
+
+/// How much has grown so far
+#[derive(Copy, Clone, Debug, Default)]
+pub struct WorldStats {
+    pub pipes: u32,
+    /// Blocks taken by pipes, every one of them a segment
+    pub segments: u32,
+    /// Segments which are corners
+    pub turns: u32,
+    /// Share of the world taken by pipes, from 0 to 1
+    pub fill: f32,
+    /// What the world's randomness was last started from, the one the world grew from unless it was restored
+    pub seed: u64,
+}
+
 pub struct World {
     max_x_block: u32,
     max_y_block: u32,
@@ -83,21 +293,90 @@ pub struct World {
 
     turn_probability: f32,
     stop_probability: f32,
+    palette: &'static Palette,
+    opacity: f32,
+    materials: Materials,
+    // distance between the centers of neighboring blocks in the scene, which instances are spaced and sized by
+    cell_size: f32,
+    // how far segments stray from the grid, from 0 for none to 1
+    jitter: f32,
+    // how far segments' colors stray from their pipe's, from 0 for none to 1
+    color_jitter: f32,
+    generator: Box<dyn PipeGenerator>,
+    // how much pipes prefer to start and turn in each direction
+    direction_weights: DirectionWeights,
+    // turns ruled out or discouraged, none unless set
+    constraints: Constraints,
+    // blocks ahead checked for room before going somewhere, none unless set
+    look_ahead: u32,
+    // segments a pipe grows at most before it is capped and a new one starts, no limit when None
+    max_pipe_length: Option<u32>,
 
-    i_pipe_instances: Vec<Instance>,
-    l_pipe_instances: Vec<Instance>,
-
-    occupied_blocks: HashSet<(u32, u32, u32)>,
+    occupied_blocks: Occupancy,
     last_block: Option<Block>,
+    // where the pipe of the last block last turned
+    last_turn: Option<Turn>,
+    // blocks laid out by hand, grown in this order instead of random pipes, see `layout::Layout`
+    layout: Option<Vec<Block>>,
+    // the competing pipe networks, empty unless teams race to fill the world
+    teams: Vec<Team>,
+    // index of the team growing next
+    team: usize,
+    // every block since the last reset, to snapshot the world with and build instances from
+    history: Vec<Block>,
+    // edits to undo, the latest last, and those undone to redo, the latest undone last
+    undo_log: VecDeque<Edit>,
+    redo_log: Vec<Edit>,
+    pipe_count: u32,
+    turn_count: u32,
+    // the only blocks pipes may grow in, anywhere in the world when None
+    mask: Option<BTreeSet<(u32, u32, u32)>>,
+    // the shape pipes grow inside of, the whole world when None, and the share of the world it takes
+    region: Option<Region>,
+    region_share: f32,
+
+    // every random choice goes through here, so that a seed always grows the same world
+    rng: StdRng,
+    // what rng was last started from, at the last reset, snapshot or restore
+    seed: u64,
 }
 
-const WORLD_X: u32 = 30;
-const WORLD_Y: u32 = 30;
-const WORLD_Z: u32 = 30;
-const TURN_PROBABILITY: f32 = 0.3;
+/// Blocks along each side of the world by default
+pub const DEFAULT_SIZE: u32 = 30;
+/// The most blocks along each side of a world, as far as `Block` positions reach
+pub const MAX_SIZE: u32 = 4096;
+
+/// Blocks along X, Y and Z of a world `size` blocks a side stretched to fill a view `aspect` times as wide
+/// as it's high, seen from the front: wider along X for a wide view and taller along Y for a tall one
+pub fn extents_for_aspect(size: u32, aspect: f32) -> [u32; 3] {
+    let stretched = |ratio: f32| ((size as f32 * ratio).round() as u32).clamp(size, MAX_SIZE);
+    [stretched(aspect), stretched(1.0 / aspect), size]
+}
+pub const TURN_PROBABILITY: f32 = 0.3;
 const STOP_PROBABILITY: f32 = 0.0;
+const RANDOM_BLOCK_ATTEMPTS: u32 = 1000;
+// edits kept to undo, the oldest are forgotten beyond this many
+const MAX_EDITS: usize = 10_000;
+/// The most teams racing to fill a world
+pub const MAX_TEAMS: u32 = 8;
+/// The most blocks pipes look ahead, as checking gets slow beyond and a pipe could meet itself
+pub const MAX_LOOK_AHEAD: u32 = 3;
+// End caps are straight pipe meshes squashed into a flange at the very end of the last block
+const CAP_SCALE: cgmath::Vector3<f32> = cgmath::Vector3::new(1.25, 0.2, 1.25);
+const CAP_OFFSET: f32 = 0.4;
+// At a jitter of 1, how far segments stray from the center of their block in blocks, how many degrees they
+// tilt and how much thicker or thinner straight pipes get
+const JITTER_OFFSET: f32 = 0.06;
+const JITTER_TILT: f32 = 4.0;
+const JITTER_RADIUS: f32 = 0.15;
+// At a color jitter of 1, how much more or less saturated and how much brighter or darker segments get
+const COLOR_JITTER_SATURATION: f32 = 0.3;
+const COLOR_JITTER_VALUE: f32 = 0.2;
+// mixed into the seed of a block's color jitter, so that it doesn't follow the block's jitter
+const COLOR_JITTER_SALT: u64 = 0x9e37_79b9_7f4a_7c15;
 
 /**
+```text
     World coordinate system
     X: to the right
     Y: to the top
@@ -114,124 +393,960 @@ const STOP_PROBABILITY: f32 = 0.0;
 
     I pipe: follow Y
     L pipe: follow Y and X
+```
 */
 impl World {
     pub fn new() -> Self {
+        Self::with_seed(random_seed(&mut StdRng::from_os_rng()))
+    }
+
+    /// A world which grows the exact same pipes every time for the same seed
+    pub fn with_seed(seed: u64) -> Self {
         Self {
-            // TODO consider scale to screen ratio
-            max_x_block: WORLD_X,
-            max_y_block: WORLD_Y,
-            max_z_block: WORLD_Z,
+            max_x_block: DEFAULT_SIZE,
+            max_y_block: DEFAULT_SIZE,
+            max_z_block: DEFAULT_SIZE,
             turn_probability: TURN_PROBABILITY,
             stop_probability: STOP_PROBABILITY,
-            i_pipe_instances: vec![],
-            l_pipe_instances: vec![],
-            occupied_blocks: HashSet::with_capacity(128),
+            palette: &palette::CLASSIC,
+            opacity: 1.0,
+            materials: Materials::default(),
+            cell_size: 1.0,
+            jitter: 0.0,
+            color_jitter: 0.0,
+            generator: Box::new(generator::Classic),
+            max_pipe_length: None,
+            direction_weights: DirectionWeights::default(),
+            constraints: Constraints::NONE,
+            look_ahead: 0,
+            occupied_blocks: Occupancy::default(),
             last_block: None,
+            last_turn: None,
+            layout: None,
+            teams: vec![],
+            team: 0,
+            history: vec![],
+            undo_log: VecDeque::new(),
+            redo_log: vec![],
+            pipe_count: 0,
+            turn_count: 0,
+            mask: None,
+            region: None,
+            region_share: 1.0,
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+        }
+    }
+
+    /// Blocks grown since the last reset
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Every block grown since the last reset, in the order they grew
+    pub fn blocks(&self) -> &[Block] {
+        &self.history
+    }
+
+    /// Instances of the straight pipes and of the elbows grown since the first `start` blocks, built
+    /// from the blocks on demand rather than kept around for the whole world
+    pub fn instances_since(&self, start: usize) -> (Vec<Instance>, Vec<Instance>) {
+        self.instances_between(start..self.history.len())
+    }
+
+    /// Instances of the blocks grown `blocks` into the world, in the order they were placed, e.g. to
+    /// replay how it grew
+    pub fn instances_between(&self, blocks: Range<usize>) -> (Vec<Instance>, Vec<Instance>) {
+        self.instances_of(blocks.start..blocks.end.min(self.history.len()))
+    }
+
+    /// Instances of the blocks at `indices` of the history, other than erased ones
+    pub fn instances_of(&self, indices: impl IntoIterator<Item = usize>) -> (Vec<Instance>, Vec<Instance>) {
+        let mut i_instances = vec![];
+        let mut l_instances = vec![];
+        for (index, block) in indices
+            .into_iter()
+            .map(|index| (index, &self.history[index]))
+            .filter(|(_, block)| !block.is_erased)
+        {
+            match block.pipe_type {
+                PipeType::I => {
+                    i_instances.push(self.jittered(self.tinted(self.i_instance_at_block(block), block), block, false))
+                }
+                PipeType::L => {
+                    // teams grow their pipes in turns, so the block placed before an elbow may be another pipe's
+                    let entry = block.entry.unwrap_or_else(|| self.history[index - 1].direction);
+                    l_instances.push(self.jittered(
+                        self.tinted(self.l_instance_at_block(block, entry), block),
+                        block,
+                        true,
+                    ));
+                }
+            }
+            if block.is_capped {
+                i_instances.push(self.jittered(self.tinted(self.cap_instance_at_block(block), block), block, false));
+            }
+        }
+        (i_instances, l_instances)
+    }
+
+    /// Blocks along each side of the world, or along Z for one stretched to fill a view, see
+    /// `extents_for_aspect`
+    pub fn size(&self) -> u32 {
+        self.max_z_block
+    }
+
+    /// Blocks along each side of the world pipes grow in from now on, capped at `MAX_SIZE`
+    pub fn set_size(&mut self, size: u32) {
+        self.set_extents([size; 3]);
+    }
+
+    /// Blocks along X, Y and Z
+    pub fn extents(&self) -> [u32; 3] {
+        [self.max_x_block, self.max_y_block, self.max_z_block]
+    }
+
+    /// Blocks along X, Y and Z of the world pipes grow in from now on, each capped at `MAX_SIZE`. Pipes
+    /// outside of a smaller world stay.
+    pub fn set_extents(&mut self, extents: [u32; 3]) {
+        let [x, y, z] = extents.map(|extent| extent.min(MAX_SIZE));
+        self.max_x_block = x;
+        self.max_y_block = y;
+        self.max_z_block = z;
+        self.region_share = self.region.map_or(1.0, |region| region.share(self.extents()));
+    }
+
+    pub fn palette(&self) -> &'static Palette {
+        self.palette
+    }
+
+    /// Colors of pipes added from now on
+    pub fn set_palette(&mut self, palette: &'static Palette) {
+        self.palette = palette;
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    /// Opacity of pipes added from now on, 1 is solid
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    /// Space and size instances built from now on to be `size` apart from block to block, see `Config::cell_size`
+    pub fn set_cell_size(&mut self, size: f32) {
+        self.cell_size = size;
+    }
+
+    /// Nudge, tilt and thicken or thin instances built from now on a little for a hand-made look, from 0
+    /// for perfectly regular plumbing to 1, see `Config::jitter`. Only the instances stray, the blocks stay
+    /// on the grid.
+    pub fn set_jitter(&mut self, jitter: f32) {
+        self.jitter = jitter;
+    }
+
+    /// Make segments of instances built from now on a little more or less saturated and brighter or darker
+    /// than their pipe, breaking up long straight runs, from 0 for one flat color a pipe to 1, see
+    /// `Config::color_jitter`. The blocks keep their pipe's color, so pipes don't drift away from it.
+    pub fn set_color_jitter(&mut self, jitter: f32) {
+        self.color_jitter = jitter;
+    }
+
+    /// What pipes added from now on are made of
+    pub fn set_materials(&mut self, materials: Materials) {
+        self.materials = materials;
+    }
+
+    /// Shape of pipes grown from now on
+    pub fn set_generator(&mut self, generator: Box<dyn PipeGenerator>) {
+        self.generator = generator;
+    }
+
+    pub fn turn_probability(&self) -> f32 {
+        self.turn_probability
+    }
+
+    /// Segments pipes grow at most, `None` for as long as they find room
+    pub fn max_pipe_length(&self) -> Option<u32> {
+        self.max_pipe_length
+    }
+
+    /// Cap pipes once they grew this many segments and start a new one, or let them grow for as long as
+    /// they find room for `None`
+    pub fn set_max_pipe_length(&mut self, length: Option<u32>) {
+        self.max_pipe_length = length;
+    }
+
+    pub fn direction_weights(&self) -> DirectionWeights {
+        self.direction_weights
+    }
+
+    /// Have pipes start and turn in some directions more than in others from now on
+    pub fn set_direction_weights(&mut self, weights: DirectionWeights) {
+        self.direction_weights = weights;
+    }
+
+    pub fn constraints(&self) -> Constraints {
+        self.constraints
+    }
+
+    /// Rule out or discourage some turns from now on
+    pub fn set_constraints(&mut self, constraints: Constraints) {
+        self.constraints = constraints;
+    }
+
+    pub fn look_ahead(&self) -> u32 {
+        self.look_ahead
+    }
+
+    /// Have pipes check this many blocks ahead from now on and steer clear of dead ends, up to
+    /// `MAX_LOOK_AHEAD`, or go wherever the generator says for 0
+    pub fn set_look_ahead(&mut self, depth: u32) {
+        self.look_ahead = depth.min(MAX_LOOK_AHEAD);
+    }
+
+    /// Chance of a pipe to turn at every block, from 0 to 1
+    pub fn set_turn_probability(&mut self, probability: f32) {
+        self.turn_probability = probability.clamp(0.0, 1.0);
+    }
+
+    /// Only grow pipes in these blocks from now on, or anywhere for `None`. Pipes already grown stay.
+    pub fn set_mask(&mut self, mask: Option<BTreeSet<(u32, u32, u32)>>) {
+        self.mask = mask;
+    }
+
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+
+    /// Only grow pipes inside `region` from now on, or anywhere for `None`. Pipes already grown stay.
+    pub fn set_region(&mut self, region: Option<Region>) {
+        self.region = region;
+        self.region_share = region.map_or(1.0, |region| region.share(self.extents()));
+    }
+
+    /// Start over with an empty world, which can be undone
+    pub fn reset(&mut self) {
+        if !self.history.is_empty() {
+            let snapshot = self.take_snapshot();
+            self.record(Edit::Reset(Some(snapshot)));
+        }
+        self.start_over();
+    }
+
+    /// Start over like `reset` does, growing from `seed` rather than one drawn from the old seed
+    pub fn reset_with_seed(&mut self, seed: u64) {
+        self.reset();
+        self.reseed(seed);
+    }
+
+    fn start_over(&mut self) {
+        self.occupied_blocks.clear();
+        self.last_block = None;
+        self.last_turn = None;
+        self.history.clear();
+        self.pipe_count = 0;
+        self.turn_count = 0;
+        self.teams.fill(Team::default());
+        self.team = 0;
+        // The new world gets a seed of its own, drawn from the old one so that a seed still grows the same
+        let seed = random_seed(&mut self.rng);
+        self.reseed(seed);
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.seed = seed;
+    }
+
+    /// The world as it is now. Reseeds the rng, which can't be saved as it is.
+    pub fn snapshot(&mut self) -> WorldSnapshot {
+        let seed = random_seed(&mut self.rng);
+        self.reseed(seed);
+        WorldSnapshot {
+            blocks: self.history.clone(),
+            pipe_count: self.pipe_count,
+            seed,
+            turn_probability: self.turn_probability,
+            palette: self.palette.name.to_string(),
+            opacity: self.opacity,
+        }
+    }
+
+    /// Replace the world with a snapshot, to grow on exactly like the world it was taken of. What was done
+    /// to the world before can't be undone anymore.
+    pub fn restore(&mut self, snapshot: &WorldSnapshot) {
+        self.turn_probability = snapshot.turn_probability;
+        self.palette = palette::find(&snapshot.palette).unwrap_or(&palette::CLASSIC);
+        self.opacity = snapshot.opacity;
+        self.replace(snapshot);
+        self.undo_log.clear();
+        self.redo_log.clear();
+    }
+
+    /// The history with what it takes to grow on from it, which is taken out of the world
+    fn take_snapshot(&mut self) -> Box<WorldSnapshot> {
+        Box::new(WorldSnapshot {
+            blocks: std::mem::take(&mut self.history),
+            pipe_count: self.pipe_count,
+            seed: self.seed,
+            turn_probability: self.turn_probability,
+            palette: self.palette.name.to_string(),
+            opacity: self.opacity,
+        })
+    }
+
+    /// Start over with the blocks of `snapshot` placed again
+    fn replace(&mut self, snapshot: &WorldSnapshot) {
+        self.start_over();
+        self.reseed(snapshot.seed);
+        for block in &snapshot.blocks {
+            self.place(*block);
+            if block.is_erased {
+                self.occupied_blocks.remove(&block.position());
+            }
+        }
+        self.pipe_count = snapshot.pipe_count;
+        // the team after the one which grew last goes on
+        if let Some(team) = snapshot.blocks.last().and_then(|block| block.team) {
+            self.team = (team as usize + 1) % self.teams.len().max(1);
+        }
+    }
+
+    pub fn stats(&self) -> WorldStats {
+        WorldStats {
+            pipes: self.pipe_count,
+            segments: self.occupied_blocks.len() as u32,
+            turns: self.turn_count,
+            fill: self.occupied_blocks.len() as f32 / self.capacity() as f32,
+            seed: self.seed,
         }
     }
 
-    pub fn get_I_pipe_instances(&self) -> &[Instance] {
-        self.i_pipe_instances.as_slice()
+    /// Blocks pipes may grow in, at least 1
+    fn capacity(&self) -> usize {
+        let capacity = match &self.mask {
+            Some(mask) => mask.len(),
+            // positions run from 0 to the max block, both included
+            None => {
+                let blocks =
+                    (self.max_x_block as usize + 1) * (self.max_y_block as usize + 1) * (self.max_z_block as usize + 1);
+                (blocks as f32 * self.region_share) as usize
+            }
+        };
+        capacity.max(1)
+    }
+
+    /// Grow the pipes of a layout a block at a time until they are all there, rather than random ones,
+    /// or random ones again for `None`. Set before anything grew, as every reset grows it over again.
+    /// Fails for a layout which doesn't fit into the world or runs into itself.
+    pub fn set_layout(&mut self, layout: Option<&Layout>) -> anyhow::Result<()> {
+        let Some(layout) = layout else {
+            self.layout = None;
+            return Ok(());
+        };
+        let mut blocks = vec![];
+        let mut taken = HashSet::new();
+        for (i, pipe) in layout.pipes.iter().enumerate() {
+            let color = self.palette.colors[i % self.palette.colors.len()];
+            let mut position = pipe.start;
+            let mut direction = pipe.moves.first().map_or(Direction::Y, |(direction, _)| *direction);
+            let steps = pipe
+                .moves
+                .iter()
+                .flat_map(|(direction, count)| std::iter::repeat_n(Some(*direction), *count as usize));
+            // a block leaves towards the next move, and the last one goes on the way it came
+            for (segment, next) in steps.chain([None]).enumerate() {
+                let is_in_world =
+                    position.0 <= self.max_x_block && position.1 <= self.max_y_block && position.2 <= self.max_z_block;
+                if !is_in_world || !taken.insert(position) {
+                    bail!(
+                        "Pipe on line {} runs into something or out of the world at {:?}",
+                        pipe.line,
+                        position
+                    );
+                }
+                let out = next.unwrap_or(direction);
+                let pipe_type = if out != direction { PipeType::L } else { PipeType::I };
+                let is_capped = next.is_none();
+                let block_position = (position.0 as u16, position.1 as u16, position.2 as u16);
+                blocks.push(Block {
+                    pipe_type,
+                    direction: out,
+                    position: block_position,
+                    color,
+                    emissive: 0.0,
+                    segment: segment as u32,
+                    material: self.materials.material,
+                    is_capped,
+                    team: None,
+                    entry: Some(direction),
+                    is_erased: false,
+                });
+                position = out.neighbor(position);
+                direction = out;
+            }
+        }
+        self.layout = Some(blocks);
+        Ok(())
+    }
+
+    /// Whether the world grows the pipes of a layout rather than random ones
+    pub fn has_layout(&self) -> bool {
+        self.layout.is_some()
+    }
+
+    /// Have this many teams race to fill the world, each growing a pipe at a time in a color of its own
+    /// and taking turns, or grow one pipe after another for 0. Up to `MAX_TEAMS`, best set before growing.
+    pub fn set_teams(&mut self, count: u32) {
+        self.teams = vec![Team::default(); count.min(MAX_TEAMS) as usize];
+        self.team = 0;
+    }
+
+    /// Share of the world each team took so far, from 0 to 1, empty without teams
+    pub fn team_fills(&self) -> Vec<f32> {
+        let capacity = self.capacity() as f32;
+        self.teams.iter().map(|team| team.segments as f32 / capacity).collect()
     }
 
-    pub fn get_L_pipe_instances(&self) -> &[Instance] {
-        self.l_pipe_instances.as_slice()
+    /// The block every pipe still growing ended at so far and which way it goes on: the newest pipe, or
+    /// with teams, the pipe of every team still in the race
+    pub fn heads(&self) -> Vec<((u32, u32, u32), Direction)> {
+        let heads = if self.teams.is_empty() {
+            vec![self.last_block]
+        } else {
+            self.teams
+                .iter()
+                .filter(|team| !team.is_out)
+                .map(|team| team.head)
+                .collect()
+        };
+        heads
+            .into_iter()
+            .flatten()
+            .filter(|block| !block.is_capped)
+            .map(|block| (block.position(), block.direction))
+            .collect()
     }
 
-    pub fn add_pipe(&mut self) {
-        let block = if rand::random::<f32>() < self.stop_probability || self.last_block.is_none() {
+    /// Index of the team which took the most of the world so far, `None` without teams
+    pub fn leading_team(&self) -> Option<usize> {
+        (0..self.teams.len()).max_by_key(|team| self.teams[*team].segments)
+    }
+
+    /// Returns false when no free block is left to start a new pipe from, or with teams, when every team
+    /// is hemmed in
+    pub fn add_pipe(&mut self) -> bool {
+        if let Some(layout) = &self.layout {
+            let Some(block) = layout.get(self.history.len()).copied() else {
+                return false;
+            };
+            if block.segment == 0 {
+                self.pipe_count += 1;
+            }
+            self.place(block);
+            self.record(Edit::AddBlock(block));
+            return true;
+        }
+        if self.teams.is_empty() {
+            return self.add_segment();
+        }
+        // teams take turns, each growing on its own pipe, and those hemmed in miss theirs
+        for _ in 0..self.teams.len() {
+            let team = &self.teams[self.team];
+            let is_out = team.is_out;
+            self.last_block = team.head;
+            self.last_turn = team.last_turn;
+            let is_grown = !is_out && self.add_segment();
+            self.teams[self.team].is_out = !is_grown;
+            self.team = (self.team + 1) % self.teams.len();
+            if is_grown {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn add_segment(&mut self) -> bool {
+        let is_capped = self.last_block.is_none_or(|block| block.is_capped);
+        let block = if self.rng.random::<f32>() < self.stop_probability || is_capped {
             self.random_block()
         } else {
             self.next_block()
         };
+        let Some(block) = block else {
+            return false;
+        };
 
-        match block.pipe_type {
-            PipeType::I => {
-                let instance = self.i_instance_at_block(&block);
-                self.i_pipe_instances.push(instance);
-            }
-            PipeType::L => {
-                let instance = self.l_instance_at_block(&block);
-                self.l_pipe_instances.push(instance);
+        if block.segment == 0 {
+            self.pipe_count += 1;
+        }
+        self.place(block);
+        self.record(Edit::AddBlock(block));
+        true
+    }
+
+    /// Place a segment by hand heading `direction`, as in build mode. One where the block placed last leads
+    /// continues its pipe, anywhere else starts a new one in the palette's next color. Elbows only turn a
+    /// pipe, so they must continue one at a right angle to it. False, placing nothing, where the block is
+    /// taken or out of the world, or for an elbow which doesn't turn a pipe.
+    pub fn build(&mut self, pipe_type: PipeType, position: (u32, u32, u32), direction: Direction) -> bool {
+        if !self.is_position_valid(&position) {
+            return false;
+        }
+        let last = self
+            .history
+            .last()
+            .copied()
+            .filter(|last| !last.is_erased && last.direction.neighbor(last.position()) == position);
+        if let PipeType::L = pipe_type
+            && last.is_none_or(|last| last.direction.is_parallel_to(direction))
+        {
+            return false;
+        }
+        let block = match last {
+            Some(last) => Block {
+                segment: last.segment + 1,
+                ..last
+            },
+            None => {
+                self.pipe_count += 1;
+                let color = self.palette.colors[self.pipe_count as usize % self.palette.colors.len()];
+                Block {
+                    pipe_type,
+                    direction,
+                    position: (0, 0, 0),
+                    color,
+                    emissive: 0.0,
+                    segment: 0,
+                    material: self.materials.material,
+                    is_capped: false,
+                    team: None,
+                    entry: None,
+                    is_erased: false,
+                }
             }
         };
+        let position = (position.0 as u16, position.1 as u16, position.2 as u16);
+        let entry = Some(last.map_or(direction, |last| last.direction));
+        let block = Block {
+            pipe_type,
+            direction,
+            position,
+            is_capped: false,
+            team: None,
+            entry,
+            ..block
+        };
+        self.place(block);
+        self.record(Edit::AddBlock(block));
+        true
+    }
 
-        self.occupied_blocks.insert(block.position);
-        self.last_block = Some(block);
+    /// Take the segments in the blocks at `positions` out of the world, returning the blocks they were in.
+    /// They stay in the history marked as erased, to bring them back, and as elbows restored from older
+    /// snapshots are built from the block before them.
+    pub fn erase(&mut self, positions: &[(u32, u32, u32)]) -> Vec<(u32, u32, u32)> {
+        let positions: HashSet<_> = positions.iter().copied().collect();
+        let indices: Vec<_> = (0..self.history.len())
+            .filter(|i| !self.history[*i].is_erased && positions.contains(&self.history[*i].position()))
+            .collect();
+        if indices.is_empty() {
+            return vec![];
+        }
+        self.record(Edit::RemoveBlock(indices.clone()));
+        self.erase_indices(&indices)
     }
 
-    pub fn add_debug_pipe(&mut self, pipe_type: PipeType, position: (u32, u32, u32), direction: Direction, color: [f32; 3]) {
-        let block = Block { pipe_type, direction, position, color };
+    fn erase_indices(&mut self, indices: &[usize]) -> Vec<(u32, u32, u32)> {
+        let mut erased = vec![];
+        for &i in indices {
+            let block = &mut self.history[i];
+            block.is_erased = true;
+            self.occupied_blocks.remove(&block.position());
+            if let Some(team) = block.team.and_then(|team| self.teams.get_mut(team as usize)) {
+                team.segments -= 1;
+            }
+            erased.push(block.position());
+        }
+        // growing on from an erased block would leave a gap, a new pipe starts instead
+        let is_erased = |head: Option<Block>| head.is_some_and(|head| erased.contains(&head.position()));
+        if is_erased(self.last_block) {
+            self.last_block = None;
+        }
+        for team in &mut self.teams {
+            if is_erased(team.head) {
+                team.head = None;
+            }
+        }
+        erased
+    }
 
-        match block.pipe_type {
-            PipeType::I => {
-                let instance = self.i_instance_at_block(&block);
-                self.i_pipe_instances.push(instance);
+    /// Undo the latest edit not undone yet: a block added, by hand or grown, the segments erased, or a
+    /// reset. `None` if there is none, or none kept, see `MAX_EDITS`.
+    pub fn undo(&mut self) -> Option<Change> {
+        let (redo, change) = match self.undo_log.pop_back()? {
+            Edit::AddBlock(_) => {
+                let block = self.unplace()?;
+                (Edit::AddBlock(block), Change::Removed(vec![block.position()]))
+            }
+            Edit::RemoveBlock(indices) => {
+                self.unerase(&indices);
+                (Edit::RemoveBlock(indices.clone()), Change::Added(indices))
             }
-            PipeType::L => {
-                let instance = self.l_instance_at_block(&block);
-                self.l_pipe_instances.push(instance);
+            Edit::Reset(snapshot) => {
+                self.replace(snapshot.as_deref()?);
+                (Edit::Reset(None), Change::Replaced)
             }
         };
+        self.redo_log.push(redo);
+        Some(change)
+    }
+
+    /// Redo the latest edit undone, unless the world changed since. `None` if there is none.
+    pub fn redo(&mut self) -> Option<Change> {
+        let (undo, change) = match self.redo_log.pop()? {
+            Edit::AddBlock(block) => {
+                if block.segment == 0 {
+                    self.pipe_count += 1;
+                }
+                self.place(block);
+                (Edit::AddBlock(block), Change::Added(vec![self.history.len() - 1]))
+            }
+            Edit::RemoveBlock(indices) => {
+                let erased = self.erase_indices(&indices);
+                (Edit::RemoveBlock(indices), Change::Removed(erased))
+            }
+            Edit::Reset(_) => {
+                let snapshot = self.take_snapshot();
+                self.start_over();
+                (Edit::Reset(Some(snapshot)), Change::Replaced)
+            }
+        };
+        self.undo_log.push_back(undo);
+        Some(change)
+    }
+
+    /// Keep `edit` to undo, which rules out redoing what was undone before. As every reset keeps a whole
+    /// world, only the latest one is kept, forgetting what was done before the one before.
+    fn record(&mut self, edit: Edit) {
+        self.redo_log.clear();
+        if let Edit::Reset(_) = edit
+            && let Some(i) = self.undo_log.iter().rposition(|edit| matches!(edit, Edit::Reset(_)))
+        {
+            self.undo_log.drain(..=i);
+        }
+        self.undo_log.push_back(edit);
+        if self.undo_log.len() > MAX_EDITS {
+            self.undo_log.pop_front();
+        }
+    }
+
+    /// Take the block added last out of the world again
+    fn unplace(&mut self) -> Option<Block> {
+        let block = self.history.pop()?;
+        if !block.is_erased {
+            self.occupied_blocks.remove(&block.position());
+        }
+        if block.segment == 0 {
+            self.pipe_count -= 1;
+        }
+        if let PipeType::L = block.pipe_type {
+            self.turn_count -= 1;
+        }
+        // growing on from the block before, or with teams, from the team's block before
+        self.last_block = self.history.last().copied().filter(|last| !last.is_erased);
+        self.last_turn = None;
+        if let Some(team) = block.team.and_then(|team| self.teams.get_mut(team as usize)) {
+            if !block.is_erased {
+                team.segments -= 1;
+            }
+            team.head = self
+                .history
+                .iter()
+                .rev()
+                .find(|other| other.team == block.team && !other.is_erased)
+                .copied();
+            team.last_turn = None;
+            team.frontier.retain(|position| *position != block.position());
+            team.is_out = false;
+        }
+        Some(block)
+    }
 
-        self.occupied_blocks.insert(block.position);
+    /// Bring the erased blocks at `indices` of the history back
+    fn unerase(&mut self, indices: &[usize]) {
+        for &i in indices {
+            let block = &mut self.history[i];
+            block.is_erased = false;
+            self.occupied_blocks.insert(block.position());
+            if let Some(team) = block.team.and_then(|team| self.teams.get_mut(team as usize)) {
+                team.segments += 1;
+            }
+        }
+    }
+
+    fn place(&mut self, block: Block) {
+        let team = block.team.map(usize::from).filter(|team| *team < self.teams.len());
+        if let Some(team) = team {
+            self.last_block = self.teams[team].head;
+            self.last_turn = self.teams[team].last_turn;
+        }
+        if block.segment == 0 {
+            self.last_turn = None;
+        }
+        if let PipeType::L = block.pipe_type {
+            self.turn_count += 1;
+            if let Some(last_block) = self.last_block {
+                self.last_turn = Some(Turn {
+                    from: last_block.direction,
+                    segment: block.segment,
+                });
+            }
+        }
+        self.occupied_blocks.insert(block.position());
+        self.history.push(block);
         self.last_block = Some(block);
+        if let Some(team) = team {
+            let team = &mut self.teams[team];
+            team.head = Some(block);
+            team.last_turn = self.last_turn;
+            team.segments += 1;
+            team.frontier.push(block.position());
+        }
     }
 
-    fn random_block(&self) -> Block {
-        let position = loop {
-            let position = (
-                rand::random_range(0..self.max_x_block / 2),
-                rand::random_range(0..self.max_y_block / 2),
-                rand::random_range(0..self.max_z_block / 2),
-            );
-            if !self.occupied_blocks.contains(&position) {
-                break position;
+    fn random_block(&mut self) -> Option<Block> {
+        let (position, direction) = match self.teams.get(self.team) {
+            // a team's network only grows where it has room, so that teams block each other
+            Some(team) if team.segments > 0 => self.branch()?,
+            _ => {
+                let position = match &self.mask {
+                    // masks are small, pick from what is left of them rather than hoping to hit it
+                    Some(mask) => {
+                        let free: Vec<_> = mask
+                            .iter()
+                            .filter(|position| !self.occupied_blocks.contains(position))
+                            .collect();
+                        **free.choose(&mut self.rng)?
+                    }
+                    None => self.random_position()?,
+                };
+                (position, self.direction_weights.choose(&ALL_DIRECTIONS, &mut self.rng))
             }
         };
 
-        Block {
+        Some(Block {
             pipe_type: PipeType::I, // always start with I for eases of impl
-            direction: Direction::random(),
-            color: *random_color(),
-            position,
+            direction,
+            color: if self.teams.is_empty() {
+                *self.palette.colors.choose(&mut self.rng).unwrap()
+            } else {
+                // every pipe of a team in its own color
+                self.palette.colors[self.team % self.palette.colors.len()]
+            },
+            emissive: self.random_glow(),
+            material: self.random_material(),
+            position: (position.0 as u16, position.1 as u16, position.2 as u16),
+            segment: 0,
+            is_capped: self.is_last_segment(0),
+            team: (!self.teams.is_empty()).then_some(self.team as u8),
+            entry: Some(direction),
+            is_erased: false,
+        })
+    }
+
+    /// A free block next to a random one of the growing team's, and the way from there to it, to start
+    /// the team's next pipe at
+    fn branch(&mut self) -> Option<((u32, u32, u32), Direction)> {
+        let offset = self.rng.random_range(0..ALL_DIRECTIONS.len());
+        while !self.teams[self.team].frontier.is_empty() {
+            let frontier = &self.teams[self.team].frontier;
+            let i = self.rng.random_range(0..frontier.len());
+            let from = frontier[i];
+            let free = (0..ALL_DIRECTIONS.len())
+                .map(|j| ALL_DIRECTIONS[(offset + j) % ALL_DIRECTIONS.len()])
+                .map(|direction| (direction.neighbor(from), direction))
+                .find(|(position, _)| self.is_position_valid(position));
+            if free.is_some() {
+                return free;
+            }
+            // taken all around, which stays that way
+            self.teams[self.team].frontier.swap_remove(i);
         }
+        None
     }
 
-    fn next_block(&self) -> Block {
-        use Direction::*;
-        let last_block = self.last_block.as_ref().unwrap();
-        let color = last_block.color;
+    fn is_last_segment(&self, segment: u32) -> bool {
+        self.max_pipe_length.is_some_and(|length| segment + 1 >= length)
+    }
 
-        let position = match last_block.direction {
-            X => (last_block.position.0 + 1, last_block.position.1, last_block.position.2),
-            Y => (last_block.position.0, last_block.position.1 + 1, last_block.position.2),
-            Z => (last_block.position.0, last_block.position.1, last_block.position.2 + 1),
-            _X => (last_block.position.0 - 1, last_block.position.1, last_block.position.2),
-            _Y => (last_block.position.0, last_block.position.1 - 1, last_block.position.2),
-            _Z => (last_block.position.0, last_block.position.1, last_block.position.2 - 1),
+    fn random_position(&mut self) -> Option<(u32, u32, u32)> {
+        // pipes start in the corner, or anywhere in the world around a region which may leave the corner out
+        let (x, y, z) = match self.region {
+            Some(_) => (self.max_x_block + 1, self.max_y_block + 1, self.max_z_block + 1),
+            None => (self.max_x_block / 2, self.max_y_block / 2, self.max_z_block / 2),
         };
+        let region = self.region;
+        let extents = self.extents();
+        (0..RANDOM_BLOCK_ATTEMPTS)
+            .map(|_| {
+                (
+                    self.rng.random_range(0..x),
+                    self.rng.random_range(0..y),
+                    self.rng.random_range(0..z),
+                )
+            })
+            .find(|position| {
+                !self.occupied_blocks.contains(position)
+                    && region.is_none_or(|region| region.contains(*position, extents))
+            })
+    }
+
+    fn is_in_region(&self, position: (u32, u32, u32)) -> bool {
+        self.region
+            .is_none_or(|region| region.contains(position, self.extents()))
+    }
+
+    fn random_glow(&mut self) -> f32 {
+        // don't draw from the rng at all for palettes without glow, so seeds keep growing the same worlds
+        if self.palette.glow_chance > 0.0 && self.rng.random::<f32>() < self.palette.glow_chance {
+            self.palette.glow
+        } else {
+            0.0
+        }
+    }
+
+    fn random_material(&mut self) -> Material {
+        // like the glow, no draw from the rng without accents
+        let Materials {
+            material,
+            accent,
+            accent_chance,
+        } = self.materials;
+        if accent_chance > 0.0 && self.rng.random::<f32>() < accent_chance {
+            accent
+        } else {
+            material
+        }
+    }
+
+    fn next_block(&mut self) -> Option<Block> {
+        let last_block = *self.last_block.as_ref().unwrap();
+        let color = last_block.color;
+        let emissive = last_block.emissive;
+        let material = last_block.material;
+        let segment = last_block.segment + 1;
+        let is_capped = self.is_last_segment(segment);
+
+        let position = last_block.direction.neighbor(last_block.position());
 
         // position is occupied, or out of the world dimension
         if !self.is_position_valid(&position) {
             return self.random_block();
         }
 
-        if rand::random::<f32>() < self.turn_probability {
-            Block {
+        let step = Step {
+            position,
+            direction: last_block.direction,
+            segment,
+            turn_probability: self.turn_probability,
+            weights: self.direction_weights,
+            last_turn: self.last_turn,
+            constraints: self.constraints,
+        };
+        let direction = self.generator.next_direction(step, &mut self.rng);
+        let direction = self.steer_clear(&step, direction);
+        let position = (position.0 as u16, position.1 as u16, position.2 as u16);
+        // anything but a right angle goes straight on
+        if !direction.is_parallel_to(last_block.direction) {
+            Some(Block {
                 color,
+                emissive,
+                material,
+                segment,
                 position,
-                direction: last_block.direction.random_perpendicular(),
+                direction,
                 pipe_type: PipeType::L,
-            }
+                is_capped,
+                team: last_block.team,
+                entry: Some(last_block.direction),
+                is_erased: false,
+            })
         } else {
-            Block {
+            Some(Block {
                 color,
+                emissive,
+                material,
+                segment,
                 position,
                 direction: last_block.direction,
                 pipe_type: PipeType::I,
-            }
+                is_capped,
+                team: last_block.team,
+                entry: Some(last_block.direction),
+                is_erased: false,
+            })
+        }
+    }
+
+    /// The generator's choice of direction, unless the pipe would soon run into something that way while
+    /// another way has more room, where it goes instead
+    fn steer_clear(&mut self, step: &Step, direction: Direction) -> Direction {
+        let depth = self.look_ahead;
+        // anything but a right angle goes straight on
+        let direction = if direction.is_parallel_to(step.direction) {
+            step.direction
+        } else {
+            direction
+        };
+        // don't draw from the rng at all without look-ahead, so seeds keep growing the same worlds
+        if depth == 0 || self.free_blocks_ahead(step.position, direction, depth) >= depth {
+            return direction;
+        }
+        let options: Vec<_> = std::iter::once(step.direction)
+            .chain(step.direction.perpendiculars().iter().copied())
+            .filter(|option| !step.is_ruled_out(*option))
+            .map(|option| (option, self.free_blocks_ahead(step.position, option, depth)))
+            .collect();
+        let chosen = self.free_blocks_ahead(step.position, direction, depth);
+        let most = options.iter().map(|(_, free)| *free).max().unwrap_or(0);
+        if most <= chosen {
+            return direction;
+        }
+        let roomiest: Vec<_> = options
+            .iter()
+            .filter(|(_, free)| *free == most)
+            .map(|(option, _)| *option)
+            .collect();
+        *roomiest.choose(&mut self.rng).unwrap()
+    }
+
+    /// Free blocks a pipe leaving `position` in `direction` could grow into within `depth` blocks, the
+    /// more the less likely it gets stuck there
+    fn free_blocks_ahead(&self, position: (u32, u32, u32), direction: Direction, depth: u32) -> u32 {
+        let mut free = vec![];
+        self.find_free_blocks(position, direction, depth, &mut free);
+        free.len() as u32
+    }
+
+    fn find_free_blocks(
+        &self,
+        position: (u32, u32, u32),
+        direction: Direction,
+        depth: u32,
+        free: &mut Vec<(u32, u32, u32)>,
+    ) {
+        let next = direction.neighbor(position);
+        if depth == 0 || !self.is_position_valid(&next) {
+            return;
+        }
+        if !free.contains(&next) {
+            free.push(next);
+        }
+        for turn in std::iter::once(direction).chain(direction.perpendiculars().iter().copied()) {
+            self.find_free_blocks(next, turn, depth - 1, free);
         }
     }
 
@@ -240,6 +1355,8 @@ impl World {
             || position.1 > self.max_y_block
             || position.2 > self.max_z_block
             || self.occupied_blocks.contains(position)
+            || self.mask.as_ref().is_some_and(|mask| !mask.contains(position))
+            || !self.is_in_region(*position)
         {
             return false;
         }
@@ -248,8 +1365,8 @@ impl World {
 
     fn i_instance_at_block(&self, block: &Block) -> Instance {
         use Direction::*;
-        let p = block.position;
-        let position = (p.0 as f32, p.1 as f32, p.2 as f32).into();
+        let p = block.position();
+        let position = cgmath::Vector3::new(p.0 as f32, p.1 as f32, p.2 as f32) * self.cell_size;
 
         let rotation = match block.direction {
             Y | _Y => cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(0.0)),
@@ -257,20 +1374,92 @@ impl World {
             Z | _Z => cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(90.0)),
         };
 
+        // the mesh runs along its local Y axis, which the rotation only ever points the positive way
+        let flow_axis = match block.direction {
+            X | Y | Z => 1.0,
+            _X | _Y | _Z => -1.0,
+        };
+
         // TODO add model offset to position
 
-        Instance { position, rotation, color: block.color }
+        Instance {
+            position,
+            rotation,
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0) * self.cell_size,
+            color: block.color,
+            alpha: self.opacity,
+            emissive: block.emissive,
+            flow_phase: block.segment as f32,
+            flow_axis,
+            material: block.material,
+        }
+    }
+
+    /// A flange at the end the pipe of `block` leaves it by, closing it off
+    fn cap_instance_at_block(&self, block: &Block) -> Instance {
+        let mut cap = self.i_instance_at_block(block);
+        cap.position += block.direction.vector() * CAP_OFFSET * self.cell_size;
+        cap.scale = CAP_SCALE * self.cell_size;
+        cap
+    }
+
+    /// `instance` of `block` moved and turned around the block's center and its straight pipes thickened or
+    /// thinned by `jitter`. The amounts only depend on where the block is, so that building its instances
+    /// again gives the same ones, and a cap follows the pipe it closes.
+    fn jittered(&self, mut instance: Instance, block: &Block, is_elbow: bool) -> Instance {
+        if self.jitter <= 0.0 {
+            return instance;
+        }
+        let p = block.position();
+        // kept apart from the world's rng, which would grow other pipes if it was drawn from here
+        let mut rng = StdRng::seed_from_u64(((p.0 as u64) << 42) ^ ((p.1 as u64) << 21) ^ p.2 as u64);
+        let mut amount = || rng.random_range(-1.0f32..=1.0) * self.jitter;
+        let offset = cgmath::Vector3::new(amount(), amount(), amount()) * JITTER_OFFSET * self.cell_size;
+        let tilt = cgmath::Quaternion::from_angle_x(cgmath::Deg(amount() * JITTER_TILT))
+            * cgmath::Quaternion::from_angle_z(cgmath::Deg(amount() * JITTER_TILT));
+        let radius = 1.0 + amount() * JITTER_RADIUS;
+
+        let center = cgmath::Vector3::new(p.0 as f32, p.1 as f32, p.2 as f32) * self.cell_size;
+        instance.position = center + offset + tilt * (instance.position - center);
+        instance.rotation = tilt * instance.rotation;
+        // elbows would no longer reach the sides of their block
+        if !is_elbow {
+            instance.scale.x *= radius;
+            instance.scale.z *= radius;
+        }
+        instance
     }
 
-    fn l_instance_at_block(&self, block: &Block) -> Instance {
+    /// `instance` of `block` with its saturation and value strayed from the pipe's color by `color_jitter`.
+    /// Like `jittered`, the amounts only depend on where the block is, and a cap takes on its pipe's.
+    fn tinted(&self, mut instance: Instance, block: &Block) -> Instance {
+        if self.color_jitter <= 0.0 {
+            return instance;
+        }
+        let p = block.position();
+        let mut rng =
+            StdRng::seed_from_u64((((p.0 as u64) << 42) ^ ((p.1 as u64) << 21) ^ p.2 as u64) ^ COLOR_JITTER_SALT);
+        let mut amount = || rng.random_range(-1.0f32..=1.0) * self.color_jitter;
+        let saturation = 1.0 + amount() * COLOR_JITTER_SATURATION;
+        let value = 1.0 + amount() * COLOR_JITTER_VALUE;
+
+        // keeping the largest channel and scaling the others' distance to it changes the saturation alone
+        let max = instance.color.iter().copied().fold(0.0, f32::max);
+        instance.color = instance
+            .color
+            .map(|channel| ((max - (max - channel) * saturation).max(0.0) * value).min(1.0));
+        instance
+    }
+
+    /// The elbow in `block`, turning from `entry`, the direction its pipe comes in by
+    fn l_instance_at_block(&self, block: &Block, entry: Direction) -> Instance {
         use Direction::*;
-        let last_block_dir = self.last_block.as_ref().unwrap().direction;
-        let p = block.position;
-        let position = (p.0 as f32, p.1 as f32, p.2 as f32).into();
+        let p = block.position();
+        let position = cgmath::Vector3::new(p.0 as f32, p.1 as f32, p.2 as f32) * self.cell_size;
 
         let rotation: cgmath::Quaternion<f32> = match block.direction {
             X => {
-                let deg = match last_block_dir {
+                let deg = match entry {
                     _Y => 0.0,
                     _Z => 90.0,
                     Y => 180.0,
@@ -280,18 +1469,18 @@ impl World {
                 cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(deg))
             }
             _X => {
-                let deg = match last_block_dir {
+                let deg = match entry {
                     _Y => 0.0,
                     _Z => 90.0,
                     Y => 180.0,
                     Z => -90.0,
                     _ => panic!("Invalid direction"),
                 };
-                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(deg)) *
-                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(90.0))
+                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(deg))
+                    * cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(90.0))
             }
             Y => {
-                let deg = match last_block_dir {
+                let deg = match entry {
                     _X => 0.0,
                     _Z => -90.0,
                     X => 180.0,
@@ -301,42 +1490,222 @@ impl World {
                 cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(deg))
             }
             _Y => {
-                let deg = match last_block_dir {
+                let deg = match entry {
                     _X => 0.0,
                     _Z => -90.0,
                     X => 180.0,
                     Z => 90.0,
                     _ => panic!("Invalid direction"),
                 };
-                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(deg)) *
-                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(180.0))
+                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(deg))
+                    * cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(180.0))
             }
             Z => {
-                let deg = match last_block_dir {
+                let deg = match entry {
                     _X => 0.0,
                     _Y => 90.0,
                     X => 180.0,
                     Y => -90.0,
                     _ => panic!("Invalid direction"),
                 };
-                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(deg)) *
-                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(90.0))
+                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(deg))
+                    * cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(90.0))
             }
             _Z => {
-                let deg = match last_block_dir {
+                let deg = match entry {
                     _X => 0.0,
                     _Y => 90.0,
                     X => 180.0,
                     Y => -90.0,
                     _ => panic!("Invalid direction"),
                 };
-                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(deg)) *
-                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(-90.0))
+                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(deg))
+                    * cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), cgmath::Deg(-90.0))
             }
         };
 
         // TODO add model offset to position
 
-        Instance { position, rotation, color: block.color }
+        Instance {
+            position,
+            rotation,
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0) * self.cell_size,
+            color: block.color,
+            alpha: self.opacity,
+            emissive: block.emissive,
+            flow_phase: block.segment as f32,
+            flow_axis: 0.0,
+            material: block.material,
+        }
+    }
+}
+
+/// A seed for a new world. Seeds are saved as TOML, which only has signed integers.
+fn random_seed(rng: &mut StdRng) -> u64 {
+    rng.random::<u64>() >> 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEEDS: u64 = 20;
+    const SEGMENTS: u32 = 3000;
+
+    /// Pipes that got stuck while the worlds of every seed grew the same number of segments, each one
+    /// starting the next pipe
+    fn stuck_pipes(look_ahead: u32) -> u32 {
+        (0..SEEDS)
+            .map(|seed| {
+                let mut world = World::with_seed(seed);
+                world.set_look_ahead(look_ahead);
+                for _ in 0..SEGMENTS {
+                    assert!(world.add_pipe());
+                }
+                world.stats().pipes - 1
+            })
+            .sum()
+    }
+
+    #[test]
+    fn look_ahead_gets_pipes_stuck_less_often() {
+        let without = stuck_pipes(0);
+        let one = stuck_pipes(1);
+        let two = stuck_pipes(2);
+        assert!(
+            one < without,
+            "{} stuck pipes looking 1 block ahead, {} without",
+            one,
+            without
+        );
+        assert!(
+            two < one,
+            "{} stuck pipes looking 2 blocks ahead, {} looking 1 ahead",
+            two,
+            one
+        );
+    }
+
+    #[test]
+    fn pipes_only_grow_inside_the_region() {
+        let region = Region::Sphere { radius: 4.0 };
+        let mut world = World::with_seed(0);
+        world.set_region(Some(region));
+        for _ in 0..500 {
+            world.add_pipe();
+        }
+        assert!(!world.blocks().is_empty());
+        for block in world.blocks() {
+            let (x, y, z) = block.position;
+            let position = (x as u32, y as u32, z as u32);
+            assert!(
+                region.contains(position, world.extents()),
+                "{:?} outside the region",
+                position
+            );
+        }
+    }
+
+    #[test]
+    fn worlds_stretch_along_the_longer_side_of_the_view() {
+        assert_eq!(extents_for_aspect(30, 1.0), [30, 30, 30]);
+        assert_eq!(extents_for_aspect(30, 16.0 / 9.0), [53, 30, 30]);
+        assert_eq!(extents_for_aspect(30, 0.5), [30, 60, 30]);
+        assert_eq!(extents_for_aspect(MAX_SIZE, 2.0), [MAX_SIZE; 3]);
+    }
+
+    #[test]
+    fn elbows_of_teams_turn_from_their_own_pipes() {
+        for seed in 0..SEEDS {
+            let mut world = World::with_seed(seed);
+            world.set_teams(3);
+            for _ in 0..500 {
+                world.add_pipe();
+            }
+            // building an elbow's instance panics unless it turns from the way its pipe comes in
+            world.instances_between(0..world.len());
+        }
+    }
+
+    #[test]
+    fn built_elbows_only_turn_a_pipe() {
+        let mut world = World::with_seed(0);
+        assert!(
+            !world.build(PipeType::L, (2, 2, 2), Direction::X),
+            "elbow without a pipe to turn"
+        );
+        assert!(world.build(PipeType::I, (2, 2, 2), Direction::Y));
+        assert!(!world.build(PipeType::I, (2, 2, 2), Direction::Y), "taken block");
+        assert!(
+            !world.build(PipeType::L, (2, 3, 2), Direction::_Y),
+            "elbow turning back"
+        );
+        assert!(!world.build(PipeType::L, (3, 3, 2), Direction::X), "elbow off the pipe");
+        assert!(world.build(PipeType::L, (2, 3, 2), Direction::X));
+        assert!(world.build(PipeType::I, (3, 3, 2), Direction::X));
+        assert_eq!(world.stats().pipes, 1);
+        // building an elbow's instance panics unless it turns from the block before it
+        world.instances_between(0..world.len());
+    }
+
+    #[test]
+    fn erased_blocks_are_free_and_stay_erased_when_restored() {
+        let mut world = World::with_seed(0);
+        assert!(world.build(PipeType::I, (2, 2, 2), Direction::Y));
+        assert!(world.build(PipeType::L, (2, 3, 2), Direction::X));
+        assert_eq!(world.erase(&[(2, 2, 2), (5, 5, 5)]), vec![(2, 2, 2)]);
+        assert_eq!(world.stats().segments, 1);
+        // the elbow still comes in the way of the erased block before it
+        let (i_instances, l_instances) = world.instances_between(0..world.len());
+        assert_eq!((i_instances.len(), l_instances.len()), (0, 1));
+
+        let snapshot = world.snapshot();
+        let mut restored = World::with_seed(1);
+        restored.restore(&snapshot);
+        assert_eq!(restored.stats().segments, 1);
+        assert!(restored.build(PipeType::I, (2, 2, 2), Direction::Z));
+    }
+
+    #[test]
+    fn undoing_everything_and_redoing_it_grows_the_same_world() {
+        let mut world = World::with_seed(0);
+        for _ in 0..50 {
+            assert!(world.add_pipe());
+        }
+        world.erase(&[world.history[10].position()]);
+        let grown: Vec<_> = world
+            .history
+            .iter()
+            .map(|block| (block.position(), block.is_erased))
+            .collect();
+        let stats = world.stats();
+        world.reset();
+
+        assert_eq!(world.undo(), Some(Change::Replaced));
+        assert_eq!(world.undo(), Some(Change::Added(vec![10])));
+        let last = world.history[49].position();
+        assert_eq!(world.undo(), Some(Change::Removed(vec![last])));
+        while world.undo().is_some() {}
+        assert_eq!((world.len(), world.stats().segments, world.stats().pipes), (0, 0, 0));
+
+        // every block, then the erasing, then the reset
+        for _ in 0..51 {
+            assert!(world.redo().is_some());
+        }
+        assert_eq!(
+            world
+                .history
+                .iter()
+                .map(|block| (block.position(), block.is_erased))
+                .collect::<Vec<_>>(),
+            grown
+        );
+        assert_eq!(
+            (world.stats().segments, world.stats().pipes),
+            (stats.segments, stats.pipes)
+        );
+        assert_eq!(world.redo(), Some(Change::Replaced));
+        assert_eq!(world.len(), 0);
+        assert_eq!(world.redo(), None);
     }
 }
@@ -0,0 +1,600 @@
+//! Renders seeded worlds from fixed cameras and compares them against the reference images in
+//! `tests/golden`. Run with `XPIPE_UPDATE_GOLDEN=1` to (re)write the references after an intended
+//! visual change, and check the new images before committing them.
+
+use std::path::PathBuf;
+
+use xpipe::headless::{
+    Antialiasing, Depth, DirectionWeights, HeadlessRenderer, LightOrbit, Material, Materials, OrbitPath, PipeStyle,
+    Shading, Tessellation,
+};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+const SEED: u64 = 1352;
+const PIPES: u32 = 300;
+const DRAIN_SECONDS: f32 = 2.0;
+// frames rendered before the one compared with TAA, a whole round of its jitter
+const TAA_FRAMES: u32 = 8;
+// A pixel differs when the perceived brightness of its difference is above this, out of 255
+const PIXEL_TOLERANCE: f32 = 12.0;
+// Share of differing pixels allowed, to absorb rasterization differences between GPUs and drivers
+const MAX_DIFFERING_PIXELS: f32 = 0.01;
+
+fn reference_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.png", name))
+}
+
+/// Share of pixels which visibly differ between the two images
+fn differing_pixels(actual: &image::RgbaImage, expected: &image::RgbaImage) -> f32 {
+    let differing = actual
+        .pixels()
+        .zip(expected.pixels())
+        .filter(|(a, e)| {
+            let [dr, dg, db, _] = std::array::from_fn(|i| (a[i] as f32 - e[i] as f32).abs());
+            // Rec. 601 luma weights, the eye is most sensitive to green and least to blue
+            0.299 * dr + 0.587 * dg + 0.114 * db > PIXEL_TOLERANCE
+        })
+        .count();
+    differing as f32 / (actual.width() * actual.height()) as f32
+}
+
+struct Scene<'a> {
+    palette: &'a str,
+    glass_opacity: Option<f32>,
+    materials: Materials,
+    max_pipe_length: Option<u32>,
+    direction_weights: DirectionWeights,
+    shading: Shading,
+    render_scale: f32,
+    aa: Antialiasing,
+    floor: bool,
+    depth_prepass: bool,
+    instance_storage: bool,
+    parallel_encoding: bool,
+    clock: Option<(u8, u8)>,
+    // seconds into draining the world away after it grew, see `DRAIN_SECONDS`
+    drained: Option<f32>,
+    post_effect: Option<&'a str>,
+    overlay_text: Option<&'a str>,
+    // pixel clicked on to highlight the segment there, whose details replace the overlay text
+    picked: Option<(u32, u32)>,
+    gizmos: bool,
+    cell_size: f32,
+    // in blocks, see `HeadlessRenderer::set_pipe_radius`
+    pipe_radius: f32,
+    jitter: f32,
+    color_jitter: f32,
+    pipe_style: PipeStyle,
+    tessellation: Tessellation,
+    // physical pixels per logical pixel, which the overlay is sized by
+    scale_factor: f32,
+    // and how many degrees into it the light is, the default orbit's start when `None`
+    light: Option<(LightOrbit, f32)>,
+    ambient: f32,
+    rim_light: f32,
+    sky_light: f32,
+    // linear colors of the sky and the ground, the defaults when `None`
+    sky_colors: Option<([f32; 3], [f32; 3])>,
+    // stops
+    exposure: f32,
+    // of the texture rendered into, like a window's surface
+    color_format: wgpu::TextureFormat,
+    depth: Depth,
+}
+
+const CLASSIC: Scene = Scene {
+    palette: "classic",
+    glass_opacity: None,
+    materials: Materials {
+        material: Material::Plastic,
+        accent: Material::Plastic,
+        accent_chance: 0.0,
+    },
+    max_pipe_length: None,
+    direction_weights: DirectionWeights::EVEN,
+    shading: Shading::Phong,
+    render_scale: 1.0,
+    aa: Antialiasing::None,
+    floor: false,
+    depth_prepass: false,
+    instance_storage: false,
+    parallel_encoding: false,
+    clock: None,
+    drained: None,
+    post_effect: None,
+    overlay_text: None,
+    picked: None,
+    gizmos: false,
+    cell_size: 1.0,
+    pipe_radius: 0.5,
+    jitter: 0.0,
+    color_jitter: 0.0,
+    pipe_style: PipeStyle::Segments,
+    tessellation: Tessellation {
+        sides: 16,
+        segments_per_unit: 8,
+    },
+    scale_factor: 1.0,
+    light: None,
+    ambient: 1.0,
+    rim_light: 0.0,
+    sky_light: 0.0,
+    sky_colors: None,
+    exposure: 0.0,
+    color_format: wgpu::TextureFormat::Rgba8UnormSrgb,
+    depth: Depth {
+        format: wgpu::TextureFormat::Depth32Float,
+        reversed: false,
+    },
+};
+
+fn check_golden(name: &str, scene: Scene, yaw: f32, pitch: f32, zoom: f32) {
+    let Some(mut renderer) = pollster::block_on(HeadlessRenderer::with_formats(
+        WIDTH,
+        HEIGHT,
+        scene.color_format,
+        scene.depth,
+    ))
+    .unwrap() else {
+        eprintln!("No GPU adapter available, skipping golden image {}", name);
+        return;
+    };
+    renderer.set_palette(scene.palette).unwrap();
+    renderer.set_glass(scene.glass_opacity);
+    renderer.set_materials(scene.materials);
+    renderer.set_max_pipe_length(scene.max_pipe_length);
+    renderer.set_direction_weights(scene.direction_weights);
+    renderer.set_shading(scene.shading);
+    renderer.set_render_scale(scene.render_scale);
+    renderer.set_antialiasing(scene.aa).unwrap();
+    renderer.set_floor(scene.floor);
+    renderer.set_depth_prepass(scene.depth_prepass);
+    renderer.set_instance_storage(scene.instance_storage).unwrap();
+    renderer.set_parallel_encoding(scene.parallel_encoding);
+    renderer.set_clock(scene.clock);
+    renderer.set_post_effect(scene.post_effect).unwrap();
+    renderer.set_scale_factor(scene.scale_factor);
+    renderer.set_overlay_text(scene.overlay_text);
+    renderer.set_gizmos(scene.gizmos);
+    renderer.set_cell_size(scene.cell_size);
+    renderer.set_pipe_radius(scene.pipe_radius);
+    renderer.set_jitter(scene.jitter);
+    renderer.set_color_jitter(scene.color_jitter);
+    renderer.set_pipe_style(scene.pipe_style);
+    renderer.set_tessellation(scene.tessellation);
+    if let Some((orbit, degrees)) = scene.light {
+        renderer.set_light(orbit, degrees);
+    }
+    renderer.set_ambient(scene.ambient);
+    renderer.set_rim_light(scene.rim_light);
+    renderer.set_sky_light(scene.sky_light);
+    renderer.set_exposure(scene.exposure);
+    if let Some((sky, ground)) = scene.sky_colors {
+        renderer.set_sky_colors(sky, ground);
+    }
+    renderer.grow_world(SEED, PIPES);
+    if let Some(seconds) = scene.drained {
+        renderer.set_drain_seconds(DRAIN_SECONDS);
+        renderer.clear_world();
+        renderer.advance_time(seconds);
+    }
+    renderer.set_camera(yaw, pitch, zoom);
+    if let Some((x, y)) = scene.picked {
+        let selection = renderer
+            .pick(x, y)
+            .unwrap_or_else(|| panic!("Nothing to pick at {}, {} in {}", x, y, name));
+        renderer.set_overlay_text(Some(&selection.to_string()));
+    }
+    if scene.aa == Antialiasing::Taa {
+        // TAA blends the frames before into the one compared, each jittered differently
+        for _ in 0..TAA_FRAMES {
+            renderer.render().unwrap();
+        }
+    }
+    let actual = renderer.render().unwrap();
+
+    let path = reference_path(name);
+    if std::env::var_os("XPIPE_UPDATE_GOLDEN").is_some() {
+        actual.save(&path).unwrap();
+        return;
+    }
+    let expected = image::open(&path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "Cannot open {:?}, run with XPIPE_UPDATE_GOLDEN=1 to create it: {}",
+                path, e
+            )
+        })
+        .into_rgba8();
+    assert_eq!(
+        actual.dimensions(),
+        expected.dimensions(),
+        "{} has the wrong size",
+        name
+    );
+
+    let differing = differing_pixels(&actual, &expected);
+    if differing > MAX_DIFFERING_PIXELS {
+        let actual_path = path.with_extension("actual.png");
+        actual.save(&actual_path).unwrap();
+        panic!(
+            "{:.2}% of the pixels of {} differ from the reference, the rendered image is at {:?}",
+            differing * 100.0,
+            name,
+            actual_path
+        );
+    }
+}
+
+#[test]
+fn front_view() {
+    check_golden("front_view", CLASSIC, 0.0, 0.0, 16.0);
+}
+
+#[test]
+fn side_view() {
+    check_golden("side_view", CLASSIC, 0.8, -0.2, 16.0);
+}
+
+#[test]
+fn close_up() {
+    check_golden("close_up", CLASSIC, 0.3, 0.1, 3.0);
+}
+
+#[test]
+fn neon_palette() {
+    let scene = Scene {
+        palette: "neon",
+        ..CLASSIC
+    };
+    check_golden("neon_palette", scene, 0.8, -0.2, 16.0);
+}
+
+#[test]
+fn glass_pipes() {
+    let scene = Scene {
+        glass_opacity: Some(0.35),
+        ..CLASSIC
+    };
+    check_golden("glass_pipes", scene, 0.8, -0.2, 16.0);
+}
+
+#[test]
+fn toon_shading() {
+    let scene = Scene {
+        shading: Shading::Toon,
+        ..CLASSIC
+    };
+    check_golden("toon_shading", scene, 0.3, 0.1, 3.0);
+}
+
+#[test]
+fn lambert_shading() {
+    let scene = Scene {
+        shading: Shading::Lambert,
+        ..CLASSIC
+    };
+    check_golden("lambert_shading", scene, 0.3, 0.1, 3.0);
+}
+
+#[test]
+fn pbr_shading() {
+    let scene = Scene {
+        shading: Shading::Pbr,
+        ..CLASSIC
+    };
+    check_golden("pbr_shading", scene, 0.3, 0.1, 3.0);
+}
+
+#[test]
+fn unlit_shading() {
+    let scene = Scene {
+        shading: Shading::Unlit,
+        ..CLASSIC
+    };
+    check_golden("unlit_shading", scene, 0.3, 0.1, 3.0);
+}
+
+#[test]
+fn chrome_among_matte() {
+    let scene = Scene {
+        materials: Materials {
+            material: Material::Matte,
+            accent: Material::Chrome,
+            accent_chance: 0.3,
+        },
+        ..CLASSIC
+    };
+    check_golden("chrome_among_matte", scene, 0.8, -0.2, 10.0);
+}
+
+#[test]
+fn capped_pipes() {
+    let scene = Scene {
+        max_pipe_length: Some(6),
+        ..CLASSIC
+    };
+    check_golden("capped_pipes", scene, 0.3, 0.1, 6.0);
+}
+
+#[test]
+fn climbing_pipes() {
+    let scene = Scene {
+        direction_weights: DirectionWeights {
+            y: 6.0,
+            neg_y: 0.2,
+            ..DirectionWeights::EVEN
+        },
+        ..CLASSIC
+    };
+    check_golden("climbing_pipes", scene, 0.3, 0.0, 12.0);
+}
+
+#[test]
+fn supersampled() {
+    let scene = Scene {
+        render_scale: 2.0,
+        ..CLASSIC
+    };
+    check_golden("supersampled", scene, 0.3, 0.1, 3.0);
+}
+
+#[test]
+fn downscaled() {
+    let scene = Scene {
+        render_scale: 0.5,
+        ..CLASSIC
+    };
+    check_golden("downscaled", scene, 0.3, 0.1, 3.0);
+}
+
+#[test]
+fn reflective_floor() {
+    let scene = Scene { floor: true, ..CLASSIC };
+    check_golden("reflective_floor", scene, 2.4, -0.9, 20.0);
+}
+
+#[test]
+fn depth_prepass() {
+    // Only changes how fast the frame is drawn, not what is in it
+    let scene = Scene {
+        floor: true,
+        depth_prepass: true,
+        ..CLASSIC
+    };
+    check_golden("reflective_floor", scene, 2.4, -0.9, 20.0);
+}
+
+#[test]
+fn instance_storage() {
+    // Only changes where the vertex shaders read instances from, not what is drawn
+    let scene = Scene {
+        floor: true,
+        instance_storage: true,
+        ..CLASSIC
+    };
+    check_golden("reflective_floor", scene, 2.4, -0.9, 20.0);
+}
+
+#[test]
+fn draining() {
+    // Halfway, the newer half of the pipes is gone and the oldest are whole
+    let scene = Scene {
+        drained: Some(DRAIN_SECONDS / 2.0),
+        ..CLASSIC
+    };
+    check_golden("draining", scene, 0.8, -0.2, 16.0);
+}
+
+#[test]
+fn flow_shading() {
+    let scene = Scene {
+        shading: Shading::Flow,
+        ..CLASSIC
+    };
+    check_golden("flow_shading", scene, 0.3, 0.1, 6.0);
+}
+
+#[test]
+fn clock() {
+    let scene = Scene {
+        clock: Some((12, 34)),
+        ..CLASSIC
+    };
+    check_golden("clock", scene, 0.0, 0.0, 1.0);
+}
+
+#[test]
+fn vignette() {
+    let scene = Scene {
+        post_effect: Some("vignette"),
+        ..CLASSIC
+    };
+    check_golden("vignette", scene, 0.0, 0.0, 16.0);
+}
+
+#[test]
+fn help_overlay() {
+    let scene = Scene {
+        overlay_text: Some("Keys\n  Space    Pause\n  H, /     This help\n\nSettings\n  Shading  Phong"),
+        ..CLASSIC
+    };
+    check_golden("help_overlay", scene, 0.0, 0.0, 16.0);
+}
+
+#[test]
+fn picked_segment() {
+    let scene = Scene {
+        picked: Some((236, 80)),
+        ..CLASSIC
+    };
+    check_golden("picked_segment", scene, 0.3, 0.1, 6.0);
+}
+
+#[test]
+fn gizmos() {
+    let scene = Scene {
+        gizmos: true,
+        ..CLASSIC
+    };
+    check_golden("gizmos", scene, 0.6, -0.3, 6.0);
+}
+
+#[test]
+fn thin_pipes() {
+    let scene = Scene {
+        cell_size: 2.0,
+        pipe_radius: 0.15,
+        ..CLASSIC
+    };
+    check_golden("thin_pipes", scene, 0.3, 0.1, 12.0);
+}
+
+#[test]
+fn jittered_pipes() {
+    let scene = Scene { jitter: 1.0, ..CLASSIC };
+    check_golden("jittered_pipes", scene, 0.3, 0.1, 6.0);
+}
+
+#[test]
+fn color_jitter() {
+    let scene = Scene {
+        color_jitter: 1.0,
+        ..CLASSIC
+    };
+    check_golden("color_jitter", scene, 0.3, 0.1, 6.0);
+}
+
+#[test]
+fn tubes() {
+    let scene = Scene {
+        pipe_style: PipeStyle::Tubes,
+        ..CLASSIC
+    };
+    check_golden("tubes", scene, 0.3, 0.1, 6.0);
+}
+
+#[test]
+fn coarse_tubes() {
+    let scene = Scene {
+        pipe_style: PipeStyle::Tubes,
+        tessellation: Tessellation {
+            sides: 5,
+            segments_per_unit: 2,
+        },
+        ..CLASSIC
+    };
+    check_golden("coarse_tubes", scene, 0.3, 0.1, 6.0);
+}
+
+#[test]
+fn high_dpi_overlay() {
+    let scene = Scene {
+        overlay_text: Some("Keys\n  Space  Pause"),
+        scale_factor: 2.0,
+        ..CLASSIC
+    };
+    check_golden("high_dpi_overlay", scene, 0.0, 0.0, 16.0);
+}
+
+#[test]
+fn non_srgb_output() {
+    // Only changes who encodes gamma, the blitter rather than the texture, not what is drawn
+    let scene = Scene {
+        color_format: wgpu::TextureFormat::Rgba8Unorm,
+        ..CLASSIC
+    };
+    check_golden("side_view", scene, 0.8, -0.2, 16.0);
+}
+
+#[test]
+fn reversed_z() {
+    // Only changes how depth is stored, which the floor, its shadows and the pre-pass all test against
+    let scene = Scene {
+        floor: true,
+        depth_prepass: true,
+        depth: Depth {
+            format: wgpu::TextureFormat::Depth32Float,
+            reversed: true,
+        },
+        ..CLASSIC
+    };
+    check_golden("reflective_floor", scene, 2.4, -0.9, 20.0);
+}
+
+#[test]
+fn parallel_encoding() {
+    // Only changes which thread encodes the scene, not what it or the blit of the effect over it draw
+    let scene = Scene {
+        post_effect: Some("vignette"),
+        parallel_encoding: true,
+        ..CLASSIC
+    };
+    check_golden("vignette", scene, 0.0, 0.0, 16.0);
+}
+
+#[test]
+fn lissajous_light() {
+    // Lights the world from high above its far side, where the default orbit never goes
+    let scene = Scene {
+        light: Some((
+            LightOrbit {
+                center: [15.0, 25.0, 15.0],
+                radius: 20.0,
+                path: OrbitPath::Lissajous,
+                ..Default::default()
+            },
+            150.0,
+        )),
+        ..CLASSIC
+    };
+    check_golden("lissajous_light", scene, 0.8, -0.2, 16.0);
+}
+
+#[test]
+fn ambient_and_rim_light() {
+    let scene = Scene {
+        ambient: 4.0,
+        rim_light: 1.0,
+        ..CLASSIC
+    };
+    check_golden("ambient_and_rim_light", scene, 0.8, -0.2, 16.0);
+}
+
+#[test]
+fn sky_light() {
+    let scene = Scene {
+        sky_light: 1.5,
+        sky_colors: Some(([0.3, 0.45, 0.8], [0.25, 0.12, 0.04])),
+        materials: Materials {
+            material: Material::Plastic,
+            accent: Material::Chrome,
+            accent_chance: 0.3,
+        },
+        ..CLASSIC
+    };
+    check_golden("sky_light", scene, 0.8, -0.2, 16.0);
+}
+
+#[test]
+fn exposure() {
+    let scene = Scene {
+        exposure: -1.5,
+        floor: true,
+        ..CLASSIC
+    };
+    check_golden("exposure", scene, 0.8, -0.2, 16.0);
+}
+
+#[test]
+fn taa() {
+    let scene = Scene {
+        aa: Antialiasing::Taa,
+        ..CLASSIC
+    };
+    check_golden("taa", scene, 0.8, -0.2, 16.0);
+}